@@ -0,0 +1,269 @@
+use crate::metrics::METRICS;
+use crate::server::authorized;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    pub static ref ADMIN: Admin = Admin::default();
+}
+
+/// Maximum duration of a mute, so that a forgotten mute does not silence the events forever.
+const MAX_MUTE_MINUTES: u64 = 24 * 60;
+
+/// The runtime controls of the forwarding, changed through the admin API: the forwarding can be
+/// paused, and the events of a namespace or with a reason muted for a while.
+#[derive(Default)]
+pub struct Admin {
+    paused: AtomicBool,
+    /// The end of the mutes, by field ("namespace" or "reason") and value.
+    mutes: Mutex<BTreeMap<(String, String), Instant>>,
+    /// The effective filters of the processor of each cluster.
+    filters: Mutex<BTreeMap<String, Value>>,
+}
+
+impl Admin {
+    /// The filter discarding the event, if the forwarding is paused or the event muted.
+    pub fn filter(&self, namespace: &str, reason: &str) -> Option<&'static str> {
+        if self.paused.load(Ordering::Relaxed) {
+            return Some("paused");
+        }
+
+        let mut mutes = self.mutes.lock().unwrap();
+        if mutes.is_empty() {
+            return None;
+        }
+
+        mutes.retain(|_, until| *until > Instant::now());
+        let muted = mutes.contains_key(&("namespace".to_string(), namespace.to_string()))
+            || mutes.contains_key(&("reason".to_string(), reason.to_string()));
+        muted.then_some("muted")
+    }
+
+    /// Records the effective filters of the processor of the cluster.
+    pub fn filters(&self, cluster: &str, filters: Value) {
+        let mut all = self.filters.lock().unwrap();
+        all.insert(cluster.to_string(), filters);
+    }
+
+    fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        info!(
+            "Forwarding {} through the admin API",
+            if paused { "paused" } else { "resumed" }
+        );
+    }
+
+    fn mute(&self, field: &str, value: &str, duration: Duration) {
+        let mut mutes = self.mutes.lock().unwrap();
+        mutes.insert(
+            (field.to_string(), value.to_string()),
+            Instant::now() + duration,
+        );
+        info!(
+            "Muted the events of {} {} for {}m through the admin API",
+            field,
+            value,
+            duration.as_secs() / 60
+        );
+    }
+
+    fn unmute(&self, field: &str, value: &str) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        mutes
+            .remove(&(field.to_string(), value.to_string()))
+            .is_some()
+    }
+
+    fn status(&self) -> Value {
+        let now = Instant::now();
+        let mutes = self
+            .mutes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|((field, value), until)| {
+                json!({ field: value, "remaining_seconds": (*until - now).as_secs() })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "paused": self.paused.load(Ordering::Relaxed),
+            "mutes": mutes,
+            "filters": *self.filters.lock().unwrap(),
+        })
+    }
+}
+
+/// Serves the admin API, authenticated by a bearer token:
+/// - `GET /status`: the pause, the mutes and the effective filters of each cluster;
+/// - `GET /metrics`: the counters, in the prometheus text format;
+/// - `POST /pause` and `POST /resume`: pauses or resumes the forwarding of all the events;
+/// - `POST /mute?namespace=<ns>&minutes=<n>` (or `reason=<reason>`): mutes the events for a while;
+/// - `DELETE /mute?namespace=<ns>` (or `reason=<reason>`): removes a mute.
+pub async fn serve(addr: SocketAddr, token: String) {
+    let token = Arc::new(token);
+    let service = make_service_fn(move |_| {
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |r| {
+                let token = token.clone();
+                async move { Ok::<_, Infallible>(route(&ADMIN, &token, r)) }
+            }))
+        }
+    });
+
+    info!("Admin API listening on {}", addr);
+    match Server::try_bind(&addr) {
+        Ok(server) => {
+            if let Err(e) = server.serve(service).await {
+                error!("Admin API server error: {}", e);
+            }
+        }
+        Err(e) => error!("Cannot listen on {}: {}", addr, e),
+    }
+}
+
+fn route(admin: &Admin, token: &str, request: Request<Body>) -> Response<Body> {
+    if !authorized(&request, token) {
+        warn!("Unauthorized admin API request: {}", request.uri().path());
+        return response(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" }));
+    }
+
+    let query = query(&request);
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/status") => response(StatusCode::OK, admin.status()),
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(METRICS.render().into())
+            .unwrap(),
+        (&Method::POST, "/pause") => {
+            admin.pause(true);
+            response(StatusCode::OK, admin.status())
+        }
+        (&Method::POST, "/resume") => {
+            admin.pause(false);
+            response(StatusCode::OK, admin.status())
+        }
+        (&Method::POST, "/mute") => {
+            let Some((field, value)) = mute_target(&query) else {
+                return bad_request("namespace or reason is required");
+            };
+            let minutes = match query.get("minutes").map(|m| m.parse::<u64>()) {
+                Some(Ok(minutes)) if minutes > 0 && minutes <= MAX_MUTE_MINUTES => minutes,
+                _ => return bad_request("minutes must be between 1 and 1440"),
+            };
+
+            admin.mute(field, value, Duration::from_secs(minutes * 60));
+            response(StatusCode::OK, admin.status())
+        }
+        (&Method::DELETE, "/mute") => {
+            let Some((field, value)) = mute_target(&query) else {
+                return bad_request("namespace or reason is required");
+            };
+            if !admin.unmute(field, value) {
+                return response(StatusCode::NOT_FOUND, json!({ "error": "not muted" }));
+            }
+
+            response(StatusCode::OK, admin.status())
+        }
+        _ => response(StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+    }
+}
+
+/// The decoded parameters of the query string.
+fn query(request: &Request<Body>) -> BTreeMap<String, String> {
+    let query = request.uri().query().unwrap_or_default();
+    form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn mute_target(query: &BTreeMap<String, String>) -> Option<(&'static str, &str)> {
+    ["namespace", "reason"]
+        .into_iter()
+        .find_map(|field| Some((field, query.get(field)?.as_str())))
+        .filter(|(_, value)| !value.is_empty())
+}
+
+fn bad_request(error: &str) -> Response<Body> {
+    response(StatusCode::BAD_REQUEST, json!({ "error": error }))
+}
+
+fn response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin::{route, Admin};
+    use hyper::{Body, Method, Request, StatusCode};
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    pub fn test_route() {
+        let admin = Admin::default();
+        let unauthorized = Request::get("/status").body(Body::empty()).unwrap();
+        assert_eq!(
+            route(&admin, "secret", unauthorized).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            route(&admin, "other", request(Method::GET, "/status")).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            route(&admin, "secret", request(Method::GET, "/status")).status(),
+            StatusCode::OK
+        );
+
+        route(&admin, "secret", request(Method::POST, "/pause"));
+        assert_eq!(admin.filter("shop", "BackOff"), Some("paused"));
+        route(&admin, "secret", request(Method::POST, "/resume"));
+        assert_eq!(admin.filter("shop", "BackOff"), None);
+
+        let response = route(
+            &admin,
+            "secret",
+            request(Method::POST, "/mute?reason=BackOff"),
+        );
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = route(
+            &admin,
+            "secret",
+            request(Method::POST, "/mute?reason=BackOff&minutes=30"),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(admin.filter("shop", "BackOff"), Some("muted"));
+        assert_eq!(admin.filter("shop", "Unhealthy"), None);
+
+        let response = route(
+            &admin,
+            "secret",
+            request(Method::DELETE, "/mute?reason=BackOff"),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(admin.filter("shop", "BackOff"), None);
+    }
+}
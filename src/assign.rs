@@ -0,0 +1,85 @@
+use crate::cache::TtlCache;
+use crate::sentry_api::SentryApi;
+use crate::sentry_event::SentryEvent;
+use log::{debug, warn};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// The annotation of the involved object, of its controllers or of its namespace naming the owner
+/// of the issues of its events: a username, an email or "team:<team id>".
+pub const OWNER_ANNOTATION: &str = "sentry-kubernetes.io/owner";
+/// Number of issues remembered as already assigned, not to look them up for every event.
+const ASSIGNED_CACHE_SIZE: usize = 10_000;
+const ASSIGNED_TTL: Duration = Duration::from_secs(24 * 3600);
+/// The event is looked up this number of times, waiting for its ingestion by sentry.
+const LOOKUP_ATTEMPTS: u32 = 5;
+const LOOKUP_DELAY: Duration = Duration::from_secs(10);
+
+/// Assigns the new issues to the owner of the involved object (the `owner` tag of the event, read
+/// from the annotations by the processor) through the sentry API.
+/// The issues already assigned are left untouched.
+pub struct IssueAssigner {
+    api: Arc<SentryApi>,
+    /// The fingerprints of the issues already handled.
+    assigned: Mutex<TtlCache<Vec<String>, ()>>,
+}
+
+impl IssueAssigner {
+    pub fn new(api: Arc<SentryApi>) -> Self {
+        Self {
+            api,
+            assigned: Mutex::new(TtlCache::new(ASSIGNED_CACHE_SIZE, ASSIGNED_TTL)),
+        }
+    }
+
+    /// Assigns the issue of the reported event in the background, if it has an owner.
+    pub fn assign(&self, event: &SentryEvent) {
+        let Some(owner) = event.tags.get("owner").cloned() else {
+            return;
+        };
+        {
+            let fingerprint = event.fingerprint();
+            let mut assigned = self.assigned.lock().unwrap();
+            if assigned.get(&fingerprint).is_some() {
+                return;
+            }
+            assigned.insert(fingerprint, ());
+        }
+
+        let (api, event_id) = (self.api.clone(), event.uid);
+        tokio::spawn(async move {
+            let mut attempts = 0;
+            let issue = loop {
+                // The event is not found until it is ingested.
+                sleep(LOOKUP_DELAY).await;
+                attempts += 1;
+                match api.issue_id(event_id).await {
+                    Ok(issue) => break issue,
+                    Err(e) if attempts >= LOOKUP_ATTEMPTS => {
+                        warn!("Cannot find the issue of event {}: {}", event_id, e);
+                        return;
+                    }
+                    Err(_) => {}
+                }
+            };
+
+            let result = match api.issue(&issue).await {
+                Ok(details) if !details["assignedTo"].is_null() => return,
+                Ok(_) => {
+                    api.update_issue(&issue, &json!({ "assignedTo": owner }))
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => debug!(
+                    target: "sentry_kubernetes::assign",
+                    "Assigned issue {} to {}", issue, owner
+                ),
+                Err(e) => warn!("Cannot assign the issue {} to {}: {}", issue, owner, e),
+            }
+        });
+    }
+}
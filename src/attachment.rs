@@ -0,0 +1,151 @@
+use log::warn;
+use sentry::protocol::{Attachment, EnvelopeItem};
+use sentry::Envelope;
+
+/// Space reserved to the truncation marker.
+const MARKER_SIZE: usize = 64;
+
+/// Size limits of the attachments sent to sentry.
+/// Larger attachments are truncated, keeping their head and tail.
+#[derive(Clone, Copy, Debug)]
+pub struct AttachmentLimits {
+    /// Maximum size of a single attachment, in bytes.
+    pub max_size: usize,
+    /// Maximum size of all the attachments of an event, in bytes.
+    pub max_total_size: usize,
+}
+
+impl AttachmentLimits {
+    /// Truncates the attachments of the envelope exceeding the limits.
+    /// Attachments are processed in order: once the per-event limit is reached the others are dropped.
+    pub fn apply(&self, envelope: Envelope) -> Envelope {
+        let sizes = envelope
+            .items()
+            .filter_map(|item| match item {
+                EnvelopeItem::Attachment(a) => Some(a.buffer.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if sizes.iter().all(|s| *s <= self.max_size)
+            && sizes.iter().sum::<usize>() <= self.max_total_size
+        {
+            return envelope;
+        }
+
+        let mut limited = Envelope::new();
+        let mut remaining = self.max_total_size;
+        for item in envelope.items() {
+            let EnvelopeItem::Attachment(attachment) = item else {
+                limited.add_item(item.clone());
+                continue;
+            };
+
+            let max = self.max_size.min(remaining);
+            if max == 0 {
+                warn!(
+                    "Attachment \"{}\" exceeds the size limit of the event, dropping it",
+                    attachment.filename
+                );
+                continue;
+            }
+
+            let buffer = truncate(&attachment.buffer, max);
+            remaining -= buffer.len();
+            limited.add_item(Attachment {
+                buffer,
+                ..attachment.clone()
+            });
+        }
+
+        limited
+    }
+}
+
+/// Truncates the buffer to the given size, keeping the head and the tail separated by a marker.
+fn truncate(buffer: &[u8], max: usize) -> Vec<u8> {
+    if buffer.len() <= max {
+        return buffer.to_vec();
+    }
+    if max <= MARKER_SIZE {
+        return buffer[..max].to_vec();
+    }
+
+    let keep = max - MARKER_SIZE;
+    let (head, tail) = (keep - keep / 2, keep / 2);
+    let marker = format!("\n[... {} bytes truncated ...]\n", buffer.len() - keep);
+
+    let mut truncated = Vec::with_capacity(keep + marker.len());
+    truncated.extend_from_slice(&buffer[..head]);
+    truncated.extend_from_slice(marker.as_bytes());
+    truncated.extend_from_slice(&buffer[buffer.len() - tail..]);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attachment::{truncate, AttachmentLimits};
+    use sentry::protocol::{Attachment, EnvelopeItem, Event};
+    use sentry::Envelope;
+
+    #[test]
+    pub fn test_truncate() {
+        assert_eq!(truncate(b"short", 10), b"short");
+        assert_eq!(truncate(b"0123456789", 4), b"0123");
+
+        let buffer = (0..1000).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+        let truncated = truncate(&buffer, 200);
+        assert!(truncated.len() <= 200);
+        assert!(truncated.starts_with(&buffer[..68]));
+        assert!(truncated.ends_with(&buffer[1000 - 68..]));
+        let text = String::from_utf8(truncated).unwrap();
+        assert!(text.contains("[... 864 bytes truncated ...]"));
+    }
+
+    #[test]
+    pub fn test_limits() {
+        let limits = AttachmentLimits {
+            max_size: 100,
+            max_total_size: 150,
+        };
+        let attachment = |name: &str, size| Attachment {
+            buffer: vec![b'x'; size],
+            filename: name.to_string(),
+            ..Default::default()
+        };
+
+        let mut envelope = Envelope::new();
+        envelope.add_item(Event::default());
+        envelope.add_item(attachment("small.log", 10));
+        let envelope = limits.apply(envelope);
+        assert_eq!(envelope.items().count(), 2);
+
+        let mut envelope = Envelope::new();
+        envelope.add_item(Event::default());
+        envelope.add_item(attachment("first.log", 100));
+        envelope.add_item(attachment("second.log", 100));
+        envelope.add_item(attachment("third.log", 100));
+        let sizes = limits
+            .apply(envelope)
+            .items()
+            .filter_map(|item| match item {
+                EnvelopeItem::Attachment(a) => Some((a.filename.clone(), a.buffer.len())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [
+                ("first.log".to_string(), 100),
+                ("second.log".to_string(), 50)
+            ]
+        );
+
+        let mut envelope = Envelope::new();
+        envelope.add_item(attachment("large.log", 1000));
+        let envelope = limits.apply(envelope);
+        let Some(EnvelopeItem::Attachment(large)) = envelope.items().next() else {
+            panic!("expected an attachment");
+        };
+        assert!(large.buffer.len() <= 100);
+    }
+}
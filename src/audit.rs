@@ -0,0 +1,462 @@
+use crate::cache::TtlCache;
+use crate::sentry_event::SentryEvent;
+use crate::server::authorized;
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta, Time};
+use log::{debug, error, info, warn};
+use sentry::types::Uuid;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Number of audit ids remembered: an audit entry is sent once per stage of the request.
+const SEEN_CACHE_SIZE: usize = 10_000;
+const SEEN_TTL: Duration = Duration::from_secs(600);
+
+/// The audit entries converted to events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditKind {
+    /// Requests denied by the authorization (403).
+    Forbidden,
+    /// Denied accesses to the secrets.
+    SecretDenied,
+    /// Exec and attach into the pods.
+    Exec,
+}
+
+impl FromStr for AuditKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "forbidden" => Ok(Self::Forbidden),
+            "secret-denied" => Ok(Self::SecretDenied),
+            "exec" => Ok(Self::Exec),
+            _ => Err(format!("unknown audit event \"{}\"", s)),
+        }
+    }
+}
+
+/// An entry of an audit.k8s.io/v1 EventList (only the fields used to build the events).
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    #[serde(rename = "auditID")]
+    audit_id: Option<String>,
+    verb: Option<String>,
+    #[serde(default)]
+    user: UserInfo,
+    object_ref: Option<ObjectRef>,
+    response_status: Option<ResponseStatus>,
+    request_received_timestamp: Option<MicroTime>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UserInfo {
+    username: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectRef {
+    resource: Option<String>,
+    namespace: Option<String>,
+    name: Option<String>,
+    subresource: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponseStatus {
+    code: Option<u16>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuditEventList {
+    #[serde(default)]
+    items: Vec<Value>,
+}
+
+/// Receives the batches of the kubernetes audit webhook backend and converts the selected entries to
+/// events: forbidden requests, denied accesses to the secrets and exec into the pods.
+pub struct AuditReceiver {
+    kinds: Vec<AuditKind>,
+    /// The audit ids already converted.
+    seen: Mutex<TtlCache<String, ()>>,
+}
+
+impl AuditReceiver {
+    pub fn new(kinds: Vec<AuditKind>) -> Self {
+        Self {
+            kinds,
+            seen: Mutex::new(TtlCache::new(SEEN_CACHE_SIZE, SEEN_TTL)),
+        }
+    }
+
+    /// The events of the selected entries of the batch.
+    pub fn events(&self, body: &[u8]) -> serde_json::Result<Vec<SentryEvent>> {
+        let list: AuditEventList = serde_json::from_slice(body)?;
+        let mut events = vec![];
+        for item in list.items {
+            let entry: AuditEntry = match serde_json::from_value(item.clone()) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    debug!("Invalid audit entry: {}", e);
+                    continue;
+                }
+            };
+            let Some(kind) = classify(&entry).filter(|k| self.kinds.contains(k)) else {
+                continue;
+            };
+            if let Some(id) = entry.audit_id.clone() {
+                let mut seen = self.seen.lock().unwrap();
+                if seen.get(&id).is_some() {
+                    continue;
+                }
+
+                seen.insert(id, ());
+            }
+
+            events.push(audit_event(&entry, kind, item));
+        }
+
+        Ok(events)
+    }
+}
+
+/// The kind of the audit entry, if it is converted to an event.
+fn classify(entry: &AuditEntry) -> Option<AuditKind> {
+    let code = entry.response_status.as_ref().and_then(|s| s.code);
+    let object_ref = entry.object_ref.as_ref();
+    let resource = object_ref.and_then(|o| o.resource.as_deref());
+    let subresource = object_ref.and_then(|o| o.subresource.as_deref());
+
+    match (code, resource, subresource) {
+        (Some(403), Some("secrets"), _) => Some(AuditKind::SecretDenied),
+        (Some(403), _, _) => Some(AuditKind::Forbidden),
+        (_, Some("pods"), Some("exec" | "attach")) => Some(AuditKind::Exec),
+        _ => None,
+    }
+}
+
+/// A warning event of the audited request, with the audit entry in the "audit" context.
+fn audit_event(entry: &AuditEntry, kind: AuditKind, raw: Value) -> SentryEvent {
+    let user = entry.user.username.as_deref().unwrap_or("unknown user");
+    let verb = entry.verb.as_deref().unwrap_or_default();
+    let object_ref = entry.object_ref.as_ref();
+    let resource = object_ref
+        .and_then(|o| o.resource.clone())
+        .unwrap_or_default();
+    let name = object_ref.and_then(|o| o.name.clone());
+    let namespace = object_ref.and_then(|o| o.namespace.clone());
+    let target = match (&namespace, &name) {
+        (Some(namespace), Some(name)) => format!("{} {}/{}", resource, namespace, name),
+        (None, Some(name)) => format!("{} {}", resource, name),
+        _ => resource.clone(),
+    };
+
+    let (reason, message) = match kind {
+        AuditKind::Exec => (
+            "PodExec",
+            format!(
+                "{} executed {} into {}",
+                user,
+                object_ref
+                    .and_then(|o| o.subresource.as_deref())
+                    .unwrap_or("exec"),
+                target
+            ),
+        ),
+        AuditKind::Forbidden | AuditKind::SecretDenied => {
+            let reason = if kind == AuditKind::SecretDenied {
+                "SecretAccessDenied"
+            } else {
+                "Forbidden"
+            };
+            let message = entry
+                .response_status
+                .as_ref()
+                .and_then(|s| s.message.clone())
+                .unwrap_or_else(|| format!("{} cannot {} {}", user, verb, target));
+            (reason, message)
+        }
+    };
+
+    let time = entry
+        .request_received_timestamp
+        .as_ref()
+        .map(|t| Time(t.0))
+        .unwrap_or_else(|| Time(k8s_openapi::chrono::Utc::now()));
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(time.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            name,
+            namespace,
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        source: Some(EventSource {
+            component: Some("kube-apiserver".to_string()),
+            ..Default::default()
+        }),
+        last_timestamp: Some(time),
+        ..Default::default()
+    };
+
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event
+        .tags
+        .insert("user".to_string(), user.to_string());
+    if !verb.is_empty() {
+        sentry_event
+            .tags
+            .insert("verb".to_string(), verb.to_string());
+    }
+    if !resource.is_empty() {
+        sentry_event.tags.insert("resource".to_string(), resource);
+    }
+    if let Value::Object(raw) = raw {
+        sentry_event.contexts.insert(
+            "audit".to_string(),
+            raw.into_iter().collect::<BTreeMap<_, _>>(),
+        );
+    }
+
+    sentry_event
+}
+
+/// Authentication and size limit of the requests of the audit webhook.
+pub struct WebhookLimits {
+    /// Bearer token of the requests (the `token` of the user of the webhook kubeconfig).
+    pub token: String,
+    /// Maximum size of the request bodies, in bytes.
+    pub max_body_size: usize,
+}
+
+/// Serves the audit webhook: the converted events are sent to the channel.
+/// The requests without the bearer token are rejected (401), like the bodies exceeding the
+/// maximum size (413).
+pub async fn serve(
+    addr: SocketAddr,
+    limits: WebhookLimits,
+    receiver: Arc<AuditReceiver>,
+    events: mpsc::Sender<SentryEvent>,
+) {
+    let limits = Arc::new(limits);
+    let service = make_service_fn(move |_| {
+        let limits = limits.clone();
+        let receiver = receiver.clone();
+        let events = events.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                receive(request, limits.clone(), receiver.clone(), events.clone())
+            }))
+        }
+    });
+
+    info!("Receiving the audit events on {}", addr);
+    match Server::try_bind(&addr) {
+        Ok(server) => {
+            if let Err(e) = server.serve(service).await {
+                error!("Audit webhook server error: {}", e);
+            }
+        }
+        Err(e) => error!("Cannot listen on {}: {}", addr, e),
+    }
+}
+
+async fn receive(
+    request: Request<Body>,
+    limits: Arc<WebhookLimits>,
+    receiver: Arc<AuditReceiver>,
+    events: mpsc::Sender<SentryEvent>,
+) -> Result<Response<Body>, Infallible> {
+    if !authorized(&request, &limits.token) {
+        warn!("Unauthorized audit webhook request");
+        return Ok(status(StatusCode::UNAUTHORIZED));
+    }
+    if request.method() != Method::POST {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let body = match read_body(request.into_body(), limits.max_body_size).await {
+        Ok(body) => body,
+        Err(code) => return Ok(status(code)),
+    };
+    let converted = match receiver.events(&body) {
+        Ok(converted) => converted,
+        Err(e) => {
+            warn!("Invalid audit event list: {}", e);
+            return Ok(status(StatusCode::BAD_REQUEST));
+        }
+    };
+
+    for event in converted {
+        if events.send(event).await.is_err() {
+            return Ok(status(StatusCode::SERVICE_UNAVAILABLE));
+        }
+    }
+
+    Ok(status(StatusCode::OK))
+}
+
+/// Reads the body, up to the maximum size: the larger bodies are not buffered.
+async fn read_body(mut body: Body, max_size: usize) -> Result<Vec<u8>, StatusCode> {
+    let too_large = || {
+        warn!(
+            "Audit webhook request larger than {} bytes, rejecting it",
+            max_size
+        );
+        StatusCode::PAYLOAD_TOO_LARGE
+    };
+    if body.size_hint().lower() > max_size as u64 {
+        return Err(too_large());
+    }
+
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            warn!("Cannot read the audit webhook request: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::{receive, AuditKind, AuditReceiver, WebhookLimits};
+    use hyper::{Body, Request, StatusCode};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    const BATCH: &str = r#"{
+  "kind": "EventList",
+  "apiVersion": "audit.k8s.io/v1",
+  "items": [
+    {
+      "auditID": "1", "stage": "ResponseComplete", "verb": "get",
+      "user": { "username": "system:serviceaccount:shop:web" },
+      "objectRef": { "resource": "secrets", "namespace": "billing", "name": "stripe" },
+      "responseStatus": { "code": 403, "message": "secrets \"stripe\" is forbidden" }
+    },
+    {
+      "auditID": "2", "stage": "ResponseComplete", "verb": "list",
+      "user": { "username": "alice" },
+      "objectRef": { "resource": "nodes" },
+      "responseStatus": { "code": 403 }
+    },
+    {
+      "auditID": "3", "stage": "ResponseStarted", "verb": "create",
+      "user": { "username": "bob" },
+      "objectRef": { "resource": "pods", "namespace": "shop", "name": "web-0", "subresource": "exec" },
+      "responseStatus": { "code": 101 }
+    },
+    {
+      "auditID": "3", "stage": "ResponseComplete", "verb": "create",
+      "user": { "username": "bob" },
+      "objectRef": { "resource": "pods", "namespace": "shop", "name": "web-0", "subresource": "exec" },
+      "responseStatus": { "code": 101 }
+    },
+    {
+      "auditID": "4", "stage": "ResponseComplete", "verb": "get",
+      "user": { "username": "alice" },
+      "objectRef": { "resource": "pods", "namespace": "shop" },
+      "responseStatus": { "code": 200 }
+    }
+  ]
+}"#;
+
+    #[test]
+    pub fn test_events() {
+        let receiver = AuditReceiver::new(vec![
+            AuditKind::Forbidden,
+            AuditKind::SecretDenied,
+            AuditKind::Exec,
+        ]);
+        let events = receiver.events(BATCH.as_bytes()).unwrap();
+        let reasons = events.iter().map(|e| e.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons, ["SecretAccessDenied", "Forbidden", "PodExec"]);
+
+        assert_eq!(events[0].namespace, "billing");
+        assert_eq!(events[0].name, "stripe");
+        assert_eq!(
+            events[0].message.as_deref(),
+            Some("secrets \"stripe\" is forbidden")
+        );
+        assert_eq!(events[0].tags["user"], "system:serviceaccount:shop:web");
+        assert_eq!(events[0].contexts["audit"]["auditID"], "1");
+        assert_eq!(
+            events[1].message.as_deref(),
+            Some("alice cannot list nodes")
+        );
+        assert_eq!(
+            events[2].message.as_deref(),
+            Some("bob executed exec into pods shop/web-0")
+        );
+
+        // The entries already received are not converted again.
+        assert!(receiver.events(BATCH.as_bytes()).unwrap().is_empty());
+
+        let receiver = AuditReceiver::new(vec![AuditKind::Exec]);
+        assert_eq!(receiver.events(BATCH.as_bytes()).unwrap().len(), 1);
+        assert!(receiver.events(b"not json").is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_receive() {
+        let limits = Arc::new(WebhookLimits {
+            token: "secret".to_string(),
+            max_body_size: BATCH.len(),
+        });
+        let receiver = Arc::new(AuditReceiver::new(vec![AuditKind::Exec]));
+        let (sender, mut events) = mpsc::channel(10);
+        let request = |token: &str, body: String| {
+            Request::post("/")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let receive = |request| receive(request, limits.clone(), receiver.clone(), sender.clone());
+
+        let response = receive(request("other", BATCH.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = receive(request("secret", format!("{} ", BATCH)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(events.try_recv().is_err());
+
+        let response = receive(request("secret", BATCH.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(events.try_recv().unwrap().reason, "PodExec");
+    }
+}
@@ -0,0 +1,211 @@
+use crate::sentry_event::SentryEvent;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a pod is listed among the pending pods of its group after its last event.
+const PENDING_POD_TTL: Duration = Duration::from_secs(900);
+/// Maximum number of pending pods listed in the context of an event.
+const MAX_PENDING_PODS: usize = 50;
+
+/// Groups the events of the cluster-autoscaler per node group (or per cause, when the node group is
+/// not known) instead of per pod, with the parsed node groups, reasons and the pods recently pending
+/// on the same group in the "autoscaler" context: a failing scale-up emits an event per pending pod.
+#[derive(Default)]
+pub struct AutoscalerGroups {
+    /// The pods recently reported pending, by group.
+    pending: Mutex<HashMap<String, BTreeMap<String, Instant>>>,
+}
+
+impl AutoscalerGroups {
+    /// Sets the grouping and the context of the cluster-autoscaler events. Other events are left as is.
+    pub fn group(&self, sentry_event: &mut SentryEvent) {
+        if sentry_event.component != "cluster-autoscaler" {
+            return;
+        }
+
+        let message = sentry_event.message.clone().unwrap_or_default();
+        let (node_groups, reasons) = match sentry_event.reason.as_str() {
+            "FailedToScaleUpGroup" => match message
+                .strip_prefix("Scale-up failed for group ")
+                .and_then(|m| m.split_once(':'))
+            {
+                Some((group, error)) => (
+                    vec![group.trim().to_string()],
+                    vec![error.trim().to_string()],
+                ),
+                None => (vec![], vec![message.clone()]),
+            },
+            "TriggeredScaleUp" => (scale_up_groups(&message), vec![]),
+            "NotTriggerScaleUp" => (vec![], scale_up_failures(&message)),
+            "ScaleDown" | "ScaleDownFailed" => (vec![], vec![]),
+            _ => return,
+        };
+
+        // Without node group, the events are grouped by their cause (ex: the reasons a pod did not
+        // trigger a scale-up), or by the kind of the scaled down object.
+        let group = if !node_groups.is_empty() {
+            node_groups.join(",")
+        } else if !reasons.is_empty() {
+            reasons.join("; ")
+        } else {
+            sentry_event.kind.clone().unwrap_or_default()
+        };
+        sentry_event.grouping = Some(vec![
+            "cluster-autoscaler".to_string(),
+            sentry_event.reason.clone(),
+            group.clone(),
+        ]);
+        if let [node_group] = node_groups.as_slice() {
+            sentry_event
+                .tags
+                .insert("node_group".to_string(), node_group.clone());
+        }
+
+        let mut context = BTreeMap::from([
+            ("nodeGroups".to_string(), json!(node_groups)),
+            ("reasons".to_string(), json!(reasons)),
+        ]);
+        if sentry_event.kind.as_deref() == Some("Pod") && sentry_event.reason.contains("ScaleUp") {
+            let pods = self.pending_pods(&group, sentry_event.obj_name());
+            context.insert("pendingPodCount".to_string(), json!(pods.len()));
+            context.insert(
+                "pendingPods".to_string(),
+                json!(pods.into_iter().take(MAX_PENDING_PODS).collect::<Vec<_>>()),
+            );
+        }
+        sentry_event
+            .contexts
+            .insert("autoscaler".to_string(), context);
+    }
+
+    /// Records the pending pod, returning the pods recently pending on the group.
+    fn pending_pods(&self, group: &str, pod: String) -> Vec<String> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, pods| {
+            pods.retain(|_, seen| seen.elapsed() < PENDING_POD_TTL);
+            !pods.is_empty()
+        });
+
+        let pods = pending.entry(group.to_string()).or_default();
+        pods.insert(pod, Instant::now());
+        pods.keys().cloned().collect()
+    }
+}
+
+/// The node groups of a TriggeredScaleUp message: "pod triggered scale-up: [{group 1->2 (max: 10)}]".
+fn scale_up_groups(message: &str) -> Vec<String> {
+    message
+        .split('{')
+        .skip(1)
+        .filter_map(|group| group.split_whitespace().next())
+        .map(|group| group.trim_end_matches('}').to_string())
+        .collect()
+}
+
+/// The reasons of a NotTriggerScaleUp message, without the number of node groups:
+/// "pod didn't trigger scale-up: 2 node(s) didn't match Pod's node affinity/selector, 1 max node group size reached".
+fn scale_up_failures(message: &str) -> Vec<String> {
+    let reasons = message.split_once(':').map_or(message, |(_, r)| r);
+    let mut reasons = reasons
+        .split(", ")
+        .map(|reason| {
+            let reason = reason.trim();
+            match reason.split_once(' ') {
+                Some((count, rest)) if count.parse::<u32>().is_ok() => rest.to_string(),
+                _ => reason.to_string(),
+            }
+        })
+        .filter(|reason| !reason.is_empty())
+        .collect::<Vec<_>>();
+    reasons.sort();
+    reasons.dedup();
+
+    reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::autoscaler::AutoscalerGroups;
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+
+    fn event(kind: &str, name: &str, reason: &str, message: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some(kind.to_string()),
+                name: Some(name.to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some(reason.to_string()),
+            message: Some(message.to_string()),
+            type_: Some("Warning".to_string()),
+            source: Some(EventSource {
+                component: Some("cluster-autoscaler".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_not_triggered_scale_up() {
+        let groups = AutoscalerGroups::default();
+        let message = "pod didn't trigger scale-up: 2 node(s) didn't match Pod's node affinity/selector, 1 max node group size reached";
+        let mut first = event("Pod", "web-0", "NotTriggerScaleUp", message);
+        groups.group(&mut first);
+        let mut second = event(
+            "Pod",
+            "web-1",
+            "NotTriggerScaleUp",
+            "pod didn't trigger scale-up: 1 max node group size reached, 3 node(s) didn't match Pod's node affinity/selector",
+        );
+        groups.group(&mut second);
+
+        assert_eq!(first.fingerprint(), second.fingerprint());
+        assert_eq!(
+            second.fingerprint()[2],
+            "max node group size reached; node(s) didn't match Pod's node affinity/selector"
+        );
+        let context = &second.contexts["autoscaler"];
+        assert_eq!(context["pendingPodCount"], 2);
+        assert_eq!(context["pendingPods"][0], "shop/web-0");
+        assert_eq!(context["reasons"][0], "max node group size reached");
+    }
+
+    #[test]
+    pub fn test_failed_scale_up_group() {
+        let groups = AutoscalerGroups::default();
+        let mut failed = event(
+            "ConfigMap",
+            "cluster-autoscaler-status",
+            "FailedToScaleUpGroup",
+            "Scale-up failed for group eks-spot-ng: InsufficientInstanceCapacity",
+        );
+        groups.group(&mut failed);
+        assert_eq!(
+            failed.fingerprint(),
+            ["cluster-autoscaler", "FailedToScaleUpGroup", "eks-spot-ng"]
+        );
+        assert_eq!(failed.tags["node_group"], "eks-spot-ng");
+        assert_eq!(
+            failed.contexts["autoscaler"]["reasons"][0],
+            "InsufficientInstanceCapacity"
+        );
+
+        let mut triggered = event(
+            "Pod",
+            "web-0",
+            "TriggeredScaleUp",
+            "pod triggered scale-up: [{eks-spot-ng 1->2 (max: 10)}]",
+        );
+        groups.group(&mut triggered);
+        assert_eq!(triggered.tags["node_group"], "eks-spot-ng");
+
+        let mut other = event("Pod", "web-0", "BackOff", "Back-off restarting");
+        groups.group(&mut other);
+        assert!(other.grouping.is_none());
+    }
+}
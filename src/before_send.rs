@@ -0,0 +1,198 @@
+use crate::config::{BeforeSendRule, EventMatch};
+use crate::routing::glob_match;
+use sentry::protocol::Event;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+type BeforeSend = Arc<dyn Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync>;
+
+/// The before_send rules of the configuration file, applied in order.
+#[derive(Clone, Debug, Default)]
+pub struct BeforeSendRules {
+    rules: Vec<BeforeSendRule>,
+}
+
+impl BeforeSendRules {
+    pub fn new(rules: Vec<BeforeSendRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Applies the matching rules to the event. Returns None if the event has been dropped.
+    pub fn apply(&self, mut event: Event<'static>) -> Option<Event<'static>> {
+        for rule in &self.rules {
+            if !matches(&rule.matches, &event) {
+                continue;
+            }
+            if rule.drop {
+                return None;
+            }
+
+            for tag in &rule.remove_tags {
+                event.tags.remove(tag);
+            }
+            let tags = rule
+                .tags
+                .iter()
+                .map(|(name, value)| (name.clone(), interpolate(value, &event)))
+                .collect::<Vec<_>>();
+            event.tags.extend(tags);
+
+            if let Some(fingerprint) = rule.fingerprint.as_ref() {
+                event.fingerprint = fingerprint
+                    .iter()
+                    .map(|part| Cow::Owned(interpolate(part, &event)))
+                    .collect::<Vec<_>>()
+                    .into();
+            }
+            if let Some(level) = rule.level {
+                event.level = level;
+            }
+            if let Some(plugin) = rule.wasm.as_ref() {
+                event = plugin.apply(event)?;
+            }
+            if let Some(script) = rule.lua.as_ref() {
+                event = script.apply(event)?;
+            }
+            if let Some(filter) = rule.jq.as_ref() {
+                event = filter.apply(event)?;
+            }
+        }
+
+        Some(event)
+    }
+
+    /// Chains the rules with the given before_send callback (ex: the sampler).
+    /// Returns None if there are no rules and no callback.
+    pub fn before_send(self, next: Option<BeforeSend>) -> Option<BeforeSend> {
+        if self.rules.is_empty() {
+            return next;
+        }
+
+        Some(Arc::new(move |event: Event<'static>| {
+            let event = self.apply(event)?;
+            match next.as_ref() {
+                Some(next) => next(event),
+                None => Some(event),
+            }
+        }))
+    }
+}
+
+fn matches(conditions: &EventMatch, event: &Event) -> bool {
+    if !conditions.levels.is_empty() && !conditions.levels.contains(&event.level) {
+        return false;
+    }
+    if let Some(pattern) = conditions.message.as_deref() {
+        if !glob_match(pattern, event.message.as_deref().unwrap_or_default()) {
+            return false;
+        }
+    }
+
+    conditions.tags.iter().all(|(name, pattern)| {
+        event
+            .tags
+            .get(name)
+            .is_some_and(|value| glob_match(pattern, value))
+    })
+}
+
+/// Replaces the `{{tags.<name>}}` placeholders with the values of the event tags.
+/// Missing tags are replaced with an empty string.
+fn interpolate(template: &str, event: &Event) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{tags.") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+
+        result.push_str(&rest[..start]);
+        let name = &rest[start + "{{tags.".len()..start + end];
+        result.push_str(event.tags.get(name).map_or("", |v| v.as_str()));
+        rest = &rest[start + end + 2..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::before_send::{interpolate, BeforeSendRules};
+    use crate::config::BeforeSendRule;
+    use crate::sampling::SampleRates;
+    use sentry::protocol::Event;
+    use sentry::Level;
+
+    fn event() -> Event<'static> {
+        Event {
+            level: Level::Warning,
+            message: Some("Back-off restarting failed container".to_string()),
+            tags: [
+                ("namespace".to_string(), "shop".to_string()),
+                ("reason".to_string(), "BackOff".to_string()),
+                ("name".to_string(), "web-0".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_interpolate() {
+        let event = event();
+        assert_eq!(
+            interpolate("{{tags.namespace}}/{{tags.reason}}", &event),
+            "shop/BackOff"
+        );
+        assert_eq!(interpolate("{{tags.missing}}-x", &event), "-x");
+        assert_eq!(interpolate("{{tags.name", &event), "{{tags.name");
+    }
+
+    #[test]
+    pub fn test_apply_rules() {
+        let rules: Vec<BeforeSendRule> = serde_yaml::from_str(
+            r#"
+- match: { tags: { namespace: "kube-*" } }
+  drop: true
+- match: { levels: [warning], message: "Back-off*" }
+  tags: { team: "{{tags.namespace}}-team" }
+  removeTags: [name]
+  fingerprint: ["{{tags.namespace}}", "{{tags.reason}}"]
+  level: error
+"#,
+        )
+        .unwrap();
+        let rules = BeforeSendRules::new(rules);
+
+        let mut dropped = event();
+        dropped
+            .tags
+            .insert("namespace".to_string(), "kube-system".to_string());
+        assert!(rules.apply(dropped).is_none());
+
+        let transformed = rules.apply(event()).unwrap();
+        assert_eq!(transformed.level, Level::Error);
+        assert_eq!(transformed.tags.get("team").unwrap(), "shop-team");
+        assert!(!transformed.tags.contains_key("name"));
+        assert_eq!(transformed.fingerprint.as_ref(), ["shop", "BackOff"]);
+
+        let mut unmatched = event();
+        unmatched.message = Some("Readiness probe failed".to_string());
+        let unmatched = rules.apply(unmatched).unwrap();
+        assert_eq!(unmatched.level, Level::Warning);
+        assert!(unmatched.tags.contains_key("name"));
+    }
+
+    #[test]
+    pub fn test_before_send_chain() {
+        assert!(BeforeSendRules::default().before_send(None).is_none());
+
+        let rules: Vec<BeforeSendRule> = serde_yaml::from_str("[{ level: info }]").unwrap();
+        let sampler =
+            SampleRates::new(1.0, [("info".to_string(), 0.0)].into_iter().collect()).before_send();
+        let before_send = BeforeSendRules::new(rules).before_send(sampler).unwrap();
+        assert!(before_send(event()).is_none());
+    }
+}
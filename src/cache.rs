@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// A least recently used cache whose entries expire after a fixed time.
+pub struct TtlCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys ordered by last use.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    tick: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Default::default(),
+            order: Default::default(),
+            tick: 0,
+        }
+    }
+
+    /// Returns the cached value, if not expired.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.tick);
+        if entry.expires_at <= Instant::now() {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.tick += 1;
+        entry.tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+
+        Some(entry.value.clone())
+    }
+
+    /// Removes the value, returning it if not expired.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+
+        (entry.expires_at > Instant::now()).then_some(entry.value)
+    }
+
+    /// Caches the value, evicting the least recently used entry if the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(previous) = self.entries.remove(&key) {
+            self.order.remove(&previous.tick);
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                expires_at: Instant::now() + self.ttl,
+                tick: self.tick,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::TtlCache;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_lru_eviction() {
+        let mut cache = TtlCache::new(2, Duration::from_secs(60));
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        // "b" is the least recently used entry.
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        cache.insert("a", 4);
+        assert_eq!(cache.get(&"a"), Some(4));
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.order.len(), 2);
+    }
+
+    #[test]
+    pub fn test_expiration() {
+        let mut cache = TtlCache::new(10, Duration::ZERO);
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.entries.is_empty());
+        assert!(cache.order.is_empty());
+
+        let mut cache = TtlCache::new(0, Duration::from_secs(60));
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+    }
+}
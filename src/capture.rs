@@ -0,0 +1,175 @@
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use hyper::body::to_bytes;
+use hyper::header::CONTENT_ENCODING;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, warn};
+use std::convert::Infallible;
+use std::fs;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// A minimal sentry server receiving the envelopes on a local port instead of sending them to sentry.
+/// Received envelopes are kept in memory and, if an output directory is given, written to
+/// `<dir>/<n>.envelope` files (in the sentry envelope format, attachments included).
+pub struct CaptureServer {
+    addr: SocketAddr,
+    envelopes: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl CaptureServer {
+    /// Starts the server on a random local port.
+    pub fn start(output: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &output {
+            fs::create_dir_all(dir)?;
+        }
+
+        let envelopes: Arc<Mutex<Vec<Vec<u8>>>> = Default::default();
+        let store = envelopes.clone();
+        let output = Arc::new(output);
+        let service = make_service_fn(move |_| {
+            let store = store.clone();
+            let output = output.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    receive(request, store.clone(), output.clone())
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("Capture server error: {}", e);
+            }
+        });
+
+        Ok(Self { addr, envelopes })
+    }
+
+    /// The DSN pointing to this server.
+    pub fn dsn(&self) -> String {
+        format!("http://capture@{}/1", self.addr)
+    }
+
+    /// The raw envelopes received so far (used by the tests to assert what would be sent).
+    #[allow(dead_code)]
+    pub fn envelopes(&self) -> Vec<Vec<u8>> {
+        self.envelopes.lock().unwrap().clone()
+    }
+}
+
+async fn receive(
+    request: Request<Body>,
+    store: Arc<Mutex<Vec<Vec<u8>>>>,
+    output: Arc<Option<PathBuf>>,
+) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().to_string();
+    if request.method() != Method::POST
+        || !(path.ends_with("/envelope/") || path.ends_with("/store/"))
+    {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let gzipped = request
+        .headers()
+        .get(CONTENT_ENCODING)
+        .is_some_and(|v| v == "gzip");
+    let body = match to_bytes(request.into_body()).await {
+        Ok(body) if gzipped => {
+            let mut decoded = vec![];
+            match GzDecoder::new(&body[..]).read_to_end(&mut decoded) {
+                Ok(_) => decoded,
+                Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+            }
+        }
+        Ok(body) => body.to_vec(),
+        Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+
+    let index = {
+        let mut envelopes = store.lock().unwrap();
+        envelopes.push(body.clone());
+        envelopes.len()
+    };
+
+    debug!(target: "sentry_kubernetes::capture", "Captured envelope #{} ({} bytes)", index, body.len());
+    if let Some(dir) = output.as_ref() {
+        let file = dir.join(format!("{:06}.envelope", index));
+        if let Err(e) = fs::write(&file, &body) {
+            warn!(
+                "Cannot write captured envelope to {}: {}",
+                file.display(),
+                e
+            );
+        }
+    }
+
+    Ok(Response::new(Body::from("{}")))
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::CaptureServer;
+    use crate::transport::HttpTransportFactory;
+    use sentry::protocol::{Attachment, Envelope, EnvelopeItem, Event};
+    use sentry::{Client, ClientOptions, Hub};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_capture() {
+        let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+        let server = CaptureServer::start(Some(dir.clone())).unwrap();
+
+        let client = Arc::new(Client::from(ClientOptions {
+            dsn: Some(FromStr::from_str(&server.dsn()).unwrap()),
+            transport: Some(Arc::new(HttpTransportFactory::new(Default::default()))),
+            ..Default::default()
+        }));
+        let hub = Hub::new(Some(client.clone()), Default::default());
+        hub.configure_scope(|scope| {
+            scope.add_attachment(Attachment {
+                buffer: b"pod logs".to_vec(),
+                filename: "logs.txt".to_string(),
+                ..Default::default()
+            })
+        });
+        let uuid = hub.capture_event(Event {
+            message: Some("Back-off restarting failed container".to_string()),
+            ..Default::default()
+        });
+
+        tokio::task::spawn_blocking(move || client.flush(Some(Duration::from_secs(5))))
+            .await
+            .unwrap();
+
+        let envelopes = server.envelopes();
+        assert_eq!(envelopes.len(), 1);
+        assert_eq!(
+            std::fs::read(dir.join("000001.envelope")).unwrap(),
+            envelopes[0]
+        );
+
+        let envelope = Envelope::from_slice(&envelopes[0]).unwrap();
+        let event = envelope.event().unwrap();
+        assert_eq!(event.event_id, uuid);
+        assert!(envelope
+            .items()
+            .any(|i| matches!(i, EnvelopeItem::Attachment(a) if a.buffer == b"pod logs")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
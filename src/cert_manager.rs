@@ -0,0 +1,149 @@
+use crate::kube_api::KubeApi;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::DynamicObject;
+use sentry::Level;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// The events of the certificates expiring within this number of days are reported as errors.
+const EXPIRY_ERROR_DAYS: i64 = 7;
+/// Maximum number of owners walked up from an Order or a Challenge to its Certificate.
+const MAX_OWNER_DEPTH: usize = 3;
+
+/// Whether the event is about a cert-manager resource: Certificate, CertificateRequest, Order or Challenge.
+pub fn is_cert_manager(event: &SentryEvent) -> bool {
+    let group = event
+        .api_version
+        .as_deref()
+        .and_then(|v| v.split_once('/'))
+        .map(|(group, _)| group);
+    matches!(group, Some("cert-manager.io" | "acme.cert-manager.io"))
+        && matches!(
+            event.kind.as_deref(),
+            Some("Certificate" | "CertificateRequest" | "Order" | "Challenge")
+        )
+}
+
+/// The Certificate of the involved object, walking up its owners (Challenge → Order →
+/// CertificateRequest → Certificate).
+pub async fn certificate(objects: &dyn KubeApi, event: &SentryEvent) -> Option<DynamicObject> {
+    let (mut api_version, mut kind) = (event.api_version.clone()?, event.kind.clone()?);
+    let mut name = event.name.clone();
+    for _ in 0..=MAX_OWNER_DEPTH {
+        let object = objects
+            .object(&api_version, &kind, &event.namespace, &name)
+            .await?;
+        if kind == "Certificate" {
+            return Some(object);
+        }
+
+        let owner = object.metadata.owner_references?.into_iter().find(|o| {
+            o.api_version.starts_with("cert-manager.io/")
+                || o.api_version.starts_with("acme.cert-manager.io/")
+        })?;
+        (api_version, kind, name) = (owner.api_version, owner.kind, owner.name);
+    }
+
+    None
+}
+
+/// Adds the dnsNames and the renewal times of the certificate in the "certificate" context, and
+/// reports the events of the certificates about to expire as errors.
+pub fn enrich(event: &mut SentryEvent, certificate: &DynamicObject) {
+    let spec = &certificate.data["spec"];
+    let status = &certificate.data["status"];
+    let ready = status["conditions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["type"] == "Ready");
+    let context = BTreeMap::from([
+        ("name".to_string(), json!(certificate.metadata.name)),
+        ("dnsNames".to_string(), spec["dnsNames"].clone()),
+        ("commonName".to_string(), spec["commonName"].clone()),
+        ("secretName".to_string(), spec["secretName"].clone()),
+        ("issuerRef".to_string(), spec["issuerRef"].clone()),
+        ("notBefore".to_string(), status["notBefore"].clone()),
+        ("notAfter".to_string(), status["notAfter"].clone()),
+        ("renewalTime".to_string(), status["renewalTime"].clone()),
+        ("ready".to_string(), ready.cloned().unwrap_or(Value::Null)),
+    ]);
+
+    if let Some(name) = certificate.metadata.name.clone() {
+        event.tags.insert("certificate".to_string(), name);
+    }
+    let not_after = status["notAfter"]
+        .as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+    if let Some(not_after) = not_after {
+        let days = (not_after.with_timezone(&Utc) - Utc::now()).num_days();
+        event
+            .tags
+            .insert("certificate_expires_in_days".to_string(), days.to_string());
+        if days < EXPIRY_ERROR_DAYS && event.type_ != "normal" {
+            event.level = event.level.max(Level::Error);
+        }
+    }
+    event.contexts.insert("certificate".to_string(), context);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cert_manager::{enrich, is_cert_manager};
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use k8s_openapi::chrono::{Duration, SecondsFormat, Utc};
+    use kube::api::{ApiResource, DynamicObject};
+    use kube::core::GroupVersionKind;
+    use sentry::Level;
+    use serde_json::json;
+
+    fn event(api_version: &str, kind: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                api_version: Some(api_version.to_string()),
+                kind: Some(kind.to_string()),
+                name: Some("shop-tls".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some("Failed".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_enrich() {
+        assert!(is_cert_manager(&event("cert-manager.io/v1", "Certificate")));
+        assert!(is_cert_manager(&event(
+            "acme.cert-manager.io/v1",
+            "Challenge"
+        )));
+        assert!(!is_cert_manager(&event("v1", "Pod")));
+
+        let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(
+            "cert-manager.io",
+            "v1",
+            "Certificate",
+        ));
+        let not_after = (Utc::now() + Duration::days(3)).to_rfc3339_opts(SecondsFormat::Secs, true);
+        let certificate = DynamicObject::new("shop-tls", &resource).data(json!({
+            "spec": { "dnsNames": ["shop.example.com"], "secretName": "shop-tls" },
+            "status": {
+                "notAfter": not_after,
+                "conditions": [{ "type": "Ready", "status": "False", "reason": "Failed" }],
+            },
+        }));
+
+        let mut event = event("cert-manager.io/v1", "Certificate");
+        enrich(&mut event, &certificate);
+        let context = &event.contexts["certificate"];
+        assert_eq!(context["dnsNames"][0], "shop.example.com");
+        assert_eq!(context["ready"]["status"], "False");
+        assert_eq!(event.tags["certificate"], "shop-tls");
+        assert_eq!(event.tags["certificate_expires_in_days"], "2");
+        assert_eq!(event.level, Level::Error);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::sentry_event::event_time;
+use k8s_openapi::api::core::v1::{ConfigMap, Event};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client};
+use log::{debug, info, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Key of the ConfigMap holding the time of the last processed event.
+const LAST_EVENT_TIME: &str = "lastEventTime";
+
+/// The time of the last processed event, persisted in a ConfigMap of the namespace of the controller.
+/// After a restart, the events listed by the watcher which precede it are not reported again, while
+/// the ones occurred in the meantime are.
+pub struct Checkpoint {
+    api: Api<ConfigMap>,
+    name: String,
+    interval: Duration,
+    /// The checkpoint loaded at startup.
+    resume_from: Option<DateTime<Utc>>,
+    state: Mutex<State>,
+}
+
+struct State {
+    last: Option<DateTime<Utc>>,
+    stored: Option<DateTime<Utc>>,
+    stored_at: Instant,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint from the given ConfigMap. The checkpoint is saved at most once per interval.
+    pub async fn load(client: Client, name: &str, interval: Duration) -> Self {
+        let api: Api<ConfigMap> = Api::default_namespaced(client);
+        let resume_from = match api.get_opt(name).await {
+            Ok(config_map) => config_map
+                .and_then(|cm| cm.data?.get(LAST_EVENT_TIME).cloned())
+                .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                .map(|time| time.with_timezone(&Utc)),
+            Err(e) => {
+                warn!("Cannot read the checkpoint from ConfigMap {}: {}", name, e);
+                None
+            }
+        };
+        if let Some(time) = resume_from {
+            info!("Resuming from the events after {}", time.to_rfc3339());
+        }
+
+        Self {
+            api,
+            name: name.to_string(),
+            interval,
+            resume_from,
+            state: Mutex::new(State {
+                last: resume_from,
+                stored: resume_from,
+                stored_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns true if the event has been processed before the checkpoint was saved.
+    /// The event times have a precision of seconds: the events of the same second are processed again.
+    pub fn is_processed(&self, event: &Event) -> bool {
+        match (self.resume_from, event_time(event)) {
+            (Some(resume_from), Some(time)) => time < resume_from,
+            _ => false,
+        }
+    }
+
+    /// Records the time of a processed event, saving the checkpoint if the interval has elapsed.
+    pub async fn record(&self, time: DateTime<Utc>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if Some(time) > state.last {
+                state.last = Some(time);
+            }
+            if state.stored_at.elapsed() < self.interval {
+                return;
+            }
+
+            state.stored_at = Instant::now();
+        }
+
+        self.save().await;
+    }
+
+    /// Saves the time of the last processed event, if changed.
+    pub async fn save(&self) {
+        let last = {
+            let state = self.state.lock().unwrap();
+            if state.last == state.stored {
+                return;
+            }
+
+            state.last
+        };
+        let Some(last) = last else {
+            return;
+        };
+
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                ..Default::default()
+            },
+            data: Some([(LAST_EVENT_TIME.to_string(), last.to_rfc3339())].into()),
+            ..Default::default()
+        };
+        let params = PatchParams::apply("sentry-kubernetes").force();
+        match self
+            .api
+            .patch(&self.name, &params, &Patch::Apply(&config_map))
+            .await
+        {
+            Ok(_) => {
+                debug!("Saved checkpoint {}", last.to_rfc3339());
+                let mut state = self.state.lock().unwrap();
+                state.stored = Some(last);
+            }
+            Err(e) => warn!(
+                "Cannot save the checkpoint to ConfigMap {}: {}",
+                self.name, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::{Checkpoint, State};
+    use k8s_openapi::api::core::v1::Event;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use k8s_openapi::chrono::{DateTime, Utc};
+    use kube::{Api, Client};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    fn time(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().into()
+    }
+
+    #[tokio::test]
+    pub async fn test_is_processed() {
+        let client = Client::try_default().await.unwrap();
+        let resume_from = Some(time("2023-04-08T22:28:00Z"));
+        let checkpoint = Checkpoint {
+            api: Api::default_namespaced(client),
+            name: "sentry-kubernetes".to_string(),
+            interval: Duration::from_secs(10),
+            resume_from,
+            state: Mutex::new(State {
+                last: resume_from,
+                stored: resume_from,
+                stored_at: Instant::now(),
+            }),
+        };
+
+        let event = |t: &str| Event {
+            last_timestamp: Some(Time(time(t))),
+            ..Default::default()
+        };
+        assert!(checkpoint.is_processed(&event("2023-04-08T22:27:59Z")));
+        assert!(!checkpoint.is_processed(&event("2023-04-08T22:28:00Z")));
+        assert!(!checkpoint.is_processed(&event("2023-04-08T22:28:01Z")));
+        assert!(!checkpoint.is_processed(&Event::default()));
+
+        // Within the interval, the checkpoint is only updated in memory.
+        checkpoint.record(time("2023-04-08T22:29:00Z")).await;
+        checkpoint.record(time("2023-04-08T22:28:30Z")).await;
+        let state = checkpoint.state.lock().unwrap();
+        assert_eq!(state.last, Some(time("2023-04-08T22:29:00Z")));
+        assert_eq!(state.stored, resume_from);
+    }
+}
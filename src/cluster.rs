@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// A cluster whose events are watched, and how to connect to it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cluster {
+    /// Name of the cluster reported in the events. If None, CLUSTER_NAME is used.
+    pub name: Option<String>,
+    /// Path of the kubeconfig file. If None, the KUBECONFIG (or ~/.kube/config) file is used,
+    /// falling back to the in-cluster configuration.
+    pub kubeconfig: Option<PathBuf>,
+    /// Context of the kubeconfig. If None, the current context is used.
+    pub context: Option<String>,
+}
+
+impl Cluster {
+    /// The name of the cluster in the logs.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("default")
+    }
+}
+
+/// Lists the clusters to watch:
+/// - a cluster for each kubeconfig file of the given directory, named after the file;
+/// - otherwise a cluster for each context, named after the context if more than one is given;
+/// - otherwise the cluster of the current context (or the one the controller is running in).
+pub fn clusters(contexts: Vec<String>, kubeconfig_dir: Option<&str>) -> Result<Vec<Cluster>> {
+    if let Some(dir) = kubeconfig_dir {
+        let mut paths = fs::read_dir(dir)
+            .with_context(|| format!("Cannot read the kubeconfig directory {}", dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            // Skip hidden files (ex: the ..data links of a mounted secret)
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.'))
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        if paths.is_empty() {
+            anyhow::bail!("No kubeconfig file found in {}", dir);
+        }
+
+        return Ok(paths
+            .into_iter()
+            .map(|path| Cluster {
+                name: path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned()),
+                kubeconfig: Some(path),
+                context: None,
+            })
+            .collect());
+    }
+
+    Ok(match contexts.len() {
+        0 => vec![Cluster::default()],
+        1 => vec![Cluster {
+            context: contexts.into_iter().next(),
+            ..Default::default()
+        }],
+        _ => contexts
+            .into_iter()
+            .map(|context| Cluster {
+                name: Some(context.clone()),
+                kubeconfig: None,
+                context: Some(context),
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::{clusters, Cluster};
+    use std::fs;
+
+    #[test]
+    pub fn test_clusters_from_contexts() {
+        assert_eq!(clusters(vec![], None).unwrap(), vec![Cluster::default()]);
+        assert_eq!(
+            clusters(vec!["staging".to_string()], None).unwrap(),
+            vec![Cluster {
+                name: None,
+                kubeconfig: None,
+                context: Some("staging".to_string()),
+            }]
+        );
+
+        let clusters = clusters(vec!["eu-1".to_string(), "us-1".to_string()], None).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[1].name.as_deref(), Some("us-1"));
+        assert_eq!(clusters[1].context.as_deref(), Some("us-1"));
+    }
+
+    #[test]
+    pub fn test_clusters_from_directory() {
+        let dir = std::env::temp_dir().join(format!("clusters-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(clusters(vec![], dir.to_str()).is_err());
+
+        fs::write(dir.join("us-1.yaml"), "").unwrap();
+        fs::write(dir.join("eu-1.yaml"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+
+        let clusters = clusters(vec!["ignored".to_string()], dir.to_str()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names = clusters
+            .iter()
+            .map(|c| c.name.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["eu-1", "us-1"]);
+        assert_eq!(clusters[0].kubeconfig, Some(dir.join("eu-1.yaml")));
+        assert_eq!(clusters[0].context, None);
+    }
+}
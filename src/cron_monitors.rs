@@ -0,0 +1,245 @@
+use crate::cache::TtlCache;
+use crate::objects::owner;
+use k8s_openapi::api::batch::v1::{CronJob, Job};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::Utc;
+use kube::{Api, Client};
+use log::{debug, warn};
+use sentry::protocol::{MonitorCheckIn, MonitorCheckInStatus, MonitorConfig, MonitorSchedule};
+use sentry::types::Uuid;
+use sentry::Hub;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The annotation of the CronJob (or of its job template) overriding the slug of the monitor.
+const MONITOR_SLUG_ANNOTATION: &str = "sentry-kubernetes.io/monitor-slug";
+/// Maximum length of the slugs accepted by sentry.
+const MAX_SLUG_LENGTH: usize = 50;
+/// Number of Jobs remembered, with the status of their last check-in.
+const CHECK_IN_CACHE_SIZE: usize = 10_000;
+const CHECK_IN_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Sends the executions of the CronJobs to the sentry cron monitors: an `in_progress` check-in
+/// when a Job starts, then an `ok` or `error` check-in when it completes or fails.
+/// The monitors are created (or updated) with the schedule and the time zone of the CronJob.
+pub struct CronMonitors {
+    client: Client,
+    /// Jobs finished before startup are not checked in.
+    started: Time,
+    /// The status of the last check-in, by Job uid.
+    checked_in: Mutex<TtlCache<String, MonitorCheckInStatus>>,
+}
+
+impl CronMonitors {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            started: Time(Utc::now()),
+            checked_in: Mutex::new(TtlCache::new(CHECK_IN_CACHE_SIZE, CHECK_IN_TTL)),
+        }
+    }
+
+    /// Checks the Job in, if owned by a CronJob and its status changed since the last check-in.
+    pub async fn check_in(&self, job: Job) {
+        let (Some(uid), Some(cron_job)) =
+            (job.metadata.uid.clone(), owner(&job.metadata, "CronJob"))
+        else {
+            return;
+        };
+        let Some(status) = status(&job, &self.started) else {
+            return;
+        };
+        {
+            let mut checked_in = self.checked_in.lock().unwrap();
+            match checked_in.get(&uid) {
+                Some(last) if last == status || last != MonitorCheckInStatus::InProgress => return,
+                _ => checked_in.insert(uid.clone(), status),
+            }
+        }
+
+        let namespace = job.metadata.namespace.clone().unwrap_or_default();
+        let api: Api<CronJob> = Api::namespaced(self.client.clone(), &namespace);
+        let cron_job = match api.get_opt(&cron_job).await {
+            Ok(Some(cron_job)) => cron_job,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Cannot read the cronjob {}/{}: {}", namespace, cron_job, e);
+                return;
+            }
+        };
+
+        let Some(client) = Hub::current().client() else {
+            return;
+        };
+        let check_in = MonitorCheckIn {
+            // The check-ins of a Job share its uid: sentry updates the in-progress check-in.
+            check_in_id: Uuid::parse_str(&uid).unwrap_or_else(|_| Uuid::new_v4()),
+            monitor_slug: monitor_slug(&job, &cron_job),
+            status,
+            environment: client.options().environment.as_deref().map(str::to_string),
+            duration: duration(&job),
+            monitor_config: monitor_config(&cron_job),
+        };
+        debug!(
+            target: "sentry_kubernetes::cron_monitors",
+            "Check-in of monitor {}: {:?}", check_in.monitor_slug, status
+        );
+        client.send_envelope(check_in.into());
+    }
+}
+
+/// The status of the check-in of the Job: `in_progress` once started, then `ok` or `error`.
+/// The Jobs which finished before the given time are not checked in.
+fn status(job: &Job, since: &Time) -> Option<MonitorCheckInStatus> {
+    let status = job.status.as_ref()?;
+    status.start_time.as_ref()?;
+    let finished = status
+        .conditions
+        .iter()
+        .flatten()
+        .find(|c| (c.type_ == "Complete" || c.type_ == "Failed") && c.status == "True");
+
+    match finished {
+        None => Some(MonitorCheckInStatus::InProgress),
+        Some(c) if c.last_transition_time.as_ref().is_some_and(|t| t < since) => None,
+        Some(c) if c.type_ == "Complete" => Some(MonitorCheckInStatus::Ok),
+        Some(_) => Some(MonitorCheckInStatus::Error),
+    }
+}
+
+/// The seconds the Job ran for, once finished.
+fn duration(job: &Job) -> Option<f64> {
+    let status = job.status.as_ref()?;
+    let start = status.start_time.as_ref()?;
+    let end = status.completion_time.as_ref().or_else(|| {
+        status
+            .conditions
+            .iter()
+            .flatten()
+            .find(|c| c.type_ == "Failed" && c.status == "True")?
+            .last_transition_time
+            .as_ref()
+    })?;
+
+    Some((end.0 - start.0).num_milliseconds() as f64 / 1000.0)
+}
+
+/// The slug of the monitor: the annotation of the job template or of the CronJob, defaulting to
+/// "<namespace>-<cronjob name>".
+fn monitor_slug(job: &Job, cron_job: &CronJob) -> String {
+    let annotated = [&job.metadata, &cron_job.metadata]
+        .into_iter()
+        .find_map(|m| m.annotations.as_ref()?.get(MONITOR_SLUG_ANNOTATION));
+    if let Some(slug) = annotated {
+        return slug.clone();
+    }
+
+    let name = format!(
+        "{}-{}",
+        cron_job.metadata.namespace.as_deref().unwrap_or_default(),
+        cron_job.metadata.name.as_deref().unwrap_or_default()
+    );
+    let slug = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(MAX_SLUG_LENGTH)
+        .collect::<String>();
+
+    slug.trim_matches('-').to_string()
+}
+
+/// The configuration of the monitor, from the schedule of the CronJob.
+fn monitor_config(cron_job: &CronJob) -> Option<MonitorConfig> {
+    let spec = cron_job.spec.as_ref()?;
+    Some(MonitorConfig {
+        schedule: MonitorSchedule::Crontab {
+            value: spec.schedule.clone(),
+        },
+        checkin_margin: None,
+        max_runtime: None,
+        timezone: spec.time_zone.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cron_monitors::{duration, monitor_slug, status};
+    use k8s_openapi::api::batch::v1::{CronJob, Job, JobCondition, JobStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::{DateTime, Utc};
+    use sentry::protocol::MonitorCheckInStatus;
+
+    fn time(t: &str) -> Time {
+        Time(DateTime::parse_from_rfc3339(t).unwrap().with_timezone(&Utc))
+    }
+
+    fn job(condition: Option<&str>) -> Job {
+        Job {
+            status: Some(JobStatus {
+                start_time: Some(time("2023-04-08T22:00:00Z")),
+                completion_time: (condition == Some("Complete"))
+                    .then(|| time("2023-04-08T22:01:30Z")),
+                conditions: condition.map(|type_| {
+                    vec![JobCondition {
+                        type_: type_.to_string(),
+                        status: "True".to_string(),
+                        last_transition_time: Some(time("2023-04-08T22:01:30Z")),
+                        ..Default::default()
+                    }]
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_status() {
+        let since = time("2023-04-08T21:00:00Z");
+        assert_eq!(status(&Job::default(), &since), None);
+        assert_eq!(
+            status(&job(None), &since),
+            Some(MonitorCheckInStatus::InProgress)
+        );
+        assert_eq!(
+            status(&job(Some("Complete")), &since),
+            Some(MonitorCheckInStatus::Ok)
+        );
+        assert_eq!(
+            status(&job(Some("Failed")), &since),
+            Some(MonitorCheckInStatus::Error)
+        );
+        assert_eq!(duration(&job(Some("Failed"))), Some(90.0));
+        assert_eq!(duration(&job(None)), None);
+
+        // Finished before startup.
+        let since = time("2023-04-08T23:00:00Z");
+        assert_eq!(status(&job(Some("Complete")), &since), None);
+    }
+
+    #[test]
+    pub fn test_monitor_slug() {
+        let mut cron_job = CronJob {
+            metadata: ObjectMeta {
+                name: Some("Nightly_Backup".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            monitor_slug(&Job::default(), &cron_job),
+            "shop-nightly-backup"
+        );
+
+        cron_job.metadata.annotations = Some(
+            [(
+                "sentry-kubernetes.io/monitor-slug".to_string(),
+                "backups".to_string(),
+            )]
+            .into(),
+        );
+        assert_eq!(monitor_slug(&Job::default(), &cron_job), "backups");
+    }
+}
@@ -0,0 +1,255 @@
+use crate::sentry_event::SentryEvent;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use hyper::header::{USER_AGENT, WARNING};
+use hyper::{Body, Request, Response};
+use k8s_openapi::api::core::v1::{Event, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use sentry::types::Uuid;
+use sentry::Level;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// The user agent of the requests without one.
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The deprecation warnings returned by the API server, aggregated per API group/version and user
+/// agent. Each aggregate is reported once, and again when a new warning is received for it.
+#[derive(Default)]
+pub struct DeprecationWarnings {
+    warnings: Mutex<HashMap<(String, String), Deprecation>>,
+}
+
+#[derive(Default)]
+struct Deprecation {
+    messages: BTreeSet<String>,
+    requests: u64,
+    reported: bool,
+}
+
+impl DeprecationWarnings {
+    /// Records the deprecation warning returned for a request on the path.
+    pub fn record(&self, path: &str, user_agent: &str, warning: &str) {
+        let Some(message) = warning_text(warning).filter(|m| m.contains("deprecated")) else {
+            return;
+        };
+
+        let key = (api_version(path), user_agent.to_string());
+        let mut warnings = self.warnings.lock().unwrap();
+        let deprecation = warnings.entry(key).or_default();
+        deprecation.requests += 1;
+        if deprecation.messages.insert(message) {
+            deprecation.reported = false;
+        }
+    }
+
+    /// The events of the deprecations not reported yet.
+    pub fn due(&self) -> Vec<SentryEvent> {
+        let mut warnings = self.warnings.lock().unwrap();
+        warnings
+            .iter_mut()
+            .filter(|(_, deprecation)| !deprecation.reported)
+            .map(|((api_version, user_agent), deprecation)| {
+                deprecation.reported = true;
+                deprecation_event(api_version, user_agent, deprecation)
+            })
+            .collect()
+    }
+}
+
+/// The text of a Warning header (`299 - "text"`), unquoted.
+fn warning_text(header: &str) -> Option<String> {
+    let mut parts = header.splitn(3, ' ');
+    let (_code, _agent, text) = (parts.next()?, parts.next()?, parts.next()?);
+    let mut chars = text.trim().strip_prefix('"')?.chars();
+    let mut unquoted = String::new();
+    // The quoted text may be followed by a date.
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+
+    Some(unquoted)
+}
+
+/// The API group/version of the request path (ex: "batch/v1beta1", or "v1" for the core group).
+fn api_version(path: &str) -> String {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["api", version, ..] => version.to_string(),
+        ["apis", group, version, ..] => format!("{}/{}", group, version),
+        _ => path.to_string(),
+    }
+}
+
+/// An info event of the deprecated API, with the warnings in the "deprecation" context.
+fn deprecation_event(
+    api_version: &str,
+    user_agent: &str,
+    deprecation: &Deprecation,
+) -> SentryEvent {
+    let now = Time(Utc::now());
+    let message = deprecation
+        .messages
+        .iter()
+        .next_back()
+        .cloned()
+        .unwrap_or_else(|| format!("{} is deprecated", api_version));
+    let event = Event {
+        metadata: ObjectMeta {
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(now.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            name: Some(api_version.to_string()),
+            ..Default::default()
+        },
+        reason: Some("DeprecatedAPI".to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    };
+
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.level = Level::Info;
+    sentry_event
+        .tags
+        .insert("api_version".to_string(), api_version.to_string());
+    sentry_event
+        .tags
+        .insert("user_agent".to_string(), user_agent.to_string());
+    sentry_event.contexts.insert(
+        "deprecation".to_string(),
+        BTreeMap::from([
+            ("apiVersion".to_string(), json!(api_version)),
+            ("userAgent".to_string(), json!(user_agent)),
+            ("warnings".to_string(), json!(deprecation.messages)),
+            ("requests".to_string(), json!(deprecation.requests)),
+        ]),
+    );
+
+    sentry_event
+}
+
+/// Records the deprecation warnings of the responses of the kubernetes client.
+#[derive(Clone)]
+pub struct DeprecationLayer {
+    warnings: Option<Arc<DeprecationWarnings>>,
+}
+
+impl DeprecationLayer {
+    /// Does nothing if no warnings are given.
+    pub fn new(warnings: Option<Arc<DeprecationWarnings>>) -> Self {
+        Self { warnings }
+    }
+}
+
+impl<S> Layer<S> for DeprecationLayer {
+    type Service = DeprecationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeprecationService {
+            inner,
+            warnings: self.warnings.clone(),
+        }
+    }
+}
+
+pub struct DeprecationService<S> {
+    inner: S,
+    warnings: Option<Arc<DeprecationWarnings>>,
+}
+
+impl<S, B> Service<Request<Body>> for DeprecationService<S>
+where
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let Some(warnings) = self.warnings.clone() else {
+            return self.inner.call(request).boxed();
+        };
+
+        let path = request.uri().path().to_string();
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or(DEFAULT_USER_AGENT)
+            .to_string();
+        self.inner
+            .call(request)
+            .inspect_ok(move |response| {
+                for warning in response.headers().get_all(WARNING) {
+                    if let Ok(warning) = warning.to_str() {
+                        warnings.record(&path, &user_agent, warning);
+                    }
+                }
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deprecations::{api_version, warning_text, DeprecationWarnings};
+    use sentry::Level;
+
+    #[test]
+    pub fn test_warning_text() {
+        assert_eq!(
+            warning_text(r#"299 - "batch/v1beta1 CronJob is deprecated in v1.21+, unavailable in v1.25+; use batch/v1 CronJob""#).as_deref(),
+            Some("batch/v1beta1 CronJob is deprecated in v1.21+, unavailable in v1.25+; use batch/v1 CronJob")
+        );
+        assert_eq!(
+            warning_text(r#"299 - "a \"quoted\" text" "Wed, 21 Oct 2015 07:28:00 GMT""#).as_deref(),
+            Some(r#"a "quoted" text"#)
+        );
+        assert_eq!(warning_text("299"), None);
+
+        assert_eq!(api_version("/apis/batch/v1beta1/cronjobs"), "batch/v1beta1");
+        assert_eq!(api_version("/api/v1/namespaces/default/pods"), "v1");
+    }
+
+    #[test]
+    pub fn test_due() {
+        let warnings = DeprecationWarnings::default();
+        let warning = r#"299 - "policy/v1beta1 PodSecurityPolicy is deprecated in v1.21+, unavailable in v1.25+""#;
+        warnings.record("/apis/policy/v1beta1/podsecuritypolicies", "agent", warning);
+        warnings.record("/apis/policy/v1beta1/podsecuritypolicies", "agent", warning);
+        warnings.record("/api/v1/pods", "agent", r#"299 - "unrelated warning""#);
+
+        let events = warnings.due();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "DeprecatedAPI");
+        assert_eq!(events[0].level, Level::Info);
+        assert_eq!(events[0].tags["api_version"], "policy/v1beta1");
+        assert_eq!(events[0].contexts["deprecation"]["requests"], 2);
+        assert!(warnings.due().is_empty());
+
+        warnings.record(
+            "/apis/policy/v1beta1/poddisruptionbudgets",
+            "agent",
+            r#"299 - "policy/v1beta1 PodDisruptionBudget is deprecated in v1.21+""#,
+        );
+        assert_eq!(warnings.due()[0].contexts["deprecation"]["requests"], 3);
+    }
+}
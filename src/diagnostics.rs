@@ -0,0 +1,131 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use pprof::protos::Message;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Default and maximum duration of the CPU profiles.
+const DEFAULT_PROFILE_SECONDS: u64 = 30;
+const MAX_PROFILE_SECONDS: u64 = 300;
+/// Sampling frequency (in Hz) of the CPU profiles.
+const PROFILE_FREQUENCY: i32 = 99;
+
+/// Samples the heap allocations (every 512KiB on average), but only once the profiling is activated.
+#[allow(non_upper_case_globals)]
+#[export_name = "malloc_conf"]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:false,lg_prof_sample:19\0";
+
+/// Starts the tokio-console instrumentation (listening on TOKIO_CONSOLE_BIND, 127.0.0.1:6669 by
+/// default) and activates the sampling of the heap allocations.
+pub async fn init() {
+    console_subscriber::init();
+    jemalloc_pprof::activate_jemalloc_profiling().await;
+}
+
+/// Serves the profiles in the pprof format (`go tool pprof http://<addr>/debug/pprof/profile`):
+/// - `GET /debug/pprof/profile?seconds=<n>`: profiles the CPU for the given seconds (30 by default);
+/// - `GET /debug/pprof/heap`: the allocations sampled since startup and not freed yet.
+pub async fn serve(addr: SocketAddr) {
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|r| async {
+            Ok::<_, Infallible>(route(r).await)
+        }))
+    });
+
+    info!("Diagnostics listening on {}", addr);
+    match Server::try_bind(&addr) {
+        Ok(server) => {
+            if let Err(e) = server.serve(service).await {
+                error!("Diagnostics server error: {}", e);
+            }
+        }
+        Err(e) => error!("Cannot listen on {}: {}", addr, e),
+    }
+}
+
+async fn route(request: Request<Body>) -> Response<Body> {
+    let profile = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/debug/pprof/profile") => cpu_profile(profile_duration(&request)).await,
+        (&Method::GET, "/debug/pprof/heap") => heap_profile().await,
+        _ => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+
+    match profile {
+        Ok(profile) => Response::builder()
+            .header("content-type", "application/octet-stream")
+            .body(profile.into())
+            .unwrap(),
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(e.to_string().into())
+            .unwrap(),
+    }
+}
+
+/// The duration of the CPU profile, from the `seconds` parameter of the query.
+fn profile_duration(request: &Request<Body>) -> Duration {
+    let seconds = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|p| p.strip_prefix("seconds=")?.parse().ok())
+        .unwrap_or(DEFAULT_PROFILE_SECONDS);
+
+    Duration::from_secs(seconds.clamp(1, MAX_PROFILE_SECONDS))
+}
+
+async fn cpu_profile(duration: Duration) -> anyhow::Result<Vec<u8>> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(PROFILE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    tokio::time::sleep(duration).await;
+
+    let profile = guard.report().build()?.pprof()?;
+    Ok(profile.write_to_bytes()?)
+}
+
+async fn heap_profile() -> anyhow::Result<Vec<u8>> {
+    let Some(prof_ctl) = jemalloc_pprof::PROF_CTL.as_ref() else {
+        anyhow::bail!("The heap profiling is not available");
+    };
+
+    let mut prof_ctl = prof_ctl.lock().await;
+    if !prof_ctl.activated() {
+        anyhow::bail!("The heap profiling is not activated");
+    }
+
+    prof_ctl.dump_pprof()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::profile_duration;
+    use hyper::{Body, Request};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_profile_duration() {
+        let request = |uri| Request::get(uri).body(Body::empty()).unwrap();
+        assert_eq!(
+            profile_duration(&request("/debug/pprof/profile")),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            profile_duration(&request("/debug/pprof/profile?seconds=5")),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            profile_duration(&request("/debug/pprof/profile?seconds=3600")),
+            Duration::from_secs(300)
+        );
+    }
+}
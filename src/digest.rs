@@ -0,0 +1,40 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Encodes the bytes (ex: a digest) as lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compares the secrets (ex: a bearer token) in constant time: their HMACs have the same length
+/// whatever the secrets, and are compared in constant time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mac = |value: &[u8]| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"sentry-kubernetes")
+            .expect("HMAC accepts keys of any size");
+        mac.update(value);
+        mac
+    };
+
+    let expected = mac(b).finalize().into_bytes();
+    mac(a).verify_slice(&expected).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{constant_time_eq, hex};
+
+    #[test]
+    pub fn test_hex() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+    }
+
+    #[test]
+    pub fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"s3cr3t", b"s3cr3t"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3T"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3t-longer"));
+        assert!(!constant_time_eq(b"", b"s3cr3t"));
+    }
+}
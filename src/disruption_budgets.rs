@@ -0,0 +1,286 @@
+use crate::processor::workload_name;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod};
+use k8s_openapi::api::policy::v1::PodDisruptionBudget;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Reports the PodDisruptionBudgets allowing no disruption while some of their pods are unhealthy,
+/// for longer than a threshold: a drain or a node upgrade would be blocked, and one more failing pod
+/// reduces the availability below the budget. The covered workload is reported in the tags.
+pub struct DisruptionBudgetWatcher {
+    client: Client,
+    threshold: Duration,
+    /// The blocked budgets by uid, since when and whether they have been reported.
+    blocked: Mutex<HashMap<String, Blocked>>,
+}
+
+struct Blocked {
+    budget: PodDisruptionBudget,
+    since: Instant,
+    reported: bool,
+}
+
+impl DisruptionBudgetWatcher {
+    pub fn new(client: Client, threshold: Duration) -> Self {
+        Self {
+            client,
+            threshold,
+            blocked: Default::default(),
+        }
+    }
+
+    /// Records whether the budget is blocked. The budgets are reported by the periodic checks.
+    pub fn update(&self, budget: &PodDisruptionBudget) {
+        let Some(uid) = budget.metadata.uid.clone() else {
+            return;
+        };
+
+        let mut blocked = self.blocked.lock().unwrap();
+        if is_blocked(budget) {
+            let entry = blocked.entry(uid).or_insert_with(|| Blocked {
+                budget: budget.clone(),
+                since: Instant::now(),
+                reported: false,
+            });
+            entry.budget = budget.clone();
+        } else {
+            blocked.remove(&uid);
+        }
+    }
+
+    /// The events of the budgets blocked longer than the threshold, not reported yet.
+    pub async fn due(&self) -> Vec<SentryEvent> {
+        let due = {
+            let mut blocked = self.blocked.lock().unwrap();
+            blocked
+                .values_mut()
+                .filter(|b| !b.reported && b.since.elapsed() >= self.threshold)
+                .map(|b| {
+                    b.reported = true;
+                    b.budget.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut events = vec![];
+        for budget in due {
+            // The deletions are not notified: check that the budget still exists.
+            let (Some(namespace), Some(name)) = (&budget.metadata.namespace, &budget.metadata.name)
+            else {
+                continue;
+            };
+            let api: Api<PodDisruptionBudget> = Api::namespaced(self.client.clone(), namespace);
+            match api.get_opt(name).await {
+                Ok(Some(budget)) if is_blocked(&budget) => {
+                    let workload = self.workload(&budget).await;
+                    events.push(blocked_event(&budget, self.threshold, workload));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Cannot read the budget {}/{}: {}", namespace, name, e),
+            }
+        }
+
+        events
+    }
+
+    /// The workload of the pods covered by the budget.
+    async fn workload(&self, budget: &PodDisruptionBudget) -> Option<String> {
+        let namespace = budget.metadata.namespace.as_deref()?;
+        let selector = selector(budget.spec.as_ref()?.selector.as_ref()?)?;
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
+        match api
+            .list(&ListParams::default().labels(&selector).limit(1))
+            .await
+        {
+            Ok(pods) => pods.items.first().and_then(workload_name),
+            Err(e) => {
+                warn!("Cannot list the pods of the budget {}: {}", selector, e);
+                None
+            }
+        }
+    }
+}
+
+/// No disruption is allowed while some of the covered pods are unhealthy.
+fn is_blocked(budget: &PodDisruptionBudget) -> bool {
+    let Some(status) = budget.status.as_ref() else {
+        return false;
+    };
+
+    status.disruptions_allowed == 0 && status.current_healthy < status.expected_pods
+}
+
+/// The label selector as a query string (ex: "app=web,tier in (front,back)").
+fn selector(selector: &LabelSelector) -> Option<String> {
+    let labels = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .map(|(key, value)| format!("{}={}", key, value));
+    let expressions = selector.match_expressions.iter().flatten().map(|e| {
+        let values = e.values.clone().unwrap_or_default().join(",");
+        match e.operator.as_str() {
+            "In" => format!("{} in ({})", e.key, values),
+            "NotIn" => format!("{} notin ({})", e.key, values),
+            "DoesNotExist" => format!("!{}", e.key),
+            _ => e.key.clone(),
+        }
+    });
+    let selector = labels.chain(expressions).collect::<Vec<_>>().join(",");
+
+    // An empty selector matches all the pods of the namespace: no workload in particular.
+    (!selector.is_empty()).then_some(selector)
+}
+
+/// A warning event of the budget, with its status in the "pod disruption budget" context.
+fn blocked_event(
+    budget: &PodDisruptionBudget,
+    threshold: Duration,
+    workload: Option<String>,
+) -> SentryEvent {
+    let name = budget.metadata.name.clone().unwrap_or_default();
+    let status = budget.status.clone().unwrap_or_default();
+    let message = format!(
+        "PodDisruptionBudget {} has allowed no disruption for more than {} seconds: {} of {} pods healthy, {} desired",
+        name,
+        threshold.as_secs(),
+        status.current_healthy,
+        status.expected_pods,
+        status.desired_healthy
+    );
+    let now = Time(Utc::now());
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: budget.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(now.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("policy/v1".to_string()),
+            kind: Some("PodDisruptionBudget".to_string()),
+            name: Some(name),
+            namespace: budget.metadata.namespace.clone(),
+            uid: budget.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some("DisruptionBudgetBlocked".to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    };
+
+    let spec = budget.spec.as_ref();
+    let mut sentry_event = SentryEvent::from(event);
+    if let Some(workload) = workload {
+        sentry_event
+            .tags
+            .insert("workload".to_string(), workload.clone());
+        sentry_event.workload = Some(workload);
+    }
+    sentry_event.contexts.insert(
+        "pod disruption budget".to_string(),
+        BTreeMap::from([
+            (
+                "minAvailable".to_string(),
+                json!(spec.and_then(|s| s.min_available.clone())),
+            ),
+            (
+                "maxUnavailable".to_string(),
+                json!(spec.and_then(|s| s.max_unavailable.clone())),
+            ),
+            ("currentHealthy".to_string(), json!(status.current_healthy)),
+            ("desiredHealthy".to_string(), json!(status.desired_healthy)),
+            ("expectedPods".to_string(), json!(status.expected_pods)),
+            (
+                "disruptionsAllowed".to_string(),
+                json!(status.disruptions_allowed),
+            ),
+            ("conditions".to_string(), json!(status.conditions)),
+        ]),
+    );
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disruption_budgets::{blocked_event, is_blocked, selector};
+    use k8s_openapi::api::policy::v1::{
+        PodDisruptionBudget, PodDisruptionBudgetSpec, PodDisruptionBudgetStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+        LabelSelector, LabelSelectorRequirement, ObjectMeta,
+    };
+    use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+    use std::time::Duration;
+
+    fn budget(healthy: i32, allowed: i32) -> PodDisruptionBudget {
+        PodDisruptionBudget {
+            metadata: ObjectMeta {
+                name: Some("web".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            spec: Some(PodDisruptionBudgetSpec {
+                min_available: Some(IntOrString::Int(2)),
+                ..Default::default()
+            }),
+            status: Some(PodDisruptionBudgetStatus {
+                current_healthy: healthy,
+                desired_healthy: 2,
+                expected_pods: 3,
+                disruptions_allowed: allowed,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    pub fn test_blocked_event() {
+        assert!(!is_blocked(&budget(3, 1)));
+        assert!(!is_blocked(&budget(3, 0)));
+        assert!(is_blocked(&budget(2, 0)));
+
+        let event = blocked_event(
+            &budget(2, 0),
+            Duration::from_secs(600),
+            Some("web".to_string()),
+        );
+        assert_eq!(event.reason, "DisruptionBudgetBlocked");
+        assert_eq!(
+            event.message.as_deref(),
+            Some("PodDisruptionBudget web has allowed no disruption for more than 600 seconds: 2 of 3 pods healthy, 2 desired")
+        );
+        assert_eq!(event.tags["workload"], "web");
+        assert_eq!(event.contexts["pod disruption budget"]["minAvailable"], 2);
+    }
+
+    #[test]
+    pub fn test_selector() {
+        let label_selector = LabelSelector {
+            match_labels: Some([("app".to_string(), "web".to_string())].into()),
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: "tier".to_string(),
+                operator: "In".to_string(),
+                values: Some(vec!["front".to_string(), "back".to_string()]),
+            }]),
+        };
+        assert_eq!(
+            selector(&label_selector).as_deref(),
+            Some("app=web,tier in (front,back)")
+        );
+        assert_eq!(selector(&LabelSelector::default()), None);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::cert_manager;
+use crate::hpa;
+use crate::kube_api::{Kube, KubeApi};
+use crate::metrics::METRICS;
+use crate::node::NodeCapacity;
+use crate::processor::workload_name;
+use crate::sentry_event::{SentryEvent, CULPRIT_FORMAT, TRANSACTION_FORMAT};
+use futures::Future;
+use kube::Client;
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Adds the details of the involved objects to the events, looked up in the cluster.
+#[derive(Clone)]
+pub struct Enricher {
+    kube: Arc<dyn KubeApi>,
+    timeout: Duration,
+    deletions: bool,
+}
+
+/// Looks up the objects with the client, through a cache (1000 objects, for 60 seconds),
+/// giving up after 5 seconds.
+impl From<Client> for Enricher {
+    fn from(client: Client) -> Self {
+        let kube = Kube::new(client, None, 1000, Duration::from_secs(60));
+        Self::new(Arc::new(kube), Duration::from_secs(5))
+    }
+}
+
+impl Enricher {
+    pub fn new(kube: Arc<dyn KubeApi>, timeout: Duration) -> Self {
+        Self {
+            kube,
+            timeout,
+            deletions: false,
+        }
+    }
+
+    /// Tags `being_deleted=true` the events of the objects being deleted (with a deletion
+    /// timestamp), ex: the failures of the pods during their teardown.
+    #[must_use]
+    pub fn deletions(mut self, enabled: bool) -> Self {
+        self.deletions = enabled;
+        self
+    }
+
+    /// Adds the workload of the involved pod and the capacity and labels of its node, the
+    /// Certificate of the cert-manager events, the HorizontalPodAutoscaler of its failures, or
+    /// whether the involved object is being deleted.
+    pub async fn enrich(&self, sentry_event: &mut SentryEvent) {
+        let mut being_deleted = false;
+        if sentry_event.kind.as_deref() == Some("Pod")
+            && (sentry_event.source_host.is_none()
+                || CULPRIT_FORMAT.contains("{{workload}}")
+                || TRANSACTION_FORMAT
+                    .as_deref()
+                    .is_some_and(|f| f.contains("{{workload}}"))
+                || self.deletions)
+        {
+            let pod = self
+                .lookup(
+                    "pod",
+                    self.kube.pod(&sentry_event.namespace, &sentry_event.name),
+                )
+                .await;
+            if let Some(pod) = pod {
+                being_deleted = pod.metadata.deletion_timestamp.is_some();
+                sentry_event.workload = workload_name(&pod);
+                if sentry_event.source_host.is_none() {
+                    sentry_event.source_host = pod.spec.as_ref().and_then(|p| p.node_name.clone());
+                }
+            }
+        } else if self.deletions {
+            if let (Some(api_version), Some(kind)) = (&sentry_event.api_version, &sentry_event.kind)
+            {
+                let lookup = self.kube.metadata(
+                    api_version,
+                    kind,
+                    &sentry_event.namespace,
+                    &sentry_event.name,
+                );
+                if let Some(meta) = self.lookup("object", lookup).await {
+                    being_deleted = meta.deletion_timestamp.is_some();
+                }
+            }
+        }
+        if being_deleted {
+            sentry_event
+                .tags
+                .insert("being_deleted".to_string(), "true".to_string());
+        }
+
+        if let Some(hostname) = sentry_event.source_host.as_deref() {
+            if let Some(node) = self.lookup("node", self.kube.node(hostname)).await {
+                sentry_event.node_capacity = Some(NodeCapacity::from(node.as_ref()));
+                sentry_event.node_labels = node.metadata.labels.clone().unwrap_or_default();
+            }
+        }
+
+        if cert_manager::is_cert_manager(sentry_event) {
+            let certificate = self
+                .lookup(
+                    "certificate",
+                    cert_manager::certificate(self.kube.as_ref(), sentry_event),
+                )
+                .await;
+            if let Some(certificate) = certificate {
+                cert_manager::enrich(sentry_event, &certificate);
+                METRICS.event_enriched();
+            }
+        }
+
+        if hpa::is_hpa_failure(sentry_event) {
+            let lookup = self.kube.object(
+                hpa::HPA_API_VERSION,
+                "HorizontalPodAutoscaler",
+                &sentry_event.namespace,
+                &sentry_event.name,
+            );
+            if let Some(autoscaler) = self.lookup("horizontal pod autoscaler", lookup).await {
+                hpa::enrich(sentry_event, &autoscaler);
+                METRICS.event_enriched();
+            }
+        }
+
+        if sentry_event.workload.is_some() || sentry_event.node_capacity.is_some() {
+            METRICS.event_enriched();
+        }
+    }
+
+    /// Runs a lookup, giving up after the timeout:
+    /// a slow API server must not stall the processing of the events.
+    pub(crate) async fn lookup<T>(
+        &self,
+        what: &str,
+        lookup: impl Future<Output = Option<T>>,
+    ) -> Option<T> {
+        match timeout(self.timeout, lookup).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("Timed out looking up the {} of the event, skipping", what);
+                METRICS.enrichment_timeout();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enrich::Enricher;
+    use crate::kube_api::FakeKube;
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::apps::v1::Deployment;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::Utc;
+    use kube::api::{ApiResource, DynamicObject};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn meta(name: &str, deleted: bool) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("shop".to_string()),
+            deletion_timestamp: deleted.then(|| Time(Utc::now())),
+            ..Default::default()
+        }
+    }
+
+    fn event(api_version: &str, kind: &str, name: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                api_version: Some(api_version.to_string()),
+                kind: Some(kind.to_string()),
+                name: Some(name.to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    pub async fn test_deletions() {
+        let deployment = ApiResource::erase::<Deployment>(&());
+        let mut deleting = DynamicObject::new("api", &deployment).within("shop");
+        deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        let kube = Arc::new(FakeKube {
+            pods: vec![
+                Pod {
+                    metadata: meta("web-0", true),
+                    ..Default::default()
+                },
+                Pod {
+                    metadata: meta("web-1", false),
+                    ..Default::default()
+                },
+            ],
+            objects: vec![
+                deleting,
+                DynamicObject::new("web", &deployment).within("shop"),
+            ],
+            ..Default::default()
+        });
+
+        let enricher = Enricher::new(kube.clone(), Duration::from_secs(1)).deletions(true);
+        let being_deleted = |event: &SentryEvent| event.tags.get("being_deleted").cloned();
+        for (api_version, kind, name, expected) in [
+            ("v1", "Pod", "web-0", Some("true".to_string())),
+            ("v1", "Pod", "web-1", None),
+            ("apps/v1", "Deployment", "api", Some("true".to_string())),
+            ("apps/v1", "Deployment", "web", None),
+        ] {
+            let mut event = event(api_version, kind, name);
+            enricher.enrich(&mut event).await;
+            assert_eq!(being_deleted(&event), expected, "{} {}", kind, name);
+        }
+
+        let enricher = Enricher::new(kube, Duration::from_secs(1));
+        let mut event = event("apps/v1", "Deployment", "api");
+        enricher.enrich(&mut event).await;
+        assert_eq!(being_deleted(&event), None, "disabled");
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+/// Resolves the sentry environment of an event.
+///
+/// A namespace explicitly listed in the mapping table always wins; otherwise
+/// the template is rendered replacing the `{{cluster}}` and `{{namespace}}`
+/// placeholders. A template without placeholders behaves as a static environment.
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentResolver {
+    template: Option<String>,
+    namespaces: BTreeMap<String, String>,
+    cluster: String,
+}
+
+impl EnvironmentResolver {
+    pub fn new(template: &str, namespaces: BTreeMap<String, String>, cluster: &str) -> Self {
+        Self {
+            template: if template.is_empty() {
+                None
+            } else {
+                Some(template.to_string())
+            },
+            namespaces,
+            cluster: cluster.to_string(),
+        }
+    }
+
+    pub fn resolve(&self, namespace: &str) -> Option<String> {
+        if let Some(env) = self.namespaces.get(namespace) {
+            return Some(env.clone());
+        }
+
+        self.template.as_ref().map(|t| {
+            t.replace("{{cluster}}", &self.cluster)
+                .replace("{{namespace}}", namespace)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::EnvironmentResolver;
+    use std::collections::BTreeMap;
+
+    #[test]
+    pub fn test_resolve_environment() {
+        let resolver = EnvironmentResolver::new("", Default::default(), "");
+        assert_eq!(resolver.resolve("default"), None);
+
+        let resolver = EnvironmentResolver::new("production", Default::default(), "");
+        assert_eq!(resolver.resolve("default"), Some("production".to_string()));
+
+        let mut namespaces = BTreeMap::new();
+        namespaces.insert("staging".to_string(), "stage".to_string());
+        let resolver = EnvironmentResolver::new("{{cluster}}/{{namespace}}", namespaces, "eu-1");
+        assert_eq!(
+            resolver.resolve("default"),
+            Some("eu-1/default".to_string())
+        );
+        assert_eq!(resolver.resolve("staging"), Some("stage".to_string()));
+    }
+}
@@ -0,0 +1,501 @@
+use futures::prelude::*;
+use futures::stream::BoxStream;
+use k8s_openapi::api::core::v1::{Event, EventSeries};
+use k8s_openapi::api::events::v1 as events;
+use kube::api::{ListParams, VersionMatch, WatchEvent, WatchParams};
+use kube::runtime::watcher::ListSemantic;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// The API the events are read from. The events of events.k8s.io/v1 are converted to core/v1 Events,
+/// so that the rest of the pipeline handles a single type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventsApi {
+    /// core/v1, available on every cluster.
+    Core,
+    /// events.k8s.io/v1, available since Kubernetes 1.19.
+    EventsV1,
+}
+
+impl FromStr for EventsApi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "core" | "v1" | "core/v1" => Ok(Self::Core),
+            "events.k8s.io" | "events.k8s.io/v1" => Ok(Self::EventsV1),
+            _ => Err(format!("unknown events API \"{}\"", s)),
+        }
+    }
+}
+
+impl EventsApi {
+    /// Uses events.k8s.io/v1 if it is served by the API server, otherwise falls back to core/v1.
+    pub async fn detect(client: &Client) -> Self {
+        match client.list_api_group_resources("events.k8s.io/v1").await {
+            Ok(resources) if resources.resources.iter().any(|r| r.name == "events") => {
+                debug!("Using the events.k8s.io/v1 API");
+                Self::EventsV1
+            }
+            _ => {
+                info!("events.k8s.io/v1 is not available, using the core/v1 events");
+                Self::Core
+            }
+        }
+    }
+
+    /// Watches the events of the given namespaces (all of them if empty).
+    /// `seen` is called on each change, bookmark and resumed watch seen by the watches, with `true`
+    /// when the events of a namespace have been (re)listed.
+    pub fn watch(
+        self,
+        client: Client,
+        namespaces: &[String],
+        mut config: watcher::Config,
+        seen: impl Fn(bool) + Clone + Send + Sync + 'static,
+    ) -> BoxStream<'static, Result<Event, watcher::Error>> {
+        config.field_selector = config.field_selector.map(|f| self.field_selector(&f));
+        match self {
+            Self::Core => watch_all::<Event>(client, namespaces, config, seen),
+            Self::EventsV1 => watch_all::<events::Event>(client, namespaces, config, seen)
+                .map_ok(to_core_event)
+                .boxed(),
+        }
+    }
+
+    /// Lists the events of the given namespaces (all of them if empty).
+    pub async fn list(
+        self,
+        client: Client,
+        namespaces: &[String],
+        params: &ListParams,
+    ) -> kube::Result<Vec<Event>> {
+        let mut params = params.clone();
+        params.field_selector = params.field_selector.map(|f| self.field_selector(&f));
+        let mut events = vec![];
+        match self {
+            Self::Core => {
+                for api in apis::<Event>(client, namespaces) {
+                    events.extend(api.list(&params).await?.items);
+                }
+            }
+            Self::EventsV1 => {
+                for api in apis::<events::Event>(client, namespaces) {
+                    events.extend(
+                        api.list(&params)
+                            .await?
+                            .items
+                            .into_iter()
+                            .map(to_core_event),
+                    );
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Translates a field selector written for the core/v1 events to the field names of this API.
+    fn field_selector(self, selector: &str) -> String {
+        if self == Self::Core {
+            return selector.to_string();
+        }
+
+        selector
+            .split(',')
+            .map(|requirement| {
+                let index = requirement.find(['=', '!']).unwrap_or(requirement.len());
+                let (field, rest) = requirement.split_at(index);
+                let field = match field.trim() {
+                    "source" => "deprecatedSource".to_string(),
+                    "reportingComponent" => "reportingController".to_string(),
+                    f => match f.strip_prefix("involvedObject.") {
+                        Some(f) => format!("regarding.{}", f),
+                        None => f.to_string(),
+                    },
+                };
+                field + rest
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The APIs of the events: one per namespace, otherwise a cluster-wide one.
+fn apis<K>(client: Client, namespaces: &[String]) -> Vec<Api<K>>
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+    K::DynamicType: Default,
+{
+    if namespaces.is_empty() {
+        vec![Api::all(client)]
+    } else {
+        namespaces
+            .iter()
+            .map(|ns| Api::namespaced(client.clone(), ns))
+            .collect()
+    }
+}
+
+fn watch_all<K>(
+    client: Client,
+    namespaces: &[String],
+    config: watcher::Config,
+    seen: impl Fn(bool) + Clone + Send + Sync + 'static,
+) -> BoxStream<'static, Result<K, watcher::Error>>
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Debug
+        + Send
+        + 'static,
+    K::DynamicType: Default,
+{
+    stream::select_all(apis::<K>(client, namespaces).into_iter().map(|api| {
+        watch_events(api, config.clone(), seen.clone())
+            .default_backoff()
+            .applied_objects()
+            .boxed()
+    }))
+    .boxed()
+}
+
+enum WatchState<K> {
+    Empty {
+        continue_token: Option<String>,
+        objects: Vec<K>,
+    },
+    Listed {
+        resource_version: String,
+    },
+    Watching {
+        resource_version: String,
+        stream: BoxStream<'static, kube::Result<WatchEvent<K>>>,
+    },
+}
+
+/// Same as the kube watcher, except that the bookmarks and the resumed watches (each WATCH_TIMEOUT)
+/// are reported to `seen`, as the changes are: a quiet watch is not taken for a stalled one.
+fn watch_events<K>(
+    api: Api<K>,
+    config: watcher::Config,
+    seen: impl Fn(bool) + Send + Sync + 'static,
+) -> impl Stream<Item = Result<watcher::Event<K>, watcher::Error>> + Send
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let initial = WatchState::Empty {
+        continue_token: None,
+        objects: vec![],
+    };
+    stream::unfold(
+        (api, config, seen, initial),
+        |(api, config, seen, mut state)| async move {
+            loop {
+                let (event, next) = watch_step(&api, &config, &seen, state).await;
+                state = next;
+                if let Some(event) = event {
+                    return Some((event, (api, config, seen, state)));
+                }
+            }
+        },
+    )
+}
+
+/// Progresses the watch by a step, returning the event to emit, if any, and the next state.
+async fn watch_step<K>(
+    api: &Api<K>,
+    config: &watcher::Config,
+    seen: &(impl Fn(bool) + Send + Sync),
+    state: WatchState<K>,
+) -> (
+    Option<Result<watcher::Event<K>, watcher::Error>>,
+    WatchState<K>,
+)
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let empty = || WatchState::Empty {
+        continue_token: None,
+        objects: vec![],
+    };
+    match state {
+        WatchState::Empty {
+            continue_token,
+            mut objects,
+        } => {
+            let (resource_version, version_match) = match config.list_semantic {
+                ListSemantic::Any => (Some("0".to_string()), Some(VersionMatch::NotOlderThan)),
+                ListSemantic::MostRecent => (None, None),
+            };
+            let params = ListParams {
+                label_selector: config.label_selector.clone(),
+                field_selector: config.field_selector.clone(),
+                timeout: config.timeout,
+                version_match,
+                resource_version,
+                limit: config.page_size,
+                continue_token,
+            };
+            match api.list(&params).await {
+                Ok(list) => {
+                    objects.extend(list.items);
+                    match (
+                        list.metadata.continue_.filter(|t| !t.is_empty()),
+                        list.metadata.resource_version,
+                    ) {
+                        (Some(token), _) => (
+                            None,
+                            WatchState::Empty {
+                                continue_token: Some(token),
+                                objects,
+                            },
+                        ),
+                        (None, Some(resource_version)) => {
+                            seen(true);
+                            (
+                                Some(Ok(watcher::Event::Restarted(objects))),
+                                WatchState::Listed { resource_version },
+                            )
+                        }
+                        (None, None) => (Some(Err(watcher::Error::NoResourceVersion)), empty()),
+                    }
+                }
+                Err(e) => (Some(Err(watcher::Error::InitialListFailed(e))), empty()),
+            }
+        }
+        WatchState::Listed { resource_version } => {
+            let params = WatchParams {
+                label_selector: config.label_selector.clone(),
+                field_selector: config.field_selector.clone(),
+                timeout: config.timeout,
+                bookmarks: config.bookmarks,
+            };
+            match api.watch(&params, &resource_version).await {
+                Ok(stream) => {
+                    seen(false);
+                    (
+                        None,
+                        WatchState::Watching {
+                            resource_version,
+                            stream: stream.boxed(),
+                        },
+                    )
+                }
+                Err(e) => (
+                    Some(Err(watcher::Error::WatchStartFailed(e))),
+                    WatchState::Listed { resource_version },
+                ),
+            }
+        }
+        WatchState::Watching {
+            resource_version,
+            mut stream,
+        } => match stream.next().await {
+            Some(Ok(WatchEvent::Added(object) | WatchEvent::Modified(object))) => {
+                seen(false);
+                let resource_version = object.resource_version().unwrap_or(resource_version);
+                (
+                    Some(Ok(watcher::Event::Applied(object))),
+                    WatchState::Watching {
+                        resource_version,
+                        stream,
+                    },
+                )
+            }
+            Some(Ok(WatchEvent::Deleted(object))) => {
+                seen(false);
+                let resource_version = object.resource_version().unwrap_or(resource_version);
+                (
+                    Some(Ok(watcher::Event::Deleted(object))),
+                    WatchState::Watching {
+                        resource_version,
+                        stream,
+                    },
+                )
+            }
+            Some(Ok(WatchEvent::Bookmark(bookmark))) => {
+                seen(false);
+                (
+                    None,
+                    WatchState::Watching {
+                        resource_version: bookmark.metadata.resource_version,
+                        stream,
+                    },
+                )
+            }
+            // 410 Gone: the resource version is too old, the events are relisted.
+            Some(Ok(WatchEvent::Error(e))) => {
+                let next = if e.code == 410 {
+                    empty()
+                } else {
+                    WatchState::Watching {
+                        resource_version,
+                        stream,
+                    }
+                };
+                (Some(Err(watcher::Error::WatchError(e))), next)
+            }
+            Some(Err(e)) => (
+                Some(Err(watcher::Error::WatchFailed(e))),
+                WatchState::Watching {
+                    resource_version,
+                    stream,
+                },
+            ),
+            // The watch request timed out: it is resumed from the last seen resource version.
+            None => (None, WatchState::Listed { resource_version }),
+        },
+    }
+}
+
+/// Converts an events.k8s.io/v1 Event to a core/v1 one: `note` is the message, `regarding` the
+/// involved object, `reportingController` the reporting component, and the count comes from `series`.
+pub fn to_core_event(event: events::Event) -> Event {
+    let count = event
+        .series
+        .as_ref()
+        .map(|s| s.count)
+        .or(event.deprecated_count);
+    Event {
+        action: event.action,
+        count,
+        event_time: event.event_time,
+        first_timestamp: event.deprecated_first_timestamp,
+        involved_object: event.regarding.unwrap_or_default(),
+        last_timestamp: event.deprecated_last_timestamp,
+        message: event.note,
+        metadata: event.metadata,
+        reason: event.reason,
+        related: event.related,
+        reporting_component: event.reporting_controller,
+        reporting_instance: event.reporting_instance,
+        series: event.series.map(|s| EventSeries {
+            count: Some(s.count),
+            last_observed_time: Some(s.last_observed_time),
+        }),
+        source: event.deprecated_source,
+        type_: event.type_,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events_api::{to_core_event, watch_events, EventsApi};
+    use futures::StreamExt;
+    use hyper::{Body, Request, Response};
+    use k8s_openapi::api::core::v1::ObjectReference;
+    use k8s_openapi::api::events::v1::{Event, EventSeries};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+    use k8s_openapi::chrono::{DateTime, Utc};
+    use kube::runtime::watcher;
+    use kube::{Api, Client};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    pub fn test_to_core_event() {
+        let time: DateTime<Utc> = DateTime::parse_from_rfc3339("2023-04-08T22:28:00Z")
+            .unwrap()
+            .into();
+        let event = to_core_event(Event {
+            note: Some("Back-off restarting failed container".to_string()),
+            reason: Some("BackOff".to_string()),
+            regarding: Some(ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                ..Default::default()
+            }),
+            reporting_controller: Some("kubelet".to_string()),
+            series: Some(EventSeries {
+                count: 7,
+                last_observed_time: MicroTime(time),
+            }),
+            deprecated_count: Some(1),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            event.message.as_deref(),
+            Some("Back-off restarting failed container")
+        );
+        assert_eq!(event.involved_object.name.as_deref(), Some("web-0"));
+        assert_eq!(event.reporting_component.as_deref(), Some("kubelet"));
+        assert_eq!(event.count, Some(7));
+        assert_eq!(
+            event.series.unwrap().last_observed_time,
+            Some(MicroTime(time))
+        );
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+
+        let event = to_core_event(Event {
+            deprecated_count: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(event.count, Some(2));
+        assert!(event.series.is_none());
+    }
+
+    #[test]
+    pub fn test_field_selector() {
+        let selector = "type=Warning,involvedObject.kind!=Pod,source=kubelet,reportingComponent==x";
+        assert_eq!(EventsApi::Core.field_selector(selector), selector);
+        assert_eq!(
+            EventsApi::EventsV1.field_selector(selector),
+            "type=Warning,regarding.kind!=Pod,deprecatedSource=kubelet,reportingController==x"
+        );
+        assert_eq!(EventsApi::EventsV1.field_selector(""), "");
+    }
+
+    #[test]
+    pub fn test_from_str() {
+        assert_eq!("core".parse(), Ok(EventsApi::Core));
+        assert_eq!("events.k8s.io/v1".parse(), Ok(EventsApi::EventsV1));
+        assert!("auto".parse::<EventsApi>().is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_watch_activity() {
+        // The first watch only sees a bookmark, the resumed one sees an event.
+        let requests: Arc<Mutex<Vec<String>>> = Default::default();
+        let log = requests.clone();
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let query = request.uri().query().unwrap_or_default().to_string();
+            let mut log = log.lock().unwrap();
+            let body = if !query.contains("watch=true") {
+                r#"{"metadata":{"resourceVersion":"1"},"items":[]}"#
+            } else if log.iter().any(|q| q.contains("watch=true")) {
+                r#"{"type":"ADDED","object":{"metadata":{"name":"web-0.1","resourceVersion":"12"},"involvedObject":{}}}"#
+            } else {
+                r#"{"type":"BOOKMARK","object":{"kind":"Event","apiVersion":"v1","metadata":{"resourceVersion":"10"}}}"#
+            };
+            log.push(query);
+            async move { Ok::<_, Infallible>(Response::new(Body::from(body))) }
+        });
+        let api = Api::<k8s_openapi::api::core::v1::Event>::all(Client::new(service, "default"));
+
+        let seen: Arc<Mutex<Vec<bool>>> = Default::default();
+        let activity = seen.clone();
+        let events = watch_events(api, watcher::Config::default(), move |listed| {
+            activity.lock().unwrap().push(listed)
+        })
+        .take(2)
+        .collect::<Vec<_>>()
+        .await;
+
+        assert!(matches!(events[0], Ok(watcher::Event::Restarted(_))));
+        assert!(matches!(events[1], Ok(watcher::Event::Applied(_))));
+        // Listed, watching, bookmark, watch resumed, event.
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![true, false, false, false, false]
+        );
+        assert!(requests.lock().unwrap()[2].contains("resourceVersion=10"));
+    }
+}
@@ -0,0 +1,419 @@
+use crate::processor::Verdict;
+use crate::sentry_event::SentryEvent;
+use regex::Regex;
+use sentry::Level;
+use serde_json::{json, Map, Value};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// A stage of the filter chain of the processor.
+/// Returns `Verdict::Send` to pass the event on to the next filters: a `Breadcrumb` or a `Discard`
+/// verdict stops the chain.
+pub trait Filter: Send + Sync {
+    fn filter(&self, event: &SentryEvent) -> Verdict;
+
+    /// Adds the settings of the filter to the effective filters shown by the admin API.
+    fn describe(&self, _settings: &mut Map<String, Value>) {}
+}
+
+impl<F: Fn(&SentryEvent) -> Verdict + Send + Sync> Filter for F {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        self(event)
+    }
+}
+
+/// The filters run in order on each event, until one of them does not send it.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl From<Vec<Box<dyn Filter>>> for FilterChain {
+    fn from(filters: Vec<Box<dyn Filter>>) -> Self {
+        Self { filters }
+    }
+}
+
+impl FilterChain {
+    /// Appends the filter to the chain.
+    pub fn register(&mut self, filter: impl Filter + 'static) -> &mut Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn filter(&self, event: &SentryEvent) -> Verdict {
+        self.filters
+            .iter()
+            .map(|filter| filter.filter(event))
+            .find(|verdict| *verdict != Verdict::Send)
+            .unwrap_or(Verdict::Send)
+    }
+
+    /// The settings of the filters of the chain.
+    pub fn describe(&self) -> Map<String, Value> {
+        let mut settings = Map::new();
+        for filter in &self.filters {
+            filter.describe(&mut settings);
+        }
+        settings
+    }
+}
+
+/// Discards the events older than the maximum age (ex: listed again after a reconnection).
+pub struct AgeFilter {
+    pub max_age: Option<Duration>,
+}
+
+impl Filter for AgeFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        match (self.max_age, event.event_time) {
+            (Some(max_age), Some(time)) if time.elapsed().is_ok_and(|age| age > max_age) => {
+                Verdict::Discard("age")
+            }
+            _ => Verdict::Send,
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        let max_age = self.max_age.map(|age| age.as_secs());
+        settings.insert("max_event_age".to_string(), json!(max_age));
+    }
+}
+
+/// Discards the events last occurred before a time (ex: the startup, to ignore the existing events).
+pub struct ExistingFilter {
+    pub before: Option<SystemTime>,
+}
+
+impl Filter for ExistingFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        match (self.before, event.event_time) {
+            (Some(before), Some(time)) if time < before => Verdict::Discard("existing"),
+            _ => Verdict::Send,
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        settings.insert(
+            "ignore_existing_events".to_string(),
+            json!(self.before.is_some()),
+        );
+    }
+}
+
+/// Discards the events reported by the excluded components (ex: kubelet).
+pub struct ComponentFilter {
+    pub excluded: Vec<String>,
+}
+
+impl Filter for ComponentFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        if self.excluded.contains(&event.component) {
+            Verdict::Discard("component")
+        } else {
+            Verdict::Send
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        settings.insert("excluded_components".to_string(), json!(self.excluded));
+    }
+}
+
+/// Discards the events with the excluded reasons (ex: FailedMount).
+pub struct ReasonFilter {
+    pub excluded: Vec<String>,
+}
+
+impl Filter for ReasonFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        if self.excluded.contains(&event.reason) {
+            Verdict::Discard("reason")
+        } else {
+            Verdict::Send
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        settings.insert("excluded_reasons".to_string(), json!(self.excluded));
+    }
+}
+
+/// A pattern matched against the message of the events: a regex between slashes
+/// (ex: `/webhook .* timed out/`), or a substring.
+#[derive(Clone, Debug)]
+pub enum MessagePattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl MessagePattern {
+    pub fn is_match(&self, message: &str) -> bool {
+        match self {
+            Self::Substring(substring) => message.contains(substring.as_str()),
+            Self::Regex(regex) => regex.is_match(message),
+        }
+    }
+}
+
+impl FromStr for MessagePattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(regex) if !regex.is_empty() => Ok(Self::Regex(Regex::new(regex)?)),
+            _ => Ok(Self::Substring(s.to_string())),
+        }
+    }
+}
+
+impl Display for MessagePattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Substring(substring) => write!(f, "{}", substring),
+            Self::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+/// Discards the events whose message matches any of the excluded patterns (ex: the flaky webhook
+/// errors of the FailedCreate events), without excluding their reason.
+pub struct MessageFilter {
+    pub excluded: Vec<MessagePattern>,
+}
+
+impl Filter for MessageFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        let message = event.message.as_deref().unwrap_or_default();
+        if self
+            .excluded
+            .iter()
+            .any(|pattern| pattern.is_match(message))
+        {
+            Verdict::Discard("message")
+        } else {
+            Verdict::Send
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        let excluded = self.excluded.iter().map(ToString::to_string);
+        settings.insert(
+            "excluded_messages".to_string(),
+            json!(excluded.collect::<Vec<_>>()),
+        );
+    }
+}
+
+/// Discards the events of the excluded namespaces and, if any namespace is included, the events
+/// of the other namespaces.
+pub struct NamespaceFilter {
+    pub included: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl Filter for NamespaceFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        if self.excluded.contains(&event.namespace)
+            || (!self.included.is_empty() && !self.included.contains(&event.namespace))
+        {
+            Verdict::Discard("namespace")
+        } else {
+            Verdict::Send
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        settings.insert("namespaces".to_string(), json!(self.included));
+        settings.insert("excluded_namespaces".to_string(), json!(self.excluded));
+    }
+}
+
+/// Only sends the events of the given levels (the errors are always sent): the others are
+/// recorded as breadcrumbs.
+pub struct LevelFilter {
+    pub levels: Vec<String>,
+}
+
+impl Filter for LevelFilter {
+    fn filter(&self, event: &SentryEvent) -> Verdict {
+        if event.level == Level::Error || self.levels.iter().any(|l| *l == event.level.to_string())
+        {
+            Verdict::Send
+        } else {
+            Verdict::Breadcrumb
+        }
+    }
+
+    fn describe(&self, settings: &mut Map<String, Value>) {
+        settings.insert("levels".to_string(), json!(self.levels));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{
+        AgeFilter, ComponentFilter, ExistingFilter, Filter, FilterChain, LevelFilter,
+        MessageFilter, MessagePattern, NamespaceFilter, ReasonFilter,
+    };
+    use crate::processor::Verdict;
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+    use sentry::Level;
+    use serde_json::json;
+    use std::time::{Duration, SystemTime};
+
+    fn event() -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            source: Some(EventSource {
+                component: Some("kubelet".to_string()),
+                host: None,
+            }),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_age_filter() {
+        let filter = AgeFilter {
+            max_age: Some(Duration::from_secs(1800)),
+        };
+        let mut event = event();
+        assert_eq!(filter.filter(&event), Verdict::Send, "no event time");
+
+        event.event_time = Some(SystemTime::now() - Duration::from_secs(3600));
+        assert_eq!(filter.filter(&event), Verdict::Discard("age"));
+        event.event_time = Some(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(filter.filter(&event), Verdict::Send);
+    }
+
+    #[test]
+    pub fn test_existing_filter() {
+        let filter = ExistingFilter {
+            before: Some(SystemTime::now()),
+        };
+        let mut event = event();
+        event.event_time = Some(SystemTime::now() - Duration::from_secs(1));
+        assert_eq!(filter.filter(&event), Verdict::Discard("existing"));
+        event.event_time = Some(SystemTime::now() + Duration::from_secs(1));
+        assert_eq!(filter.filter(&event), Verdict::Send);
+    }
+
+    #[test]
+    pub fn test_component_and_reason_filters() {
+        let components = ComponentFilter {
+            excluded: vec!["kubelet".to_string()],
+        };
+        let reasons = ReasonFilter {
+            excluded: vec!["FailedMount".to_string()],
+        };
+        let mut event = event();
+        assert_eq!(components.filter(&event), Verdict::Discard("component"));
+        assert_eq!(reasons.filter(&event), Verdict::Send);
+
+        event.component = "scheduler".to_string();
+        event.reason = "FailedMount".to_string();
+        assert_eq!(components.filter(&event), Verdict::Send);
+        assert_eq!(reasons.filter(&event), Verdict::Discard("reason"));
+    }
+
+    #[test]
+    pub fn test_message_filter() {
+        let filter = MessageFilter {
+            excluded: vec![
+                "context deadline exceeded".parse().unwrap(),
+                "/webhook \"[a-z.-]+\" .* timed out/".parse().unwrap(),
+            ],
+        };
+        let mut event = event();
+        event.message = Some("Error creating: Internal error occurred: failed calling webhook \"validate.kyverno.svc\": Post \"https://kyverno-svc/validate\": context deadline exceeded".to_string());
+        assert_eq!(filter.filter(&event), Verdict::Discard("message"));
+        event.message =
+            Some("Error creating: webhook \"policy.example.com\" call timed out".to_string());
+        assert_eq!(filter.filter(&event), Verdict::Discard("message"));
+        event.message =
+            Some("Error creating: pods \"web-0\" is forbidden: exceeded quota".to_string());
+        assert_eq!(filter.filter(&event), Verdict::Send);
+
+        assert!(matches!(
+            "/".parse::<MessagePattern>().unwrap(),
+            MessagePattern::Substring(_)
+        ));
+        assert!("/(unclosed/".parse::<MessagePattern>().is_err());
+        assert_eq!(
+            filter.excluded[1].to_string(),
+            "/webhook \"[a-z.-]+\" .* timed out/"
+        );
+    }
+
+    #[test]
+    pub fn test_namespace_filter() {
+        let excluded = NamespaceFilter {
+            included: vec![],
+            excluded: vec!["shop".to_string()],
+        };
+        let included = NamespaceFilter {
+            included: vec!["billing".to_string()],
+            excluded: vec![],
+        };
+        let mut event = event();
+        assert_eq!(excluded.filter(&event), Verdict::Discard("namespace"));
+        assert_eq!(included.filter(&event), Verdict::Discard("namespace"));
+
+        event.namespace = "billing".to_string();
+        assert_eq!(excluded.filter(&event), Verdict::Send);
+        assert_eq!(included.filter(&event), Verdict::Send);
+    }
+
+    #[test]
+    pub fn test_level_filter() {
+        let filter = LevelFilter {
+            levels: vec!["warning".to_string()],
+        };
+        let mut event = event();
+        assert_eq!(filter.filter(&event), Verdict::Send);
+
+        event.level = Level::Info;
+        assert_eq!(filter.filter(&event), Verdict::Breadcrumb);
+        event.level = Level::Error;
+        assert_eq!(
+            filter.filter(&event),
+            Verdict::Send,
+            "errors are always sent"
+        );
+    }
+
+    #[test]
+    pub fn test_chain() {
+        let mut chain = FilterChain::default();
+        assert_eq!(chain.filter(&event()), Verdict::Send);
+
+        chain
+            .register(LevelFilter {
+                levels: vec!["warning".to_string()],
+            })
+            .register(|event: &SentryEvent| match event.name.starts_with("web-") {
+                true => Verdict::Discard("web"),
+                false => Verdict::Send,
+            });
+        assert_eq!(chain.filter(&event()), Verdict::Discard("web"));
+
+        let mut info = event();
+        info.level = Level::Info;
+        assert_eq!(chain.filter(&info), Verdict::Breadcrumb, "stops the chain");
+
+        assert_eq!(
+            serde_json::Value::from(chain.describe()),
+            json!({ "levels": ["warning"] })
+        );
+    }
+}
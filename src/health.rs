@@ -0,0 +1,158 @@
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    pub static ref HEALTH: Health = Health::default();
+}
+
+/// The state of the controller, exposed on the /healthz and /readyz endpoints.
+/// A replica is ready once the sentry client is initialized and the events of all its clusters
+/// have been listed, or while it is waiting for the leadership (a standby replica is healthy).
+/// It is not alive anymore if the watch of a cluster has seen nothing for longer than the stall
+/// timeout: a hung watch is otherwise indistinguishable from a quiet cluster.
+#[derive(Default)]
+pub struct Health {
+    standby: AtomicBool,
+    sentry_initialized: AtomicBool,
+    stall_timeout: Mutex<Option<Duration>>,
+    /// The watch of each cluster.
+    watches: Mutex<BTreeMap<String, Watch>>,
+}
+
+struct Watch {
+    /// Whether the events have been listed.
+    established: bool,
+    last_activity: Instant,
+    stalled: bool,
+}
+
+impl Watch {
+    fn new() -> Self {
+        Self {
+            established: false,
+            last_activity: Instant::now(),
+            stalled: false,
+        }
+    }
+}
+
+impl Health {
+    pub fn standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Relaxed);
+    }
+
+    pub fn sentry_initialized(&self) {
+        self.sentry_initialized.store(true, Ordering::Relaxed);
+    }
+
+    /// The watches are never considered stalled if no timeout is given.
+    pub fn stall_timeout(&self, timeout: Option<Duration>) {
+        *self.stall_timeout.lock().unwrap() = timeout;
+    }
+
+    pub fn watch_started(&self, cluster: &str) {
+        let mut watches = self.watches.lock().unwrap();
+        watches
+            .entry(cluster.to_string())
+            .or_insert_with(Watch::new);
+    }
+
+    /// Records a change seen by the watch of the cluster, `listed` if the events have been
+    /// (re)listed.
+    pub fn watch_activity(&self, cluster: &str, listed: bool) {
+        let mut watches = self.watches.lock().unwrap();
+        let watch = watches
+            .entry(cluster.to_string())
+            .or_insert_with(Watch::new);
+        watch.established |= listed;
+        watch.last_activity = Instant::now();
+        if watch.stalled {
+            watch.stalled = false;
+            info!("The kubernetes watch of cluster {} has resumed", cluster);
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        if self.standby.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let watches = self.watches.lock().unwrap();
+        self.sentry_initialized.load(Ordering::Relaxed)
+            && !watches.is_empty()
+            && watches.values().all(|w| w.established)
+    }
+
+    /// Whether no watch is stalled. An error is logged when a watch stalls.
+    pub fn is_live(&self) -> bool {
+        let Some(timeout) = *self.stall_timeout.lock().unwrap() else {
+            return true;
+        };
+
+        let mut live = true;
+        for (cluster, watch) in self.watches.lock().unwrap().iter_mut() {
+            let silence = watch.last_activity.elapsed();
+            if silence <= timeout {
+                continue;
+            }
+
+            live = false;
+            if !watch.stalled {
+                watch.stalled = true;
+                error!(
+                    "The kubernetes watch of cluster {} has seen nothing for {}s, it is likely stalled",
+                    cluster,
+                    silence.as_secs()
+                );
+            }
+        }
+
+        live
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::Health;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_is_ready() {
+        let health = Health::default();
+        health.standby(true);
+        assert!(health.is_ready());
+
+        health.standby(false);
+        health.sentry_initialized();
+        assert!(!health.is_ready());
+
+        health.watch_started("eu");
+        health.watch_started("us");
+        health.watch_activity("eu", true);
+        assert!(!health.is_ready());
+
+        health.watch_activity("us", false);
+        assert!(!health.is_ready());
+        health.watch_activity("us", true);
+        assert!(health.is_ready());
+    }
+
+    #[test]
+    pub fn test_is_live() {
+        let health = Health::default();
+        health.watch_started("eu");
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(health.is_live());
+
+        health.stall_timeout(Some(Duration::from_millis(10)));
+        assert!(!health.is_live());
+        assert!(health.watches.lock().unwrap()["eu"].stalled);
+
+        health.watch_activity("eu", false);
+        assert!(health.is_live());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::metrics::METRICS;
+use log::debug;
+use sentry::protocol::{
+    MonitorCheckIn, MonitorCheckInStatus, MonitorConfig, MonitorIntervalUnit, MonitorSchedule,
+};
+use sentry::types::Uuid;
+use sentry::Hub;
+use std::time::{Duration, Instant};
+
+/// Minutes after the expected check-in before the heartbeat is considered missed.
+const CHECK_IN_MARGIN: u64 = 1;
+
+/// Periodically checks in to a sentry cron monitor, proving that the watcher is alive: sentry
+/// alerts when the check-ins stop. If no event has been received for longer than the maximum
+/// silence, the check-in is an error: the watch is likely stalled.
+pub struct Heartbeat {
+    slug: String,
+    interval: Duration,
+    max_silence: Option<Duration>,
+    started: Instant,
+}
+
+impl Heartbeat {
+    /// The silence is not checked if no maximum is given.
+    pub fn new(slug: &str, interval: Duration, max_silence: Option<Duration>) -> Self {
+        Self {
+            slug: slug.to_string(),
+            interval,
+            max_silence,
+            started: Instant::now(),
+        }
+    }
+
+    /// Checks in every interval, until aborted.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            let Some(client) = Hub::main().client() else {
+                continue;
+            };
+
+            let check_in = self.check_in(
+                METRICS.last_event_age(),
+                client.options().environment.as_deref(),
+            );
+            debug!(
+                target: "sentry_kubernetes::heartbeat",
+                "Heartbeat check-in: {:?}", check_in.status
+            );
+            client.send_envelope(check_in.into());
+        }
+    }
+
+    /// The check-in, given the time elapsed since the last event (if any).
+    fn check_in(
+        &self,
+        last_event_age: Option<Duration>,
+        environment: Option<&str>,
+    ) -> MonitorCheckIn {
+        // Before the first event, the silence is counted from the startup.
+        let silence = last_event_age.unwrap_or_else(|| self.started.elapsed());
+        let status = match self.max_silence {
+            Some(max_silence) if silence > max_silence => MonitorCheckInStatus::Error,
+            _ => MonitorCheckInStatus::Ok,
+        };
+
+        MonitorCheckIn {
+            check_in_id: Uuid::new_v4(),
+            monitor_slug: self.slug.clone(),
+            status,
+            environment: environment.map(str::to_string),
+            duration: None,
+            monitor_config: Some(MonitorConfig {
+                schedule: MonitorSchedule::Interval {
+                    value: ((self.interval.as_secs_f64() / 60.0).ceil() as u64).max(1),
+                    unit: MonitorIntervalUnit::Minute,
+                },
+                checkin_margin: Some(CHECK_IN_MARGIN),
+                max_runtime: None,
+                timezone: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heartbeat::Heartbeat;
+    use sentry::protocol::{MonitorCheckInStatus, MonitorSchedule};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_check_in() {
+        let heartbeat = Heartbeat::new(
+            "sentry-kubernetes",
+            Duration::from_secs(90),
+            Some(Duration::from_secs(600)),
+        );
+        let check_in = heartbeat.check_in(Some(Duration::from_secs(30)), Some("production"));
+        assert_eq!(check_in.monitor_slug, "sentry-kubernetes");
+        assert_eq!(check_in.status, MonitorCheckInStatus::Ok);
+        assert_eq!(check_in.environment.as_deref(), Some("production"));
+        assert!(matches!(
+            check_in.monitor_config.unwrap().schedule,
+            MonitorSchedule::Interval { value: 2, .. }
+        ));
+
+        let check_in = heartbeat.check_in(Some(Duration::from_secs(900)), None);
+        assert_eq!(check_in.status, MonitorCheckInStatus::Error);
+        assert_eq!(
+            heartbeat.check_in(None, None).status,
+            MonitorCheckInStatus::Ok
+        );
+
+        let heartbeat = Heartbeat::new("sentry-kubernetes", Duration::from_secs(60), None);
+        let check_in = heartbeat.check_in(Some(Duration::from_secs(900)), None);
+        assert_eq!(check_in.status, MonitorCheckInStatus::Ok);
+    }
+}
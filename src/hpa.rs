@@ -0,0 +1,104 @@
+use crate::sentry_event::SentryEvent;
+use kube::api::DynamicObject;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The API version the HorizontalPodAutoscalers are read with.
+pub const HPA_API_VERSION: &str = "autoscaling/v2";
+
+/// The reasons of the events of the HorizontalPodAutoscalers unable to read their metrics or scale.
+const FAILURE_REASONS: [&str; 8] = [
+    "FailedGetResourceMetric",
+    "FailedGetContainerResourceMetric",
+    "FailedGetPodsMetric",
+    "FailedGetObjectMetric",
+    "FailedGetExternalMetric",
+    "FailedComputeMetricsReplicas",
+    "FailedGetScale",
+    "FailedRescale",
+];
+
+/// Whether the event is a failure of a HorizontalPodAutoscaler (ex: the metrics server is down).
+pub fn is_hpa_failure(event: &SentryEvent) -> bool {
+    event.kind.as_deref() == Some("HorizontalPodAutoscaler")
+        && FAILURE_REASONS.contains(&event.reason.as_str())
+}
+
+/// Adds the target metrics, the current and desired replicas and the scale target of the
+/// HorizontalPodAutoscaler in the "horizontal pod autoscaler" context.
+pub fn enrich(event: &mut SentryEvent, hpa: &DynamicObject) {
+    let spec = &hpa.data["spec"];
+    let status = &hpa.data["status"];
+    let target = &spec["scaleTargetRef"];
+    if let (Some(kind), Some(name)) = (target["kind"].as_str(), target["name"].as_str()) {
+        event
+            .tags
+            .insert("scale_target".to_string(), format!("{}/{}", kind, name));
+    }
+
+    let context = [
+        ("scaleTargetRef", target),
+        ("minReplicas", &spec["minReplicas"]),
+        ("maxReplicas", &spec["maxReplicas"]),
+        ("metrics", &spec["metrics"]),
+        ("currentReplicas", &status["currentReplicas"]),
+        ("desiredReplicas", &status["desiredReplicas"]),
+        ("currentMetrics", &status["currentMetrics"]),
+        ("conditions", &status["conditions"]),
+        ("lastScaleTime", &status["lastScaleTime"]),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.clone()))
+    .collect::<BTreeMap<String, Value>>();
+    event
+        .contexts
+        .insert("horizontal pod autoscaler".to_string(), context);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hpa::{enrich, is_hpa_failure};
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use kube::api::{ApiResource, DynamicObject};
+    use kube::core::GroupVersionKind;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_enrich() {
+        let mut event = SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("HorizontalPodAutoscaler".to_string()),
+                name: Some("web".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some("FailedGetResourceMetric".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        });
+        assert!(is_hpa_failure(&event));
+
+        let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(
+            "autoscaling",
+            "v2",
+            "HorizontalPodAutoscaler",
+        ));
+        let hpa = DynamicObject::new("web", &resource).data(json!({
+            "spec": {
+                "scaleTargetRef": { "apiVersion": "apps/v1", "kind": "Deployment", "name": "web" },
+                "minReplicas": 2,
+                "maxReplicas": 10,
+                "metrics": [{ "type": "Resource", "resource": { "name": "cpu" } }],
+            },
+            "status": { "currentReplicas": 2, "desiredReplicas": 2 },
+        }));
+        enrich(&mut event, &hpa);
+
+        assert_eq!(event.tags["scale_target"], "Deployment/web");
+        let context = &event.contexts["horizontal pod autoscaler"];
+        assert_eq!(context["maxReplicas"], 10);
+        assert_eq!(context["currentReplicas"], 2);
+        assert_eq!(context["metrics"][0]["resource"]["name"], "cpu");
+    }
+}
@@ -0,0 +1,282 @@
+use crate::cache::TtlCache;
+use crate::objects::owner;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::batch::v1::{Job, JobCondition};
+use k8s_openapi::api::core::v1::{ContainerStateTerminated, Event, ObjectReference, Pod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use serde_json::{json, to_value, Value};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Number of failed Jobs remembered, not to report them again.
+const REPORTED_CACHE_SIZE: usize = 10_000;
+const REPORTED_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Reports the Jobs which have failed (backoff limit or deadline exceeded), with their CronJob and
+/// the exit information of the last failed container.
+pub struct JobFailureWatcher {
+    client: Client,
+    /// Uid of the reported Jobs.
+    reported: Mutex<TtlCache<String, ()>>,
+}
+
+impl JobFailureWatcher {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            reported: Mutex::new(TtlCache::new(REPORTED_CACHE_SIZE, REPORTED_TTL)),
+        }
+    }
+
+    /// The failure event of the Job, if it has failed and has not been reported yet.
+    pub async fn events(&self, job: Job) -> Vec<SentryEvent> {
+        let (Some(uid), Some(condition)) = (job.metadata.uid.clone(), failed_condition(&job))
+        else {
+            return vec![];
+        };
+        {
+            let mut reported = self.reported.lock().unwrap();
+            if reported.get(&uid).is_some() {
+                return vec![];
+            }
+
+            reported.insert(uid.clone(), ());
+        }
+
+        let last_exit = self.last_exit(&job, &uid).await;
+        vec![failure_event(&job, condition, last_exit)]
+    }
+
+    /// The pod and the termination of the last failed container of the Job.
+    async fn last_exit(&self, job: &Job, uid: &str) -> Option<(Pod, ContainerStateTerminated)> {
+        let namespace = job.metadata.namespace.as_deref()?;
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
+        let params = ListParams::default().labels(&format!("controller-uid={}", uid));
+        let pods = match api.list(&params).await {
+            Ok(pods) => pods.items,
+            Err(e) => {
+                warn!("Cannot list the pods of the job {}: {}", uid, e);
+                return None;
+            }
+        };
+
+        pods.into_iter()
+            .filter_map(|pod| {
+                let terminated = failed_termination(&pod)?;
+                Some((pod, terminated))
+            })
+            .max_by_key(|(_, terminated)| terminated.finished_at.clone())
+    }
+}
+
+/// The Failed condition of the Job, if set.
+fn failed_condition(job: &Job) -> Option<&JobCondition> {
+    job.status
+        .as_ref()?
+        .conditions
+        .as_ref()?
+        .iter()
+        .find(|c| c.type_ == "Failed" && c.status == "True")
+}
+
+/// The last termination with a non-zero exit code of the containers of the pod.
+fn failed_termination(pod: &Pod) -> Option<ContainerStateTerminated> {
+    let status = pod.status.as_ref()?;
+    status
+        .init_container_statuses
+        .iter()
+        .chain(status.container_statuses.iter())
+        .flatten()
+        .flat_map(|s| {
+            let state = s.state.as_ref().and_then(|s| s.terminated.as_ref());
+            let last_state = s.last_state.as_ref().and_then(|s| s.terminated.as_ref());
+            state.into_iter().chain(last_state)
+        })
+        .filter(|t| t.exit_code != 0)
+        .max_by_key(|t| t.finished_at.clone())
+        .cloned()
+}
+
+/// A warning event of the Job, with the "job" and "last exit" contexts.
+/// The CronJob is reported as the workload of the event.
+fn failure_event(
+    job: &Job,
+    condition: &JobCondition,
+    last_exit: Option<(Pod, ContainerStateTerminated)>,
+) -> SentryEvent {
+    let name = job.metadata.name.clone().unwrap_or_default();
+    let time = condition
+        .last_transition_time
+        .clone()
+        .unwrap_or_else(|| Time(Utc::now()));
+    let message = condition
+        .message
+        .clone()
+        .unwrap_or_else(|| format!("Job {} has failed", name));
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: job.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(time.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("batch/v1".to_string()),
+            kind: Some("Job".to_string()),
+            name: Some(name),
+            namespace: job.metadata.namespace.clone(),
+            uid: job.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(
+            condition
+                .reason
+                .clone()
+                .unwrap_or_else(|| "JobFailed".to_string()),
+        ),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(time),
+        ..Default::default()
+    };
+
+    let spec = job.spec.as_ref();
+    let status = job.status.as_ref();
+    let cron_job = owner(&job.metadata, "CronJob");
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.contexts.insert(
+        "job".to_string(),
+        BTreeMap::from([
+            ("cronJob".to_string(), json!(cron_job)),
+            ("reason".to_string(), json!(condition.reason)),
+            ("message".to_string(), json!(condition.message)),
+            (
+                "backoffLimit".to_string(),
+                json!(spec.and_then(|s| s.backoff_limit)),
+            ),
+            (
+                "activeDeadlineSeconds".to_string(),
+                json!(spec.and_then(|s| s.active_deadline_seconds)),
+            ),
+            ("failed".to_string(), json!(status.and_then(|s| s.failed))),
+            (
+                "succeeded".to_string(),
+                json!(status.and_then(|s| s.succeeded)),
+            ),
+            (
+                "startTime".to_string(),
+                json!(status.and_then(|s| s.start_time.clone())),
+            ),
+        ]),
+    );
+    if let Some((pod, terminated)) = last_exit {
+        let mut context = match to_value(terminated) {
+            Ok(Value::Object(terminated)) => terminated.into_iter().collect::<BTreeMap<_, _>>(),
+            _ => BTreeMap::new(),
+        };
+        context.insert("pod".to_string(), json!(pod.metadata.name));
+        sentry_event.source_host = pod.spec.and_then(|s| s.node_name);
+        sentry_event
+            .contexts
+            .insert("last exit".to_string(), context);
+    }
+    sentry_event.workload = cron_job;
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::job_failures::{failed_condition, failed_termination, failure_event};
+    use k8s_openapi::api::batch::v1::{Job, JobCondition, JobStatus};
+    use k8s_openapi::api::core::v1::{
+        ContainerState, ContainerStateTerminated, ContainerStatus, Pod, PodStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+
+    fn job(conditions: Vec<JobCondition>) -> Job {
+        Job {
+            metadata: ObjectMeta {
+                name: Some("backup-28012345".to_string()),
+                namespace: Some("ops".to_string()),
+                uid: Some("5f0c1b8e-0f5e-4a47-9d55-2b0f6c1d7a11".to_string()),
+                owner_references: Some(vec![OwnerReference {
+                    kind: "CronJob".to_string(),
+                    name: "backup".to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            status: Some(JobStatus {
+                conditions: Some(conditions),
+                failed: Some(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_failure_event() {
+        assert!(failed_condition(&job(vec![])).is_none());
+        let job = job(vec![JobCondition {
+            type_: "Failed".to_string(),
+            status: "True".to_string(),
+            reason: Some("BackoffLimitExceeded".to_string()),
+            message: Some("Job has reached the specified backoff limit".to_string()),
+            ..Default::default()
+        }]);
+        let condition = failed_condition(&job).unwrap();
+
+        let terminated = |exit_code: i32| ContainerStateTerminated {
+            exit_code,
+            reason: Some("Error".to_string()),
+            ..Default::default()
+        };
+        let pod = Pod {
+            metadata: ObjectMeta {
+                name: Some("backup-28012345-x2x4z".to_string()),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                container_statuses: Some(vec![
+                    ContainerStatus {
+                        state: Some(ContainerState {
+                            terminated: Some(terminated(0)),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                    ContainerStatus {
+                        state: Some(ContainerState {
+                            terminated: Some(terminated(2)),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let last_exit = failed_termination(&pod).unwrap();
+        assert_eq!(last_exit.exit_code, 2);
+
+        let event = failure_event(&job, condition, Some((pod, last_exit)));
+        assert_eq!(event.reason, "BackoffLimitExceeded");
+        assert_eq!(event.kind.as_deref(), Some("Job"));
+        assert_eq!(event.namespace, "ops");
+        assert_eq!(event.workload.as_deref(), Some("backup"));
+        assert_eq!(event.contexts["job"]["cronJob"], "backup");
+        assert_eq!(event.contexts["job"]["failed"], 3);
+        assert_eq!(event.contexts["last exit"]["exitCode"], 2);
+        assert_eq!(event.contexts["last exit"]["pod"], "backup-28012345-x2x4z");
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use sentry::protocol::Event;
+use std::fmt;
+
+#[cfg(feature = "jq")]
+use {
+    jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val},
+    log::warn,
+    std::sync::Arc,
+};
+
+/// A jq expression reshaping the events of a before_send rule (in the sentry event format),
+/// ex: `.tags.team = .tags.namespace | del(.extra.uid)`.
+/// The first output of the expression replaces the event; the event is dropped if there is no
+/// output (ex: `select(.level != "info")`). If the expression fails, the event is kept unchanged.
+#[derive(Clone)]
+pub struct JqFilter {
+    expression: String,
+    #[cfg(feature = "jq")]
+    filter: Arc<Filter>,
+}
+
+impl fmt::Debug for JqFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("JqFilter").field(&self.expression).finish()
+    }
+}
+
+impl JqFilter {
+    /// Compiles the expression, with the jq standard library (ex: `map`, `select`, `with_entries`).
+    #[cfg(feature = "jq")]
+    pub fn compile(expression: &str) -> Result<Self> {
+        let mut defs = ParseCtx::new(Vec::new());
+        defs.insert_natives(jaq_core::core());
+        defs.insert_defs(jaq_std::std());
+
+        let (main, errors) = jaq_parse::parse(expression, jaq_parse::main());
+        let Some(main) = main.filter(|_| errors.is_empty()) else {
+            anyhow::bail!("Invalid jq expression {:?}: {:?}", expression, errors);
+        };
+        let filter = defs.compile(main);
+        if !defs.errs.is_empty() {
+            let errors = defs.errs.iter().map(|(e, _)| e.to_string());
+            anyhow::bail!(
+                "Invalid jq expression {:?}: {}",
+                expression,
+                errors.collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        Ok(Self {
+            expression: expression.to_string(),
+            filter: Arc::new(filter),
+        })
+    }
+
+    #[cfg(not(feature = "jq"))]
+    pub fn compile(expression: &str) -> Result<Self> {
+        anyhow::bail!(
+            "Cannot compile the jq expression {:?}: sentry-kubernetes is built without the \"jq\" feature",
+            expression
+        )
+    }
+
+    /// Runs the expression on the event. Returns None if the expression has no output.
+    pub fn apply(&self, event: Event<'static>) -> Option<Event<'static>> {
+        #[cfg(feature = "jq")]
+        match self.run(&event) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!(
+                    "jq expression {:?} failed, keeping the event: {:#}",
+                    self.expression, e
+                );
+                Some(event)
+            }
+        }
+
+        #[cfg(not(feature = "jq"))]
+        Some(event)
+    }
+
+    #[cfg(feature = "jq")]
+    fn run(&self, event: &Event<'static>) -> Result<Option<Event<'static>>> {
+        let input = Val::from(serde_json::to_value(event)?);
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self.filter.run((Ctx::new([], &inputs), input));
+
+        let Some(output) = outputs.next() else {
+            return Ok(None);
+        };
+        let output = serde_json::Value::from(output.map_err(|e| anyhow::anyhow!("{}", e))?);
+
+        Ok(Some(serde_json::from_value(output)?))
+    }
+}
+
+#[cfg(all(test, feature = "jq"))]
+mod tests {
+    use crate::jq::JqFilter;
+    use sentry::protocol::Event;
+    use sentry::Level;
+
+    fn event() -> Event<'static> {
+        Event {
+            level: Level::Warning,
+            message: Some("Back-off restarting failed container".to_string()),
+            tags: [
+                ("namespace".to_string(), "shop".to_string()),
+                ("name".to_string(), "web-0".to_string()),
+            ]
+            .into(),
+            extra: [("uid".to_string(), "f4f1a725".into())].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_apply() {
+        assert!(JqFilter::compile(".tags[").is_err());
+        assert!(JqFilter::compile("undefined_function(.)").is_err());
+
+        let filter = JqFilter::compile(
+            r#".tags.team = .tags.namespace + "-team" | del(.tags.name) | .extra.object_uid = .extra.uid | del(.extra.uid)"#,
+        )
+        .unwrap();
+        let transformed = filter.apply(event()).unwrap();
+        assert_eq!(transformed.tags["team"], "shop-team");
+        assert!(!transformed.tags.contains_key("name"));
+        assert_eq!(transformed.extra["object_uid"], "f4f1a725");
+        assert!(!transformed.extra.contains_key("uid"));
+        assert_eq!(transformed.message, event().message);
+
+        let select = JqFilter::compile(r#"select(.level != "warning")"#).unwrap();
+        assert!(select.apply(event()).is_none());
+
+        let failing = JqFilter::compile(".tags.namespace | error").unwrap();
+        assert_eq!(failing.apply(event()).unwrap().tags, event().tags);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::objects::ObjectResolver;
+use crate::stores::ObjectStores;
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::{Node, Pod, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::DynamicObject;
+use kube::{Api, Client};
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The lookups of the objects of the cluster made by the processor to enrich and route the events.
+/// None is returned if the object does not exist or cannot be read.
+#[async_trait]
+pub trait KubeApi: Send + Sync {
+    async fn pod(&self, namespace: &str, name: &str) -> Option<Arc<Pod>>;
+
+    async fn node(&self, name: &str) -> Option<Arc<Node>>;
+
+    /// The metadata of an object of any kind (cluster-scoped objects ignore the namespace).
+    async fn metadata(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<ObjectMeta>;
+
+    /// The whole object, of any kind (ex: to read its spec and status).
+    async fn object(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<DynamicObject>;
+
+    async fn secret(&self, namespace: &str, name: &str) -> Option<Secret>;
+}
+
+/// The lookups against the API server. The pods and the nodes are read from the reflector stores
+/// if started, the other objects are fetched (and cached) through the dynamic API.
+pub struct Kube {
+    client: Client,
+    stores: Option<ObjectStores>,
+    objects: ObjectResolver,
+}
+
+impl Kube {
+    pub fn new(
+        client: Client,
+        stores: Option<ObjectStores>,
+        cache_size: usize,
+        cache_ttl: Duration,
+    ) -> Self {
+        Self {
+            objects: ObjectResolver::new(client.clone(), cache_size, cache_ttl),
+            client,
+            stores,
+        }
+    }
+}
+
+#[async_trait]
+impl KubeApi for Kube {
+    async fn pod(&self, namespace: &str, name: &str) -> Option<Arc<Pod>> {
+        match &self.stores {
+            Some(stores) => stores.pod(namespace, name).await,
+            None => Api::namespaced(self.client.clone(), namespace)
+                .get(name)
+                .await
+                .ok()
+                .map(Arc::new),
+        }
+    }
+
+    async fn node(&self, name: &str) -> Option<Arc<Node>> {
+        match &self.stores {
+            Some(stores) => stores.node(name).await,
+            None => Api::all(self.client.clone())
+                .get(name)
+                .await
+                .ok()
+                .map(Arc::new),
+        }
+    }
+
+    async fn metadata(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<ObjectMeta> {
+        self.objects
+            .metadata(api_version, kind, namespace, name)
+            .await
+    }
+
+    async fn object(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<DynamicObject> {
+        self.objects
+            .object(api_version, kind, namespace, name)
+            .await
+    }
+
+    async fn secret(&self, namespace: &str, name: &str) -> Option<Secret> {
+        let api = Api::<Secret>::namespaced(self.client.clone(), namespace);
+        match api.get(name).await {
+            Ok(secret) => Some(secret),
+            Err(e) => {
+                warn!("Cannot read secret {}/{}: {}", namespace, name, e);
+                None
+            }
+        }
+    }
+}
+
+/// In-memory objects, to test the processor without a cluster.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeKube {
+    pub pods: Vec<Pod>,
+    pub nodes: Vec<Node>,
+    /// The objects of the other kinds, looked up by kind, namespace and name.
+    pub objects: Vec<DynamicObject>,
+    pub secrets: Vec<Secret>,
+}
+
+#[cfg(test)]
+fn matches(meta: &ObjectMeta, namespace: &str, name: &str) -> bool {
+    meta.name.as_deref() == Some(name)
+        && (namespace.is_empty() || meta.namespace.as_deref().unwrap_or_default() == namespace)
+}
+
+#[cfg(test)]
+#[async_trait]
+impl KubeApi for FakeKube {
+    async fn pod(&self, namespace: &str, name: &str) -> Option<Arc<Pod>> {
+        let pod = self
+            .pods
+            .iter()
+            .find(|p| matches(&p.metadata, namespace, name));
+        pod.cloned().map(Arc::new)
+    }
+
+    async fn node(&self, name: &str) -> Option<Arc<Node>> {
+        let node = self.nodes.iter().find(|n| matches(&n.metadata, "", name));
+        node.cloned().map(Arc::new)
+    }
+
+    async fn metadata(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<ObjectMeta> {
+        let object = self.object(api_version, kind, namespace, name).await?;
+        Some(object.metadata)
+    }
+
+    async fn object(
+        &self,
+        _api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<DynamicObject> {
+        let object = self.objects.iter().find(|o| {
+            o.types.as_ref().is_some_and(|t| t.kind == kind)
+                && matches(&o.metadata, namespace, name)
+        });
+        object.cloned()
+    }
+
+    async fn secret(&self, namespace: &str, name: &str) -> Option<Secret> {
+        let secret = self
+            .secrets
+            .iter()
+            .find(|s| matches(&s.metadata, namespace, name));
+        secret.cloned()
+    }
+}
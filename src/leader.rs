@@ -0,0 +1,187 @@
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::PostParams;
+use kube::{Api, Client};
+use log::{debug, info, warn};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Leader election on a coordination.k8s.io Lease: multiple replicas can run, but only the holder
+/// of the lease processes the events. The other replicas wait for the lease to expire.
+pub struct LeaderElection {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+    lease_duration: Duration,
+}
+
+impl LeaderElection {
+    /// The lease is created in the default namespace of the client.
+    pub fn new(client: Client, name: &str, identity: &str, lease_duration: Duration) -> Self {
+        Self {
+            api: Api::default_namespaced(client),
+            name: name.to_string(),
+            identity: identity.to_string(),
+            lease_duration,
+        }
+    }
+
+    /// Waits until the lease is acquired.
+    pub async fn acquire(&self) {
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {
+                    info!("Acquired the leadership ({})", self.identity);
+                    return;
+                }
+                Ok(false) => debug!("Lease {} is held by another replica", self.name),
+                Err(e) => warn!("Cannot acquire the lease {}: {}", self.name, e),
+            }
+
+            sleep(self.retry_period()).await;
+        }
+    }
+
+    /// Renews the lease periodically. Returns when the leadership is lost: the lease has been taken
+    /// by another replica, or it could not be renewed before its expiration.
+    pub async fn keep(&self) {
+        let mut last_renew = tokio::time::Instant::now();
+        loop {
+            sleep(self.lease_duration / 3).await;
+            match self.try_acquire_or_renew().await {
+                Ok(true) => last_renew = tokio::time::Instant::now(),
+                Ok(false) => return,
+                Err(e) => {
+                    warn!("Cannot renew the lease {}: {}", self.name, e);
+                    if last_renew.elapsed() >= self.lease_duration {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Releases the lease, so that another replica can take over without waiting for its expiration.
+    pub async fn release(&self) {
+        let Ok(Some(mut lease)) = self.api.get_opt(&self.name).await else {
+            return;
+        };
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return;
+        }
+
+        spec.holder_identity = None;
+        spec.renew_time = None;
+        if let Err(e) = self
+            .api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await
+        {
+            warn!("Cannot release the lease {}: {}", self.name, e);
+        }
+    }
+
+    fn retry_period(&self) -> Duration {
+        (self.lease_duration / 5).max(Duration::from_secs(1))
+    }
+
+    async fn try_acquire_or_renew(&self) -> kube::Result<bool> {
+        let now = Utc::now();
+        let Some(mut lease) = self.api.get_opt(&self.name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(self.spec(None, now)),
+            };
+            return match self.api.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(true),
+                Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+                Err(e) => Err(e),
+            };
+        };
+
+        let current = lease.spec.take().unwrap_or_default();
+        if !can_acquire(&current, &self.identity, now) {
+            return Ok(false);
+        }
+
+        // The resource version of the read lease makes the update fail if another replica
+        // has acquired it in the meantime.
+        lease.spec = Some(self.spec(Some(current), now));
+        match self
+            .api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn spec(&self, current: Option<LeaseSpec>, now: DateTime<Utc>) -> LeaseSpec {
+        let current = current.unwrap_or_default();
+        let renewing = current.holder_identity.as_deref() == Some(self.identity.as_str());
+        LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(self.lease_duration.as_secs() as i32),
+            acquire_time: if renewing {
+                current.acquire_time
+            } else {
+                Some(MicroTime(now))
+            },
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: if renewing {
+                current.lease_transitions
+            } else {
+                Some(current.lease_transitions.unwrap_or_default() + 1)
+            },
+        }
+    }
+}
+
+/// The lease can be acquired if it is already held by this replica, if it is not held, or if it has expired.
+fn can_acquire(spec: &LeaseSpec, identity: &str, now: DateTime<Utc>) -> bool {
+    let Some(holder) = spec.holder_identity.as_deref().filter(|h| !h.is_empty()) else {
+        return true;
+    };
+    if holder == identity {
+        return true;
+    }
+
+    let Some(renew_time) = spec.renew_time.as_ref() else {
+        return true;
+    };
+    let duration = spec.lease_duration_seconds.unwrap_or_default();
+    renew_time.0 + k8s_openapi::chrono::Duration::seconds(duration.into()) < now
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::leader::can_acquire;
+    use k8s_openapi::api::coordination::v1::LeaseSpec;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+    use k8s_openapi::chrono::{Duration, Utc};
+
+    #[test]
+    pub fn test_can_acquire() {
+        let now = Utc::now();
+        assert!(can_acquire(&LeaseSpec::default(), "a", now));
+
+        let spec = LeaseSpec {
+            holder_identity: Some("b".to_string()),
+            lease_duration_seconds: Some(15),
+            renew_time: Some(MicroTime(now - Duration::seconds(5))),
+            ..Default::default()
+        };
+        assert!(!can_acquire(&spec, "a", now));
+        assert!(can_acquire(&spec, "b", now));
+
+        // The holder has not renewed the lease in time.
+        assert!(can_acquire(&spec, "a", now + Duration::seconds(11)));
+    }
+}
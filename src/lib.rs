@@ -0,0 +1,88 @@
+//! The sentry-kubernetes event pipeline, as a library: the `sentry-kubernetes` binary is built on
+//! it, and operators can embed the pipeline with the stream combinators of [`pipeline`].
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use sentry_kubernetes::config::NdjsonConfig;
+//! use sentry_kubernetes::sink::NdjsonSink;
+//!
+//! # async fn run(client: kube::Client) {
+//! sentry_kubernetes::pipeline(client.clone())
+//!     .filter(|event| event.namespace != "kube-system")
+//!     .enrich(client)
+//!     .sink(NdjsonSink::new(NdjsonConfig::default()))
+//!     .for_each(|_| async {})
+//!     .await;
+//! # }
+//! ```
+
+pub mod admin;
+pub mod assign;
+pub mod attachment;
+pub mod audit;
+pub mod autoscaler;
+pub mod before_send;
+pub mod cache;
+pub mod capture;
+pub mod cert_manager;
+pub mod checkpoint;
+pub mod cluster;
+pub mod config;
+pub mod cron_monitors;
+pub mod deprecations;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod digest;
+pub mod disruption_budgets;
+pub mod enrich;
+pub mod environment;
+pub mod events_api;
+pub mod filter;
+pub mod health;
+pub mod heartbeat;
+pub mod hpa;
+pub mod job_failures;
+pub mod jq;
+pub mod kube_api;
+pub mod leader;
+pub mod loadtest;
+pub mod logging;
+pub mod lua;
+pub mod metrics;
+pub mod node;
+pub mod node_conditions;
+pub mod node_lifecycle;
+pub mod objects;
+pub mod pending_claims;
+pub mod pipeline;
+pub mod pod_status;
+pub mod policy;
+pub mod processor;
+pub mod queue;
+pub mod release_health;
+pub mod resolve;
+pub mod rollout_transactions;
+pub mod rollouts;
+pub mod routing;
+pub mod sampling;
+pub mod secrets;
+pub mod self_monitoring;
+pub mod sentry_api;
+pub mod sentry_event;
+pub mod server;
+pub mod shard;
+pub mod sink;
+pub mod spool;
+pub mod spot;
+pub mod stages;
+pub mod stores;
+pub mod stuck_pods;
+pub mod tail;
+pub mod transport;
+pub mod wasm;
+pub mod watchers;
+
+pub use crate::pipeline::{pipeline, EventStreamExt, Pipeline};
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either the \"native-tls\" or the \"rustls\" feature must be enabled");
@@ -0,0 +1,214 @@
+use crate::processor::Processor;
+use futures::prelude::*;
+use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use log::info;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+/// The events are generated in batches, at this interval.
+const BATCH_INTERVAL: Duration = Duration::from_millis(10);
+/// Number of seconds worth of events between the progress logs.
+const PROGRESS_SECONDS: u64 = 10;
+
+/// The reasons and the messages of the synthetic events, cycled through.
+const REASONS: [(&str, &str); 5] = [
+    ("BackOff", "Back-off restarting failed container"),
+    (
+        "Unhealthy",
+        "Readiness probe failed: HTTP probe failed with statuscode: 503",
+    ),
+    (
+        "FailedScheduling",
+        "0/5000 nodes are available: 5000 Insufficient cpu.",
+    ),
+    (
+        "FailedMount",
+        "MountVolume.SetUp failed for volume \"config\": configmap \"app\" not found",
+    ),
+    (
+        "OOMKilling",
+        "Memory cgroup out of memory: Killed process 4242 (app)",
+    ),
+];
+/// Number of distinct namespaces and pods the synthetic events are spread over.
+const NAMESPACES: u64 = 50;
+const PODS: u64 = 5000;
+
+/// The n-th synthetic event: a warning about one of the pods, with a unique uid and resource
+/// version so that it is not discarded as a duplicate.
+pub fn synthetic_event(n: u64) -> Event {
+    let (reason, message) = REASONS[(n % REASONS.len() as u64) as usize];
+    let pod = n % PODS;
+    let namespace = format!("loadtest-{}", pod % NAMESPACES);
+    let now = Time(Utc::now());
+
+    Event {
+        metadata: ObjectMeta {
+            name: Some(format!("pod-{}.{:x}", pod, n)),
+            namespace: Some(namespace.clone()),
+            uid: Some(format!("00000000-0000-4000-8000-{:012x}", n)),
+            resource_version: Some(n.to_string()),
+            creation_timestamp: Some(now.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: Some(format!("pod-{}", pod)),
+            namespace: Some(namespace),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        type_: Some("Warning".to_string()),
+        source: Some(EventSource {
+            component: Some("kubelet".to_string()),
+            host: Some(format!("node-{}", pod % 1000)),
+        }),
+        count: Some(1),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    }
+}
+
+/// The throughput and the latencies (from the generation to the end of the processing) measured
+/// during a load test.
+#[derive(Default)]
+pub struct Report {
+    elapsed: Duration,
+    /// The processing latencies, sorted once the test is over.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn percentile(&self, percentile: usize) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let index = (self.latencies.len() * percentile / 100).min(self.latencies.len() - 1);
+        self.latencies[index]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.latencies.len();
+        writeln!(
+            f,
+            "Processed {} events in {:.1}s: {:.1} events/s",
+            count,
+            self.elapsed.as_secs_f64(),
+            count as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        write!(
+            f,
+            "Latency: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.percentile(50),
+            self.percentile(95),
+            self.percentile(99),
+            self.latencies.last().copied().unwrap_or_default()
+        )
+    }
+}
+
+/// Runs the synthetic events through the pipeline of the processor at the given rate (events per
+/// second) for the given duration, processing up to `concurrency` events at the same time.
+/// Once the rate cannot be sustained, the latencies grow with the backlog.
+pub async fn run(
+    processor: &Processor,
+    rate: u64,
+    duration: Duration,
+    concurrency: usize,
+) -> Report {
+    let total = rate * duration.as_secs();
+    let per_batch = rate as f64 * BATCH_INTERVAL.as_secs_f64();
+    let started = Instant::now();
+    let generated = stream::unfold(
+        (interval(BATCH_INTERVAL), 0u64, 0f64),
+        move |(mut ticks, n, mut budget)| async move {
+            if n >= total {
+                return None;
+            }
+
+            ticks.tick().await;
+            budget += per_batch;
+            let batch = (budget.floor() as u64).min(total - n);
+            budget -= batch as f64;
+            let events = (n..n + batch).map(|i| (synthetic_event(i), Instant::now()));
+
+            Some((
+                stream::iter(events.collect::<Vec<_>>()),
+                (ticks, n + batch, budget),
+            ))
+        },
+    )
+    .flatten();
+
+    let latencies = Mutex::new(Vec::with_capacity(total as usize));
+    let progress = (rate * PROGRESS_SECONDS).max(1);
+    generated
+        .for_each_concurrent(concurrency.max(1), |(event, generated_at)| {
+            let latencies = &latencies;
+            async move {
+                processor.process(event).await;
+                let mut latencies = latencies.lock().unwrap();
+                latencies.push(generated_at.elapsed());
+                if (latencies.len() as u64).checked_rem(progress) == Some(0) {
+                    info!("Processed {}/{} events", latencies.len(), total);
+                }
+            }
+        })
+        .await;
+
+    let mut latencies = latencies.into_inner().unwrap();
+    latencies.sort();
+
+    Report {
+        elapsed: started.elapsed(),
+        latencies,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loadtest::{synthetic_event, Report};
+    use crate::sentry_event::SentryEvent;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_synthetic_event() {
+        let first = SentryEvent::from(synthetic_event(0));
+        let second = SentryEvent::from(synthetic_event(1));
+        assert_ne!(first.uid, second.uid);
+        assert_eq!(first.reason, "BackOff");
+        assert_eq!(second.reason, "Unhealthy");
+        assert_eq!(first.namespace, "loadtest-0");
+        assert_eq!(first.kind.as_deref(), Some("Pod"));
+        assert_eq!(
+            SentryEvent::from(synthetic_event(5000)).name,
+            first.name,
+            "the pods are reused"
+        );
+    }
+
+    #[test]
+    pub fn test_report() {
+        let report = Report {
+            elapsed: Duration::from_secs(2),
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+        };
+        assert_eq!(report.percentile(50), Duration::from_millis(51));
+        assert_eq!(report.percentile(99), Duration::from_millis(100));
+        assert_eq!(
+            report.to_string(),
+            "Processed 100 events in 2.0s: 50.0 events/s\n\
+             Latency: p50 51ms, p95 96ms, p99 100ms, max 100ms"
+        );
+    }
+}
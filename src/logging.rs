@@ -0,0 +1,166 @@
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Map, Value};
+use std::future::Future;
+use std::io::Write;
+
+tokio::task_local! {
+    /// The fields of the event being processed, added to the logs of its processing.
+    static EVENT_FIELDS: Map<String, Value>;
+}
+
+/// Runs the processing of the event, adding its fields (uid, namespace, kind, name and reason)
+/// to the JSON logs written meanwhile.
+pub fn with_event<F, Fut>(event: SentryEvent, process: F) -> impl Future<Output = Fut::Output>
+where
+    F: FnOnce(SentryEvent) -> Fut,
+    Fut: Future,
+{
+    with_fields(event_fields(&event), process(event))
+}
+
+/// Runs the future, adding the fields to the JSON logs written meanwhile.
+pub fn with_fields<Fut: Future>(
+    fields: Map<String, Value>,
+    future: Fut,
+) -> impl Future<Output = Fut::Output> {
+    EVENT_FIELDS.scope(fields, future)
+}
+
+/// The fields of the event added to the logs of its processing: uid, namespace, kind, name and reason.
+pub fn event_fields(event: &SentryEvent) -> Map<String, Value> {
+    let mut fields = Map::new();
+    fields.insert("uid".to_string(), event.uid.to_string().into());
+    fields.insert("namespace".to_string(), event.namespace.clone().into());
+    if let Some(kind) = &event.kind {
+        fields.insert("kind".to_string(), kind.clone().into());
+    }
+    fields.insert("name".to_string(), event.name.clone().into());
+    fields.insert("reason".to_string(), event.reason.clone().into());
+
+    fields
+}
+
+/// Writes the logs as JSON lines (timestamp, level, module, message and the fields of the event
+/// being processed), to be parsed by the log aggregators.
+pub struct JsonLogger {
+    level: LevelFilter,
+    /// The levels of the modules, by target prefix.
+    module_levels: Vec<(String, LevelFilter)>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter) -> Self {
+        Self {
+            level,
+            module_levels: vec![],
+        }
+    }
+
+    pub fn with_module_level(mut self, target: &str, level: LevelFilter) -> Self {
+        self.module_levels.push((target.to_string(), level));
+        // The most specific target first.
+        self.module_levels
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        self
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.module_levels
+            .iter()
+            .find(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    fn format(record: &Record) -> Value {
+        let mut line = json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "level": record.level().as_str(),
+            "module": record.module_path().unwrap_or(record.target()),
+            "message": record.args().to_string(),
+        });
+        if record.module_path() != Some(record.target()) {
+            line["target"] = record.target().into();
+        }
+        if let Ok(fields) = EVENT_FIELDS.try_with(|fields| fields.clone()) {
+            line["event"] = fields.into();
+        }
+
+        line
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", Self::format(record));
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{with_event, JsonLogger};
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use log::{Level, LevelFilter, Record};
+
+    #[test]
+    pub fn test_level() {
+        let logger = JsonLogger::new(LevelFilter::Info)
+            .with_module_level("sentry", LevelFilter::Debug)
+            .with_module_level("sentry::transport", LevelFilter::Off);
+        assert_eq!(logger.level("sentry_kubernetes"), LevelFilter::Info);
+        assert_eq!(logger.level("sentry"), LevelFilter::Debug);
+        assert_eq!(logger.level("sentry::client"), LevelFilter::Debug);
+        assert_eq!(logger.level("sentry::transport"), LevelFilter::Off);
+    }
+
+    #[tokio::test]
+    pub async fn test_format() {
+        let format = || {
+            JsonLogger::format(
+                &Record::builder()
+                    .args(format_args!("excluded by level filter"))
+                    .level(Level::Debug)
+                    .target("sentry_kubernetes::processor")
+                    .module_path(Some("sentry_kubernetes::processor"))
+                    .build(),
+            )
+        };
+
+        let line = format();
+        assert_eq!(line["level"], "DEBUG");
+        assert_eq!(line["module"], "sentry_kubernetes::processor");
+        assert_eq!(line["message"], "excluded by level filter");
+        assert!(line.get("target").is_none());
+        assert!(line.get("event").is_none());
+
+        let event = SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        });
+        let line = with_event(event, |_| async { format() }).await;
+        assert_eq!(line["event"]["namespace"], "shop");
+        assert_eq!(line["event"]["kind"], "Pod");
+        assert_eq!(line["event"]["name"], "web-0");
+        assert_eq!(line["event"]["reason"], "BackOff");
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use sentry::protocol::Event;
+use std::fmt;
+
+#[cfg(feature = "lua")]
+use {
+    anyhow::Context,
+    log::warn,
+    mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Value},
+    std::fs,
+    std::sync::atomic::{AtomicU32, Ordering},
+    std::sync::{Arc, Mutex},
+};
+
+/// Instructions a script may execute on each event (counted by thousands), so that a looping
+/// script cannot stall the pipeline.
+#[cfg(feature = "lua")]
+const MAX_KILO_INSTRUCTIONS: u32 = 50_000;
+/// Maximum memory used by a script.
+#[cfg(feature = "lua")]
+const MAX_MEMORY: usize = 64 << 20;
+
+/// A Lua script transforming the events of a before_send rule, loaded from a file.
+/// The script defines one or both of the functions, called with the event (a table, in the sentry
+/// event format):
+/// - `filter(event)`: returns false (or nil) to drop the event;
+/// - `transform(event)`: returns the modified event, or nil to keep it unchanged.
+///
+/// Only the base, string, table, math and utf8 libraries are available (no I/O). The globals are
+/// kept between the events. If the script fails, the event is kept unchanged.
+#[derive(Clone)]
+pub struct LuaScript {
+    path: String,
+    #[cfg(feature = "lua")]
+    lua: Arc<Mutex<Lua>>,
+    /// The remaining instructions (by thousands) of the current call.
+    #[cfg(feature = "lua")]
+    budget: Arc<AtomicU32>,
+}
+
+impl fmt::Debug for LuaScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LuaScript").field(&self.path).finish()
+    }
+}
+
+impl LuaScript {
+    /// Loads and runs the script, which must define `filter` or `transform`.
+    #[cfg(feature = "lua")]
+    pub fn load(path: &str) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the Lua script {}", path))?;
+        let libs = StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8;
+        let lua = Lua::new_with(libs, LuaOptions::default())?;
+        lua.set_memory_limit(MAX_MEMORY)?;
+
+        let budget = Arc::new(AtomicU32::new(MAX_KILO_INSTRUCTIONS));
+        let remaining = budget.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(1000),
+            move |_, _| {
+                // Once exhausted, every hook fails: the script cannot catch the error and go on.
+                remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                    .map(|_| ())
+                    .map_err(|_| {
+                        mlua::Error::RuntimeError("instruction limit exceeded".to_string())
+                    })
+            },
+        );
+        lua.load(&source)
+            .set_name(path)
+            .exec()
+            .with_context(|| format!("Cannot load the Lua script {}", path))?;
+
+        let globals = lua.globals();
+        let defined = globals.contains_key("filter")? || globals.contains_key("transform")?;
+        drop(globals);
+        if !defined {
+            anyhow::bail!(
+                "The Lua script {} defines neither filter nor transform",
+                path
+            );
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            lua: Arc::new(Mutex::new(lua)),
+            budget,
+        })
+    }
+
+    #[cfg(not(feature = "lua"))]
+    pub fn load(path: &str) -> Result<Self> {
+        anyhow::bail!(
+            "Cannot load the Lua script {}: sentry-kubernetes is built without the \"lua\" feature",
+            path
+        )
+    }
+
+    /// Runs the script on the event. Returns None if the script dropped the event.
+    pub fn apply(&self, event: Event<'static>) -> Option<Event<'static>> {
+        #[cfg(feature = "lua")]
+        match self.run(&event) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!(
+                    "Lua script {} failed, keeping the event: {:#}",
+                    self.path, e
+                );
+                Some(event)
+            }
+        }
+
+        #[cfg(not(feature = "lua"))]
+        Some(event)
+    }
+
+    #[cfg(feature = "lua")]
+    fn run(&self, event: &Event<'static>) -> Result<Option<Event<'static>>> {
+        let lua = self.lua.lock().unwrap();
+        self.budget.store(MAX_KILO_INSTRUCTIONS, Ordering::Relaxed);
+
+        let globals = lua.globals();
+        let value = lua.to_value(event)?;
+        if let Some(filter) = globals.get::<_, Option<Function>>("filter")? {
+            if !filter.call::<_, bool>(value.clone())? {
+                return Ok(None);
+            }
+        }
+
+        let Some(transform) = globals.get::<_, Option<Function>>("transform")? else {
+            return Ok(Some(event.clone()));
+        };
+        let transformed = match transform.call::<_, Value>(value)? {
+            Value::Nil => event.clone(),
+            transformed => lua.from_value(transformed)?,
+        };
+
+        Ok(Some(transformed))
+    }
+}
+
+#[cfg(all(test, feature = "lua"))]
+mod tests {
+    use crate::lua::LuaScript;
+    use sentry::protocol::Event;
+    use sentry::Level;
+    use std::fs;
+
+    fn script(name: &str, source: &str) -> anyhow::Result<LuaScript> {
+        let path = std::env::temp_dir().join(format!("{}-{}.lua", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+
+        let script = LuaScript::load(path.to_str().unwrap());
+        fs::remove_file(path).unwrap();
+        script
+    }
+
+    fn event() -> Event<'static> {
+        Event {
+            level: Level::Warning,
+            message: Some("Back-off restarting failed container".to_string()),
+            tags: [("namespace".to_string(), "shop".to_string())].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_apply() {
+        assert!(script("empty", "local x = 1").is_err());
+        assert!(script("io", "io.open('/etc/passwd')").is_err());
+
+        let script = script(
+            "transform",
+            r#"
+function filter(event)
+  return event.tags.namespace ~= "kube-system"
+end
+
+function transform(event)
+  if event.level ~= "warning" then
+    return nil
+  end
+  event.level = "error"
+  event.tags.team = event.tags.namespace .. "-team"
+  return event
+end
+"#,
+        )
+        .unwrap();
+
+        let transformed = script.apply(event()).unwrap();
+        assert_eq!(transformed.level, Level::Error);
+        assert_eq!(transformed.tags["team"], "shop-team");
+        assert_eq!(transformed.message, event().message);
+
+        let mut dropped = event();
+        dropped
+            .tags
+            .insert("namespace".to_string(), "kube-system".to_string());
+        assert!(script.apply(dropped).is_none());
+
+        let mut unchanged = event();
+        unchanged.level = Level::Info;
+        assert_eq!(script.apply(unchanged).unwrap().level, Level::Info);
+    }
+
+    #[test]
+    pub fn test_limits() {
+        let looping = script("loop", "function filter(event) while true do end end").unwrap();
+        assert!(looping.apply(event()).is_some(), "kept when out of budget");
+
+        let failing = script("error", "function transform(event) error('boom') end").unwrap();
+        assert!(failing.apply(event()).is_some());
+    }
+}
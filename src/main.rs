@@ -1,40 +1,382 @@
-use crate::processor::Processor;
+#![recursion_limit = "256"]
+
 use anyhow::Result;
 use futures::prelude::*;
 use getopts::Options;
-use k8s_openapi::api::core::v1::Event;
-use kube::runtime::{watcher, WatchStreamExt};
-use kube::{Api, Client};
+use k8s_openapi::api::apps::v1::{Deployment, ReplicaSet, StatefulSet};
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::{Node, PersistentVolumeClaim, Pod};
+use k8s_openapi::api::policy::v1::PodDisruptionBudget;
+use k8s_openapi::chrono::Utc;
+use kube::api::ListParams;
+use kube::client::ClientBuilder;
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::runtime::watcher;
+use kube::Client;
 use lazy_static::lazy_static;
-use log::{debug, error, info, LevelFilter};
+use log::{debug, error, info, warn, LevelFilter, Log};
 use sentry::types::Dsn;
+use sentry::{Hub, Level};
+use sentry_kubernetes::admin::ADMIN;
+use sentry_kubernetes::assign::IssueAssigner;
+use sentry_kubernetes::audit::{AuditKind, AuditReceiver, WebhookLimits};
+use sentry_kubernetes::before_send::BeforeSendRules;
+use sentry_kubernetes::capture::CaptureServer;
+use sentry_kubernetes::checkpoint::Checkpoint;
+use sentry_kubernetes::cluster::Cluster;
+use sentry_kubernetes::config::{Config, NdjsonConfig};
+use sentry_kubernetes::cron_monitors::CronMonitors;
+use sentry_kubernetes::deprecations::{DeprecationLayer, DeprecationWarnings};
+use sentry_kubernetes::disruption_budgets::DisruptionBudgetWatcher;
+use sentry_kubernetes::environment::EnvironmentResolver;
+use sentry_kubernetes::events_api::EventsApi;
+use sentry_kubernetes::filter::MessagePattern;
+use sentry_kubernetes::health::HEALTH;
+use sentry_kubernetes::heartbeat::Heartbeat;
+use sentry_kubernetes::job_failures::JobFailureWatcher;
+use sentry_kubernetes::leader::LeaderElection;
+use sentry_kubernetes::logging::JsonLogger;
+use sentry_kubernetes::metrics::METRICS;
+use sentry_kubernetes::node_conditions::NodeConditionWatcher;
+use sentry_kubernetes::node_lifecycle::NodeLifecycleWatcher;
+use sentry_kubernetes::pending_claims::PendingClaimWatcher;
+use sentry_kubernetes::pod_status::PodStatusWatcher;
+use sentry_kubernetes::processor::{Processor, ProcessorBuilder};
+use sentry_kubernetes::queue::OverflowPolicy;
+use sentry_kubernetes::release_health::ReleaseSessions;
+use sentry_kubernetes::resolve::IssueResolver;
+use sentry_kubernetes::rollout_transactions::RolloutTransactions;
+use sentry_kubernetes::rollouts::{Rollout, RolloutWatcher};
+use sentry_kubernetes::routing::{ClientPool, Router};
+use sentry_kubernetes::sampling::SampleRates;
+use sentry_kubernetes::self_monitoring::SelfMonitoringLogger;
+use sentry_kubernetes::sentry_api::SentryApi;
+use sentry_kubernetes::sentry_event::{event_time, SentryEvent, CLUSTER_NAME};
+use sentry_kubernetes::shard::{hostname_ordinal, Shard};
+use sentry_kubernetes::sink::NdjsonSink;
+use sentry_kubernetes::stuck_pods::{StuckPodThresholds, StuckPodWatcher};
+use sentry_kubernetes::transport::HttpTransportFactory;
+use sentry_kubernetes::watchers::WatcherRegistry;
+use sentry_kubernetes::{
+    admin, audit, cluster, loadtest, queue, self_monitoring, server, sink, tail,
+};
 use simple_logger::SimpleLogger;
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
 use tokio::time::sleep;
+use tower::limit::RateLimitLayer;
+
+#[cfg(feature = "diagnostics")]
+use sentry_kubernetes::diagnostics;
+
+type BeforeSend = Arc<
+    dyn Fn(sentry::protocol::Event<'static>) -> Option<sentry::protocol::Event<'static>>
+        + Send
+        + Sync,
+>;
+
+#[cfg(feature = "diagnostics")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
-mod processor;
-mod sentry_event;
+/// Delays between the restarts of a failing kubernetes watcher.
+const MIN_WATCH_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_WATCH_BACKOFF: Duration = Duration::from_secs(60);
+/// Interval between the checks of the debounced node conditions, the pending claims and the
+/// other thresholds of the watched resources.
+const WATCHER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Interval between the checks of the removed nodes.
+const NODE_REMOVAL_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Interval between the reports of the API deprecation warnings, aggregating the warnings meanwhile.
+const DEPRECATION_REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
 lazy_static! {
-    static ref SENTRY_DSN: String = env::var("DSN").unwrap_or_default();
     static ref ENV: String = env::var("ENVIRONMENT").unwrap_or_default();
     static ref RELEASE: String = env::var("RELEASE").unwrap_or_default();
+    static ref METRICS_ADDR: String = env::var("METRICS_ADDR").unwrap_or_default();
+    static ref HEALTH_ADDR: String = env::var("HEALTH_ADDR").unwrap_or_default();
+    static ref DIAGNOSTICS_ADDR: String = env::var("DIAGNOSTICS_ADDR").unwrap_or_default();
+    static ref ADMIN_ADDR: String = env::var("ADMIN_ADDR").unwrap_or_default();
+    static ref ADMIN_TOKEN: String = env::var("ADMIN_TOKEN").unwrap_or_default();
+    static ref WATCH_STALL_TIMEOUT: u64 = env::var("WATCH_STALL_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    static ref ANNOTATION_ROUTING: bool = env::var("ANNOTATION_ROUTING")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WORKER_THREADS: usize = env::var("WORKER_THREADS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    static ref MAX_BLOCKING_THREADS: usize = env::var("MAX_BLOCKING_THREADS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    static ref PROCESS_CONCURRENCY: usize = env::var("PROCESS_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(4);
+    static ref EVENT_QUEUE_SIZE: usize = env::var("EVENT_QUEUE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1000);
+    static ref SENTRY_CLIENT_POOL_SIZE: usize = env::var("SENTRY_CLIENT_POOL_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100);
+    static ref EVENT_QUEUE_OVERFLOW: OverflowPolicy = match env::var("EVENT_QUEUE_OVERFLOW") {
+        Ok(policy) if !policy.is_empty() => policy.parse().unwrap_or_else(|e| {
+            warn!("{}, blocking the watch when the queue is full", e);
+            OverflowPolicy::Block
+        }),
+        _ => OverflowPolicy::Block,
+    };
+    static ref DISABLE_ENRICHMENT: bool = env::var("DISABLE_ENRICHMENT")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref SENTRY_DEBUG: bool = env::var("SENTRY_DEBUG")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref CHECKPOINT_CONFIGMAP: String = env::var("CHECKPOINT_CONFIGMAP").unwrap_or_default();
+    static ref CHECKPOINT_INTERVAL: u64 = env::var("CHECKPOINT_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10);
+    static ref IGNORE_EXISTING_EVENTS: bool = env::var("IGNORE_EXISTING_EVENTS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_POD_STATUS: bool = env::var("WATCH_POD_STATUS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_NODE_CONDITIONS: bool = env::var("WATCH_NODE_CONDITIONS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref NODE_CONDITION_DEBOUNCE: u64 = env::var("NODE_CONDITION_DEBOUNCE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    static ref WATCH_NODE_LIFECYCLE: bool = env::var("WATCH_NODE_LIFECYCLE")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_JOB_FAILURES: bool = env::var("WATCH_JOB_FAILURES")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_ROLLOUT_TRANSACTIONS: bool = env::var("WATCH_ROLLOUT_TRANSACTIONS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_RELEASE_HEALTH: bool = env::var("WATCH_RELEASE_HEALTH")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref RELEASE_HEALTH_WINDOW: u64 = env::var("RELEASE_HEALTH_WINDOW")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(900);
+    static ref WATCH_PENDING_CLAIMS: bool = env::var("WATCH_PENDING_CLAIMS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref CLAIM_PENDING_THRESHOLD: u64 = env::var("CLAIM_PENDING_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+    static ref WATCH_CRON_MONITORS: bool = env::var("WATCH_CRON_MONITORS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref WATCH_STUCK_PODS: bool = env::var("WATCH_STUCK_PODS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref STUCK_POD_THRESHOLDS: StuckPodThresholds = {
+        let seconds = |name: &str, default: u64| {
+            let seconds = env::var(name).ok().and_then(|v| v.parse().ok());
+            Duration::from_secs(seconds.unwrap_or(default))
+        };
+        StuckPodThresholds {
+            pending: seconds("STUCK_POD_PENDING_THRESHOLD", 900),
+            not_ready: seconds("STUCK_POD_NOT_READY_THRESHOLD", 1800),
+            realert_interval: seconds("STUCK_POD_REALERT_INTERVAL", 3600),
+        }
+    };
+    static ref WATCH_ROLLOUTS: bool = env::var("WATCH_ROLLOUTS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref ROLLOUT_DEADLINE: u64 = env::var("ROLLOUT_DEADLINE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(600);
+    static ref WATCH_DISRUPTION_BUDGETS: bool = env::var("WATCH_DISRUPTION_BUDGETS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref DISRUPTION_BUDGET_THRESHOLD: u64 = env::var("DISRUPTION_BUDGET_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(600);
+    static ref WATCH_DEPRECATED_APIS: bool = env::var("WATCH_DEPRECATED_APIS")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref AUDIT_WEBHOOK_ADDR: String = env::var("AUDIT_WEBHOOK_ADDR").unwrap_or_default();
+    static ref AUDIT_WEBHOOK_TOKEN: String = env::var("AUDIT_WEBHOOK_TOKEN").unwrap_or_default();
+    static ref AUDIT_WEBHOOK_MAX_BODY_SIZE: usize = env::var("AUDIT_WEBHOOK_MAX_BODY_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10 << 20);
+    static ref AUDIT_EVENTS: Vec<AuditKind> =
+        list_env("AUDIT_EVENTS", Some("forbidden,secret-denied,exec".to_string()))
+            .iter()
+            .filter_map(|kind| kind.parse().map_err(|e| warn!("{}", e)).ok())
+            .collect();
+    static ref SPOT_INTERRUPTION_LEVEL: Option<Level> = match env::var("SPOT_INTERRUPTION_LEVEL") {
+        Ok(level) if !level.is_empty() => level.parse().map_err(|_| {
+            warn!("Invalid SPOT_INTERRUPTION_LEVEL \"{}\", not demoting the spot interruptions", level);
+        }).ok(),
+        _ => None,
+    };
+    static ref SELF_MONITORING_DSN: String = env::var("SELF_MONITORING_DSN").unwrap_or_default();
+    static ref DELETION_CORRELATION: bool = env::var("DELETION_CORRELATION")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref SEND_STARTUP_EVENT: bool = env::var("SEND_STARTUP_EVENT")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref HEARTBEAT_MONITOR: String = env::var("HEARTBEAT_MONITOR").unwrap_or_default();
+    static ref HEARTBEAT_INTERVAL: u64 = env::var("HEARTBEAT_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    static ref HEARTBEAT_MAX_SILENCE: u64 = env::var("HEARTBEAT_MAX_SILENCE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    static ref SENTRY_AUTH_TOKEN: String = env::var("SENTRY_AUTH_TOKEN").unwrap_or_default();
+    static ref SENTRY_ORG: String = env::var("SENTRY_ORG").unwrap_or_default();
+    static ref ASSIGN_ISSUES: bool = env::var("ASSIGN_ISSUES")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref SENTRY_API_URL: Option<String> =
+        env::var("SENTRY_API_URL").ok().filter(|v| !v.is_empty());
+    static ref EVENT_FIELD_SELECTOR: String = env::var("EVENT_FIELD_SELECTOR").unwrap_or_default();
+    /// None to detect the API served by the cluster.
+    static ref EVENTS_API: Option<EventsApi> = match env::var("EVENTS_API") {
+        Ok(api) if !api.is_empty() && api != "auto" => api.parse().map_err(|e| {
+            warn!("{}, detecting the events API", e);
+        }).ok(),
+        _ => None,
+    };
+    static ref KUBE_CLIENT_QPS: f32 = env::var("KUBE_CLIENT_QPS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20.0);
+    static ref KUBE_CLIENT_BURST: u64 = env::var("KUBE_CLIENT_BURST")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(40);
+    static ref KUBE_CONNECT_TIMEOUT: u64 = env::var("KUBE_CONNECT_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10);
+    static ref KUBE_READ_TIMEOUT: u64 = env::var("KUBE_READ_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(295);
+    static ref WATCH_TIMEOUT: u32 = env::var("WATCH_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(290);
+    static ref SHARD_COUNT: u32 = env::var("SHARD_COUNT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+    /// The shard of this replica: SHARD_INDEX, or the ordinal of the StatefulSet pod.
+    static ref SHARD: Option<Shard> = env::var("SHARD_INDEX")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .or_else(|| env::var("HOSTNAME").ok().and_then(|h| hostname_ordinal(&h)))
+        .filter(|index| *SHARD_COUNT > 1 && index < &*SHARD_COUNT)
+        .map(|index| Shard::new(index, *SHARD_COUNT));
+    static ref LEADER_ELECTION: bool = env::var("LEADER_ELECTION")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    static ref LEADER_ELECTION_LEASE: String =
+        env::var("LEADER_ELECTION_LEASE").unwrap_or("sentry-kubernetes".to_string());
+    static ref LEADER_ELECTION_LEASE_DURATION: u64 = env::var("LEADER_ELECTION_LEASE_DURATION")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(15);
+    static ref BACKFILL_MINUTES: u64 = env::var("BACKFILL_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
 }
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!(
+        "Usage: {} [options] [export|tail|loadtest]\n\n\
+         The export command dumps the events passing the filters as NDJSON, without sending them to sentry.\n\
+         The tail command prints the decision of the pipeline on each event (the filter discarding it, its\n\
+         fingerprint, tags and routes), without sending them to sentry.\n\
+         The loadtest command sends synthetic events through the pipeline at the given rate, then reports\n\
+         the throughput and the processing latencies.",
+        program
+    );
     print!("{}", opts.usage(&brief));
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// Builds the tokio runtime, sized by WORKER_THREADS and MAX_BLOCKING_THREADS (ex: to match the CPU
+/// request of the container, as the default is a worker thread per CPU of the node).
+fn main() -> Result<()> {
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if *WORKER_THREADS > 0 {
+        runtime.worker_threads(*WORKER_THREADS);
+    }
+    if *MAX_BLOCKING_THREADS > 0 {
+        runtime.max_blocking_threads(*MAX_BLOCKING_THREADS);
+    }
+
+    runtime.enable_all().build()?.block_on(run())
+}
+
+async fn run() -> Result<()> {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optopt("l", "log-level", "set output file name", "ERROR");
+    opts.optopt("c", "config", "set the configuration file", "FILE");
+    opts.optopt(
+        "o",
+        "output",
+        "export: write the events to this file instead of stdout",
+        "FILE",
+    );
+    opts.optopt(
+        "d",
+        "duration",
+        "export, tail: stop after this number of seconds instead of waiting for an interrupt (loadtest: 60 by default)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "rate",
+        "loadtest: the number of events generated per second (100 by default)",
+        "EVENTS",
+    );
+    opts.optopt(
+        "",
+        "context",
+        "use this context of the kubeconfig instead of the current one (comma-separated list to watch multiple clusters)",
+        "CONTEXT",
+    );
+    opts.optopt(
+        "",
+        "capture-to",
+        "send the events to a local capture server writing the envelopes to this directory, instead of sentry",
+        "DIR",
+    );
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -51,13 +393,229 @@ async fn main() -> Result<()> {
     let log_level = env::var("LOG_LEVEL").unwrap_or("INFO".to_string());
     let log_level = matches.opt_get_default("l", log_level).unwrap();
     let log_level = LevelFilter::from_str(&log_level).unwrap_or(LevelFilter::Error);
-    SimpleLogger::new().with_level(log_level).init().unwrap();
+    // The SDK diagnostics are logged at debug level with the "sentry" target.
+    let sdk_level = if *SENTRY_DEBUG {
+        LevelFilter::Debug
+    } else {
+        log_level
+    };
+    let mut logger: Box<dyn Log> =
+        if env::var("LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json")) {
+            Box::new(JsonLogger::new(log_level).with_module_level("sentry", sdk_level))
+        } else {
+            Box::new(
+                SimpleLogger::new()
+                    .with_level(log_level)
+                    .with_module_level("sentry", sdk_level),
+            )
+        };
+    let mut max_level = log_level.max(sdk_level);
+    if !SELF_MONITORING_DSN.is_empty() {
+        logger = Box::new(SelfMonitoringLogger::new(logger));
+        max_level = max_level.max(LevelFilter::Warn);
+    }
+    log::set_max_level(max_level);
+    log::set_boxed_logger(logger).unwrap();
 
-    let client = Client::try_default().await?;
-    loop {
-        if let Err(e) = watch_loop(client.clone()).await {
-            error!("{}", e.to_string());
-            sleep(Duration::from_secs(5)).await;
+    let config_file = matches
+        .opt_str("c")
+        .or_else(|| env::var("CONFIG_FILE").ok())
+        .filter(|f| !f.is_empty());
+    let config = match config_file {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+
+    let contexts = match matches.opt_str("context") {
+        Some(contexts) => contexts
+            .split(',')
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect(),
+        None => list_env("KUBE_CONTEXT", None),
+    };
+    let kubeconfig_dir = env::var("KUBECONFIG_DIR").ok().filter(|d| !d.is_empty());
+    let clusters = cluster::clusters(contexts, kubeconfig_dir.as_deref())?;
+    match matches.free.first().map(String::as_str) {
+        None => {}
+        Some("export") => {
+            if clusters.len() > 1 {
+                warn!("Exporting the events of the first cluster only");
+            }
+
+            let duration = matches.opt_get::<u64>("d")?.map(Duration::from_secs);
+            return export(&clusters[0], &config, matches.opt_str("o"), duration).await;
+        }
+        Some("tail") => {
+            if clusters.len() > 1 {
+                warn!("Tailing the events of the first cluster only");
+            }
+
+            let duration = matches.opt_get::<u64>("d")?.map(Duration::from_secs);
+            return tail(&clusters[0], &config, duration).await;
+        }
+        Some("loadtest") => {
+            let rate = matches.opt_get_default("rate", 100)?;
+            let duration = Duration::from_secs(matches.opt_get_default("d", 60)?);
+            let capture = matches
+                .opt_str("capture-to")
+                .map(|dir| CaptureServer::start(Some(dir.into())))
+                .transpose()?;
+            return loadtest(&clusters[0], &config, capture, rate, duration).await;
+        }
+        Some(command) => {
+            print_usage(&program, opts);
+            anyhow::bail!("Unknown command \"{}\"", command);
+        }
+    }
+
+    if *SHARD_COUNT > 1 {
+        let Some(shard) = *SHARD else {
+            anyhow::bail!("Cannot determine the shard of this replica: set SHARD_INDEX (lower than SHARD_COUNT) or run as a StatefulSet");
+        };
+        info!("Processing the events of the namespaces of shard {}", shard);
+    }
+
+    if !METRICS_ADDR.is_empty() {
+        tokio::spawn(server::serve(METRICS_ADDR.parse()?));
+    }
+    if !HEALTH_ADDR.is_empty() && *HEALTH_ADDR != *METRICS_ADDR {
+        tokio::spawn(server::serve(HEALTH_ADDR.parse()?));
+    }
+    if !DIAGNOSTICS_ADDR.is_empty() {
+        #[cfg(feature = "diagnostics")]
+        {
+            diagnostics::init().await;
+            tokio::spawn(diagnostics::serve(DIAGNOSTICS_ADDR.parse()?));
+        }
+        #[cfg(not(feature = "diagnostics"))]
+        warn!("DIAGNOSTICS_ADDR is ignored: sentry-kubernetes is built without the \"diagnostics\" feature");
+    }
+    if !ADMIN_ADDR.is_empty() {
+        if ADMIN_TOKEN.is_empty() {
+            anyhow::bail!("ADMIN_TOKEN is required to expose the admin API");
+        }
+        tokio::spawn(admin::serve(ADMIN_ADDR.parse()?, ADMIN_TOKEN.clone()));
+    }
+
+    let capture = match matches.opt_str("capture-to") {
+        Some(dir) => {
+            let server = CaptureServer::start(Some(dir.clone().into()))?;
+            info!("Capturing sentry envelopes to {}", dir);
+            Some(server)
+        }
+        None => None,
+    };
+
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    let shutdown_tx = Arc::new(shutdown_tx);
+    let signal_tx = shutdown_tx.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down");
+        let _ = signal_tx.send(true);
+    });
+
+    // Only the leader watches the events. The lease is held in the (first) cluster of the controller.
+    let mut leadership = None;
+    if *LEADER_ELECTION {
+        let identity = env::var("HOSTNAME")
+            .unwrap_or_else(|_| format!("sentry-kubernetes-{}", std::process::id()));
+        let leader = Arc::new(LeaderElection::new(
+            kube_client(&clusters[0], None).await?,
+            &LEADER_ELECTION_LEASE,
+            &identity,
+            Duration::from_secs(*LEADER_ELECTION_LEASE_DURATION),
+        ));
+
+        info!("Waiting for the leadership");
+        HEALTH.standby(true);
+        tokio::select! {
+            _ = leader.acquire() => {}
+            _ = wait_shutdown(shutdown.clone()) => return Ok(()),
+        }
+        HEALTH.standby(false);
+
+        let keeper = leader.clone();
+        let task = tokio::spawn(async move {
+            keeper.keep().await;
+            error!("Lost the leadership, shutting down");
+            let _ = shutdown_tx.send(true);
+        });
+        leadership = Some((leader, task));
+    }
+
+    let result = watch_loop(&clusters, &config, capture.as_ref(), shutdown).await;
+    if let Some((leader, task)) = leadership {
+        task.abort();
+        leader.release().await;
+    }
+
+    result
+}
+
+/// Creates the kubernetes client from the kubeconfig (KUBECONFIG or ~/.kube/config) or the in-cluster
+/// configuration, with the timeouts and the rate limit of the requests set through the environment.
+/// If a kubeconfig file or a context is given, it is used instead of the current one.
+/// The deprecation warnings returned by the API server are recorded in `deprecations`, if given.
+async fn kube_client(
+    cluster: &Cluster,
+    deprecations: Option<Arc<DeprecationWarnings>>,
+) -> Result<Client> {
+    let options = KubeConfigOptions {
+        context: cluster.context.clone(),
+        ..Default::default()
+    };
+    let mut config = match (&cluster.kubeconfig, &cluster.context) {
+        (Some(path), _) => {
+            kube::Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?
+        }
+        (None, Some(_)) => kube::Config::from_kubeconfig(&options).await?,
+        (None, None) => kube::Config::infer().await?,
+    };
+    config.connect_timeout = Some(Duration::from_secs(*KUBE_CONNECT_TIMEOUT));
+    config.read_timeout = Some(Duration::from_secs(*KUBE_READ_TIMEOUT));
+    if u64::from(*WATCH_TIMEOUT) >= *KUBE_READ_TIMEOUT {
+        warn!("WATCH_TIMEOUT should be lower than KUBE_READ_TIMEOUT, or the watches are interrupted by the read timeout");
+    }
+
+    let builder = ClientBuilder::try_from(config)?.with_layer(&DeprecationLayer::new(deprecations));
+    if *KUBE_CLIENT_QPS <= 0.0 {
+        return Ok(builder.build());
+    }
+
+    // At most "burst" requests are sent in each window of burst/qps seconds.
+    let burst = KUBE_CLIENT_BURST.max(1);
+    let period = Duration::from_secs_f32(burst as f32 / *KUBE_CLIENT_QPS);
+    Ok(builder
+        .with_layer(&RateLimitLayer::new(burst, period))
+        .build())
+}
+
+/// Resolves when SIGTERM or SIGINT is received.
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = terminate => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+async fn wait_shutdown(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            future::pending::<()>().await;
         }
     }
 }
@@ -71,12 +629,55 @@ fn list_env(name: &str, default: Option<String>) -> Vec<String> {
         .collect::<Vec<_>>()
 }
 
-async fn watch_loop(client: Client) -> Result<()> {
-    info!("Initializing Sentry client");
-    let dsn = Dsn::from_str(&SENTRY_DSN)?;
-    let _sentry = sentry::init(sentry::ClientOptions {
-        dsn: Some(dsn),
-        environment: if ENV.is_empty() {
+fn map_env(name: &str) -> BTreeMap<String, String> {
+    list_env(name, None)
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Reads the default sample rate (SENTRY_SAMPLE_RATE) and the per-level ones (SENTRY_LEVEL_SAMPLE_RATES).
+fn sample_rates() -> SampleRates {
+    let parse = |name: &str, value: &str| match value.parse::<f32>() {
+        Ok(rate) => Some(rate),
+        Err(_) => {
+            warn!("Invalid sample rate \"{}\" in {}, ignoring", value, name);
+            None
+        }
+    };
+
+    let default = env::var("SENTRY_SAMPLE_RATE")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .and_then(|v| parse("SENTRY_SAMPLE_RATE", &v))
+        .unwrap_or(1.0);
+    let levels = map_env("SENTRY_LEVEL_SAMPLE_RATES")
+        .into_iter()
+        .filter_map(|(level, rate)| {
+            Some((
+                level.to_lowercase(),
+                parse("SENTRY_LEVEL_SAMPLE_RATES", &rate)?,
+            ))
+        })
+        .collect();
+
+    SampleRates::new(default, levels)
+}
+
+fn client_options(config: &Config) -> sentry::ClientOptions {
+    let transport = &config.transport;
+    sentry::ClientOptions {
+        transport: Some(Arc::new(HttpTransportFactory::new(transport.clone()))),
+        shutdown_timeout: Duration::from_secs(transport.shutdown_timeout),
+        debug: *SENTRY_DEBUG,
+        before_send: Some(sink::omit_server_name(exempt_startup_event(
+            BeforeSendRules::new(config.before_send.clone())
+                .before_send(sample_rates().before_send()),
+        ))),
+        environment: if ENV.is_empty() || ENV.contains("{{") {
             None
         } else {
             Some(ENV.clone().into())
@@ -87,44 +688,841 @@ async fn watch_loop(client: Client) -> Result<()> {
             Some(RELEASE.clone().into())
         },
         ..Default::default()
+    }
+}
+
+/// Watches the events of the clusters until a shutdown is requested, then flushes the sinks and the
+/// sentry clients. The sentry clients are created once and shared by the watches of all the clusters.
+async fn watch_loop(
+    clusters: &[Cluster],
+    config: &Config,
+    capture: Option<&CaptureServer>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!("Initializing Sentry client");
+    let dsns = if let Some(capture) = capture {
+        vec![capture.dsn()]
+    } else if config.routing.default_dsn.is_empty() {
+        list_env("DSN", None)
+    } else {
+        config.routing.default_dsn.clone()
+    };
+
+    // The own problems of the controller are neither filtered nor sampled.
+    if !SELF_MONITORING_DSN.is_empty() {
+        self_monitoring::init(sentry::ClientOptions {
+            dsn: Some(Dsn::from_str(&SELF_MONITORING_DSN)?),
+            release: Some(format!("sentry-kubernetes@{}", env!("CARGO_PKG_VERSION")).into()),
+            before_send: None,
+            ..client_options(config)
+        });
+    }
+
+    let main_dsn = dsns.first().cloned().unwrap_or_default();
+    let _sentry = sentry::init(sentry::ClientOptions {
+        dsn: Some(Dsn::from_str(&main_dsn)?),
+        ..client_options(config)
+    });
+    let client_pool =
+        Arc::new(ClientPool::new(client_options(config)).capacity(*SENTRY_CLIENT_POOL_SIZE));
+    if let Some(main_client) = Hub::current().client() {
+        client_pool.insert(&main_dsn, main_client);
+        HEALTH.sentry_initialized();
+    }
+
+    if *SEND_STARTUP_EVENT {
+        let event = startup_event(clusters);
+        for dsn in &dsns {
+            if let Some(uuid) = client_pool.capture_event(dsn, event.clone()) {
+                info!("Sent the startup test event (uuid = {}) to {}", uuid, dsn);
+            }
+        }
+    }
+
+    // When capturing, the routing rules are ignored: all the events go to the capture server.
+    let router = match capture {
+        Some(_) => Router::default(),
+        None => Router::from(&config.routing),
+    };
+    // The issues are resolved and assigned through the sentry API, if an auth token is given.
+    let sentry_api = (!SENTRY_AUTH_TOKEN.is_empty() && !SENTRY_ORG.is_empty() && capture.is_none())
+        .then(|| {
+            Arc::new(SentryApi::new(
+                SENTRY_API_URL.clone(),
+                &main_dsn,
+                &SENTRY_ORG,
+                &SENTRY_AUTH_TOKEN,
+            ))
+        });
+    let resolver = sentry_api
+        .clone()
+        .map(|api| Arc::new(IssueResolver::new(api)));
+    let assigner = sentry_api
+        .filter(|_| *ASSIGN_ISSUES)
+        .map(|api| Arc::new(IssueAssigner::new(api)));
+    let pipeline = Pipeline {
+        config,
+        router: router.default_dsns(&dsns),
+        client_pool: client_pool.clone(),
+        annotation_routing: *ANNOTATION_ROUTING && capture.is_none(),
+        resolver,
+        assigner,
+    };
+
+    // The stalled watches are logged even if no liveness probe checks them.
+    let stall_check = (*WATCH_STALL_TIMEOUT > 0).then(|| {
+        HEALTH.stall_timeout(Some(Duration::from_secs(*WATCH_STALL_TIMEOUT)));
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(WATCHER_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                HEALTH.is_live();
+            }
+        })
     });
+    let heartbeat = (!HEARTBEAT_MONITOR.is_empty() && capture.is_none()).then(|| {
+        let max_silence =
+            (*HEARTBEAT_MAX_SILENCE > 0).then(|| Duration::from_secs(*HEARTBEAT_MAX_SILENCE));
+        let heartbeat = Heartbeat::new(
+            &HEARTBEAT_MONITOR,
+            Duration::from_secs((*HEARTBEAT_INTERVAL).max(1)),
+            max_silence,
+        );
+        tokio::spawn(heartbeat.run())
+    });
+
+    let clusters_watch = future::try_join_all(
+        clusters
+            .iter()
+            .map(|cluster| watch_cluster(cluster, &pipeline, shutdown.clone())),
+    );
+    if AUDIT_WEBHOOK_ADDR.is_empty() {
+        clusters_watch.await?;
+    } else {
+        let audit = receive_audit_events(&clusters[0], &pipeline, shutdown.clone());
+        future::try_join(clusters_watch, audit).await?;
+    }
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.abort();
+    }
+    if let Some(stall_check) = stall_check {
+        stall_check.abort();
+    }
+
+    let timeout = Duration::from_secs(config.transport.shutdown_timeout);
+    tokio::task::spawn_blocking(move || client_pool.close(timeout)).await?;
+
+    Ok(())
+}
+
+/// The event sent on startup to verify the delivery to sentry, listing the watched clusters.
+fn startup_event(clusters: &[Cluster]) -> sentry::protocol::Event<'static> {
+    let clusters = clusters
+        .iter()
+        .map(|c| c.name.clone().unwrap_or_else(|| CLUSTER_NAME.clone()))
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    let mut tags = BTreeMap::from([("startup_test".to_string(), "true".to_string())]);
+    if !clusters.is_empty() {
+        tags.insert("cluster".to_string(), clusters.join(","));
+    }
 
-    info!("Staring kubernetes watcher");
+    sentry::protocol::Event {
+        level: Level::Info,
+        message: Some(format!(
+            "sentry-kubernetes {} started: this is a test event verifying the delivery to sentry",
+            env!("CARGO_PKG_VERSION")
+        )),
+        logger: Some("sentry-kubernetes".to_string()),
+        fingerprint: vec!["sentry-kubernetes".into(), "startup".into()].into(),
+        tags,
+        ..Default::default()
+    }
+}
+
+/// Exempts the startup test event from the before_send rules and the sampling: it must always be
+/// delivered.
+fn exempt_startup_event(next: Option<BeforeSend>) -> Option<BeforeSend> {
+    let next = next?;
+    Some(Arc::new(move |event: sentry::protocol::Event<'static>| {
+        if event.tags.get("startup_test").map(String::as_str) == Some("true") {
+            Some(event)
+        } else {
+            next(event)
+        }
+    }))
+}
+
+/// What the pipelines of the clusters share: the configuration and the sentry clients.
+struct Pipeline<'a> {
+    config: &'a Config,
+    router: Router,
+    client_pool: Arc<ClientPool>,
+    annotation_routing: bool,
+    resolver: Option<Arc<IssueResolver>>,
+    assigner: Option<Arc<IssueAssigner>>,
+}
+
+/// Watches the events of a cluster until a shutdown is requested, then flushes the sinks.
+/// If the watcher fails, only the watch is restarted, with an exponential backoff.
+/// If the credentials are rejected, the kubernetes client is recreated.
+async fn watch_cluster(
+    cluster: &Cluster,
+    pipeline: &Pipeline<'_>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!(
+        "Staring kubernetes watcher of cluster {}",
+        cluster.display_name()
+    );
+
+    HEALTH.watch_started(cluster.display_name());
+    let deprecations = WATCH_DEPRECATED_APIS.then(|| Arc::new(DeprecationWarnings::default()));
+    let mut client = kube_client(cluster, deprecations.clone()).await?;
+    let mut backfill_window =
+        (*BACKFILL_MINUTES > 0).then(|| Duration::from_secs(*BACKFILL_MINUTES * 60));
+    let mut backoff = MIN_WATCH_BACKOFF;
+    loop {
+        let mut builder = processor_builder(client.clone(), cluster, pipeline.config)
+            .router(pipeline.router.clone())
+            .annotation_routing(pipeline.annotation_routing)
+            .sinks(sink::build(&pipeline.config.sinks, &pipeline.client_pool));
+        if let Some(resolver) = pipeline.resolver.clone() {
+            builder = builder.sinks(vec![Box::new(move |event: &SentryEvent| {
+                resolver.record(event)
+            })]);
+        }
+        if let Some(assigner) = pipeline.assigner.clone() {
+            builder =
+                builder
+                    .issue_owners(true)
+                    .sinks(vec![Box::new(move |event: &SentryEvent| {
+                        assigner.assign(event)
+                    })]);
+        }
+        if !CHECKPOINT_CONFIGMAP.is_empty() {
+            let interval = Duration::from_secs(*CHECKPOINT_INTERVAL);
+            // Each shard saves the time of its own last processed event.
+            let name = match *SHARD {
+                Some(shard) => shard.resource_name(&CHECKPOINT_CONFIGMAP),
+                None => CHECKPOINT_CONFIGMAP.clone(),
+            };
+            let checkpoint = Checkpoint::load(client.clone(), &name, interval).await;
+            builder = builder.checkpoint(checkpoint);
+        }
+        let processor: Processor = builder.into();
+        ADMIN.filters(cluster.display_name(), processor.filters());
+        let registry = resource_watchers(&client, deprecations.clone(), pipeline.resolver.clone());
 
+        if let Some(window) = backfill_window.take() {
+            tokio::select! {
+                result = backfill(client.clone(), &processor, window) => {
+                    if let Err(e) = result {
+                        warn!("Cannot backfill the events of cluster {}: {}", cluster.display_name(), e);
+                    }
+                }
+                _ = wait_shutdown(shutdown.clone()) => {}
+            }
+        }
+
+        let unauthorized = loop {
+            let started = Instant::now();
+            tokio::select! {
+                result = watch(cluster, client.clone(), &processor, &registry) => match result {
+                    Ok(()) => error!("Kubernetes event watcher of cluster {} stopped", cluster.display_name()),
+                    Err(e) if e.is::<Unauthorized>() => break true,
+                    Err(e) => error!("Cluster {}: {}", cluster.display_name(), e),
+                },
+                _ = wait_shutdown(shutdown.clone()) => break false,
+            }
+
+            // A watch which has been running for a while is not failing repeatedly.
+            if started.elapsed() > MAX_WATCH_BACKOFF {
+                backoff = MIN_WATCH_BACKOFF;
+            }
+
+            METRICS.watcher_restart();
+            info!("Restarting kubernetes watcher in {}s", backoff.as_secs());
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = wait_shutdown(shutdown.clone()) => break false,
+            }
+            backoff = (backoff * 2).min(MAX_WATCH_BACKOFF);
+        };
+
+        info!("Flushing queued events");
+        processor.close().await;
+        if !unauthorized {
+            return Ok(());
+        }
+
+        // The client (and the processor using it) is recreated, reading the credentials again
+        // (ex: a rotated service account token).
+        warn!(
+            "Cluster {}: {}, recreating the kubernetes client in {}s",
+            cluster.display_name(),
+            Unauthorized,
+            backoff.as_secs()
+        );
+        METRICS.watcher_restart();
+        tokio::select! {
+            _ = sleep(backoff) => {}
+            _ = wait_shutdown(shutdown.clone()) => return Ok(()),
+        }
+        backoff = (backoff * 2).min(MAX_WATCH_BACKOFF);
+        client = kube_client(cluster, deprecations.clone()).await?;
+    }
+}
+
+/// Receives the audit webhook batches until a shutdown is requested, processing the selected audit
+/// entries through the pipeline of the (first) cluster, then flushes the sinks.
+async fn receive_audit_events(
+    cluster: &Cluster,
+    pipeline: &Pipeline<'_>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    if AUDIT_WEBHOOK_TOKEN.is_empty() {
+        anyhow::bail!("AUDIT_WEBHOOK_TOKEN is required to receive the audit events");
+    }
+    let addr = AUDIT_WEBHOOK_ADDR.parse()?;
+    let limits = WebhookLimits {
+        token: AUDIT_WEBHOOK_TOKEN.clone(),
+        max_body_size: *AUDIT_WEBHOOK_MAX_BODY_SIZE,
+    };
+    let client = kube_client(cluster, None).await?;
+    let processor: Processor = processor_builder(client, cluster, pipeline.config)
+        .router(pipeline.router.clone())
+        .annotation_routing(pipeline.annotation_routing)
+        .sinks(sink::build(&pipeline.config.sinks, &pipeline.client_pool))
+        .into();
+
+    let (sender, mut events) = mpsc::channel(*EVENT_QUEUE_SIZE);
+    let receiver = Arc::new(AuditReceiver::new(AUDIT_EVENTS.clone()));
+    let server = tokio::spawn(audit::serve(addr, limits, receiver, sender));
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => processor.process_resource_event(event).await,
+                None => break,
+            },
+            _ = wait_shutdown(shutdown.clone()) => break,
+        }
+    }
+
+    server.abort();
+    processor.close().await;
+
+    Ok(())
+}
+
+/// Dumps the events passing the filters as NDJSON, for the given duration or until interrupted.
+async fn export(
+    cluster: &Cluster,
+    config: &Config,
+    output: Option<String>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    info!("Exporting events");
+
+    let client = kube_client(cluster, None).await?;
+    let processor: Processor = processor_builder(client.clone(), cluster, config)
+        .router(Router::from(&config.routing))
+        .sinks(vec![Box::new(NdjsonSink::new(NdjsonConfig {
+            path: output,
+            ..Default::default()
+        }))])
+        .into();
+
+    let deadline = async {
+        match duration {
+            Some(duration) => sleep(duration).await,
+            None => future::pending().await,
+        }
+    };
+
+    let registry = resource_watchers(&client, None, None);
+    tokio::select! {
+        result = watch(cluster, client, &processor, &registry) => result?,
+        _ = deadline => {}
+        _ = shutdown_signal() => {}
+    }
+
+    processor.close().await;
+    Ok(())
+}
+
+/// Prints the decision of the pipeline on each event, for the given duration or until interrupted.
+/// The events are routed but not sent.
+async fn tail(cluster: &Cluster, config: &Config, duration: Option<Duration>) -> Result<()> {
+    info!("Tailing events");
+
+    let client = kube_client(cluster, None).await?;
+    let processor: Processor = processor_builder(client.clone(), cluster, config)
+        .router(Router::from(&config.routing))
+        .tracer(Box::new(|event, verdict| {
+            println!("{}", tail::verdict_line(event, verdict))
+        }))
+        .into();
+
+    let deadline = async {
+        match duration {
+            Some(duration) => sleep(duration).await,
+            None => future::pending().await,
+        }
+    };
+
+    let registry = resource_watchers(&client, None, None);
+    tokio::select! {
+        result = watch(cluster, client, &processor, &registry) => result?,
+        _ = deadline => {}
+        _ = shutdown_signal() => {}
+    }
+
+    processor.close().await;
+    Ok(())
+}
+
+/// Sends synthetic events through the pipeline of the cluster at the given rate for the given
+/// duration, to the capture server if given (else to sentry), then prints the throughput and the
+/// latencies. The enrichment is disabled, as the involved objects do not exist.
+async fn loadtest(
+    cluster: &Cluster,
+    config: &Config,
+    capture: Option<CaptureServer>,
+    rate: u64,
+    duration: Duration,
+) -> Result<()> {
+    info!(
+        "Load testing with {} events/s for {}s",
+        rate,
+        duration.as_secs()
+    );
+
+    let dsns = if let Some(capture) = &capture {
+        vec![capture.dsn()]
+    } else if config.routing.default_dsn.is_empty() {
+        list_env("DSN", None)
+    } else {
+        config.routing.default_dsn.clone()
+    };
+    let main_dsn = dsns.first().cloned().unwrap_or_default();
+    let _sentry = sentry::init(sentry::ClientOptions {
+        dsn: Some(Dsn::from_str(&main_dsn)?),
+        ..client_options(config)
+    });
+    let client_pool =
+        Arc::new(ClientPool::new(client_options(config)).capacity(*SENTRY_CLIENT_POOL_SIZE));
+    if let Some(main_client) = Hub::current().client() {
+        client_pool.insert(&main_dsn, main_client);
+    }
+
+    let router = match capture {
+        Some(_) => Router::default(),
+        None => Router::from(&config.routing),
+    };
+    let client = kube_client(cluster, None).await?;
+    let processor: Processor = processor_builder(client, cluster, config)
+        .router(router.default_dsns(&dsns))
+        .enrichment(false)
+        .sinks(sink::build(&config.sinks, &client_pool))
+        .into();
+
+    let report = loadtest::run(&processor, rate, duration, *PROCESS_CONCURRENCY).await;
+    processor.close().await;
+    let timeout = Duration::from_secs(config.transport.shutdown_timeout);
+    tokio::task::spawn_blocking(move || client_pool.close(timeout)).await?;
+
+    println!("{}", report);
+    Ok(())
+}
+
+/// Creates a processor builder with the filters configured through the environment.
+fn processor_builder(client: Client, cluster: &Cluster, config: &Config) -> ProcessorBuilder {
+    let cluster_name = cluster.name.as_deref().unwrap_or(&CLUSTER_NAME);
     let event_namespaces = list_env("EVENT_NAMESPACES", None);
     let exclude_components = list_env("COMPONENT_FILTER", None);
     let exclude_reasons = list_env("REASON_FILTER", None);
+    let exclude_messages = message_patterns(&env::var("MESSAGE_FILTER").unwrap_or_default());
     let exclude_namespaces = list_env("EVENT_NAMESPACES_EXCLUDED", None);
     let event_levels = list_env("EVENT_LEVELS", Some("warning,error".to_string()));
+    let environment =
+        EnvironmentResolver::new(&ENV, map_env("NAMESPACE_ENVIRONMENTS"), cluster_name);
+    let cache_size = env::var("ENRICHMENT_CACHE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1000);
+    let cache_ttl = env::var("ENRICHMENT_CACHE_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    let max_event_age = env::var("MAX_EVENT_AGE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let enrichment_timeout = env::var("ENRICHMENT_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
 
     info!("Only reporting events of levels: {:?}", &event_levels);
-    let processor: Processor<_> = Processor::builder(client.clone(), |sentry_event| {
-        let uuid = sentry::capture_event(sentry::protocol::Event::from(sentry_event));
-        debug!(target: "sentry_kubernetes::sentry_client", "Captured event (uuid = {})", uuid);
-    })
-    .event_namespaces(event_namespaces, exclude_namespaces)
-    .event_components(exclude_components)
-    .event_reasons(exclude_reasons)
-    .event_levels(event_levels)
-    .into();
-
-    let api = Api::<Event>::all(client);
-    watcher(api, Default::default())
-        .applied_objects()
-        .try_for_each(|event| async {
-            debug!(target: "sentry_kubernetes::kubernetes_event_watcher", "Processing event: {:#?}", event);
-            processor.process(event).await;
-
-            Ok(())
+    let builder = Processor::builder(client)
+        .event_namespaces(event_namespaces, exclude_namespaces)
+        .event_components(exclude_components)
+        .event_reasons(exclude_reasons)
+        .event_messages(exclude_messages)
+        .event_levels(event_levels)
+        .environment(environment)
+        .cluster(cluster_name)
+        .object_cache(cache_size, Duration::from_secs(cache_ttl))
+        .enrichment(!*DISABLE_ENRICHMENT)
+        .deletion_correlation(*DELETION_CORRELATION)
+        .enrichment_timeout(Duration::from_secs(enrichment_timeout))
+        .ignore_existing_events(*IGNORE_EXISTING_EVENTS)
+        .policy(config.policy.clone());
+    let builder = match *SHARD {
+        Some(shard) => builder.shard(shard),
+        None => builder,
+    };
+    let builder = match *SPOT_INTERRUPTION_LEVEL {
+        Some(level) => builder.spot_interruption_level(level),
+        None => builder,
+    };
+    if max_event_age > 0 {
+        builder.max_event_age(Duration::from_secs(max_event_age))
+    } else {
+        builder
+    }
+}
+
+/// Parses the patterns of MESSAGE_FILTER, one per line (the messages can contain commas),
+/// skipping the invalid regexes.
+fn message_patterns(patterns: &str) -> Vec<MessagePattern> {
+    patterns
+        .lines()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .filter_map(|pattern| match pattern.parse() {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                warn!(
+                    "Invalid MESSAGE_FILTER pattern \"{}\", ignoring: {}",
+                    pattern, e
+                );
+                None
+            }
         })
+        .collect()
+}
+
+/// Watches the events, processing up to PROCESS_CONCURRENCY events at the same time in each stage
+/// of the pipeline: a slow lookup does not delay the following events.
+/// The watch stream and the processing are decoupled by a bounded queue (EVENT_QUEUE_SIZE).
+/// If EVENT_NAMESPACES is set, a watcher is opened for each namespace instead of a cluster-wide one.
+/// EVENT_FIELD_SELECTOR filters the events in the API server (ex: type=Warning).
+/// The resources of the registry are watched alongside the events.
+async fn watch(
+    cluster: &Cluster,
+    client: Client,
+    processor: &Processor,
+    registry: &WatcherRegistry,
+) -> Result<()> {
+    let (sender, receiver) = queue::channel(*EVENT_QUEUE_SIZE, *EVENT_QUEUE_OVERFLOW);
+    let api = events_api(&client).await;
+    let namespaces = list_env("EVENT_NAMESPACES", None);
+    let config = watcher::Config {
+        bookmarks: true,
+        ..Default::default()
+    }
+    .timeout(*WATCH_TIMEOUT);
+
+    // The resource events are few, they are processed as they are received.
+    let mut resource_events = registry.watch(client.clone(), config.clone());
+    let resources = async {
+        while let Some(event) = resource_events.next().await {
+            match event {
+                Ok(event) => processor.process_resource_event(event).await,
+                Err(e) if is_unauthorized(&e) => return Err(Unauthorized),
+                Err(_) => METRICS.watcher_restart(),
+            }
+        }
+
+        // The events are still watched if no resource is registered.
+        future::pending().await
+    };
+
+    // Watch errors do not end the stream: the watchers resume from the last seen resource version,
+    // kept up to date by the bookmarks, and relist the events only when it is too old.
+    // The sender is dropped when the watchers end, ending the consumer once the queue is drained.
+    // The watch is established once the events have been listed, and stalled if it sees nothing.
+    let cluster_name = cluster.display_name().to_string();
+    let seen = move |listed| HEALTH.watch_activity(&cluster_name, listed);
+    let producer = async move {
+        let config = config.fields(&EVENT_FIELD_SELECTOR);
+        let mut events = api.watch(client, &namespaces, config, seen);
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => sender.push(event).await,
+                Err(e) if is_unauthorized(&e) => return Err(Unauthorized),
+                Err(e) => {
+                    warn!("Kubernetes event watcher error, resuming: {}", e);
+                    METRICS.watcher_restart();
+                }
+            }
+        }
+
+        Ok(())
+    };
+
+    // The events go through the stages of the pipeline, connected by bounded channels.
+    let received = receiver.inspect(|event| {
+        debug!(target: "sentry_kubernetes::kubernetes_event_watcher", "Processing event: {:#?}", event);
+    });
+    let consumer = processor.run(received, *PROCESS_CONCURRENCY);
+
+    // The queued events are processed even if the credentials have been rejected.
+    tokio::select! {
+        (result, _) = future::join(producer, consumer) => result?,
+        result = resources => result?,
+    }
+
+    Ok(())
+}
+
+/// The resources watched besides the events.
+fn resource_watchers(
+    client: &Client,
+    deprecations: Option<Arc<DeprecationWarnings>>,
+    resolver: Option<Arc<IssueResolver>>,
+) -> WatcherRegistry {
+    let mut registry = WatcherRegistry::default();
+    if *WATCH_POD_STATUS {
+        let pods = PodStatusWatcher::new();
+        registry.register("pod status", move |pod: &Pod| pods.events(pod));
+    }
+    if *WATCH_NODE_CONDITIONS {
+        let debounce = Duration::from_secs(*NODE_CONDITION_DEBOUNCE);
+        let nodes = Arc::new(NodeConditionWatcher::new(debounce));
+        let debounced = nodes.clone();
+        registry
+            .register("node conditions", move |node: &Node| nodes.events(node))
+            .register_periodic("node conditions", WATCHER_CHECK_INTERVAL, move || {
+                future::ready(debounced.due())
+            });
+    }
+    if *WATCH_NODE_LIFECYCLE {
+        let nodes = Arc::new(NodeLifecycleWatcher::new(client.clone()));
+        let removed = nodes.clone();
+        registry
+            .register("node lifecycle", move |node: &Node| nodes.events(node))
+            .register_periodic("node lifecycle", NODE_REMOVAL_CHECK_INTERVAL, move || {
+                let removed = removed.clone();
+                async move { removed.removed().await }
+            });
+    }
+    if *WATCH_JOB_FAILURES {
+        let jobs = Arc::new(JobFailureWatcher::new(client.clone()));
+        registry.register_async("job failures", move |job: Job| {
+            let jobs = jobs.clone();
+            async move { jobs.events(job).await }
+        });
+    }
+    if *WATCH_CRON_MONITORS {
+        let monitors = Arc::new(CronMonitors::new(client.clone()));
+        registry.register_async("cron monitors", move |job: Job| {
+            let monitors = monitors.clone();
+            async move {
+                monitors.check_in(job).await;
+                vec![]
+            }
+        });
+    }
+    if *WATCH_STUCK_PODS {
+        let pods = Arc::new(StuckPodWatcher::new(client.clone(), *STUCK_POD_THRESHOLDS));
+        let stuck = pods.clone();
+        registry
+            .register("stuck pods", move |pod: &Pod| {
+                pods.update(pod);
+                vec![]
+            })
+            .register_periodic("stuck pods", WATCHER_CHECK_INTERVAL, move || {
+                let stuck = stuck.clone();
+                async move { stuck.due().await }
+            });
+    }
+    if *WATCH_ROLLOUTS {
+        let deadline = Duration::from_secs(*ROLLOUT_DEADLINE);
+        let rollouts = Arc::new(RolloutWatcher::new(client.clone(), deadline));
+        let (deployments, stateful_sets, stalled) =
+            (rollouts.clone(), rollouts.clone(), rollouts.clone());
+        registry
+            .register("deployments", move |deployment: &Deployment| {
+                deployments.update(Rollout::from(deployment))
+            })
+            .register("statefulsets", move |stateful_set: &StatefulSet| {
+                stateful_sets.update(Rollout::from(stateful_set))
+            })
+            .register_periodic("rollouts", WATCHER_CHECK_INTERVAL, move || {
+                let stalled = stalled.clone();
+                async move { stalled.due().await }
+            });
+    }
+    if *WATCH_ROLLOUT_TRANSACTIONS {
+        let transactions = Arc::new(RolloutTransactions::default());
+        let (replica_sets, pods, expired) =
+            (transactions.clone(), transactions.clone(), transactions);
+        registry
+            .register("rollout replicasets", move |replica_set: &ReplicaSet| {
+                replica_sets.replica_set(replica_set);
+                vec![]
+            })
+            .register("rollout pods", move |pod: &Pod| {
+                pods.pod(pod);
+                vec![]
+            })
+            .register_periodic("rollout transactions", WATCHER_CHECK_INTERVAL, move || {
+                expired.due();
+                future::ready(vec![])
+            });
+    }
+    if *WATCH_RELEASE_HEALTH {
+        let window = Duration::from_secs(*RELEASE_HEALTH_WINDOW);
+        let sessions = Arc::new(ReleaseSessions::new(window));
+        let (replica_sets, pods, ended) = (sessions.clone(), sessions.clone(), sessions);
+        registry
+            .register("release replicasets", move |replica_set: &ReplicaSet| {
+                replica_sets.replica_set(replica_set);
+                vec![]
+            })
+            .register("release sessions", move |pod: &Pod| {
+                pods.pod(pod);
+                vec![]
+            })
+            .register_periodic("release sessions", WATCHER_CHECK_INTERVAL, move || {
+                ended.due();
+                future::ready(vec![])
+            });
+    }
+    if *WATCH_PENDING_CLAIMS {
+        let threshold = Duration::from_secs(*CLAIM_PENDING_THRESHOLD);
+        let claims = Arc::new(PendingClaimWatcher::new(client.clone(), threshold));
+        let stalled = claims.clone();
+        registry
+            .register("pending claims", move |claim: &PersistentVolumeClaim| {
+                claims.update(claim);
+                vec![]
+            })
+            .register_periodic("pending claims", WATCHER_CHECK_INTERVAL, move || {
+                let stalled = stalled.clone();
+                async move { stalled.due().await }
+            });
+    }
+    if *WATCH_DISRUPTION_BUDGETS {
+        let threshold = Duration::from_secs(*DISRUPTION_BUDGET_THRESHOLD);
+        let budgets = Arc::new(DisruptionBudgetWatcher::new(client.clone(), threshold));
+        let blocked = budgets.clone();
+        registry
+            .register("disruption budgets", move |budget: &PodDisruptionBudget| {
+                budgets.update(budget);
+                vec![]
+            })
+            .register_periodic("disruption budgets", WATCHER_CHECK_INTERVAL, move || {
+                let blocked = blocked.clone();
+                async move { blocked.due().await }
+            });
+    }
+    if let Some(deprecations) = deprecations {
+        registry.register_periodic("deprecated apis", DEPRECATION_REPORT_INTERVAL, move || {
+            future::ready(deprecations.due())
+        });
+    }
+    if let Some(resolver) = resolver {
+        let pods = resolver.clone();
+        registry
+            .register_async("pod recovery", move |pod: Pod| {
+                let pods = pods.clone();
+                async move {
+                    pods.pod(&pod).await;
+                    vec![]
+                }
+            })
+            .register_async("node recovery", move |node: Node| {
+                let nodes = resolver.clone();
+                async move {
+                    nodes.node(&node).await;
+                    vec![]
+                }
+            });
+    }
+
+    registry
+}
+
+/// The API server rejected the credentials of the client (ex: an expired service account token).
+#[derive(Debug)]
+struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The kubernetes API server rejected the credentials")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+fn is_unauthorized(error: &watcher::Error) -> bool {
+    match error {
+        watcher::Error::InitialListFailed(e)
+        | watcher::Error::WatchStartFailed(e)
+        | watcher::Error::WatchFailed(e) => {
+            matches!(e, kube::Error::Api(response) if response.code == 401)
+        }
+        watcher::Error::WatchError(response) => response.code == 401,
+        _ => false,
+    }
+}
+
+/// Processes the events occurred in the given time window, in chronological order, before the watch
+/// is started. The events are received again from the watcher and discarded by the dedupe stage.
+async fn backfill(client: Client, processor: &Processor, window: Duration) -> Result<()> {
+    let since = Utc::now() - k8s_openapi::chrono::Duration::from_std(window)?;
+    let api = events_api(&client).await;
+    let params = ListParams::default().fields(&EVENT_FIELD_SELECTOR);
+    let mut events = api
+        .list(client, &list_env("EVENT_NAMESPACES", None), &params)
         .await?;
+    events.retain(|e| event_time(e).is_some_and(|time| time >= since));
+
+    events.sort_by_key(event_time);
+    info!(
+        "Backfilling {} events of the last {} minutes",
+        events.len(),
+        window.as_secs() / 60
+    );
+    processor
+        .run(stream::iter(events), *PROCESS_CONCURRENCY)
+        .await;
 
     Ok(())
 }
 
+/// The API the events are read from: EVENTS_API if set, otherwise detected.
+async fn events_api(client: &Client) -> EventsApi {
+    match *EVENTS_API {
+        Some(api) => api,
+        None => EventsApi::detect(client).await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::list_env;
+    use crate::{
+        exempt_startup_event, is_unauthorized, list_env, map_env, message_patterns, startup_event,
+    };
+    use kube::error::ErrorResponse;
+    use kube::runtime::watcher;
+    use sentry::Level;
+    use sentry_kubernetes::cluster::Cluster;
+    use std::sync::Arc;
 
     #[test]
     pub fn test_list_env() {
@@ -143,4 +1541,72 @@ mod tests {
             vec!["warning".to_string(), "x".to_string(), "error".to_string()]
         );
     }
+
+    #[test]
+    pub fn test_message_patterns() {
+        let patterns = message_patterns(
+            "Readiness probe failed: HTTP probe failed with statuscode: 503, retrying\n\n  /webhook \"[a-z.-]+\" .* timed out/\n/[/\n",
+        );
+        assert_eq!(
+            patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec![
+                "Readiness probe failed: HTTP probe failed with statuscode: 503, retrying",
+                "/webhook \"[a-z.-]+\" .* timed out/",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_map_env() {
+        assert!(map_env("THIS_SHOULD_NOT_BE_DEFINED").is_empty());
+
+        std::env::set_var("TEST_MAP_ENV", "prod=production, dev = development,invalid");
+        let map = map_env("TEST_MAP_ENV");
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("prod"), Some(&"production".to_string()));
+        assert_eq!(map.get("dev"), Some(&"development".to_string()));
+    }
+
+    #[test]
+    pub fn test_is_unauthorized() {
+        let response = |code| ErrorResponse {
+            status: "Failure".to_string(),
+            message: String::new(),
+            reason: String::new(),
+            code,
+        };
+
+        assert!(is_unauthorized(&watcher::Error::WatchError(response(401))));
+        assert!(is_unauthorized(&watcher::Error::InitialListFailed(
+            kube::Error::Api(response(401))
+        )));
+        assert!(!is_unauthorized(&watcher::Error::WatchStartFailed(
+            kube::Error::Api(response(403))
+        )));
+        assert!(!is_unauthorized(&watcher::Error::NoResourceVersion));
+    }
+
+    #[test]
+    pub fn test_startup_event() {
+        let clusters = [
+            Cluster {
+                name: Some("eu-1".to_string()),
+                ..Default::default()
+            },
+            Cluster {
+                name: Some("us-1".to_string()),
+                ..Default::default()
+            },
+        ];
+        let event = startup_event(&clusters);
+        assert_eq!(event.level, Level::Info);
+        assert_eq!(event.tags["startup_test"], "true");
+        assert_eq!(event.tags["cluster"], "eu-1,us-1");
+        assert!(event.message.clone().unwrap().contains("test event"));
+
+        let before_send = exempt_startup_event(Some(Arc::new(|_| None))).unwrap();
+        assert!(before_send(event).is_some());
+        assert!(before_send(sentry::protocol::Event::default()).is_none());
+        assert!(exempt_startup_event(None).is_none());
+    }
 }
@@ -0,0 +1,261 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// Operational counters, exposed in the prometheus text format on the /metrics endpoint.
+#[derive(Default)]
+pub struct Metrics {
+    events_received: AtomicU64,
+    events_filtered: Mutex<BTreeMap<&'static str, u64>>,
+    events_enriched: AtomicU64,
+    enrichment_timeouts: AtomicU64,
+    events_sent: AtomicU64,
+    send_failures: Mutex<BTreeMap<&'static str, u64>>,
+    events_dropped: Mutex<BTreeMap<&'static str, u64>>,
+    queue_depth: AtomicU64,
+    queue_blocked: AtomicU64,
+    /// Time spent (in seconds) and number of runs of each pipeline stage.
+    stages: Mutex<BTreeMap<&'static str, (f64, u64)>>,
+    watcher_restarts: AtomicU64,
+    /// Unix timestamp (in seconds) of the last received event, 0 if none.
+    last_event: AtomicU64,
+}
+
+impl Metrics {
+    pub fn event_received(&self) {
+        self.events_received.fetch_add(1, Ordering::Relaxed);
+        self.last_event.store(unix_now(), Ordering::Relaxed);
+    }
+
+    pub fn event_filtered(&self, filter: &'static str) {
+        *self
+            .events_filtered
+            .lock()
+            .unwrap()
+            .entry(filter)
+            .or_default() += 1;
+    }
+
+    pub fn event_enriched(&self) {
+        self.events_enriched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn enrichment_timeout(&self) {
+        self.enrichment_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_sent(&self) {
+        self.events_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn send_failure(&self, sink: &'static str) {
+        *self.send_failures.lock().unwrap().entry(sink).or_default() += 1;
+    }
+
+    pub fn event_dropped(&self, reason: &'static str) {
+        *self
+            .events_dropped
+            .lock()
+            .unwrap()
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    pub fn queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn queue_blocked(&self) {
+        self.queue_blocked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stage_duration(&self, stage: &'static str, duration: Duration) {
+        let mut stages = self.stages.lock().unwrap();
+        let (seconds, runs) = stages.entry(stage).or_default();
+        *seconds += duration.as_secs_f64();
+        *runs += 1;
+    }
+
+    pub fn watcher_restart(&self) {
+        self.watcher_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time elapsed since the last received event, if any.
+    pub fn last_event_age(&self) -> Option<Duration> {
+        match self.last_event.load(Ordering::Relaxed) {
+            0 => None,
+            last_event => Some(Duration::from_secs(unix_now().saturating_sub(last_event))),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut counter = |name: &str, help: &str, label: &str, values: Vec<(&str, u64)>| {
+            let _ = writeln!(output, "# HELP sentry_kubernetes_{} {}", name, help);
+            let _ = writeln!(output, "# TYPE sentry_kubernetes_{} counter", name);
+            for (value_label, value) in values {
+                if label.is_empty() {
+                    let _ = writeln!(output, "sentry_kubernetes_{} {}", name, value);
+                } else {
+                    let _ = writeln!(
+                        output,
+                        "sentry_kubernetes_{}{{{}=\"{}\"}} {}",
+                        name, label, value_label, value
+                    );
+                }
+            }
+        };
+
+        let load = |value: &AtomicU64| vec![("", value.load(Ordering::Relaxed))];
+        let labeled = |values: &Mutex<BTreeMap<&'static str, u64>>| {
+            values
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>()
+        };
+
+        counter(
+            "events_received_total",
+            "Kubernetes events received from the watcher.",
+            "",
+            load(&self.events_received),
+        );
+        counter(
+            "events_filtered_total",
+            "Events discarded by the filters.",
+            "filter",
+            labeled(&self.events_filtered),
+        );
+        counter(
+            "events_enriched_total",
+            "Events enriched with pod or node information.",
+            "",
+            load(&self.events_enriched),
+        );
+        counter(
+            "enrichment_timeouts_total",
+            "Enrichment lookups given up after the enrichment timeout.",
+            "",
+            load(&self.enrichment_timeouts),
+        );
+        counter(
+            "events_sent_total",
+            "Events dispatched to the sinks.",
+            "",
+            load(&self.events_sent),
+        );
+        counter(
+            "send_failures_total",
+            "Events which could not be delivered, by sink.",
+            "sink",
+            labeled(&self.send_failures),
+        );
+        counter(
+            "events_dropped_total",
+            "Events dropped before being processed, by reason.",
+            "reason",
+            labeled(&self.events_dropped),
+        );
+        counter(
+            "queue_blocked_total",
+            "Events which waited for room in the full event queue.",
+            "",
+            load(&self.queue_blocked),
+        );
+        counter(
+            "watcher_restarts_total",
+            "Restarts of the kubernetes event watcher after an error.",
+            "",
+            load(&self.watcher_restarts),
+        );
+
+        let stages = self.stages.lock().unwrap().clone();
+        counter(
+            "stage_runs_total",
+            "Events processed by each pipeline stage.",
+            "stage",
+            stages.iter().map(|(k, (_, runs))| (*k, *runs)).collect(),
+        );
+        let _ = writeln!(
+            output,
+            "# HELP sentry_kubernetes_stage_seconds_total Time spent in each pipeline stage."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE sentry_kubernetes_stage_seconds_total counter"
+        );
+        for (stage, (seconds, _)) in &stages {
+            let _ = writeln!(
+                output,
+                "sentry_kubernetes_stage_seconds_total{{stage=\"{}\"}} {}",
+                stage, seconds
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP sentry_kubernetes_queue_depth Events waiting to be processed."
+        );
+        let _ = writeln!(output, "# TYPE sentry_kubernetes_queue_depth gauge");
+        let _ = writeln!(
+            output,
+            "sentry_kubernetes_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP sentry_kubernetes_last_event_age_seconds Seconds elapsed since the last received event (-1 if none)."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE sentry_kubernetes_last_event_age_seconds gauge"
+        );
+        let age = self.last_event_age().map_or(-1, |age| age.as_secs() as i64);
+        let _ = writeln!(output, "sentry_kubernetes_last_event_age_seconds {}", age);
+
+        output
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+
+    #[test]
+    pub fn test_render() {
+        let metrics = Metrics::default();
+        assert!(metrics
+            .render()
+            .contains("sentry_kubernetes_last_event_age_seconds -1\n"));
+
+        metrics.event_received();
+        metrics.event_received();
+        metrics.event_filtered("reason");
+        metrics.send_failure("webhook");
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE sentry_kubernetes_events_received_total counter\n"));
+        assert!(output.contains("sentry_kubernetes_events_received_total 2\n"));
+        assert!(output.contains("sentry_kubernetes_events_filtered_total{filter=\"reason\"} 1\n"));
+        assert!(output.contains("sentry_kubernetes_send_failures_total{sink=\"webhook\"} 1\n"));
+        assert!(output.contains("sentry_kubernetes_events_sent_total 0\n"));
+        assert!(output.contains("sentry_kubernetes_last_event_age_seconds 0\n"));
+    }
+}
@@ -0,0 +1,151 @@
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use sentry::protocol::{Context, DeviceContext};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Capacity and allocatable resources of the node an event refers to.
+#[derive(Clone, Debug, Default)]
+pub struct NodeCapacity {
+    pub name: String,
+    pub arch: Option<String>,
+    pub instance_type: Option<String>,
+    pub capacity: BTreeMap<String, Quantity>,
+    pub allocatable: BTreeMap<String, Quantity>,
+}
+
+impl From<&Node> for NodeCapacity {
+    fn from(node: &Node) -> Self {
+        let status = node.status.as_ref();
+        Self {
+            name: node.metadata.name.clone().unwrap_or_default(),
+            arch: status
+                .and_then(|s| s.node_info.as_ref())
+                .map(|i| i.architecture.clone()),
+            instance_type: node
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|l| l.get("node.kubernetes.io/instance-type"))
+                .cloned(),
+            capacity: status.and_then(|s| s.capacity.clone()).unwrap_or_default(),
+            allocatable: status
+                .and_then(|s| s.allocatable.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&NodeCapacity> for Context {
+    fn from(value: &NodeCapacity) -> Self {
+        let mut other = BTreeMap::new();
+        for (prefix, resources) in [
+            ("capacity", &value.capacity),
+            ("allocatable", &value.allocatable),
+        ] {
+            for key in ["cpu", "pods"] {
+                if let Some(quantity) = resources.get(key) {
+                    other.insert(
+                        format!("{}_{}", key, prefix),
+                        Value::String(quantity.0.clone()),
+                    );
+                }
+            }
+        }
+
+        Context::Device(Box::new(DeviceContext {
+            name: Some(value.name.clone()),
+            model: value.instance_type.clone(),
+            arch: value.arch.clone(),
+            memory_size: value.capacity.get("memory").and_then(quantity_to_bytes),
+            usable_memory: value.allocatable.get("memory").and_then(quantity_to_bytes),
+            storage_size: value
+                .capacity
+                .get("ephemeral-storage")
+                .and_then(quantity_to_bytes),
+            free_storage: value
+                .allocatable
+                .get("ephemeral-storage")
+                .and_then(quantity_to_bytes),
+            other,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Converts a kubernetes quantity (ex: "16318004Ki", "1G", "128974848") to bytes.
+fn quantity_to_bytes(quantity: &Quantity) -> Option<u64> {
+    let value = quantity.0.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" => 1000,
+        "M" => 1000u64.pow(2),
+        "G" => 1000u64.pow(3),
+        "T" => 1000u64.pow(4),
+        "P" => 1000u64.pow(5),
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        "Pi" => 1 << 50,
+        _ => return None,
+    };
+
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| (n * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::{quantity_to_bytes, NodeCapacity};
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+    use sentry::protocol::Context;
+
+    #[test]
+    pub fn test_quantity_to_bytes() {
+        assert_eq!(
+            quantity_to_bytes(&Quantity("128974848".into())),
+            Some(128974848)
+        );
+        assert_eq!(quantity_to_bytes(&Quantity("16Ki".into())), Some(16384));
+        assert_eq!(
+            quantity_to_bytes(&Quantity("1.5G".into())),
+            Some(1500000000)
+        );
+        assert_eq!(quantity_to_bytes(&Quantity("3920m".into())), None);
+    }
+
+    #[test]
+    pub fn test_device_context() {
+        let capacity = NodeCapacity {
+            name: "node-1".to_string(),
+            capacity: [
+                ("cpu".to_string(), Quantity("4".into())),
+                ("memory".to_string(), Quantity("8Gi".into())),
+                ("pods".to_string(), Quantity("110".into())),
+            ]
+            .into_iter()
+            .collect(),
+            allocatable: [("cpu".to_string(), Quantity("3920m".into()))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        let Context::Device(device) = Context::from(&capacity) else {
+            panic!("expected a device context");
+        };
+        assert_eq!(device.name.as_deref(), Some("node-1"));
+        assert_eq!(device.memory_size, Some(8 << 30));
+        assert_eq!(device.usable_memory, None);
+        assert_eq!(device.other.get("cpu_capacity"), Some(&"4".into()));
+        assert_eq!(device.other.get("cpu_allocatable"), Some(&"3920m".into()));
+        assert_eq!(device.other.get("pods_capacity"), Some(&"110".into()));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::node::NodeCapacity;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{Event, Node, NodeCondition, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use sentry::types::Uuid;
+use serde_json::{to_value, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Reports the nodes which are not ready or under memory, disk or PID pressure, from their
+/// conditions. A condition is reported once it has lasted for the debounce time, so that a short
+/// blip (ex: a missed heartbeat) is not reported.
+pub struct NodeConditionWatcher {
+    debounce: Duration,
+    /// The failing conditions, by node name and condition type.
+    failing: Mutex<HashMap<(String, String), Failing>>,
+}
+
+struct Failing {
+    node: Node,
+    condition: NodeCondition,
+    since: Instant,
+    reported: bool,
+}
+
+impl NodeConditionWatcher {
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            failing: Default::default(),
+        }
+    }
+
+    /// Records the transitions of the conditions of the node, returning the events of the failing
+    /// conditions which lasted for the debounce time.
+    pub fn events(&self, node: &Node) -> Vec<SentryEvent> {
+        let Some(name) = node.metadata.name.as_ref() else {
+            return vec![];
+        };
+
+        let conditions = node
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .into_iter()
+            .flatten();
+        let mut failing = self.failing.lock().unwrap();
+        for condition in conditions {
+            let key = (name.clone(), condition.type_.clone());
+            if !is_failing(condition) {
+                failing.remove(&key);
+                continue;
+            }
+
+            let entry = failing.entry(key).or_insert_with(|| Failing {
+                node: node.clone(),
+                condition: condition.clone(),
+                since: Instant::now(),
+                reported: false,
+            });
+            entry.node = node.clone();
+            entry.condition = condition.clone();
+        }
+
+        self.take_due(&mut failing)
+    }
+
+    /// The events of the failing conditions which lasted for the debounce time since the last check.
+    pub fn due(&self) -> Vec<SentryEvent> {
+        let mut failing = self.failing.lock().unwrap();
+        self.take_due(&mut failing)
+    }
+
+    fn take_due(&self, failing: &mut HashMap<(String, String), Failing>) -> Vec<SentryEvent> {
+        failing
+            .values_mut()
+            .filter(|f| !f.reported && f.since.elapsed() >= self.debounce)
+            .map(|f| {
+                f.reported = true;
+                condition_event(&f.node, &f.condition)
+            })
+            .collect()
+    }
+}
+
+/// Ready is failing if False or Unknown, the pressure conditions if True.
+fn is_failing(condition: &NodeCondition) -> bool {
+    match condition.type_.as_str() {
+        "Ready" => condition.status != "True",
+        "MemoryPressure" | "DiskPressure" | "PIDPressure" => condition.status == "True",
+        _ => false,
+    }
+}
+
+/// A warning event of the node (ex: NodeNotReady, NodeMemoryPressure), with the condition in the
+/// "node condition" context and the capacity and labels of the node.
+fn condition_event(node: &Node, condition: &NodeCondition) -> SentryEvent {
+    let name = node.metadata.name.clone().unwrap_or_default();
+    let reason = match condition.type_.as_str() {
+        "Ready" if condition.status == "Unknown" => "NodeStatusUnknown".to_string(),
+        "Ready" => "NodeNotReady".to_string(),
+        type_ => format!("Node{}", type_),
+    };
+    let message = condition
+        .message
+        .clone()
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| {
+            format!(
+                "Node {} condition {} is {}",
+                name, condition.type_, condition.status
+            )
+        });
+    let time = condition
+        .last_transition_time
+        .clone()
+        .unwrap_or_else(|| Time(Utc::now()));
+
+    let event = Event {
+        metadata: ObjectMeta {
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(time.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Node".to_string()),
+            name: Some(name),
+            uid: node.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(time),
+        ..Default::default()
+    };
+
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.node_capacity = Some(NodeCapacity::from(node));
+    sentry_event.node_labels = node.metadata.labels.clone().unwrap_or_default();
+    if let Ok(Value::Object(context)) = to_value(condition) {
+        sentry_event
+            .contexts
+            .insert("node condition".to_string(), context.into_iter().collect());
+    }
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node_conditions::NodeConditionWatcher;
+    use k8s_openapi::api::core::v1::{Node, NodeCondition, NodeStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use std::time::Duration;
+
+    fn node(conditions: &[(&str, &str)]) -> Node {
+        Node {
+            metadata: ObjectMeta {
+                name: Some("node-1".to_string()),
+                ..Default::default()
+            },
+            status: Some(NodeStatus {
+                conditions: Some(
+                    conditions
+                        .iter()
+                        .map(|(type_, status)| NodeCondition {
+                            type_: type_.to_string(),
+                            status: status.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_events() {
+        let watcher = NodeConditionWatcher::new(Duration::ZERO);
+        assert!(watcher
+            .events(&node(&[("Ready", "True"), ("MemoryPressure", "False")]))
+            .is_empty());
+
+        let events = watcher.events(&node(&[("Ready", "Unknown"), ("DiskPressure", "True")]));
+        let mut reasons = events.iter().map(|e| e.reason.as_str()).collect::<Vec<_>>();
+        reasons.sort();
+        assert_eq!(reasons, ["NodeDiskPressure", "NodeStatusUnknown"]);
+        assert_eq!(events[0].kind.as_deref(), Some("Node"));
+        assert_eq!(events[0].source_host.as_deref(), Some("node-1"));
+        assert!(events[0].contexts.contains_key("node condition"));
+
+        // Reported once, until the condition recovers.
+        assert!(watcher
+            .events(&node(&[("Ready", "Unknown"), ("DiskPressure", "True")]))
+            .is_empty());
+        watcher.events(&node(&[("Ready", "True"), ("DiskPressure", "True")]));
+        let events = watcher.events(&node(&[("Ready", "False"), ("DiskPressure", "True")]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "NodeNotReady");
+    }
+
+    #[test]
+    pub fn test_debounce() {
+        let watcher = NodeConditionWatcher::new(Duration::from_secs(60));
+        assert!(watcher.events(&node(&[("Ready", "False")])).is_empty());
+        assert!(watcher.due().is_empty());
+
+        // Recovered before the debounce time: never reported.
+        watcher.events(&node(&[("Ready", "True")]));
+        assert!(watcher.failing.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::node::NodeCapacity;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{Event, Node, ObjectReference};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::ListParams;
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// The taints set on the nodes being drained by the cluster autoscalers before their removal.
+const DRAIN_TAINTS: [&str; 3] = [
+    "ToBeDeletedByClusterAutoscaler",
+    "karpenter.sh/disruption",
+    "node.kubernetes.io/out-of-service",
+];
+
+/// Records the nodes added, removed, cordoned and drained as Normal events: they are breadcrumbs of
+/// the following events (ex: the pods failing after a node is drained), unless the info level is
+/// reported.
+pub struct NodeLifecycleWatcher {
+    client: Client,
+    /// The nodes created before are not reported as added.
+    started: DateTime<Utc>,
+    /// The last state of the nodes, by name.
+    nodes: Mutex<HashMap<String, NodeState>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NodeState {
+    cordoned: bool,
+    draining: bool,
+}
+
+impl From<&Node> for NodeState {
+    fn from(node: &Node) -> Self {
+        let spec = node.spec.as_ref();
+        Self {
+            cordoned: spec.and_then(|s| s.unschedulable).unwrap_or(false),
+            draining: spec
+                .and_then(|s| s.taints.as_ref())
+                .into_iter()
+                .flatten()
+                .any(|t| DRAIN_TAINTS.contains(&t.key.as_str())),
+        }
+    }
+}
+
+impl NodeLifecycleWatcher {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            started: Utc::now(),
+            nodes: Default::default(),
+        }
+    }
+
+    /// The events of the transitions of the node since its last update.
+    pub fn events(&self, node: &Node) -> Vec<SentryEvent> {
+        let Some(name) = node.metadata.name.clone() else {
+            return vec![];
+        };
+
+        let state = NodeState::from(node);
+        let previous = self.nodes.lock().unwrap().insert(name.clone(), state);
+        let mut events = vec![];
+        let Some(previous) = previous else {
+            let created = node.metadata.creation_timestamp.as_ref().map(|t| t.0);
+            if created.is_some_and(|created| created >= self.started) {
+                events.push(lifecycle_event(
+                    node,
+                    &name,
+                    "NodeAdded",
+                    format!("Node {} added", name),
+                ));
+            }
+            return events;
+        };
+
+        match (previous.cordoned, state.cordoned) {
+            (false, true) => events.push(lifecycle_event(
+                node,
+                &name,
+                "NodeCordoned",
+                format!("Node {} cordoned", name),
+            )),
+            (true, false) => events.push(lifecycle_event(
+                node,
+                &name,
+                "NodeUncordoned",
+                format!("Node {} uncordoned", name),
+            )),
+            _ => {}
+        }
+        if state.draining && !previous.draining {
+            events.push(lifecycle_event(
+                node,
+                &name,
+                "NodeDraining",
+                format!("Node {} is being drained", name),
+            ));
+        }
+
+        events
+    }
+
+    /// The events of the nodes removed since the last check (the deletions are not notified).
+    pub async fn removed(&self) -> Vec<SentryEvent> {
+        let api: Api<Node> = Api::all(self.client.clone());
+        let existing = match api.list_metadata(&ListParams::default()).await {
+            Ok(list) => list
+                .items
+                .into_iter()
+                .filter_map(|n| n.metadata.name)
+                .collect::<HashSet<_>>(),
+            Err(e) => {
+                warn!("Cannot list the nodes: {}", e);
+                return vec![];
+            }
+        };
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let removed = nodes
+            .keys()
+            .filter(|name| !existing.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        removed
+            .into_iter()
+            .map(|name| {
+                nodes.remove(&name);
+                let message = format!("Node {} removed", name);
+                lifecycle_event(&Node::default(), &name, "NodeRemoved", message)
+            })
+            .collect()
+    }
+}
+
+/// A Normal event of the node, with its capacity and labels.
+fn lifecycle_event(node: &Node, name: &str, reason: &str, message: String) -> SentryEvent {
+    let now = Time(Utc::now());
+    let event = Event {
+        metadata: ObjectMeta {
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(now.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Node".to_string()),
+            name: Some(name.to_string()),
+            uid: node.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message),
+        type_: Some("Normal".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    };
+
+    let mut sentry_event = SentryEvent::from(event);
+    if node.status.is_some() {
+        sentry_event.node_capacity = Some(NodeCapacity::from(node));
+    }
+    sentry_event.node_labels = node.metadata.labels.clone().unwrap_or_default();
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node_lifecycle::NodeLifecycleWatcher;
+    use k8s_openapi::api::core::v1::{Node, NodeSpec, Taint};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::{Duration, Utc};
+    use kube::Client;
+    use sentry::Level;
+
+    fn node(unschedulable: bool, taint: Option<&str>) -> Node {
+        Node {
+            metadata: ObjectMeta {
+                name: Some("node-1".to_string()),
+                creation_timestamp: Some(Time(Utc::now() - Duration::hours(1))),
+                ..Default::default()
+            },
+            spec: Some(NodeSpec {
+                unschedulable: Some(unschedulable),
+                taints: taint.map(|key| {
+                    vec![Taint {
+                        key: key.to_string(),
+                        effect: "NoSchedule".to_string(),
+                        ..Default::default()
+                    }]
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_events() {
+        let watcher = NodeLifecycleWatcher::new(Client::try_default().await.unwrap());
+        // Existing before the start: not added.
+        assert!(watcher.events(&node(false, None)).is_empty());
+        assert!(watcher.events(&node(false, None)).is_empty());
+
+        let events = watcher.events(&node(true, Some("ToBeDeletedByClusterAutoscaler")));
+        let reasons = events.iter().map(|e| e.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons, ["NodeCordoned", "NodeDraining"]);
+        assert_eq!(events[0].level, Level::Info);
+        assert_eq!(events[0].source_host.as_deref(), Some("node-1"));
+
+        let events = watcher.events(&node(false, None));
+        assert_eq!(events[0].reason, "NodeUncordoned");
+
+        let mut added = node(false, None);
+        added.metadata.name = Some("node-2".to_string());
+        added.metadata.creation_timestamp = Some(Time(Utc::now()));
+        assert_eq!(watcher.events(&added)[0].reason, "NodeAdded");
+    }
+}
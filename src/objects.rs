@@ -0,0 +1,124 @@
+use crate::cache::TtlCache;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::DynamicObject;
+use kube::core::GroupVersion;
+use kube::discovery::{pinned_kind, ApiResource, Scope};
+use kube::{Api, Client};
+use log::debug;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Fetches the metadata of objects of any kind through the dynamic API,
+/// caching the discovered api resources by api version and kind.
+/// The fetched metadata (or its absence) is cached by kind, namespace and name for a limited time.
+pub struct ObjectResolver {
+    client: Client,
+    resources: Mutex<HashMap<(String, String), (ApiResource, Scope)>>,
+    objects: Mutex<TtlCache<(String, String, String), Option<ObjectMeta>>>,
+}
+
+impl ObjectResolver {
+    pub fn new(client: Client, cache_size: usize, cache_ttl: Duration) -> Self {
+        Self {
+            client,
+            resources: Default::default(),
+            objects: Mutex::new(TtlCache::new(cache_size, cache_ttl)),
+        }
+    }
+
+    pub async fn metadata(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<ObjectMeta> {
+        let key = (kind.to_string(), namespace.to_string(), name.to_string());
+        if let Some(cached) = self.objects.lock().unwrap().get(&key) {
+            return cached;
+        }
+
+        let api = self.api(api_version, kind, namespace).await?;
+        let metadata = match api.get(name).await {
+            Ok(object) => Some(object.metadata),
+            Err(kube::Error::Api(e)) if e.code == 404 => None,
+            Err(e) => {
+                // Transient errors are not cached.
+                debug!("Cannot fetch {} {}/{}: {}", kind, namespace, name, e);
+                return None;
+            }
+        };
+
+        self.objects.lock().unwrap().insert(key, metadata.clone());
+        metadata
+    }
+
+    /// Fetches the whole object, without caching it (ex: to read its spec and status).
+    pub async fn object(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Option<DynamicObject> {
+        let api = self.api(api_version, kind, namespace).await?;
+        match api.get_opt(name).await {
+            Ok(object) => object,
+            Err(e) => {
+                debug!("Cannot fetch {} {}/{}: {}", kind, namespace, name, e);
+                None
+            }
+        }
+    }
+
+    async fn api(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: &str,
+    ) -> Option<Api<DynamicObject>> {
+        let (resource, scope) = self.resource(api_version, kind).await?;
+        Some(match scope {
+            Scope::Namespaced => {
+                Api::<DynamicObject>::namespaced_with(self.client.clone(), namespace, &resource)
+            }
+            Scope::Cluster => Api::<DynamicObject>::all_with(self.client.clone(), &resource),
+        })
+    }
+
+    async fn resource(&self, api_version: &str, kind: &str) -> Option<(ApiResource, Scope)> {
+        let key = (api_version.to_string(), kind.to_string());
+        if let Some(resource) = self.resources.lock().unwrap().get(&key) {
+            return Some(resource.clone());
+        }
+
+        let gvk = GroupVersion::from_str(api_version).ok()?.with_kind(kind);
+        let (resource, capabilities) = match pinned_kind(&self.client, &gvk).await {
+            Ok(discovered) => discovered,
+            Err(e) => {
+                debug!("Cannot discover {} {}: {}", api_version, kind, e);
+                return None;
+            }
+        };
+
+        let discovered = (resource, capabilities.scope);
+        self.resources
+            .lock()
+            .unwrap()
+            .insert(key, discovered.clone());
+
+        Some(discovered)
+    }
+}
+
+/// The name of the owner of the given kind (ex: the CronJob of a Job).
+pub fn owner(metadata: &ObjectMeta, kind: &str) -> Option<String> {
+    metadata
+        .owner_references
+        .as_ref()?
+        .iter()
+        .find(|o| o.kind == kind)
+        .map(|o| o.name.clone())
+}
@@ -0,0 +1,273 @@
+use crate::sentry_event::{event_time, SentryEvent};
+use k8s_openapi::api::core::v1::{Event, ObjectReference, PersistentVolumeClaim};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Maximum number of events of the claim reported in its context.
+const MAX_CLAIM_EVENTS: usize = 10;
+
+/// Reports the PersistentVolumeClaims remaining Pending longer than a threshold, with their
+/// StorageClass, their provisioner and their last events (ex: the provisioning failures).
+pub struct PendingClaimWatcher {
+    client: Client,
+    threshold: Duration,
+    /// The Pending claims by uid, and whether they have been reported.
+    pending: Mutex<HashMap<String, (PersistentVolumeClaim, bool)>>,
+}
+
+impl PendingClaimWatcher {
+    pub fn new(client: Client, threshold: Duration) -> Self {
+        Self {
+            client,
+            threshold,
+            pending: Default::default(),
+        }
+    }
+
+    /// Records whether the claim is Pending. The claims are reported by the periodic checks.
+    pub fn update(&self, claim: &PersistentVolumeClaim) {
+        let Some(uid) = claim.metadata.uid.clone() else {
+            return;
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        if is_pending(claim) {
+            let reported = pending.get(&uid).is_some_and(|(_, reported)| *reported);
+            pending.insert(uid, (claim.clone(), reported));
+        } else {
+            pending.remove(&uid);
+        }
+    }
+
+    /// The events of the claims Pending longer than the threshold, not reported yet.
+    pub async fn due(&self) -> Vec<SentryEvent> {
+        let due = {
+            let mut pending = self.pending.lock().unwrap();
+            pending
+                .values_mut()
+                .filter(|(claim, reported)| !reported && self.is_stalled(claim))
+                .map(|(claim, reported)| {
+                    *reported = true;
+                    claim.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut events = vec![];
+        for claim in due {
+            // The deletions are not notified: check that the claim still exists.
+            let (Some(namespace), Some(name)) = (&claim.metadata.namespace, &claim.metadata.name)
+            else {
+                continue;
+            };
+            let api: Api<PersistentVolumeClaim> = Api::namespaced(self.client.clone(), namespace);
+            match api.get_opt(name).await {
+                Ok(Some(claim)) if is_pending(&claim) => {
+                    let claim_events = self.claim_events(&claim).await;
+                    events.push(pending_event(&claim, self.threshold, claim_events));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Cannot read the claim {}/{}: {}", namespace, name, e),
+            }
+        }
+
+        events
+    }
+
+    fn is_stalled(&self, claim: &PersistentVolumeClaim) -> bool {
+        let Some(created) = claim.metadata.creation_timestamp.as_ref() else {
+            return false;
+        };
+
+        (Utc::now() - created.0)
+            .to_std()
+            .is_ok_and(|age| age >= self.threshold)
+    }
+
+    /// The last events of the claim, most recent first.
+    async fn claim_events(&self, claim: &PersistentVolumeClaim) -> Vec<Event> {
+        let (Some(namespace), Some(uid)) = (&claim.metadata.namespace, &claim.metadata.uid) else {
+            return vec![];
+        };
+        let api: Api<Event> = Api::namespaced(self.client.clone(), namespace);
+        let params = ListParams::default().fields(&format!("involvedObject.uid={}", uid));
+        match api.list(&params).await {
+            Ok(list) => {
+                let mut events = list.items;
+                events.sort_by_key(|e| std::cmp::Reverse(event_time(e)));
+                events.truncate(MAX_CLAIM_EVENTS);
+                events
+            }
+            Err(e) => {
+                warn!("Cannot list the events of the claim {}: {}", uid, e);
+                vec![]
+            }
+        }
+    }
+}
+
+fn is_pending(claim: &PersistentVolumeClaim) -> bool {
+    claim.status.as_ref().and_then(|s| s.phase.as_deref()) == Some("Pending")
+}
+
+/// The provisioner the claim is waiting for, set by the PersistentVolume controller.
+fn provisioner(claim: &PersistentVolumeClaim) -> Option<&String> {
+    let annotations = claim.metadata.annotations.as_ref()?;
+    annotations
+        .get("volume.kubernetes.io/storage-provisioner")
+        .or_else(|| annotations.get("volume.beta.kubernetes.io/storage-provisioner"))
+}
+
+/// A warning event of the claim, with the StorageClass, the provisioner and the last events of
+/// the claim in the "persistent volume claim" context.
+fn pending_event(
+    claim: &PersistentVolumeClaim,
+    threshold: Duration,
+    claim_events: Vec<Event>,
+) -> SentryEvent {
+    let name = claim.metadata.name.clone().unwrap_or_default();
+    let mut message = format!(
+        "PersistentVolumeClaim {} has been Pending for more than {} seconds",
+        name,
+        threshold.as_secs()
+    );
+    if let Some(last) = claim_events.first().and_then(|e| e.message.as_ref()) {
+        message = format!("{}: {}", message, last);
+    }
+
+    let now = Time(Utc::now());
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: claim.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(now.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("PersistentVolumeClaim".to_string()),
+            name: Some(name),
+            namespace: claim.metadata.namespace.clone(),
+            uid: claim.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some("ClaimPending".to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(now),
+        ..Default::default()
+    };
+
+    let spec = claim.spec.as_ref();
+    let events = claim_events
+        .iter()
+        .map(|e| {
+            let component = e.source.as_ref().and_then(|s| s.component.as_ref());
+            json!({
+                "reason": e.reason,
+                "message": e.message,
+                "component": component.or(e.reporting_component.as_ref()),
+                "count": e.count,
+                "time": event_time(e),
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.contexts.insert(
+        "persistent volume claim".to_string(),
+        BTreeMap::from([
+            (
+                "storageClass".to_string(),
+                json!(spec.and_then(|s| s.storage_class_name.clone())),
+            ),
+            ("provisioner".to_string(), json!(provisioner(claim))),
+            (
+                "accessModes".to_string(),
+                json!(spec.and_then(|s| s.access_modes.clone())),
+            ),
+            (
+                "requests".to_string(),
+                json!(spec
+                    .and_then(|s| s.resources.as_ref())
+                    .and_then(|r| r.requests.clone())),
+            ),
+            (
+                "createdAt".to_string(),
+                json!(claim.metadata.creation_timestamp),
+            ),
+            ("events".to_string(), Value::Array(events)),
+        ]),
+    );
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pending_claims::{is_pending, pending_event};
+    use k8s_openapi::api::core::v1::{
+        Event, EventSource, PersistentVolumeClaim, PersistentVolumeClaimSpec,
+        PersistentVolumeClaimStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_pending_event() {
+        let claim = PersistentVolumeClaim {
+            metadata: ObjectMeta {
+                name: Some("data-db-0".to_string()),
+                namespace: Some("db".to_string()),
+                annotations: Some(
+                    [(
+                        "volume.kubernetes.io/storage-provisioner".to_string(),
+                        "ebs.csi.aws.com".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(PersistentVolumeClaimSpec {
+                storage_class_name: Some("gp3".to_string()),
+                ..Default::default()
+            }),
+            status: Some(PersistentVolumeClaimStatus {
+                phase: Some("Pending".to_string()),
+                ..Default::default()
+            }),
+        };
+        assert!(is_pending(&claim));
+
+        let events = vec![Event {
+            reason: Some("ProvisioningFailed".to_string()),
+            message: Some("failed to provision volume".to_string()),
+            source: Some(EventSource {
+                component: Some("ebs.csi.aws.com".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }];
+        let event = pending_event(&claim, Duration::from_secs(300), events);
+        assert_eq!(event.reason, "ClaimPending");
+        assert_eq!(event.kind.as_deref(), Some("PersistentVolumeClaim"));
+        assert_eq!(
+            event.message.as_deref(),
+            Some("PersistentVolumeClaim data-db-0 has been Pending for more than 300 seconds: failed to provision volume")
+        );
+
+        let context = &event.contexts["persistent volume claim"];
+        assert_eq!(context["storageClass"], "gp3");
+        assert_eq!(context["provisioner"], "ebs.csi.aws.com");
+        assert_eq!(context["events"][0]["reason"], "ProvisioningFailed");
+        assert_eq!(context["events"][0]["component"], "ebs.csi.aws.com");
+    }
+}
@@ -0,0 +1,194 @@
+use crate::cache::TtlCache;
+use crate::enrich::Enricher;
+use crate::events_api::EventsApi;
+use crate::sentry_event::SentryEvent;
+use crate::sink::EventSink;
+use crate::stages::{DEDUPE_CACHE_SIZE, DEDUPE_TTL};
+use futures::stream::{self, BoxStream};
+use futures::{future, Stream, StreamExt};
+use kube::runtime::watcher;
+use kube::Client;
+use log::warn;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Watches the kubernetes events of all the namespaces, as a pipeline.
+/// Watch errors do not end the stream: they are logged and the watch is resumed, and the events
+/// received again when the watcher relists them are discarded.
+pub fn pipeline(client: Client) -> Pipeline {
+    let events = stream::once(async move {
+        let api = EventsApi::detect(&client).await;
+        let config = watcher::Config {
+            bookmarks: true,
+            ..Default::default()
+        };
+        api.watch(client, &[], config, |_| {})
+    })
+    .flatten();
+
+    let mut seen = TtlCache::new(DEDUPE_CACHE_SIZE, DEDUPE_TTL);
+    events
+        .filter_map(move |event| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Kubernetes event watcher error, resuming: {}", e);
+                    return future::ready(None);
+                }
+            };
+
+            if let (Some(uid), Some(version)) =
+                (&event.metadata.uid, &event.metadata.resource_version)
+            {
+                let key = (uid.clone(), version.clone());
+                if seen.get(&key).is_some() {
+                    return future::ready(None);
+                }
+                seen.insert(key, ());
+            }
+
+            future::ready(Some(SentryEvent::from(event)))
+        })
+        .into_pipeline()
+}
+
+/// A stream of events, with the stages of the pipeline as combinators:
+/// `pipeline(client).filter(...).enrich(...).sink(...)`.
+/// Each stage passes the events on, so that the events sent to a sink are still yielded by the
+/// stream (ex: to send them to other sinks, or to merge them with other streams).
+/// The stages run when the stream is polled.
+pub struct Pipeline {
+    events: BoxStream<'static, SentryEvent>,
+}
+
+impl Pipeline {
+    /// Keeps the events matching the predicate.
+    #[must_use]
+    pub fn filter(self, mut predicate: impl FnMut(&SentryEvent) -> bool + Send + 'static) -> Self {
+        self.events
+            .filter(move |event| future::ready(predicate(event)))
+            .into_pipeline()
+    }
+
+    /// Adds the details of the involved objects (ex: the workload of the pods), looked up with a
+    /// kubernetes client or an [`Enricher`].
+    #[must_use]
+    pub fn enrich(self, enricher: impl Into<Enricher>) -> Self {
+        let enricher = enricher.into();
+        self.events
+            .then(move |mut event| {
+                let enricher = enricher.clone();
+                async move {
+                    enricher.enrich(&mut event).await;
+                    event
+                }
+            })
+            .into_pipeline()
+    }
+
+    /// Sends the events to the sink (ex: a `SentrySink`, or a closure).
+    /// The sink is closed, flushing its buffered events, when the stream ends.
+    #[must_use]
+    pub fn sink(self, sink: impl EventSink + 'static) -> Self {
+        let sink = Arc::new(sink);
+        let closing = sink.clone();
+        let close = stream::once(async move {
+            closing.close().await;
+            None
+        });
+
+        self.events
+            .then(move |event| {
+                let sink = sink.clone();
+                async move {
+                    sink.send(&event).await;
+                    Some(event)
+                }
+            })
+            .chain(close)
+            .filter_map(future::ready)
+            .into_pipeline()
+    }
+}
+
+impl Stream for Pipeline {
+    type Item = SentryEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+/// Turns any stream of events (ex: converted from another source, or merged streams) into a
+/// pipeline.
+pub trait EventStreamExt: Stream<Item = SentryEvent> + Send + Sized + 'static {
+    fn into_pipeline(self) -> Pipeline {
+        Pipeline {
+            events: self.boxed(),
+        }
+    }
+}
+
+impl<S: Stream<Item = SentryEvent> + Send + 'static> EventStreamExt for S {}
+
+#[cfg(test)]
+mod tests {
+    use crate::enrich::Enricher;
+    use crate::kube_api::FakeKube;
+    use crate::pipeline::EventStreamExt;
+    use crate::sentry_event::SentryEvent;
+    use futures::{stream, StreamExt};
+    use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod, PodSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn event(namespace: &str, name: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    pub async fn test_pipeline() {
+        let kube = FakeKube {
+            pods: vec![Pod {
+                metadata: ObjectMeta {
+                    name: Some("web-0".to_string()),
+                    namespace: Some("shop".to_string()),
+                    ..Default::default()
+                },
+                spec: Some(PodSpec {
+                    node_name: Some("node-1".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let enricher = Enricher::new(Arc::new(kube), Duration::from_secs(1));
+
+        let sent = Arc::new(Mutex::new(vec![]));
+        let sink_sent = sent.clone();
+        let events = stream::iter([event("shop", "web-0"), event("kube-system", "coredns")])
+            .into_pipeline()
+            .filter(|event| event.namespace != "kube-system")
+            .enrich(enricher)
+            .sink(move |event: &SentryEvent| sink_sent.lock().unwrap().push(event.name.clone()))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].source_host.as_deref(), Some("node-1"));
+        assert_eq!(*sent.lock().unwrap(), vec!["web-0"]);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::cache::TtlCache;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{
+    Container, ContainerStateTerminated, ContainerStatus, Event, EventSource, ObjectReference, Pod,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::{DateTime, Utc};
+use sentry::types::Uuid;
+use serde_json::{to_value, Value};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Number of containers whose last reported state is remembered.
+const SEEN_CACHE_SIZE: usize = 100_000;
+const SEEN_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Reports the containers entering CrashLoopBackOff and the containers killed for exceeding their
+/// memory limit, from the status of the Pods. Unlike the events, which are aggregated and rate-limited
+/// by the kubelet, every transition is reported once.
+pub struct PodStatusWatcher {
+    /// The last reported state of the containers, by pod uid and container name.
+    seen: Mutex<TtlCache<(String, String), Seen>>,
+}
+
+#[derive(Clone, Default)]
+struct Seen {
+    /// Restart count of the last reported CrashLoopBackOff.
+    crash_loop_restarts: Option<i32>,
+    /// Time of the last reported OOM kill.
+    oom_killed_at: Option<DateTime<Utc>>,
+}
+
+impl Default for PodStatusWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PodStatusWatcher {
+    pub fn new() -> Self {
+        Self {
+            seen: Mutex::new(TtlCache::new(SEEN_CACHE_SIZE, SEEN_TTL)),
+        }
+    }
+
+    /// The events of the container state transitions not reported yet.
+    pub fn events(&self, pod: &Pod) -> Vec<SentryEvent> {
+        let (Some(uid), Some(status)) = (pod.metadata.uid.as_ref(), pod.status.as_ref()) else {
+            return vec![];
+        };
+
+        let statuses = [
+            &status.init_container_statuses,
+            &status.container_statuses,
+            &status.ephemeral_container_statuses,
+        ];
+        let mut seen = self.seen.lock().unwrap();
+        let mut events = vec![];
+        for container_status in statuses.into_iter().flatten().flatten() {
+            let key = (uid.clone(), container_status.name.clone());
+            let previous = seen.get(&key).unwrap_or_default();
+            let mut current = previous.clone();
+
+            let waiting = container_status
+                .state
+                .as_ref()
+                .and_then(|s| s.waiting.as_ref());
+            if waiting.and_then(|w| w.reason.as_deref()) == Some("CrashLoopBackOff")
+                && previous.crash_loop_restarts != Some(container_status.restart_count)
+            {
+                current.crash_loop_restarts = Some(container_status.restart_count);
+                let message = waiting.and_then(|w| w.message.clone()).unwrap_or_else(|| {
+                    format!(
+                        "Back-off restarting failed container {}",
+                        container_status.name
+                    )
+                });
+                let crashed_at =
+                    last_terminated(container_status).and_then(|t| t.finished_at.clone());
+                events.push(container_event(
+                    pod,
+                    container_status,
+                    "CrashLoopBackOff",
+                    message,
+                    crashed_at,
+                ));
+            }
+
+            if let Some(killed_at) = oom_killed_at(container_status) {
+                if previous.oom_killed_at != Some(killed_at) {
+                    current.oom_killed_at = Some(killed_at);
+                    let message = format!(
+                        "Container {} was killed for exceeding its memory limit",
+                        container_status.name
+                    );
+                    events.push(container_event(
+                        pod,
+                        container_status,
+                        "OOMKilled",
+                        message,
+                        Some(Time(killed_at)),
+                    ));
+                }
+            }
+
+            seen.insert(key, current);
+        }
+
+        events
+    }
+}
+
+/// The last termination of the container: the current state if terminated, otherwise the last one.
+fn last_terminated(status: &ContainerStatus) -> Option<&ContainerStateTerminated> {
+    status
+        .state
+        .as_ref()
+        .and_then(|s| s.terminated.as_ref())
+        .or_else(|| status.last_state.as_ref()?.terminated.as_ref())
+}
+
+/// The time the container has been killed by the OOM killer, if it was its last termination.
+fn oom_killed_at(status: &ContainerStatus) -> Option<DateTime<Utc>> {
+    let terminated = last_terminated(status)?;
+    if terminated.reason.as_deref() != Some("OOMKilled") {
+        return None;
+    }
+
+    terminated
+        .finished_at
+        .as_ref()
+        .or(terminated.started_at.as_ref())
+        .map(|t| t.0)
+}
+
+/// A warning event of the container, with its status and resources in the "container" context.
+fn container_event(
+    pod: &Pod,
+    status: &ContainerStatus,
+    reason: &str,
+    message: String,
+    time: Option<Time>,
+) -> SentryEvent {
+    let time = time.unwrap_or_else(|| Time(Utc::now()));
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: pod.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(time.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: pod.metadata.name.clone(),
+            namespace: pod.metadata.namespace.clone(),
+            uid: pod.metadata.uid.clone(),
+            field_path: Some(format!("spec.containers{{{}}}", status.name)),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        source: Some(EventSource {
+            host: pod.spec.as_ref().and_then(|s| s.node_name.clone()),
+            ..Default::default()
+        }),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(time),
+        ..Default::default()
+    };
+
+    let mut context = match to_value(status) {
+        Ok(Value::Object(status)) => status.into_iter().collect::<BTreeMap<_, _>>(),
+        _ => BTreeMap::new(),
+    };
+    let resources = container(pod, &status.name)
+        .and_then(|c| c.resources.as_ref())
+        .and_then(|r| to_value(r).ok());
+    if let Some(resources) = resources {
+        context.insert("resources".to_string(), resources);
+    }
+
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event
+        .contexts
+        .insert("container".to_string(), context);
+    sentry_event
+}
+
+fn container<'a>(pod: &'a Pod, name: &str) -> Option<&'a Container> {
+    let spec = pod.spec.as_ref()?;
+    spec.containers
+        .iter()
+        .chain(spec.init_containers.iter().flatten())
+        .find(|c| c.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pod_status::PodStatusWatcher;
+    use k8s_openapi::api::core::v1::{
+        Container, ContainerState, ContainerStateTerminated, ContainerStateWaiting,
+        ContainerStatus, Pod, PodSpec, PodStatus, ResourceRequirements,
+    };
+    use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::{DateTime, Utc};
+
+    fn pod(restart_count: i32, waiting: Option<&str>, terminated: Option<(&str, &str)>) -> Pod {
+        let time = |t: &str| -> DateTime<Utc> { DateTime::parse_from_rfc3339(t).unwrap().into() };
+        Pod {
+            metadata: ObjectMeta {
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                uid: Some("8a2c0e1d-3f4b-4c5d-9e6f-7a8b9c0d1e2f".to_string()),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some("node-1".to_string()),
+                containers: vec![Container {
+                    name: "web".to_string(),
+                    resources: Some(ResourceRequirements {
+                        limits: Some(
+                            [("memory".to_string(), Quantity("128Mi".to_string()))].into(),
+                        ),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            status: Some(PodStatus {
+                container_statuses: Some(vec![ContainerStatus {
+                    name: "web".to_string(),
+                    restart_count,
+                    state: Some(ContainerState {
+                        waiting: waiting.map(|reason| ContainerStateWaiting {
+                            reason: Some(reason.to_string()),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    last_state: Some(ContainerState {
+                        terminated: terminated.map(|(reason, finished_at)| {
+                            ContainerStateTerminated {
+                                reason: Some(reason.to_string()),
+                                exit_code: 137,
+                                finished_at: Some(Time(time(finished_at))),
+                                ..Default::default()
+                            }
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    pub fn test_events() {
+        let watcher = PodStatusWatcher::new();
+        assert!(watcher.events(&pod(0, None, None)).is_empty());
+
+        let events = watcher.events(&pod(
+            1,
+            Some("CrashLoopBackOff"),
+            Some(("OOMKilled", "2023-04-08T22:28:00Z")),
+        ));
+        let reasons = events.iter().map(|e| e.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons, ["CrashLoopBackOff", "OOMKilled"]);
+        assert_eq!(events[1].kind.as_deref(), Some("Pod"));
+        assert_eq!(events[1].name, "web-0");
+        assert_eq!(events[1].namespace, "shop");
+        assert_eq!(events[1].source_host.as_deref(), Some("node-1"));
+        let context = &events[1].contexts["container"];
+        assert_eq!(context["restartCount"], 1);
+        assert_eq!(context["resources"]["limits"]["memory"], "128Mi");
+
+        // The same status is not reported again.
+        assert!(watcher
+            .events(&pod(
+                1,
+                Some("CrashLoopBackOff"),
+                Some(("OOMKilled", "2023-04-08T22:28:00Z"))
+            ))
+            .is_empty());
+
+        // The container has been restarted and crashed again.
+        let events = watcher.events(&pod(
+            2,
+            Some("CrashLoopBackOff"),
+            Some(("Error", "2023-04-08T22:29:00Z")),
+        ));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "CrashLoopBackOff");
+    }
+}
@@ -0,0 +1,207 @@
+use crate::sentry_event::SentryEvent;
+use anyhow::Result;
+use sentry::Level;
+use serde::Deserialize;
+use std::fmt;
+
+#[cfg(feature = "opa")]
+use {
+    anyhow::Context,
+    log::warn,
+    sentry::protocol::Event,
+    std::fs,
+    std::path::Path,
+    std::sync::{Arc, Mutex},
+};
+
+/// The decision of the policy on an event. The rules left undefined by the policy have no effect.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Decision {
+    /// Discards the event.
+    pub drop: bool,
+    /// Sends the event, whatever the filters (except the pause and the mutes of the admin API).
+    pub allow: bool,
+    /// Replaces the level of the event, before the level filter.
+    pub level: Option<Level>,
+    /// Sends the event to these DSNs instead of the routed ones.
+    #[serde(deserialize_with = "crate::config::one_or_many")]
+    pub dsn: Vec<String>,
+}
+
+/// A Rego policy bundle evaluated against each event (in the sentry event format, as `input`):
+/// the `drop`, `allow`, `level` and `dsn` rules of its package make the decision.
+/// The bundle is a `.rego` file or a directory of `.rego` files and `.json` data documents.
+/// If the evaluation fails, the event is processed as if there were no policy.
+#[derive(Clone)]
+pub struct Policy {
+    bundle: String,
+    #[cfg(feature = "opa")]
+    query: String,
+    #[cfg(feature = "opa")]
+    engine: Arc<Mutex<regorus::Engine>>,
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Policy").field(&self.bundle).finish()
+    }
+}
+
+impl Policy {
+    /// Loads the policies and the data documents of the bundle, with the decision rules in the
+    /// given package.
+    #[cfg(feature = "opa")]
+    pub fn load(bundle: &str, package: &str) -> Result<Self> {
+        let mut engine = regorus::Engine::new();
+        let path = Path::new(bundle);
+        let files = if path.is_dir() {
+            let mut files = fs::read_dir(path)
+                .with_context(|| format!("Cannot read the policy bundle {}", bundle))?
+                .map(|entry| Ok(entry?.path()))
+                .collect::<Result<Vec<_>>>()?;
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        for file in files {
+            match file.extension().and_then(|e| e.to_str()) {
+                Some("rego") => {
+                    let rego = fs::read_to_string(&file)?;
+                    engine
+                        .add_policy(file.display().to_string(), rego)
+                        .with_context(|| format!("Cannot load the policy {}", file.display()))?;
+                }
+                Some("json") => {
+                    let data = fs::read_to_string(&file)?;
+                    engine
+                        .add_data_json(&data)
+                        .with_context(|| format!("Cannot load the data {}", file.display()))?;
+                }
+                _ => {}
+            }
+        }
+
+        let query = format!("data.{}", package);
+        if !engine.get_packages()?.contains(&query) {
+            anyhow::bail!("The policy bundle {} has no package {}", bundle, package);
+        }
+
+        Ok(Self {
+            bundle: bundle.to_string(),
+            query,
+            engine: Arc::new(Mutex::new(engine)),
+        })
+    }
+
+    #[cfg(not(feature = "opa"))]
+    pub fn load(bundle: &str, _package: &str) -> Result<Self> {
+        anyhow::bail!(
+            "Cannot load the policy bundle {}: sentry-kubernetes is built without the \"opa\" feature",
+            bundle
+        )
+    }
+
+    pub fn evaluate(&self, sentry_event: &SentryEvent) -> Decision {
+        #[cfg(feature = "opa")]
+        match self.eval(sentry_event) {
+            Ok(decision) => decision,
+            Err(e) => {
+                warn!("Cannot evaluate the policy {}: {:#}", self.bundle, e);
+                Decision::default()
+            }
+        }
+
+        #[cfg(not(feature = "opa"))]
+        {
+            let _ = sentry_event;
+            Decision::default()
+        }
+    }
+
+    #[cfg(feature = "opa")]
+    fn eval(&self, sentry_event: &SentryEvent) -> Result<Decision> {
+        let input = serde_json::to_string(&Event::from(sentry_event))?;
+        let mut engine = self.engine.lock().unwrap();
+        engine.set_input_json(&input)?;
+
+        let results = engine.eval_query(self.query.clone(), false)?;
+        let Some(value) = results
+            .result
+            .first()
+            .and_then(|r| r.expressions.first())
+            .map(|e| e.value.to_json_str())
+            .transpose()?
+        else {
+            return Ok(Decision::default());
+        };
+
+        Ok(serde_json::from_str(&value)?)
+    }
+}
+
+#[cfg(all(test, feature = "opa"))]
+mod tests {
+    use crate::policy::{Decision, Policy};
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use sentry::Level;
+    use std::fs;
+
+    fn event(namespace: &str, reason: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            reason: Some(reason.to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_evaluate() {
+        let dir = std::env::temp_dir().join(format!("policy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("forwarding.rego"),
+            r#"
+package sentry_kubernetes
+
+import rego.v1
+
+drop if input.tags.namespace in data.ignored_namespaces
+
+level := "error" if input.tags.reason == "OOMKilling"
+
+dsn := data.team_dsns[input.tags.namespace]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("data.json"),
+            r#"{ "ignored_namespaces": ["sandbox"], "team_dsns": { "shop": "https://public@sentry.example.com/7" } }"#,
+        )
+        .unwrap();
+
+        let policy = Policy::load(dir.to_str().unwrap(), "sentry_kubernetes").unwrap();
+        assert!(Policy::load(dir.to_str().unwrap(), "other").is_err());
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(policy.evaluate(&event("sandbox", "BackOff")).drop);
+        assert_eq!(
+            policy.evaluate(&event("default", "BackOff")),
+            Decision::default()
+        );
+
+        let decision = policy.evaluate(&event("shop", "OOMKilling"));
+        assert!(!decision.drop);
+        assert_eq!(decision.level, Some(Level::Error));
+        assert_eq!(decision.dsn, vec!["https://public@sentry.example.com/7"]);
+    }
+}
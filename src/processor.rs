@@ -1,42 +1,127 @@
-use crate::sentry_event::SentryEvent;
-use k8s_openapi::api::core::v1::{Event, Node, Pod};
-use kube::{Api, Client};
+use crate::checkpoint::Checkpoint;
+use crate::enrich::Enricher;
+use crate::environment::EnvironmentResolver;
+use crate::filter::{
+    AgeFilter, ComponentFilter, ExistingFilter, Filter, FilterChain, LevelFilter, MessageFilter,
+    MessagePattern, NamespaceFilter, ReasonFilter,
+};
+use crate::kube_api::{Kube, KubeApi};
+use crate::metrics::METRICS;
+use crate::policy::Policy;
+use crate::routing::Router;
+use crate::sentry_event::{SentryEvent, CLUSTER_NAME};
+use crate::shard::Shard;
+use crate::sink::EventSink;
+use crate::spot::SpotInterruptions;
+use crate::stages::{
+    self, connect, receive, DedupeStage, EnrichStage, FilterStage, Item, RouteStage, SinkStage,
+};
+use crate::stores::ObjectStores;
+use futures::{future, Stream, StreamExt};
+use k8s_openapi::api::core::v1::{Event, Pod};
+use kube::Client;
 use log::debug;
 use sentry::{add_breadcrumb, Breadcrumb, Level};
+use serde_json::{json, Value};
 use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
 
-pub struct Processor<F: Fn(&SentryEvent)> {
-    event_namespaces: Vec<String>,
-    exclude_components: Vec<String>,
-    exclude_reasons: Vec<String>,
-    exclude_namespaces: Vec<String>,
-    event_levels: Vec<String>,
-    sender: F,
+/// Number of events buffered between two stages of the pipeline.
+const STAGE_QUEUE_SIZE: usize = 64;
+
+/// Called with each processed event and the decision of the pipeline (ex: the `tail` command).
+pub type Tracer = Box<dyn Fn(&SentryEvent, &Verdict) + Send + Sync>;
 
-    pod_api: Api<Pod>,
-    nodes_api: Api<Node>,
+/// The pipeline of the events: dedupe → enrich → filter → route → sink.
+pub struct Processor {
+    pub dedupe: DedupeStage,
+    pub enrich: EnrichStage,
+    pub filter: FilterStage,
+    pub route: RouteStage,
+    pub sink: SinkStage,
+    tracer: Option<Tracer>,
 }
 
-pub struct ProcessorBuilder<F: Fn(&SentryEvent)> {
+pub struct ProcessorBuilder {
     event_namespaces: Vec<String>,
     exclude_components: Vec<String>,
     exclude_reasons: Vec<String>,
+    exclude_messages: Vec<MessagePattern>,
     exclude_namespaces: Vec<String>,
     event_levels: Vec<String>,
-    sender: F,
-    client: Client,
+    environment: EnvironmentResolver,
+    cluster: String,
+    router: Router,
+    annotation_routing: bool,
+    issue_owners: bool,
+    sinks: Vec<Box<dyn EventSink>>,
+    cache_size: usize,
+    cache_ttl: Duration,
+    enrichment_timeout: Duration,
+    max_event_age: Option<Duration>,
+    ignore_before: Option<SystemTime>,
+    shard: Option<Shard>,
+    enrichment: bool,
+    deletion_correlation: bool,
+    checkpoint: Option<Checkpoint>,
+    spot_interruption_level: Option<Level>,
+    tracer: Option<Tracer>,
+    policy: Option<Policy>,
+    filters: Vec<Box<dyn Filter>>,
+    lookups: Lookups,
+}
+
+/// Where the processor looks up the objects of the cluster: a kubernetes client, or any
+/// implementation of the lookups (ex: fakes, to test the processor without a cluster).
+pub enum Lookups {
+    /// The API server, with the reflector stores started if the enrichment is enabled.
+    Client(Client),
+    Api(Arc<dyn KubeApi>),
+}
+
+impl From<Client> for Lookups {
+    fn from(client: Client) -> Self {
+        Self::Client(client)
+    }
+}
+
+impl<T: KubeApi + 'static> From<Arc<T>> for Lookups {
+    fn from(api: Arc<T>) -> Self {
+        Self::Api(api)
+    }
 }
 
-impl<F: Fn(&SentryEvent)> ProcessorBuilder<F> {
-    fn new(client: Client, sender: F) -> Self {
+impl ProcessorBuilder {
+    fn new(lookups: Lookups) -> Self {
         Self {
             event_namespaces: Default::default(),
             exclude_components: Default::default(),
             exclude_reasons: Default::default(),
+            exclude_messages: Default::default(),
             exclude_namespaces: Default::default(),
             event_levels: Default::default(),
-            client,
-            sender,
+            environment: Default::default(),
+            cluster: CLUSTER_NAME.clone(),
+            router: Default::default(),
+            annotation_routing: false,
+            issue_owners: false,
+            sinks: vec![],
+            cache_size: 1000,
+            cache_ttl: Duration::from_secs(60),
+            enrichment_timeout: Duration::from_secs(5),
+            max_event_age: None,
+            ignore_before: None,
+            shard: None,
+            enrichment: true,
+            deletion_correlation: false,
+            checkpoint: None,
+            spot_interruption_level: None,
+            tracer: None,
+            policy: None,
+            filters: vec![],
+            lookups,
         }
     }
 
@@ -59,135 +144,377 @@ impl<F: Fn(&SentryEvent)> ProcessorBuilder<F> {
         self
     }
 
+    /// Discards the events whose message matches any of the patterns.
+    #[must_use]
+    pub fn event_messages(mut self, exclude: Vec<MessagePattern>) -> Self {
+        self.exclude_messages = exclude;
+        self
+    }
+
     #[must_use]
     pub fn event_levels(mut self, levels: Vec<String>) -> Self {
         self.event_levels = levels;
         self
     }
-}
 
-impl<F: Fn(&SentryEvent)> From<ProcessorBuilder<F>> for Processor<F> {
-    fn from(value: ProcessorBuilder<F>) -> Self {
-        Processor::new(
-            value.event_namespaces,
-            value.exclude_components,
-            value.exclude_reasons,
-            value.exclude_namespaces,
-            value.event_levels,
-            value.client,
-            value.sender,
-        )
+    #[must_use]
+    pub fn environment(mut self, environment: EnvironmentResolver) -> Self {
+        self.environment = environment;
+        self
     }
-}
 
-impl<F: Fn(&SentryEvent)> Processor<F> {
-    pub fn builder(client: Client, sender: F) -> ProcessorBuilder<F> {
-        ProcessorBuilder::new(client, sender)
+    /// Sets the name of the cluster the events come from (defaults to CLUSTER_NAME).
+    #[must_use]
+    pub fn cluster(mut self, name: &str) -> Self {
+        self.cluster = name.to_string();
+        self
     }
 
-    fn new(
-        event_namespaces: Vec<String>,
-        exclude_components: Vec<String>,
-        exclude_reasons: Vec<String>,
-        exclude_namespaces: Vec<String>,
-        event_levels: Vec<String>,
-        client: Client,
-        sender: F,
-    ) -> Self {
+    #[must_use]
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Enables routing of the events to the DSN specified in the namespace annotations.
+    #[must_use]
+    pub fn annotation_routing(mut self, enabled: bool) -> Self {
+        self.annotation_routing = enabled;
+        self
+    }
+
+    /// Tags the events with the owner found in the annotations of the involved object, of its
+    /// controllers or of its namespace.
+    #[must_use]
+    pub fn issue_owners(mut self, enabled: bool) -> Self {
+        self.issue_owners = enabled;
+        self
+    }
+
+    /// Sets the size and the time to live of the cache of the objects fetched during enrichment.
+    #[must_use]
+    pub fn object_cache(mut self, size: usize, ttl: Duration) -> Self {
+        self.cache_size = size;
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Enables the enrichment of the events with the pod and node information (enabled by default).
+    /// When disabled, no pod or node is read from the API server.
+    #[must_use]
+    pub fn enrichment(mut self, enabled: bool) -> Self {
+        self.enrichment = enabled;
+        self
+    }
+
+    /// Sets the maximum time spent on each enrichment lookup.
+    #[must_use]
+    pub fn enrichment_timeout(mut self, timeout: Duration) -> Self {
+        self.enrichment_timeout = timeout;
+        self
+    }
+
+    /// Tags `being_deleted=true` the events of the objects being deleted, looking up the involved
+    /// object of each event (requires the enrichment).
+    #[must_use]
+    pub fn deletion_correlation(mut self, enabled: bool) -> Self {
+        self.deletion_correlation = enabled;
+        self
+    }
+
+    /// Skips the events processed before the checkpoint and keeps it up to date.
+    #[must_use]
+    pub fn checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Discards the events last occurred before the given age (ex: replayed after a relist).
+    #[must_use]
+    pub fn max_event_age(mut self, age: Duration) -> Self {
+        self.max_event_age = Some(age);
+        self
+    }
+
+    /// Only reports the events occurred after the processor is built: the existing events,
+    /// received with the initial list of the watcher, are discarded.
+    #[must_use]
+    pub fn ignore_existing_events(mut self, ignore: bool) -> Self {
+        self.ignore_before = ignore.then(SystemTime::now);
+        self
+    }
+
+    /// Only processes the events of the namespaces assigned to the shard.
+    #[must_use]
+    pub fn shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Demotes the events tagged as spot interruptions to the given level.
+    #[must_use]
+    pub fn spot_interruption_level(mut self, level: Level) -> Self {
+        self.spot_interruption_level = Some(level);
+        self
+    }
+
+    /// Reports the decision of the pipeline on each event, including the discarded ones.
+    #[must_use]
+    pub fn tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Evaluates the policy against each event, to drop or allow it, change its level or its DSNs.
+    #[must_use]
+    pub fn policy(mut self, policy: Option<Policy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Appends a filter to the chain, after the built-in filters
+    /// (age, existing, component, reason, message, namespace and level).
+    #[must_use]
+    pub fn filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    #[must_use]
+    pub fn sinks(mut self, sinks: Vec<Box<dyn EventSink>>) -> Self {
+        self.sinks.extend(sinks);
+        self
+    }
+}
+
+impl From<ProcessorBuilder> for Processor {
+    fn from(value: ProcessorBuilder) -> Self {
+        let kube: Arc<dyn KubeApi> = match value.lookups {
+            Lookups::Client(client) => {
+                let stores = value
+                    .enrichment
+                    .then(|| ObjectStores::start(client.clone(), &value.event_namespaces));
+                Arc::new(Kube::new(client, stores, value.cache_size, value.cache_ttl))
+            }
+            Lookups::Api(api) => api,
+        };
+        let mut filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(AgeFilter {
+                max_age: value.max_event_age,
+            }),
+            Box::new(ExistingFilter {
+                before: value.ignore_before,
+            }),
+            Box::new(ComponentFilter {
+                excluded: value.exclude_components,
+            }),
+            Box::new(ReasonFilter {
+                excluded: value.exclude_reasons,
+            }),
+            Box::new(MessageFilter {
+                excluded: value.exclude_messages,
+            }),
+            Box::new(NamespaceFilter {
+                included: value.event_namespaces,
+                excluded: value.exclude_namespaces,
+            }),
+            Box::new(LevelFilter {
+                levels: value.event_levels,
+            }),
+        ];
+        filters.extend(value.filters);
+
+        let enricher = Enricher::new(kube.clone(), value.enrichment_timeout)
+            .deletions(value.deletion_correlation);
         Self {
-            event_namespaces,
-            exclude_components,
-            exclude_reasons,
-            exclude_namespaces,
-            event_levels,
-            sender,
-
-            pod_api: Api::<Pod>::all(client.clone()),
-            nodes_api: Api::<Node>::all(client),
+            dedupe: DedupeStage::new(&value.cluster, value.shard, value.checkpoint),
+            enrich: EnrichStage::new(
+                &value.cluster,
+                enricher.clone(),
+                value.enrichment,
+                SpotInterruptions::new(value.spot_interruption_level),
+            ),
+            filter: FilterStage::new(FilterChain::from(filters), value.policy),
+            route: RouteStage::new(value.environment, value.router, enricher, kube)
+                .annotation_routing(value.annotation_routing)
+                .issue_owners(value.issue_owners),
+            sink: SinkStage::new(value.sinks),
+            tracer: value.tracer,
         }
     }
+}
 
+impl Processor {
+    pub fn builder(lookups: impl Into<Lookups>) -> ProcessorBuilder {
+        ProcessorBuilder::new(lookups.into())
+    }
+
+    /// Runs the events of the stream through the pipeline stages, each one a loop processing up to
+    /// `concurrency` events at the same time and passing them on to the next stage through a bounded
+    /// channel. Ends once the stream ends and the events have gone through all the stages.
+    /// The time spent in each stage is exposed in the metrics.
+    pub async fn run(&self, events: impl Stream<Item = Event>, concurrency: usize) {
+        let (deduped, enrich) = mpsc::channel(STAGE_QUEUE_SIZE);
+        let (enriched, filter) = mpsc::channel(STAGE_QUEUE_SIZE);
+        let (filtered, route) = mpsc::channel(STAGE_QUEUE_SIZE);
+        let (routed, sink) = mpsc::channel(STAGE_QUEUE_SIZE);
+        let (sent, finish) = mpsc::channel(STAGE_QUEUE_SIZE);
+
+        future::join(
+            future::join5(
+                self.dedupe.connect(events, deduped),
+                connect(&self.enrich, enrich, enriched, concurrency),
+                connect(&self.filter, filter, filtered, concurrency),
+                connect(&self.route, route, routed, concurrency),
+                connect(&self.sink, sink, sent, concurrency),
+            ),
+            receive(finish).for_each(|item| self.finish(item)),
+        )
+        .await;
+    }
+
+    /// Runs a single event through the pipeline stages: dedupe → enrich → filter → route → sink.
     pub async fn process(&self, event: Event) {
-        let mut sentry_event = SentryEvent::from(event);
-        let mut hostname = sentry_event.source_host;
-        if hostname.is_none() {
-            if sentry_event.kind.as_deref() == Some("Pod") {
-                if let Ok(pod) = self.pod_api.get(&sentry_event.name).await {
-                    hostname = pod.spec.and_then(|p| p.node_name);
-                }
-            }
+        let item = stages::timed("dedupe", async { self.dedupe.process(event) }).await;
+        self.process_item(item).await;
+    }
+
+    /// Runs an event converted from a watched resource through the pipeline stages:
+    /// enrich → filter → route → sink. The converters report each change once, so there is no dedupe.
+    pub async fn process_resource_event(&self, sentry_event: SentryEvent) {
+        METRICS.event_received();
+        let mut item = Item::new(sentry_event);
+        if !self.dedupe.owns(&item.event.namespace) {
+            item.verdict = Verdict::Discard("shard");
         }
 
-        if let Some(hostname) = hostname.as_deref() {
-            if let Ok(node) = self.nodes_api.get(hostname).await {
-                sentry_event.node_labels = node.metadata.labels.unwrap_or_default();
+        self.process_item(item).await;
+    }
+
+    async fn process_item(&self, item: Item) {
+        let item = stages::run(&self.enrich, item).await;
+        let item = stages::run(&self.filter, item).await;
+        let item = stages::run(&self.route, item).await;
+        let item = stages::run(&self.sink, item).await;
+        self.finish(item).await;
+    }
+
+    /// Ends the processing of the event: counts and traces it, records it as a breadcrumb of the
+    /// following events if not discarded, and records its time in the checkpoint.
+    async fn finish(&self, item: Item) {
+        match item.verdict {
+            Verdict::Discard(filter) => {
+                debug!("excluded by {} filter", filter);
+                METRICS.event_filtered(filter);
             }
+            Verdict::Breadcrumb => {
+                debug!("excluded by event level");
+                METRICS.event_filtered("level");
+            }
+            Verdict::Send => {}
         }
-
-        if self.exclude_components.contains(&sentry_event.component) {
-            debug!("excluded by component filter");
-            return;
+        if let Some(tracer) = &self.tracer {
+            tracer(&item.event, &item.verdict);
         }
-
-        if self.exclude_reasons.contains(&sentry_event.reason) {
-            debug!("excluded by reason filter");
-            return;
+        if !item.is_discarded() {
+            add_breadcrumb(breadcrumb(item.event));
         }
 
-        if self.exclude_namespaces.contains(&sentry_event.namespace) {
-            debug!("excluded by namespace filter");
-            return;
+        if let (Some(checkpoint), Some(time)) = (self.dedupe.checkpoint(), item.time) {
+            checkpoint.record(time).await;
         }
+    }
 
-        if !self.event_namespaces.is_empty()
-            && !self.event_namespaces.contains(&sentry_event.namespace)
-        {
-            debug!("event not in monitored namespace");
-            return;
-        }
+    /// The effective filters, as shown by the admin API.
+    pub fn filters(&self) -> Value {
+        let mut filters = self.filter.describe();
+        let shard = self.dedupe.shard().map(|shard| shard.to_string());
+        filters.insert("shard".to_string(), json!(shard));
+        Value::Object(filters)
+    }
 
-        if self
-            .event_levels
-            .iter()
-            .any(|e| e == &sentry_event.level.to_string())
-            || sentry_event.level == Level::Error
-        {
-            sentry_event.source_host = hostname;
-
-            debug!("sending event to sentry");
-            (self.sender)(&sentry_event);
-        } else {
-            debug!("excluded by event level");
+    /// Flushes the events buffered by the sinks.
+    pub async fn close(&self) {
+        if let Some(checkpoint) = self.dedupe.checkpoint() {
+            checkpoint.save().await;
         }
 
-        let mut breadcrumb = Breadcrumb {
-            data: {
-                let mut map = BTreeMap::new();
-                map.insert("name".into(), sentry_event.name.into());
-                map.insert("namespace".into(), sentry_event.namespace.into());
-                map
-            },
-            level: sentry_event.level,
-            message: sentry_event.message,
-            ..Default::default()
-        };
+        self.sink.close().await;
+    }
+}
 
-        if let Some(timestamp) = sentry_event.creation_timestamp {
-            breadcrumb.timestamp = timestamp;
-        }
+/// Outcome of the filter stage.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Send,
+    /// Not sent, only recorded as a breadcrumb of the following events.
+    Breadcrumb,
+    /// Discarded by the given filter.
+    Discard(&'static str),
+}
+
+fn breadcrumb(sentry_event: SentryEvent) -> Breadcrumb {
+    let mut breadcrumb = Breadcrumb {
+        data: {
+            let mut map = BTreeMap::new();
+            map.insert("name".into(), sentry_event.name.into());
+            map.insert("namespace".into(), sentry_event.namespace.into());
+            map
+        },
+        level: sentry_event.level,
+        message: sentry_event.message,
+        ..Default::default()
+    };
 
-        add_breadcrumb(breadcrumb);
+    if let Some(timestamp) = sentry_event.creation_timestamp {
+        breadcrumb.timestamp = timestamp;
     }
+
+    breadcrumb
+}
+
+/// Guesses the name of the workload owning the given pod, following its controller
+/// owner reference. Pods owned by a ReplicaSet are reported with the name of the
+/// deployment (the ReplicaSet name without the pod template hash).
+pub fn workload_name(pod: &Pod) -> Option<String> {
+    let owner = pod
+        .metadata
+        .owner_references
+        .as_ref()?
+        .iter()
+        .find(|o| o.controller == Some(true))?;
+
+    if owner.kind == "ReplicaSet" {
+        let hash = pod
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|l| l.get("pod-template-hash"));
+        if let Some(name) = hash.and_then(|h| owner.name.strip_suffix(&format!("-{}", h))) {
+            return Some(name.to_string());
+        }
+    }
+
+    Some(owner.name.clone())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::processor::Processor;
-    use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
-    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use crate::enrich::Enricher;
+    use crate::kube_api::FakeKube;
+    use crate::policy::Decision;
+    use crate::processor::{workload_name, Processor, Verdict};
+    use crate::routing::DSN_ANNOTATION;
+    use crate::sentry_event::SentryEvent;
+    use crate::stages::{Item, Stage};
+    use k8s_openapi::api::core::v1::{Event, EventSource, Node, ObjectReference, Pod, PodSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time};
     use k8s_openapi::chrono::DateTime;
-    use kube::Client;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use kube::api::{ApiResource, DynamicObject};
+    use sentry::Level;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
 
     fn generate_event() -> Event {
         Event {
@@ -252,22 +579,262 @@ mod tests {
     #[tokio::test]
     pub async fn test_processor_should_send_event() {
         let event = generate_event();
-        let passed = AtomicBool::new(false);
-        let client = Client::try_default().await.unwrap();
-        let processor = Processor::new(
-            vec![],
-            vec![],
-            vec![],
-            vec![],
-            vec!["warning".to_string(), "error".to_string()],
-            client,
-            |se| {
+        let passed = Arc::new(AtomicBool::new(false));
+        let sink_passed = passed.clone();
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .sinks(vec![Box::new(move |se: &SentryEvent| {
                 assert_eq!(se.type_, "warning".to_string());
-                passed.store(true, Ordering::SeqCst);
-            },
-        );
+                sink_passed.store(true, Ordering::SeqCst);
+            })])
+            .event_levels(vec!["warning".to_string(), "error".to_string()])
+            .into();
 
         processor.process(event).await;
-        assert_eq!(passed.load(Ordering::SeqCst), true);
+        assert!(passed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    pub async fn test_run() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sink_sent = sent.clone();
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .sinks(vec![Box::new(move |_: &SentryEvent| {
+                sink_sent.fetch_add(1, Ordering::SeqCst);
+            })])
+            .event_levels(vec!["warning".to_string()])
+            .into();
+
+        let mut updated = generate_event();
+        updated.metadata.resource_version = Some("355929326".to_string());
+        let events = vec![generate_event(), generate_event(), updated];
+        processor.run(futures::stream::iter(events), 2).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 2, "the duplicate is discarded");
+    }
+
+    #[tokio::test]
+    pub async fn test_dedupe() {
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default())).into();
+
+        let mut event = generate_event();
+        assert!(processor.dedupe.is_new(&event));
+        assert!(!processor.dedupe.is_new(&event));
+
+        event.metadata.resource_version = Some("355929326".to_string());
+        assert!(processor.dedupe.is_new(&event));
+
+        event.metadata.uid = None;
+        assert!(processor.dedupe.is_new(&event));
+        assert!(processor.dedupe.is_new(&event));
+    }
+
+    #[tokio::test]
+    pub async fn test_filter() {
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .event_components(vec!["kubelet".to_string()])
+            .event_namespaces(vec![], vec!["default".to_string()])
+            .event_levels(vec!["warning".to_string()])
+            .into();
+
+        let mut event = SentryEvent::from(generate_event());
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Discard("component")
+        );
+
+        event.component = "scheduler".to_string();
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Send
+        );
+
+        event.level = Level::Info;
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Breadcrumb
+        );
+
+        event.namespace = "default".to_string();
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Discard("namespace")
+        );
+
+        let allow = Decision {
+            allow: true,
+            ..Default::default()
+        };
+        assert_eq!(processor.filter.verdict(&event, &allow), Verdict::Send);
+        let drop = Decision {
+            drop: true,
+            ..Default::default()
+        };
+        event.namespace = "kube-system".to_string();
+        assert_eq!(
+            processor.filter.verdict(&event, &drop),
+            Verdict::Discard("policy")
+        );
+
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .event_levels(vec!["warning".to_string()])
+            .max_event_age(Duration::from_secs(1800))
+            .into();
+        let mut event = SentryEvent::from(generate_event());
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Discard("age")
+        );
+
+        event.event_time = Some(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Send
+        );
+
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .event_levels(vec!["warning".to_string()])
+            .ignore_existing_events(true)
+            .into();
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Discard("existing")
+        );
+
+        event.event_time = Some(SystemTime::now() + Duration::from_secs(1));
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Send
+        );
+
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .event_levels(vec!["warning".to_string()])
+            .filter(|e: &SentryEvent| match e.reason.as_str() {
+                "Failed" => Verdict::Discard("custom"),
+                _ => Verdict::Send,
+            })
+            .into();
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Discard("custom")
+        );
+        event.level = Level::Info;
+        assert_eq!(
+            processor.filter.verdict(&event, &Decision::default()),
+            Verdict::Breadcrumb
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_enrich() {
+        let kube = FakeKube {
+            pods: vec![Pod {
+                metadata: ObjectMeta {
+                    name: Some("coredns-bbbc4b766-fv96b".to_string()),
+                    namespace: Some("kube-system".to_string()),
+                    labels: Some(
+                        [("pod-template-hash".to_string(), "bbbc4b766".to_string())].into(),
+                    ),
+                    owner_references: Some(vec![OwnerReference {
+                        controller: Some(true),
+                        kind: "ReplicaSet".to_string(),
+                        name: "coredns-bbbc4b766".to_string(),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                spec: Some(PodSpec {
+                    node_name: Some("node-1".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            nodes: vec![Node {
+                metadata: ObjectMeta {
+                    name: Some("node-1".to_string()),
+                    labels: Some([("zone".to_string(), "eu-west-1a".to_string())].into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let processor: Processor = Processor::builder(Arc::new(kube)).into();
+
+        let item = Item::new(SentryEvent::from(generate_event()));
+        let event = processor.enrich.process(item).await.event;
+        assert_eq!(event.workload.as_deref(), Some("coredns"));
+        assert_eq!(event.source_host.as_deref(), Some("node-1"));
+        assert_eq!(event.node_capacity.unwrap().name, "node-1");
+        assert_eq!(event.node_labels["zone"], "eu-west-1a");
+
+        let processor: Processor = Processor::builder(Arc::new(FakeKube::default()))
+            .enrichment(false)
+            .into();
+        let item = Item::new(SentryEvent::from(generate_event()));
+        let event = processor.enrich.process(item).await.event;
+        assert_eq!(event.workload, None);
+    }
+
+    #[tokio::test]
+    pub async fn test_annotation_routing() {
+        let resource = ApiResource::erase::<k8s_openapi::api::core::v1::Namespace>(&());
+        let mut namespace = DynamicObject::new("kube-system", &resource);
+        namespace.metadata.annotations = Some(
+            [(
+                DSN_ANNOTATION.to_string(),
+                "https://public@sentry.example.com/3".to_string(),
+            )]
+            .into(),
+        );
+        let kube = FakeKube {
+            objects: vec![namespace],
+            ..Default::default()
+        };
+        let processor: Processor = Processor::builder(Arc::new(kube))
+            .annotation_routing(true)
+            .into();
+
+        let mut event = SentryEvent::from(generate_event());
+        processor.route.route(&mut event, vec![]).await;
+        assert_eq!(event.dsns, vec!["https://public@sentry.example.com/3"]);
+
+        let policy_dsns = vec!["https://public@sentry.example.com/4".to_string()];
+        processor.route.route(&mut event, policy_dsns.clone()).await;
+        assert_eq!(event.dsns, policy_dsns);
+    }
+
+    #[tokio::test]
+    pub async fn test_lookup_timeout() {
+        let enricher = Enricher::new(Arc::new(FakeKube::default()), Duration::from_millis(10));
+
+        assert_eq!(enricher.lookup("pod", async { Some(1) }).await, Some(1));
+        let pending = futures::future::pending::<Option<i32>>();
+        assert_eq!(enricher.lookup("pod", pending).await, None);
+    }
+
+    #[test]
+    pub fn test_workload_name() {
+        let mut pod = Pod::default();
+        assert_eq!(workload_name(&pod), None);
+
+        pod.metadata.labels = Some(
+            [("pod-template-hash".to_string(), "bbbc4b766".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        pod.metadata.owner_references = Some(vec![OwnerReference {
+            controller: Some(true),
+            kind: "ReplicaSet".to_string(),
+            name: "coredns-bbbc4b766".to_string(),
+            ..Default::default()
+        }]);
+        assert_eq!(workload_name(&pod), Some("coredns".to_string()));
+
+        pod.metadata.owner_references = Some(vec![OwnerReference {
+            controller: Some(true),
+            kind: "StatefulSet".to_string(),
+            name: "redis".to_string(),
+            ..Default::default()
+        }]);
+        assert_eq!(workload_name(&pod), Some("redis".to_string()));
     }
 }
@@ -0,0 +1,269 @@
+use crate::metrics::METRICS;
+use futures::Stream;
+use k8s_openapi::api::core::v1::Event;
+use log::warn;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Behavior of the queue between the watcher and the processor when it is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// Stops reading the watch stream until there is room in the queue (backpressure).
+    #[default]
+    Block,
+    /// Drops the incoming events.
+    Drop,
+    /// Drops the oldest queued events to make room for the incoming ones.
+    DropOldest,
+    /// Drops the oldest of the least severe events, queued or incoming (ex: the `Normal` events
+    /// before the `Warning` ones).
+    DropLowestSeverity,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(Self::Block),
+            "drop" | "drop-newest" => Ok(Self::Drop),
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-lowest-severity" => Ok(Self::DropLowestSeverity),
+            _ => Err(format!("unknown overflow policy \"{}\"", s)),
+        }
+    }
+}
+
+/// The severity of the queued items, for the `DropLowestSeverity` policy.
+pub trait Severity {
+    fn severity(&self) -> u8;
+}
+
+/// The `Normal` events are less severe than the others (`Warning`, or unknown types).
+impl Severity for Event {
+    fn severity(&self) -> u8 {
+        match self.type_.as_deref() {
+            Some("Normal") => 0,
+            _ => 1,
+        }
+    }
+}
+
+struct Shared<T> {
+    items: Mutex<VecDeque<T>>,
+    capacity: usize,
+    closed: AtomicBool,
+    /// Notified when an item has been queued, or the sender dropped.
+    queued: Notify,
+    /// Notified when an item has been received.
+    received: Notify,
+}
+
+/// The sending half of the event queue.
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+    policy: OverflowPolicy,
+}
+
+impl<T: Severity> QueueSender<T> {
+    /// Enqueues the item, applying the overflow policy if the queue is full.
+    /// Each drop is counted in the dropped events metric, by policy, and each wait for room in
+    /// the queue in the blocked events metric.
+    pub async fn push(&self, item: T) {
+        let mut blocked = false;
+        loop {
+            {
+                let mut items = self.shared.items.lock().unwrap();
+                if items.len() < self.shared.capacity {
+                    items.push_back(item);
+                    METRICS.queue_depth(items.len());
+                    break;
+                }
+
+                match self.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::Drop => {
+                        warn!("Event queue is full, dropping event");
+                        METRICS.event_dropped("queue_full");
+                        return;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        warn!("Event queue is full, dropping the oldest event");
+                        METRICS.event_dropped("queue_full_oldest");
+                        items.pop_front();
+                        items.push_back(item);
+                        break;
+                    }
+                    OverflowPolicy::DropLowestSeverity => {
+                        warn!("Event queue is full, dropping the least severe event");
+                        METRICS.event_dropped("queue_full_lowest_severity");
+                        let lowest = items
+                            .iter()
+                            .enumerate()
+                            .min_by_key(|(_, queued)| queued.severity())
+                            .filter(|(_, queued)| queued.severity() <= item.severity())
+                            .map(|(index, _)| index);
+                        match lowest {
+                            Some(index) => {
+                                items.remove(index);
+                                items.push_back(item);
+                                break;
+                            }
+                            None => return,
+                        }
+                    }
+                }
+            }
+
+            if !blocked {
+                blocked = true;
+                METRICS.queue_blocked();
+            }
+            self.shared.received.notified().await;
+        }
+
+        self.shared.queued.notify_one();
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.queued.notify_one();
+    }
+}
+
+/// Creates a bounded queue. The receiving stream ends once the sender has been dropped
+/// and all the queued items have been received.
+pub fn channel<T: Send + 'static>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (QueueSender<T>, impl Stream<Item = T>) {
+    let shared = Arc::new(Shared {
+        items: Mutex::new(VecDeque::new()),
+        capacity: capacity.max(1),
+        closed: AtomicBool::new(false),
+        queued: Notify::new(),
+        received: Notify::new(),
+    });
+
+    let stream = futures::stream::unfold(shared.clone(), |shared| async move {
+        loop {
+            // Checked before receiving: the last items may be queued right before the closing.
+            let closed = shared.closed.load(Ordering::Acquire);
+            let item = {
+                let mut items = shared.items.lock().unwrap();
+                let item = items.pop_front();
+                if item.is_some() {
+                    METRICS.queue_depth(items.len());
+                }
+                item
+            };
+
+            if let Some(item) = item {
+                shared.received.notify_one();
+                return Some((item, shared));
+            } else if closed {
+                return None;
+            }
+            shared.queued.notified().await;
+        }
+    });
+
+    (QueueSender { shared, policy }, stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queue::{channel, OverflowPolicy, Severity};
+    use futures::StreamExt;
+    use k8s_openapi::api::core::v1::Event;
+    use std::str::FromStr;
+
+    /// The odd numbers are more severe.
+    impl Severity for i32 {
+        fn severity(&self) -> u8 {
+            (self % 2) as u8
+        }
+    }
+
+    #[test]
+    pub fn test_overflow_policy() {
+        assert_eq!(
+            OverflowPolicy::from_str("Block").unwrap(),
+            OverflowPolicy::Block
+        );
+        assert_eq!(
+            OverflowPolicy::from_str("drop").unwrap(),
+            OverflowPolicy::Drop
+        );
+        assert_eq!(
+            OverflowPolicy::from_str("drop-oldest").unwrap(),
+            OverflowPolicy::DropOldest
+        );
+        assert_eq!(
+            OverflowPolicy::from_str("drop-lowest-severity").unwrap(),
+            OverflowPolicy::DropLowestSeverity
+        );
+        assert!(OverflowPolicy::from_str("wait").is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_drop_when_full() {
+        let (sender, receiver) = channel(2, OverflowPolicy::Drop);
+        for i in 0..5 {
+            sender.push(i).await;
+        }
+
+        drop(sender);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    pub async fn test_block_when_full() {
+        let (sender, receiver) = channel(1, OverflowPolicy::Block);
+        let producer = tokio::spawn(async move {
+            for i in 0..5 {
+                sender.push(i).await;
+            }
+        });
+
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![0, 1, 2, 3, 4]);
+        producer.await.unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_drop_oldest_when_full() {
+        let (sender, receiver) = channel(2, OverflowPolicy::DropOldest);
+        for i in 0..5 {
+            sender.push(i).await;
+        }
+
+        drop(sender);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    pub async fn test_drop_lowest_severity_when_full() {
+        let (sender, receiver) = channel(3, OverflowPolicy::DropLowestSeverity);
+        for i in [0, 1, 2, 3, 5, 4, 7] {
+            sender.push(i).await;
+        }
+
+        drop(sender);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![3, 5, 7]);
+    }
+
+    #[test]
+    pub fn test_event_severity() {
+        let event = |type_: &str| Event {
+            type_: Some(type_.to_string()),
+            ..Default::default()
+        };
+        assert!(event("Normal").severity() < event("Warning").severity());
+        assert_eq!(event("Warning").severity(), event("Critical").severity());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::objects::owner;
+use k8s_openapi::api::apps::v1::ReplicaSet;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::Utc;
+use log::debug;
+use sentry::protocol::{Envelope, SessionAttributes, SessionStatus, SessionUpdate};
+use sentry::types::Uuid;
+use sentry::Hub;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The annotation of the Deployment (copied to its ReplicaSets) overriding the release name.
+const RELEASE_ANNOTATION: &str = "sentry-kubernetes.io/release";
+
+/// Opens a sentry release-health session for each pod of a new ReplicaSet of a Deployment (a
+/// rollout), for the release of the ReplicaSet. The session of a pod is marked as crashed if one of
+/// its containers fails during the rollout window, and as exited at the end of the window.
+/// The release is the `sentry-kubernetes.io/release` annotation, or "<deployment>@<image tag>".
+pub struct ReleaseSessions {
+    window: Duration,
+    /// The ReplicaSets created after this time are rollouts.
+    started: Time,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The rollouts in progress, by namespace and ReplicaSet name.
+    rollouts: HashMap<(String, String), Rollout>,
+    /// The sessions of the pods of the rollouts, by pod uid.
+    sessions: HashMap<String, Session>,
+}
+
+struct Rollout {
+    release: String,
+    since: Instant,
+}
+
+struct Session {
+    id: Uuid,
+    /// Namespace and name of the ReplicaSet of the pod.
+    replica_set: (String, String),
+    release: String,
+    started: SystemTime,
+    sequence: u64,
+    status: SessionStatus,
+}
+
+impl ReleaseSessions {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started: Time(Utc::now()),
+            state: Default::default(),
+        }
+    }
+
+    /// Records the ReplicaSet as a rollout, if created by a Deployment after startup.
+    pub fn replica_set(&self, replica_set: &ReplicaSet) {
+        let metadata = &replica_set.metadata;
+        let created_after_start = metadata
+            .creation_timestamp
+            .as_ref()
+            .is_some_and(|t| *t > self.started);
+        if !created_after_start || owner(metadata, "Deployment").is_none() {
+            return;
+        }
+
+        let key = (
+            metadata.namespace.clone().unwrap_or_default(),
+            metadata.name.clone().unwrap_or_default(),
+        );
+        let mut state = self.state.lock().unwrap();
+        state.rollouts.entry(key).or_insert_with(|| Rollout {
+            release: release(replica_set),
+            since: Instant::now(),
+        });
+    }
+
+    /// Opens the session of the pod if it belongs to a rollout, or marks it as crashed.
+    pub fn pod(&self, pod: &Pod) {
+        if let Some(update) = self.update(pod) {
+            send(update);
+        }
+    }
+
+    /// Closes the sessions of the rollouts which lasted for the window.
+    pub fn due(&self) {
+        let mut state = self.state.lock().unwrap();
+        let window = self.window;
+        state.rollouts.retain(|_, r| r.since.elapsed() < window);
+
+        let State { rollouts, sessions } = &mut *state;
+        let mut ended = vec![];
+        sessions.retain(|_, session| {
+            if rollouts.contains_key(&session.replica_set) {
+                return true;
+            }
+
+            if session.status == SessionStatus::Ok {
+                session.status = SessionStatus::Exited;
+                session.sequence += 1;
+                ended.push(session_update(session, false));
+            }
+            false
+        });
+        drop(state);
+
+        for update in ended {
+            send(update);
+        }
+    }
+
+    /// The update of the session of the pod, if changed.
+    fn update(&self, pod: &Pod) -> Option<SessionUpdate<'static>> {
+        let uid = pod.metadata.uid.clone()?;
+        let replica_set = (
+            pod.metadata.namespace.clone().unwrap_or_default(),
+            owner(&pod.metadata, "ReplicaSet")?,
+        );
+
+        let mut state = self.state.lock().unwrap();
+        let release = state.rollouts.get(&replica_set)?.release.clone();
+        let crashed = has_crashed(pod);
+        match state.sessions.get_mut(&uid) {
+            Some(session) if crashed && session.status == SessionStatus::Ok => {
+                session.status = SessionStatus::Crashed;
+                session.sequence += 1;
+                Some(session_update(session, false))
+            }
+            Some(_) => None,
+            None => {
+                let session = Session {
+                    id: Uuid::new_v4(),
+                    replica_set,
+                    release,
+                    started: SystemTime::now(),
+                    sequence: 0,
+                    status: if crashed {
+                        SessionStatus::Crashed
+                    } else {
+                        SessionStatus::Ok
+                    },
+                };
+                let update = session_update(&session, true);
+                state.sessions.insert(uid, session);
+                Some(update)
+            }
+        }
+    }
+}
+
+/// The release of the ReplicaSet: its annotation, or "<deployment>@<tag of the first image>",
+/// "<deployment>@<revision>" if the image is not tagged.
+fn release(replica_set: &ReplicaSet) -> String {
+    let metadata = &replica_set.metadata;
+    let annotations = metadata.annotations.as_ref();
+    if let Some(release) = annotations.and_then(|a| a.get(RELEASE_ANNOTATION)) {
+        return release.clone();
+    }
+
+    let deployment = owner(metadata, "Deployment").unwrap_or_default();
+    let tag = replica_set
+        .spec
+        .as_ref()
+        .and_then(|s| s.template.as_ref())
+        .and_then(|t| t.spec.as_ref())
+        .and_then(|s| s.containers.first())
+        .and_then(|c| c.image.as_deref())
+        .and_then(|image| image.split('@').next())
+        .and_then(|image| image.rsplit_once(':'))
+        .map(|(_, tag)| tag)
+        .filter(|tag| !tag.contains('/'));
+    let version = match tag {
+        Some(tag) => tag.to_string(),
+        None => annotations
+            .and_then(|a| a.get("deployment.kubernetes.io/revision"))
+            .cloned()
+            .unwrap_or_default(),
+    };
+
+    format!("{}@{}", deployment, version)
+}
+
+/// Whether a container of the pod has failed: restarted, waiting in CrashLoopBackOff or terminated
+/// with an error.
+fn has_crashed(pod: &Pod) -> bool {
+    let Some(status) = pod.status.as_ref() else {
+        return false;
+    };
+
+    status
+        .init_container_statuses
+        .iter()
+        .chain(status.container_statuses.iter())
+        .flatten()
+        .any(|s| {
+            let state = s.state.as_ref();
+            let waiting = state.and_then(|s| s.waiting.as_ref());
+            let terminated = state.and_then(|s| s.terminated.as_ref());
+            s.restart_count > 0
+                || waiting.and_then(|w| w.reason.as_deref()) == Some("CrashLoopBackOff")
+                || terminated.is_some_and(|t| t.exit_code != 0)
+        })
+}
+
+fn session_update(session: &Session, init: bool) -> SessionUpdate<'static> {
+    SessionUpdate {
+        session_id: session.id,
+        distinct_id: None,
+        sequence: Some(session.sequence),
+        timestamp: Some(SystemTime::now()),
+        started: session.started,
+        init,
+        duration: session.started.elapsed().ok().map(|d| d.as_secs_f64()),
+        status: session.status,
+        errors: (session.status == SessionStatus::Crashed).into(),
+        attributes: SessionAttributes {
+            release: Cow::Owned(session.release.clone()),
+            environment: None,
+            ip_address: None,
+            user_agent: None,
+        },
+    }
+}
+
+/// Sends the session update with the main client, in its environment.
+fn send(mut update: SessionUpdate<'static>) {
+    let Some(client) = Hub::main().client() else {
+        return;
+    };
+
+    update.attributes.environment = client.options().environment.clone();
+    debug!(
+        target: "sentry_kubernetes::release_health",
+        "Session of release {}: {}", update.attributes.release, update.status
+    );
+    let mut envelope = Envelope::new();
+    envelope.add_item(update);
+    client.send_envelope(envelope);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::release_health::{release, ReleaseSessions};
+    use k8s_openapi::api::apps::v1::{ReplicaSet, ReplicaSetSpec};
+    use k8s_openapi::api::core::v1::{
+        Container, ContainerStatus, Pod, PodSpec, PodStatus, PodTemplateSpec,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time};
+    use k8s_openapi::chrono::{Duration as ChronoDuration, Utc};
+    use sentry::protocol::SessionStatus;
+    use std::time::Duration;
+
+    fn metadata(name: &str, owner_kind: &str, owner: &str) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("shop".to_string()),
+            uid: Some(format!("{}-uid", name)),
+            creation_timestamp: Some(Time(Utc::now() + ChronoDuration::seconds(1))),
+            owner_references: Some(vec![OwnerReference {
+                kind: owner_kind.to_string(),
+                name: owner.to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn replica_set(image: &str) -> ReplicaSet {
+        ReplicaSet {
+            metadata: ObjectMeta {
+                annotations: Some(
+                    [(
+                        "deployment.kubernetes.io/revision".to_string(),
+                        "3".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..metadata("web-5d4f", "Deployment", "web")
+            },
+            spec: Some(ReplicaSetSpec {
+                template: Some(PodTemplateSpec {
+                    spec: Some(PodSpec {
+                        containers: vec![Container {
+                            image: Some(image.to_string()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn pod(restart_count: i32) -> Pod {
+        Pod {
+            metadata: metadata("web-5d4f-x2k", "ReplicaSet", "web-5d4f"),
+            status: Some(PodStatus {
+                container_statuses: Some(vec![ContainerStatus {
+                    restart_count,
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_release() {
+        assert_eq!(
+            release(&replica_set("registry:5000/shop/web:1.4.2")),
+            "web@1.4.2"
+        );
+        assert_eq!(release(&replica_set("registry:5000/shop/web")), "web@3");
+    }
+
+    #[test]
+    pub fn test_sessions() {
+        let sessions = ReleaseSessions::new(Duration::from_secs(900));
+        assert!(sessions.update(&pod(0)).is_none());
+
+        sessions.replica_set(&replica_set("web:1.4.2"));
+        let opened = sessions.update(&pod(0)).unwrap();
+        assert!(opened.init);
+        assert_eq!(opened.status, SessionStatus::Ok);
+        assert_eq!(opened.attributes.release, "web@1.4.2");
+        assert!(sessions.update(&pod(0)).is_none());
+
+        let crashed = sessions.update(&pod(1)).unwrap();
+        assert_eq!(crashed.session_id, opened.session_id);
+        assert_eq!(crashed.status, SessionStatus::Crashed);
+        assert_eq!(crashed.errors, 1);
+        assert!(sessions.update(&pod(2)).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::cache::TtlCache;
+use crate::sentry_api::SentryApi;
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use log::{debug, warn};
+use sentry::types::Uuid;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Number of failing objects remembered, and for how long their recovery resolves their issues.
+const FAILING_CACHE_SIZE: usize = 5000;
+const FAILING_TTL: Duration = Duration::from_secs(86400);
+
+/// The reasons of the failures resolved by the recovery of their object, by kind.
+const RECOVERABLE_REASONS: [(&str, &[&str]); 2] = [
+    ("Pod", &["BackOff", "CrashLoopBackOff"]),
+    ("Node", &["NodeNotReady", "NodeStatusUnknown"]),
+];
+
+/// Resolves the sentry issues of the failures which healed by themselves: a pod becoming Ready
+/// after a CrashLoopBackOff, a node becoming Ready again.
+/// The last event reported for each fingerprint of a failing object is remembered, and its issue is
+/// looked up through the sentry API and resolved once the object has recovered.
+pub struct IssueResolver {
+    api: Arc<SentryApi>,
+    /// The failing objects, with the last event id reported by fingerprint.
+    failing: Mutex<TtlCache<String, BTreeMap<Vec<String>, Uuid>>>,
+}
+
+impl IssueResolver {
+    pub fn new(api: Arc<SentryApi>) -> Self {
+        Self {
+            api,
+            failing: Mutex::new(TtlCache::new(FAILING_CACHE_SIZE, FAILING_TTL)),
+        }
+    }
+
+    /// Remembers the reported event, if its failure can be resolved by a recovery.
+    pub fn record(&self, event: &SentryEvent) {
+        let Some(kind) = event.kind.as_deref() else {
+            return;
+        };
+        let recoverable = RECOVERABLE_REASONS
+            .iter()
+            .any(|(k, reasons)| *k == kind && reasons.contains(&event.reason.as_str()));
+        if !recoverable {
+            return;
+        }
+
+        // The node events are given the fallback namespace (ex: "default").
+        let namespace = if kind == "Node" { "" } else { &event.namespace };
+        let key = object_key(kind, namespace, &event.name);
+        let mut failing = self.failing.lock().unwrap();
+        let mut events = failing.get(&key).unwrap_or_default();
+        events.insert(event.fingerprint(), event.uid);
+        failing.insert(key, events);
+    }
+
+    /// Resolves the issues of the pod, once all its containers are ready.
+    pub async fn pod(&self, pod: &Pod) {
+        let ready = pod
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .into_iter()
+            .flatten()
+            .any(|c| c.type_ == "Ready" && c.status == "True");
+        if ready {
+            let (namespace, name) = (
+                pod.metadata.namespace.as_deref().unwrap_or_default(),
+                pod.metadata.name.as_deref().unwrap_or_default(),
+            );
+            self.recovered(&object_key("Pod", namespace, name)).await;
+        }
+    }
+
+    /// Resolves the issues of the node, once it is Ready again.
+    pub async fn node(&self, node: &Node) {
+        let ready = node
+            .status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .into_iter()
+            .flatten()
+            .any(|c| c.type_ == "Ready" && c.status == "True");
+        if ready {
+            let name = node.metadata.name.as_deref().unwrap_or_default();
+            self.recovered(&object_key("Node", "", name)).await;
+        }
+    }
+
+    async fn recovered(&self, key: &str) {
+        let Some(events) = self.failing.lock().unwrap().remove(&key.to_string()) else {
+            return;
+        };
+
+        for (fingerprint, event_id) in events {
+            match self.resolve(event_id).await {
+                Ok(issue) => debug!(
+                    target: "sentry_kubernetes::resolver",
+                    "Resolved issue {} of {} ({:?})", issue, key, fingerprint
+                ),
+                Err(e) => warn!(
+                    "Cannot resolve the issue of {} ({:?}): {}",
+                    key, fingerprint, e
+                ),
+            }
+        }
+    }
+
+    /// Looks up the issue of the event, then marks it as resolved. Returns the issue id.
+    async fn resolve(&self, event_id: Uuid) -> Result<String, reqwest::Error> {
+        let issue = self.api.issue_id(event_id).await?;
+        self.api
+            .update_issue(&issue, &json!({ "status": "resolved" }))
+            .await?;
+
+        Ok(issue)
+    }
+}
+
+fn object_key(kind: &str, namespace: &str, name: &str) -> String {
+    format!("{}/{}/{}", kind, namespace, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resolve::{object_key, IssueResolver};
+    use crate::sentry_api::SentryApi;
+    use crate::sentry_event::SentryEvent;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use std::sync::Arc;
+
+    fn event(kind: &str, reason: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some(kind.to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some(reason.to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_record() {
+        let api = SentryApi::new(None, "https://public@sentry.example.com/1", "acme", "token");
+        let resolver = IssueResolver::new(Arc::new(api));
+
+        resolver.record(&event("Pod", "BackOff"));
+        resolver.record(&event("Pod", "FailedMount"));
+        resolver.record(&event("Deployment", "BackOff"));
+        let mut failing = resolver.failing.lock().unwrap();
+        let events = failing.get(&object_key("Pod", "shop", "web-0")).unwrap();
+        assert_eq!(
+            events.keys().collect::<Vec<_>>(),
+            [&vec!["BackOff", "shop", "web-0", "Pod"]]
+        );
+        assert!(failing
+            .get(&object_key("Deployment", "shop", "web-0"))
+            .is_none());
+    }
+}
@@ -0,0 +1,370 @@
+use crate::objects::owner;
+use k8s_openapi::api::apps::v1::ReplicaSet;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use log::debug;
+use sentry::protocol::{Context, Span, SpanId, SpanStatus, TraceContext, TraceId, Transaction};
+use sentry::Hub;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// The annotation of the Deployment (copied to its ReplicaSets) opting its rollouts in.
+const TRACE_ANNOTATION: &str = "sentry-kubernetes.io/trace-rollouts";
+/// The rollouts not fully available after this time are sent as exceeding their deadline.
+const MAX_ROLLOUT_DURATION: Duration = Duration::from_secs(3600);
+/// Maximum number of pods with spans in a transaction.
+const MAX_PODS: usize = 100;
+
+/// Sends a sentry transaction spanning the rollouts of the opted-in Deployments, from the creation
+/// of the new ReplicaSet to its full availability, with the scheduling, the image pulls (and
+/// container starts) and the readiness of each of its pods as spans.
+pub struct RolloutTransactions {
+    /// The ReplicaSets created after this time are rollouts.
+    started: Time,
+    /// The rollouts in progress, by namespace and ReplicaSet name.
+    rollouts: Mutex<HashMap<(String, String), Rollout>>,
+}
+
+struct Rollout {
+    replica_set: ReplicaSet,
+    /// The last seen state of the pods of the ReplicaSet, by uid.
+    pods: BTreeMap<String, Pod>,
+}
+
+impl Default for RolloutTransactions {
+    fn default() -> Self {
+        Self {
+            started: Time(Utc::now()),
+            rollouts: Default::default(),
+        }
+    }
+}
+
+impl RolloutTransactions {
+    /// Follows the new ReplicaSets of the opted-in Deployments, and sends their transaction once
+    /// all their replicas are available.
+    pub fn replica_set(&self, replica_set: &ReplicaSet) {
+        let metadata = &replica_set.metadata;
+        let key = key(metadata);
+        let mut rollouts = self.rollouts.lock().unwrap();
+        let rollout = match rollouts.get_mut(&key) {
+            Some(rollout) => rollout,
+            None if self.is_traced(replica_set) => rollouts.entry(key.clone()).or_insert(Rollout {
+                replica_set: replica_set.clone(),
+                pods: Default::default(),
+            }),
+            None => return,
+        };
+        rollout.replica_set = replica_set.clone();
+
+        let desired = replica_set
+            .spec
+            .as_ref()
+            .and_then(|s| s.replicas)
+            .unwrap_or(1);
+        let available = replica_set
+            .status
+            .as_ref()
+            .and_then(|s| s.available_replicas)
+            .unwrap_or_default();
+        if desired > 0 && available >= desired {
+            if let Some(rollout) = rollouts.remove(&key) {
+                send(transaction(&rollout, SystemTime::now(), SpanStatus::Ok));
+            }
+        }
+    }
+
+    /// Records the state of the pod, if it belongs to a followed rollout.
+    pub fn pod(&self, pod: &Pod) {
+        let (Some(uid), Some(replica_set)) =
+            (pod.metadata.uid.clone(), owner(&pod.metadata, "ReplicaSet"))
+        else {
+            return;
+        };
+
+        let key = (
+            pod.metadata.namespace.clone().unwrap_or_default(),
+            replica_set,
+        );
+        let mut rollouts = self.rollouts.lock().unwrap();
+        if let Some(rollout) = rollouts.get_mut(&key) {
+            if rollout.pods.len() < MAX_PODS || rollout.pods.contains_key(&uid) {
+                rollout.pods.insert(uid, pod.clone());
+            }
+        }
+    }
+
+    /// Sends the rollouts not available after the maximum duration as exceeding their deadline.
+    pub fn due(&self) {
+        let now = SystemTime::now();
+        let mut rollouts = self.rollouts.lock().unwrap();
+        rollouts.retain(|_, rollout| {
+            let started = start_time(&rollout.replica_set.metadata);
+            if now.duration_since(started).unwrap_or_default() < MAX_ROLLOUT_DURATION {
+                return true;
+            }
+
+            send(transaction(rollout, now, SpanStatus::DeadlineExceeded));
+            false
+        });
+    }
+
+    /// Whether the ReplicaSet is a new rollout of an opted-in Deployment.
+    fn is_traced(&self, replica_set: &ReplicaSet) -> bool {
+        let metadata = &replica_set.metadata;
+        let opted_in = metadata
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get(TRACE_ANNOTATION))
+            .is_some_and(|v| v == "true");
+        let created_after_start = metadata
+            .creation_timestamp
+            .as_ref()
+            .is_some_and(|t| *t > self.started);
+
+        opted_in && created_after_start && owner(metadata, "Deployment").is_some()
+    }
+}
+
+fn key(metadata: &ObjectMeta) -> (String, String) {
+    (
+        metadata.namespace.clone().unwrap_or_default(),
+        metadata.name.clone().unwrap_or_default(),
+    )
+}
+
+fn start_time(metadata: &ObjectMeta) -> SystemTime {
+    metadata
+        .creation_timestamp
+        .as_ref()
+        .map_or_else(SystemTime::now, |t| t.0.into())
+}
+
+/// The transaction of the rollout, ending at the given time.
+fn transaction(rollout: &Rollout, end: SystemTime, status: SpanStatus) -> Transaction<'static> {
+    let metadata = &rollout.replica_set.metadata;
+    let (namespace, name) = key(metadata);
+    let deployment = owner(metadata, "Deployment").unwrap_or_default();
+    let (trace_id, root) = (TraceId::default(), SpanId::default());
+
+    let spans = rollout
+        .pods
+        .values()
+        .flat_map(|pod| pod_spans(pod, trace_id, root))
+        .collect();
+    let mut tags = BTreeMap::from([
+        ("namespace".to_string(), namespace.clone()),
+        ("deployment".to_string(), deployment.clone()),
+        ("replicaset".to_string(), name),
+    ]);
+    if let Some(revision) = metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get("deployment.kubernetes.io/revision"))
+    {
+        tags.insert("revision".to_string(), revision.clone());
+    }
+
+    Transaction {
+        name: Some(format!("rollout {}/{}", namespace, deployment)),
+        tags,
+        start_timestamp: start_time(metadata),
+        timestamp: Some(end),
+        spans,
+        contexts: BTreeMap::from([(
+            "trace".to_string(),
+            Context::Trace(Box::new(TraceContext {
+                span_id: root,
+                trace_id,
+                op: Some("rollout".to_string()),
+                status: Some(status),
+                ..Default::default()
+            })),
+        )]),
+        ..Default::default()
+    }
+}
+
+/// The spans of the pod: its scheduling, the pull of the image and the start of each container,
+/// then its readiness.
+fn pod_spans(pod: &Pod, trace_id: TraceId, parent: SpanId) -> Vec<Span> {
+    let name = pod.metadata.name.clone().unwrap_or_default();
+    let span = |op: &str, description: String, start: SystemTime, end: SystemTime| Span {
+        span_id: SpanId::default(),
+        trace_id,
+        parent_span_id: Some(parent),
+        op: Some(op.to_string()),
+        description: Some(description),
+        start_timestamp: start,
+        timestamp: Some(end),
+        status: Some(SpanStatus::Ok),
+        ..Default::default()
+    };
+
+    let status = pod.status.as_ref();
+    let condition = |type_: &str| -> Option<SystemTime> {
+        let condition = status?
+            .conditions
+            .as_ref()?
+            .iter()
+            .find(|c| c.type_ == type_ && c.status == "True")?;
+        Some(condition.last_transition_time.as_ref()?.0.into())
+    };
+    let created = start_time(&pod.metadata);
+    let mut spans = vec![];
+    let Some(scheduled) = condition("PodScheduled") else {
+        return spans;
+    };
+    spans.push(span("pod.schedule", name.clone(), created, scheduled));
+
+    let containers = status
+        .and_then(|s| s.container_statuses.as_ref())
+        .into_iter()
+        .flatten();
+    let mut started_at = vec![];
+    for container in containers {
+        let running = container.state.as_ref().and_then(|s| s.running.as_ref());
+        let Some(started) = running.and_then(|r| r.started_at.as_ref()) else {
+            continue;
+        };
+        let started = SystemTime::from(started.0);
+        started_at.push(started);
+        spans.push(span(
+            "image.pull",
+            format!("{} {} (pull and start)", name, container.image),
+            scheduled,
+            started,
+        ));
+    }
+
+    if let (Some(started), Some(ready)) = (started_at.into_iter().max(), condition("Ready")) {
+        spans.push(span("pod.readiness", name, started, ready));
+    }
+
+    spans
+}
+
+/// Sends the transaction with the main client, in its environment.
+fn send(mut transaction: Transaction<'static>) {
+    let Some(client) = Hub::main().client() else {
+        return;
+    };
+
+    transaction.environment = client.options().environment.clone();
+    debug!(
+        target: "sentry_kubernetes::rollout_transactions",
+        "Rollout transaction {:?} with {} spans",
+        transaction.name,
+        transaction.spans.len()
+    );
+    client.send_envelope(transaction.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rollout_transactions::{transaction, RolloutTransactions};
+    use k8s_openapi::api::apps::v1::{ReplicaSet, ReplicaSetSpec, ReplicaSetStatus};
+    use k8s_openapi::api::core::v1::{
+        ContainerState, ContainerStateRunning, ContainerStatus, Pod, PodCondition, PodStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference, Time};
+    use k8s_openapi::chrono::{Duration, Utc};
+    use sentry::protocol::SpanStatus;
+    use std::time::SystemTime;
+
+    fn metadata(name: &str, owner_kind: &str, owner: &str, offset: i64) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("shop".to_string()),
+            uid: Some(format!("{}-uid", name)),
+            creation_timestamp: Some(Time(Utc::now() + Duration::seconds(offset))),
+            owner_references: Some(vec![OwnerReference {
+                kind: owner_kind.to_string(),
+                name: owner.to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn replica_set(opted_in: bool) -> ReplicaSet {
+        let mut metadata = metadata("web-5d4f", "Deployment", "web", 1);
+        if opted_in {
+            metadata.annotations = Some(
+                [(
+                    "sentry-kubernetes.io/trace-rollouts".to_string(),
+                    "true".to_string(),
+                )]
+                .into(),
+            );
+        }
+
+        ReplicaSet {
+            metadata,
+            spec: Some(ReplicaSetSpec {
+                replicas: Some(1),
+                ..Default::default()
+            }),
+            status: Some(ReplicaSetStatus {
+                available_replicas: Some(0),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn pod() -> Pod {
+        let time = |offset| Some(Time(Utc::now() + Duration::seconds(offset)));
+        let condition = |type_: &str, offset| PodCondition {
+            type_: type_.to_string(),
+            status: "True".to_string(),
+            last_transition_time: time(offset),
+            ..Default::default()
+        };
+        Pod {
+            metadata: metadata("web-5d4f-x2k", "ReplicaSet", "web-5d4f", 2),
+            status: Some(PodStatus {
+                conditions: Some(vec![condition("PodScheduled", 3), condition("Ready", 20)]),
+                container_statuses: Some(vec![ContainerStatus {
+                    image: "web:1.4.2".to_string(),
+                    state: Some(ContainerState {
+                        running: Some(ContainerStateRunning {
+                            started_at: time(15),
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_transaction() {
+        let transactions = RolloutTransactions::default();
+        transactions.replica_set(&replica_set(false));
+        assert!(transactions.rollouts.lock().unwrap().is_empty());
+
+        transactions.replica_set(&replica_set(true));
+        transactions.pod(&pod());
+        let rollouts = transactions.rollouts.lock().unwrap();
+        let rollout = rollouts.values().next().unwrap();
+        let transaction = transaction(rollout, SystemTime::now(), SpanStatus::Ok);
+
+        assert_eq!(transaction.name.as_deref(), Some("rollout shop/web"));
+        assert_eq!(transaction.tags["replicaset"], "web-5d4f");
+        let ops = transaction
+            .spans
+            .iter()
+            .map(|s| s.op.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ops, ["pod.schedule", "image.pull", "pod.readiness"]);
+        assert_eq!(
+            transaction.spans[1].description.as_deref(),
+            Some("web-5d4f-x2k web:1.4.2 (pull and start)")
+        );
+    }
+}
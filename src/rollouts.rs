@@ -0,0 +1,423 @@
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{Event, ObjectReference, PodTemplateSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The rollout of a Deployment or a StatefulSet, and its problem if any.
+#[derive(Clone, Debug)]
+pub struct Rollout {
+    kind: &'static str,
+    metadata: ObjectMeta,
+    images: Vec<String>,
+    revision: Option<String>,
+    replicas: i32,
+    ready_replicas: i32,
+    updated_replicas: i32,
+    problem: Option<Problem>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Problem {
+    reason: String,
+    message: String,
+    /// The time the problem started, if known.
+    since: Option<DateTime<Utc>>,
+    /// The problem is reported without waiting for the rollout deadline (ex: the Deployment
+    /// controller already applied its progress deadline).
+    immediate: bool,
+}
+
+impl From<&Deployment> for Rollout {
+    fn from(deployment: &Deployment) -> Self {
+        let status = deployment.status.as_ref();
+        let conditions = status
+            .and_then(|s| s.conditions.as_ref())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let condition = |type_: &str| conditions.iter().find(|c| c.type_ == type_);
+
+        let problem = match (condition("Progressing"), condition("Available")) {
+            (Some(progressing), _)
+                if progressing.status == "False"
+                    && progressing.reason.as_deref() == Some("ProgressDeadlineExceeded") =>
+            {
+                Some(Problem {
+                    reason: "ProgressDeadlineExceeded".to_string(),
+                    message: progressing.message.clone().unwrap_or_default(),
+                    since: progressing.last_transition_time.as_ref().map(|t| t.0),
+                    immediate: true,
+                })
+            }
+            (_, Some(available)) if available.status == "False" => Some(Problem {
+                reason: available
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| "MinimumReplicasUnavailable".to_string()),
+                message: available.message.clone().unwrap_or_default(),
+                since: available.last_transition_time.as_ref().map(|t| t.0),
+                immediate: false,
+            }),
+            _ => None,
+        };
+
+        let spec = deployment.spec.as_ref();
+        Self {
+            kind: "Deployment",
+            metadata: deployment.metadata.clone(),
+            images: images(spec.map(|s| &s.template)),
+            revision: deployment
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get("deployment.kubernetes.io/revision"))
+                .cloned(),
+            replicas: spec.and_then(|s| s.replicas).unwrap_or(1),
+            ready_replicas: status.and_then(|s| s.ready_replicas).unwrap_or_default(),
+            updated_replicas: status.and_then(|s| s.updated_replicas).unwrap_or_default(),
+            problem,
+        }
+    }
+}
+
+impl From<&StatefulSet> for Rollout {
+    fn from(stateful_set: &StatefulSet) -> Self {
+        let spec = stateful_set.spec.as_ref();
+        let status = stateful_set.status.as_ref();
+        let replicas = spec.and_then(|s| s.replicas).unwrap_or(1);
+        let ready_replicas = status.and_then(|s| s.ready_replicas).unwrap_or_default();
+        let name = stateful_set.metadata.name.as_deref().unwrap_or_default();
+
+        // StatefulSets have no progress deadline: the problem is reported once it lasted for the
+        // rollout deadline.
+        let problem = (ready_replicas < replicas).then(|| Problem {
+            reason: "ReplicasUnavailable".to_string(),
+            message: format!(
+                "StatefulSet {} has {} of {} replicas ready",
+                name, ready_replicas, replicas
+            ),
+            since: None,
+            immediate: false,
+        });
+
+        Self {
+            kind: "StatefulSet",
+            metadata: stateful_set.metadata.clone(),
+            images: images(spec.map(|s| &s.template)),
+            revision: status.and_then(|s| s.update_revision.clone()),
+            replicas,
+            ready_replicas,
+            updated_replicas: status.and_then(|s| s.updated_replicas).unwrap_or_default(),
+            problem,
+        }
+    }
+}
+
+/// The images of the containers of the pod template.
+fn images(template: Option<&PodTemplateSpec>) -> Vec<String> {
+    template
+        .and_then(|t| t.spec.as_ref())
+        .map(|s| {
+            s.containers
+                .iter()
+                .filter_map(|c| c.image.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reports the Deployments whose rollout exceeded its progress deadline, and the Deployments and
+/// StatefulSets with unavailable replicas for longer than the rollout deadline.
+/// Each problem is reported once per revision.
+pub struct RolloutWatcher {
+    client: Client,
+    deadline: Duration,
+    /// The rollouts with a problem, by uid.
+    failing: Mutex<HashMap<String, Failing>>,
+}
+
+struct Failing {
+    rollout: Rollout,
+    since: DateTime<Utc>,
+    reported: bool,
+}
+
+impl RolloutWatcher {
+    pub fn new(client: Client, deadline: Duration) -> Self {
+        Self {
+            client,
+            deadline,
+            failing: Default::default(),
+        }
+    }
+
+    /// Records the problem of the rollout, returning its event if it can be reported immediately.
+    pub fn update(&self, rollout: Rollout) -> Vec<SentryEvent> {
+        let Some(uid) = rollout.metadata.uid.clone() else {
+            return vec![];
+        };
+
+        let mut failing = self.failing.lock().unwrap();
+        let Some(problem) = rollout.problem.clone() else {
+            failing.remove(&uid);
+            return vec![];
+        };
+
+        let now = Utc::now();
+        let previous = failing.remove(&uid).filter(|f| {
+            f.rollout.revision == rollout.revision
+                && f.rollout.problem.as_ref().map(|p| &p.reason) == Some(&problem.reason)
+        });
+        let entry = failing.entry(uid).or_insert(Failing {
+            since: previous
+                .as_ref()
+                .map(|f| f.since)
+                .or(problem.since)
+                .unwrap_or(now),
+            reported: previous.is_some_and(|f| f.reported),
+            rollout,
+        });
+
+        if !entry.reported && problem.immediate {
+            entry.reported = true;
+            return vec![rollout_event(&entry.rollout, now)];
+        }
+
+        vec![]
+    }
+
+    /// The events of the problems which lasted for the rollout deadline, not reported yet.
+    pub async fn due(&self) -> Vec<SentryEvent> {
+        let now = Utc::now();
+        let due = {
+            let mut failing = self.failing.lock().unwrap();
+            failing
+                .values_mut()
+                .filter(|f| {
+                    !f.reported && (now - f.since).to_std().unwrap_or_default() >= self.deadline
+                })
+                .map(|f| {
+                    f.reported = true;
+                    f.rollout.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut events = vec![];
+        for rollout in due {
+            // The deletions are not notified: check that the problem is still there.
+            match self.refresh(&rollout).await {
+                Ok(Some(current)) if current.problem.is_some() => {
+                    events.push(rollout_event(&current, now));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Cannot read the {} rollout: {}", rollout.kind, e),
+            }
+        }
+
+        events
+    }
+
+    async fn refresh(&self, rollout: &Rollout) -> kube::Result<Option<Rollout>> {
+        let (Some(namespace), Some(name)) = (&rollout.metadata.namespace, &rollout.metadata.name)
+        else {
+            return Ok(None);
+        };
+
+        Ok(match rollout.kind {
+            "Deployment" => Api::<Deployment>::namespaced(self.client.clone(), namespace)
+                .get_opt(name)
+                .await?
+                .map(|d| Rollout::from(&d)),
+            _ => Api::<StatefulSet>::namespaced(self.client.clone(), namespace)
+                .get_opt(name)
+                .await?
+                .map(|s| Rollout::from(&s)),
+        })
+    }
+}
+
+/// A warning event of the rollout, tagged with its image and revision, with the replicas in the
+/// "rollout" context.
+fn rollout_event(rollout: &Rollout, now: DateTime<Utc>) -> SentryEvent {
+    let problem = rollout.problem.clone().unwrap_or(Problem {
+        reason: "RolloutFailed".to_string(),
+        message: String::new(),
+        since: None,
+        immediate: false,
+    });
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: rollout.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(Time(now)),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("apps/v1".to_string()),
+            kind: Some(rollout.kind.to_string()),
+            name: rollout.metadata.name.clone(),
+            namespace: rollout.metadata.namespace.clone(),
+            uid: rollout.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(problem.reason),
+        message: Some(problem.message).filter(|m| !m.is_empty()),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(Time(now)),
+        ..Default::default()
+    };
+
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.workload = rollout.metadata.name.clone();
+    if !rollout.images.is_empty() {
+        sentry_event
+            .tags
+            .insert("image".to_string(), rollout.images.join(","));
+    }
+    if let Some(revision) = rollout.revision.clone() {
+        sentry_event.tags.insert("revision".to_string(), revision);
+    }
+    sentry_event.contexts.insert(
+        "rollout".to_string(),
+        BTreeMap::from([
+            ("images".to_string(), json!(rollout.images)),
+            ("revision".to_string(), json!(rollout.revision)),
+            ("replicas".to_string(), json!(rollout.replicas)),
+            ("readyReplicas".to_string(), json!(rollout.ready_replicas)),
+            (
+                "updatedReplicas".to_string(),
+                json!(rollout.updated_replicas),
+            ),
+        ]),
+    );
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rollouts::{Rollout, RolloutWatcher};
+    use k8s_openapi::api::apps::v1::{
+        Deployment, DeploymentCondition, DeploymentSpec, DeploymentStatus, StatefulSet,
+        StatefulSetSpec, StatefulSetStatus,
+    };
+    use k8s_openapi::api::core::v1::{Container, PodSpec, PodTemplateSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube::Client;
+    use std::time::Duration;
+
+    fn metadata(name: &str) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("shop".to_string()),
+            uid: Some(format!("uid-{}", name)),
+            annotations: Some(
+                [(
+                    "deployment.kubernetes.io/revision".to_string(),
+                    "7".to_string(),
+                )]
+                .into(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn deployment(conditions: &[(&str, &str, &str)]) -> Deployment {
+        Deployment {
+            metadata: metadata("web"),
+            spec: Some(DeploymentSpec {
+                replicas: Some(3),
+                template: PodTemplateSpec {
+                    spec: Some(PodSpec {
+                        containers: vec![Container {
+                            image: Some("shop/web:1.4.2".to_string()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            status: Some(DeploymentStatus {
+                conditions: Some(
+                    conditions
+                        .iter()
+                        .map(|(type_, status, reason)| DeploymentCondition {
+                            type_: type_.to_string(),
+                            status: status.to_string(),
+                            reason: Some(reason.to_string()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ready_replicas: Some(1),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_deployment() {
+        let client = Client::try_default().await.unwrap();
+        let watcher = RolloutWatcher::new(client, Duration::from_secs(600));
+        let healthy = deployment(&[
+            ("Progressing", "True", "NewReplicaSetAvailable"),
+            ("Available", "True", "MinimumReplicasAvailable"),
+        ]);
+        assert!(Rollout::from(&healthy).problem.is_none());
+        assert!(watcher.update(Rollout::from(&healthy)).is_empty());
+
+        // Unavailable replicas wait for the rollout deadline.
+        let unavailable = deployment(&[("Available", "False", "MinimumReplicasUnavailable")]);
+        assert!(watcher.update(Rollout::from(&unavailable)).is_empty());
+
+        let stalled = deployment(&[
+            ("Progressing", "False", "ProgressDeadlineExceeded"),
+            ("Available", "False", "MinimumReplicasUnavailable"),
+        ]);
+        let events = watcher.update(Rollout::from(&stalled));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "ProgressDeadlineExceeded");
+        assert_eq!(events[0].kind.as_deref(), Some("Deployment"));
+        assert_eq!(events[0].tags["image"], "shop/web:1.4.2");
+        assert_eq!(events[0].tags["revision"], "7");
+        assert_eq!(events[0].contexts["rollout"]["readyReplicas"], 1);
+
+        // Reported once per revision.
+        assert!(watcher.update(Rollout::from(&stalled)).is_empty());
+    }
+
+    #[test]
+    pub fn test_stateful_set() {
+        let mut stateful_set = StatefulSet {
+            metadata: metadata("db"),
+            spec: Some(StatefulSetSpec {
+                replicas: Some(3),
+                ..Default::default()
+            }),
+            status: Some(StatefulSetStatus {
+                ready_replicas: Some(2),
+                update_revision: Some("db-5d8f7c9b4".to_string()),
+                ..Default::default()
+            }),
+        };
+        let rollout = Rollout::from(&stateful_set);
+        let problem = rollout.problem.unwrap();
+        assert_eq!(problem.reason, "ReplicasUnavailable");
+        assert_eq!(problem.message, "StatefulSet db has 2 of 3 replicas ready");
+        assert_eq!(rollout.revision.as_deref(), Some("db-5d8f7c9b4"));
+
+        stateful_set.status.as_mut().unwrap().ready_replicas = Some(3);
+        assert!(Rollout::from(&stateful_set).problem.is_none());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::cert_manager::is_cert_manager;
+use crate::config::{DsnSource, DsnTargets, LabelRoute, NamespaceRoute, RoutingConfig, SecretRef};
+use crate::sentry_event::SentryEvent;
+use log::{debug, warn};
+use sentry::protocol::Event;
+use sentry::types::{Dsn, Uuid};
+use sentry::{Client, ClientOptions, Hub};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Annotation holding the DSN the events of the annotated object should be sent to.
+pub const DSN_ANNOTATION: &str = "sentry-kubernetes.io/dsn";
+/// Annotation referencing a secret (in the same namespace) containing the DSN, as "name/key".
+pub const DSN_SECRET_ANNOTATION: &str = "sentry-kubernetes.io/dsn-secret";
+
+/// Resolves the DSNs an event should be sent to from the statically configured rules.
+/// Rules are evaluated in order: cert-manager, object labels, namespace, level.
+/// A rule can route the events to several DSNs (ex: the project of a team and the one of the SREs).
+/// Events not matching any rule are sent to all the default DSNs.
+#[derive(Clone, Debug, Default)]
+pub struct Router {
+    labels: Vec<LabelRoute>,
+    namespaces: Vec<NamespaceRoute>,
+    levels: BTreeMap<String, DsnTargets>,
+    cert_manager: Option<DsnTargets>,
+    defaults: Vec<DsnSource>,
+}
+
+impl From<&RoutingConfig> for Router {
+    fn from(value: &RoutingConfig) -> Self {
+        Self {
+            labels: value.labels.clone(),
+            namespaces: value.namespaces.clone(),
+            levels: value.levels.clone(),
+            cert_manager: value.cert_manager.clone(),
+            defaults: vec![],
+        }
+    }
+}
+
+impl Router {
+    #[must_use]
+    pub fn default_dsns(mut self, dsns: &[String]) -> Self {
+        self.defaults = dsns.iter().cloned().map(DsnSource::Inline).collect();
+        self
+    }
+
+    /// Whether the labels of the involved object are needed to route the events.
+    pub fn has_label_rules(&self) -> bool {
+        !self.labels.is_empty()
+    }
+
+    pub fn route(&self, event: &SentryEvent) -> Vec<DsnSource> {
+        if let Some(targets) = self
+            .cert_manager
+            .as_ref()
+            .filter(|_| is_cert_manager(event))
+        {
+            return targets.0.clone();
+        }
+
+        let by_label = self.labels.iter().find_map(|r| {
+            let value = event.object_labels.get(&r.label)?;
+            r.values.get(value)
+        });
+
+        by_label
+            .or_else(|| {
+                self.namespaces
+                    .iter()
+                    .find(|r| glob_match(&r.namespace, &event.namespace))
+                    .map(|r| &r.dsn)
+            })
+            .or_else(|| self.levels.get(&event.level.to_string()))
+            .map(|targets| targets.0.clone())
+            .unwrap_or_else(|| self.defaults.clone())
+    }
+}
+
+/// Matches a text against a glob pattern supporting the `*` and `?` wildcards.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Reads the DSN from the annotations of an object living in the given namespace.
+pub fn annotated_dsn(annotations: &BTreeMap<String, String>, namespace: &str) -> Option<DsnSource> {
+    if let Some(dsn) = annotations.get(DSN_ANNOTATION) {
+        return Some(DsnSource::Inline(dsn.clone()));
+    }
+
+    let reference = annotations.get(DSN_SECRET_ANNOTATION)?;
+    let (name, key) = reference.split_once('/').unwrap_or((reference, "dsn"));
+
+    Some(DsnSource::Secret {
+        secret: SecretRef {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            key: key.to_string(),
+        },
+    })
+}
+
+/// A pool of sentry clients keyed by DSN.
+/// Clients are lazily created on first use sharing the same options (environment, release, etc.)
+/// Up to `capacity` of them are kept: the least recently used one is closed to make room for a new DSN
+/// (each client has its own transport thread). The clients registered with `insert` are never evicted.
+pub struct ClientPool {
+    options: ClientOptions,
+    capacity: usize,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Default)]
+struct Clients {
+    pinned: HashMap<String, Arc<Client>>,
+    /// The created clients, with the tick of their last use.
+    created: HashMap<String, (Arc<Client>, u64)>,
+    tick: u64,
+}
+
+impl ClientPool {
+    pub fn new(options: ClientOptions) -> Self {
+        Self {
+            options,
+            capacity: 100,
+            clients: Default::default(),
+        }
+    }
+
+    /// Sets the maximum number of created clients (at least 1).
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Registers an already initialized client (ex: the one bound to the main hub).
+    pub fn insert(&self, dsn: &str, client: Arc<Client>) {
+        self.clients
+            .lock()
+            .unwrap()
+            .pinned
+            .insert(dsn.to_string(), client);
+    }
+
+    pub fn client(&self, dsn: &str) -> Option<Arc<Client>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.pinned.get(dsn) {
+            return Some(client.clone());
+        }
+
+        clients.tick += 1;
+        let tick = clients.tick;
+        if let Some((client, last_use)) = clients.created.get_mut(dsn) {
+            *last_use = tick;
+            return Some(client.clone());
+        }
+
+        let parsed = match Dsn::from_str(dsn) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Invalid routed DSN \"{}\": {}", dsn, e);
+                return None;
+            }
+        };
+
+        let mut evicted = vec![];
+        while clients.created.len() >= self.capacity {
+            let Some(oldest) = clients
+                .created
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(dsn, _)| dsn.clone())
+            else {
+                break;
+            };
+            if let Some((client, _)) = clients.created.remove(&oldest) {
+                evicted.push((oldest, client));
+            }
+        }
+
+        let client = Arc::new(Client::from(sentry::apply_defaults(ClientOptions {
+            dsn: Some(parsed),
+            ..self.options.clone()
+        })));
+        clients
+            .created
+            .insert(dsn.to_string(), (client.clone(), tick));
+        drop(clients);
+
+        // The evicted clients are flushed away from the caller, which may be an async task.
+        for (dsn, client) in evicted {
+            debug!("Closing the least recently used sentry client of {}", dsn);
+            let timeout = self.options.shutdown_timeout;
+            std::thread::spawn(move || client.close(Some(timeout)));
+        }
+
+        Some(client)
+    }
+
+    /// Closes all the clients, waiting up to the given timeout for each one to send the queued events.
+    pub fn close(&self, timeout: Duration) {
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+        let created = clients
+            .created
+            .into_iter()
+            .map(|(dsn, (client, _))| (dsn, client));
+        for (dsn, client) in clients.pinned.into_iter().chain(created) {
+            if !client.close(Some(timeout)) {
+                warn!("Timed out flushing the events queued for {}", dsn);
+            }
+        }
+    }
+
+    /// Captures the event with the client of the given DSN, preserving the current scope (breadcrumbs).
+    pub fn capture_event(&self, dsn: &str, event: Event<'static>) -> Option<Uuid> {
+        let client = self.client(dsn)?;
+        let hub = Hub::new_from_top(Hub::current());
+        hub.bind_client(Some(client));
+
+        Some(hub.capture_event(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DsnSource, RoutingConfig, SecretRef};
+    use crate::routing::{annotated_dsn, glob_match, ClientPool, Router};
+    use crate::sentry_event::SentryEvent;
+    use crate::transport::HttpTransportFactory;
+    use k8s_openapi::api::core::v1::Event;
+    use sentry::{ClientOptions, Level};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("team-a-*", "team-a-prod"));
+        assert!(glob_match("team-?-prod", "team-b-prod"));
+        assert!(glob_match("*-prod", "team-b-prod"));
+        assert!(glob_match("kube-system", "kube-system"));
+        assert!(!glob_match("team-a-*", "team-b-prod"));
+        assert!(!glob_match("*-prod", "team-b-staging"));
+    }
+
+    #[test]
+    pub fn test_router() {
+        let config: RoutingConfig = serde_yaml::from_str(
+            r#"
+namespaces:
+  - namespace: "team-a-*"
+    dsn: https://public@sentry.example.com/1
+  - namespace: "*"
+    dsn: https://public@sentry.example.com/2
+labels:
+  - label: team
+    values:
+      payments: https://public@sentry.example.com/5
+certManager: https://public@sentry.example.com/7
+"#,
+        )
+        .unwrap();
+        let router = Router::from(&config);
+
+        let mut event = SentryEvent::from(Event {
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        });
+        event.namespace = "team-a-prod".to_string();
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/1".to_string()
+            )]
+        );
+
+        event.namespace = "default".to_string();
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/2".to_string()
+            )]
+        );
+
+        event
+            .object_labels
+            .insert("team".to_string(), "payments".to_string());
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/5".to_string()
+            )]
+        );
+
+        event
+            .object_labels
+            .insert("team".to_string(), "search".to_string());
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/2".to_string()
+            )]
+        );
+
+        event.api_version = Some("cert-manager.io/v1".to_string());
+        event.kind = Some("Certificate".to_string());
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/7".to_string()
+            )]
+        );
+        (event.api_version, event.kind) = (None, None);
+
+        assert!(Router::default().route(&event).is_empty());
+
+        let config: RoutingConfig = serde_yaml::from_str(
+            r#"
+levels:
+  error: https://public@sentry.example.com/6
+"#,
+        )
+        .unwrap();
+        let router = Router::from(&config);
+        assert!(router.route(&event).is_empty());
+
+        event.level = Level::Error;
+        assert_eq!(
+            router.route(&event),
+            vec![DsnSource::Inline(
+                "https://public@sentry.example.com/6".to_string()
+            )]
+        );
+
+        let router = Router::default().default_dsns(&[
+            "https://public@sentry.example.com/3".to_string(),
+            "https://public@sentry.example.com/4".to_string(),
+        ]);
+        assert_eq!(router.route(&event).len(), 2);
+    }
+
+    #[test]
+    pub fn test_route_to_several_dsns() {
+        let config: RoutingConfig = serde_yaml::from_str(
+            r#"
+labels:
+  - label: team
+    values:
+      payments:
+        - https://public@sentry.example.com/1
+        - https://public@sentry.example.com/9
+namespaces:
+  - namespace: "*"
+    dsn: https://public@sentry.example.com/2
+"#,
+        )
+        .unwrap();
+        let router = Router::from(&config);
+
+        let mut event = SentryEvent::from(Event::default());
+        event.namespace = "payments".to_string();
+        event
+            .object_labels
+            .insert("team".to_string(), "payments".to_string());
+        assert_eq!(
+            router.route(&event),
+            vec![
+                DsnSource::Inline("https://public@sentry.example.com/1".to_string()),
+                DsnSource::Inline("https://public@sentry.example.com/9".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_annotated_dsn() {
+        let mut annotations = BTreeMap::new();
+        assert_eq!(annotated_dsn(&annotations, "default"), None);
+
+        annotations.insert(
+            "sentry-kubernetes.io/dsn-secret".to_string(),
+            "sentry".to_string(),
+        );
+        assert_eq!(
+            annotated_dsn(&annotations, "default"),
+            Some(DsnSource::Secret {
+                secret: SecretRef {
+                    namespace: "default".to_string(),
+                    name: "sentry".to_string(),
+                    key: "dsn".to_string(),
+                }
+            })
+        );
+
+        annotations.insert(
+            "sentry-kubernetes.io/dsn-secret".to_string(),
+            "sentry/project-dsn".to_string(),
+        );
+        let Some(DsnSource::Secret { secret }) = annotated_dsn(&annotations, "default") else {
+            panic!("expected a secret reference");
+        };
+        assert_eq!(secret.key, "project-dsn");
+
+        annotations.insert(
+            "sentry-kubernetes.io/dsn".to_string(),
+            "https://public@sentry.example.com/1".to_string(),
+        );
+        assert_eq!(
+            annotated_dsn(&annotations, "default"),
+            Some(DsnSource::Inline(
+                "https://public@sentry.example.com/1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    pub fn test_client_pool() {
+        let pool = ClientPool::new(ClientOptions {
+            transport: Some(Arc::new(HttpTransportFactory::new(Default::default()))),
+            ..Default::default()
+        });
+        assert!(pool.client("not a dsn").is_none());
+
+        let dsn = "https://public@sentry.example.com/1";
+        let client = pool.client(dsn).unwrap();
+        assert!(Arc::ptr_eq(&client, &pool.client(dsn).unwrap()));
+        assert!(!Arc::ptr_eq(
+            &client,
+            &pool.client("https://public@sentry.example.com/2").unwrap()
+        ));
+    }
+
+    #[test]
+    pub fn test_client_pool_eviction() {
+        let pool = ClientPool::new(ClientOptions {
+            transport: Some(Arc::new(HttpTransportFactory::new(Default::default()))),
+            ..Default::default()
+        })
+        .capacity(2);
+        let dsn = |project: u32| format!("https://public@sentry.example.com/{}", project);
+        let main = pool.client(&dsn(0)).unwrap();
+        pool.insert(&dsn(0), main.clone());
+
+        let first = pool.client(&dsn(1)).unwrap();
+        let second = pool.client(&dsn(2)).unwrap();
+        assert!(Arc::ptr_eq(&first, &pool.client(&dsn(1)).unwrap()));
+
+        // The least recently used client (the second one) is evicted and closed.
+        pool.client(&dsn(3)).unwrap();
+        assert!(Arc::ptr_eq(&first, &pool.client(&dsn(1)).unwrap()));
+        let closed = (0..100).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            !second.is_enabled()
+        });
+        assert!(closed);
+        assert!(!Arc::ptr_eq(&second, &pool.client(&dsn(2)).unwrap()));
+        assert!(Arc::ptr_eq(&main, &pool.client(&dsn(0)).unwrap()));
+
+        let clients = pool.clients.lock().unwrap();
+        assert_eq!(clients.created.len(), 2);
+        assert!(clients.created.contains_key(&dsn(2)));
+        assert!(!clients.created.contains_key(&dsn(3)));
+    }
+}
@@ -0,0 +1,93 @@
+use sentry::protocol::Event;
+use sentry::Level;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+type BeforeSend = Arc<dyn Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync>;
+
+/// Sample rates of the events sent to sentry.
+/// The rate configured for the level of the event takes precedence over the default one,
+/// which only applies to the levels below error: the errors are all kept unless a rate is set for them.
+#[derive(Clone, Debug)]
+pub struct SampleRates {
+    default: f32,
+    levels: BTreeMap<String, f32>,
+}
+
+impl SampleRates {
+    pub fn new(default: f32, levels: BTreeMap<String, f32>) -> Self {
+        Self { default, levels }
+    }
+
+    pub fn rate(&self, level: Level) -> f32 {
+        let default = match level {
+            Level::Error | Level::Fatal => 1.0,
+            _ => self.default,
+        };
+        self.levels
+            .get(&level.to_string())
+            .copied()
+            .unwrap_or(default)
+            .clamp(0.0, 1.0)
+    }
+
+    /// Builds the before_send callback dropping the events not sampled.
+    /// Returns None if all the events are kept.
+    pub fn before_send(self) -> Option<BeforeSend> {
+        if self.default >= 1.0 && self.levels.values().all(|r| *r >= 1.0) {
+            return None;
+        }
+
+        Some(Arc::new(move |event: Event<'static>| {
+            let rate = self.rate(event.level);
+            if rate >= 1.0 || rand::random::<f32>() < rate {
+                Some(event)
+            } else {
+                None
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampling::SampleRates;
+    use sentry::protocol::Event;
+    use sentry::Level;
+
+    #[test]
+    pub fn test_sample_rates() {
+        assert!(SampleRates::new(1.0, Default::default())
+            .before_send()
+            .is_none());
+
+        let rates = SampleRates::new(
+            0.5,
+            [("warning".to_string(), 0.0), ("error".to_string(), 1.0)]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(rates.rate(Level::Info), 0.5);
+        assert_eq!(rates.rate(Level::Warning), 0.0);
+        assert_eq!(
+            rates.rate(Level::Fatal),
+            1.0,
+            "errors are not downsampled by default"
+        );
+
+        let before_send = rates.before_send().unwrap();
+        let event = |level| Event {
+            level,
+            ..Default::default()
+        };
+        assert!(before_send(event(Level::Warning)).is_none());
+        assert!(before_send(event(Level::Error)).is_some());
+
+        let rates = SampleRates::new(0.1, [("fatal".to_string(), 0.5)].into_iter().collect());
+        assert_eq!(rates.rate(Level::Warning), 0.1);
+        assert_eq!(rates.rate(Level::Error), 1.0);
+        assert_eq!(rates.rate(Level::Fatal), 0.5);
+        let before_send = rates.before_send().unwrap();
+        assert!((0..100).all(|_| before_send(event(Level::Error)).is_some()));
+    }
+}
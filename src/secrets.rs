@@ -0,0 +1,142 @@
+use crate::config::{DsnSource, SecretRef};
+use crate::kube_api::KubeApi;
+use k8s_openapi::api::core::v1::Secret;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Secret values are re-read after this interval, so rotated DSNs are eventually picked up.
+const SECRET_TTL: Duration = Duration::from_secs(300);
+
+/// Resolves DSN sources, reading and caching the referenced kubernetes secrets.
+pub struct SecretStore {
+    api: Arc<dyn KubeApi>,
+    cache: Mutex<HashMap<SecretRef, (Instant, Option<String>)>>,
+}
+
+impl SecretStore {
+    pub fn new(api: Arc<dyn KubeApi>) -> Self {
+        Self {
+            api,
+            cache: Default::default(),
+        }
+    }
+
+    pub async fn resolve(&self, source: &DsnSource) -> Option<String> {
+        match source {
+            DsnSource::Inline(dsn) => Some(dsn.clone()),
+            DsnSource::Secret { secret } => self.secret_value(secret).await,
+        }
+    }
+
+    async fn secret_value(&self, secret: &SecretRef) -> Option<String> {
+        if let Some((fetched_at, value)) = self.cache.lock().unwrap().get(secret) {
+            if fetched_at.elapsed() < SECRET_TTL {
+                return value.clone();
+            }
+        }
+
+        let value = self
+            .api
+            .secret(&secret.namespace, &secret.name)
+            .await
+            .and_then(|s| read_key(&s, &secret.key));
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(secret.clone(), (Instant::now(), value.clone()));
+
+        value
+    }
+}
+
+fn read_key(secret: &Secret, key: &str) -> Option<String> {
+    let bytes = secret.data.as_ref()?.get(key)?;
+    let value = String::from_utf8(bytes.0.clone()).ok()?;
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DsnSource, SecretRef};
+    use crate::kube_api::FakeKube;
+    use crate::secrets::{read_key, SecretStore};
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use k8s_openapi::ByteString;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn test_read_key() {
+        let secret = Secret {
+            data: Some(
+                [(
+                    "dsn".to_string(),
+                    ByteString(b"https://public@sentry.example.com/1\n".to_vec()),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            read_key(&secret, "dsn"),
+            Some("https://public@sentry.example.com/1".to_string())
+        );
+        assert_eq!(read_key(&secret, "other"), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_resolve_inline() {
+        let store = SecretStore::new(Arc::new(FakeKube::default()));
+        let source = DsnSource::Inline("https://public@sentry.example.com/1".to_string());
+        assert_eq!(
+            store.resolve(&source).await,
+            Some("https://public@sentry.example.com/1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_resolve_secret() {
+        let store = SecretStore::new(Arc::new(FakeKube {
+            secrets: vec![Secret {
+                metadata: ObjectMeta {
+                    name: Some("sentry".to_string()),
+                    namespace: Some("shop".to_string()),
+                    ..Default::default()
+                },
+                data: Some(
+                    [(
+                        "dsn".to_string(),
+                        ByteString(b"https://public@sentry.example.com/2".to_vec()),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }));
+        let source = |namespace: &str| DsnSource::Secret {
+            secret: SecretRef {
+                namespace: namespace.to_string(),
+                name: "sentry".to_string(),
+                key: "dsn".to_string(),
+            },
+        };
+
+        assert_eq!(
+            store.resolve(&source("shop")).await,
+            Some("https://public@sentry.example.com/2".to_string())
+        );
+        assert_eq!(store.resolve(&source("other")).await, None);
+    }
+}
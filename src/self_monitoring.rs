@@ -0,0 +1,107 @@
+use lazy_static::lazy_static;
+use log::{Level, Log, Metadata, Record};
+use sentry::integrations::log::event_from_record;
+use sentry::integrations::panic::PanicIntegration;
+use sentry::{Client, ClientOptions};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+lazy_static! {
+    /// The client reporting the errors of the controller itself, once initialized.
+    static ref CLIENT: RwLock<Option<Arc<Client>>> = RwLock::new(None);
+}
+
+/// The modules whose logs are not reported: the delivery failures of the self-monitoring client
+/// would be reported through the failing transport again.
+const IGNORED_TARGETS: [&str; 2] = ["sentry_kubernetes::transport", "sentry_kubernetes::spool"];
+
+/// Time given to the client to send the event of a panic, before the process aborts.
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reports the own problems of the controller (panics, and the warnings and errors it logs: watch
+/// failures, enrichment timeouts, sink failures...) to a dedicated DSN, separated from the events of
+/// the clusters.
+pub fn init(options: ClientOptions) {
+    let client = Arc::new(Client::from(options));
+    *CLIENT.write().unwrap() = Some(client);
+
+    let next = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(client) = self_client() {
+            let event = PanicIntegration::new().event_from_panic_info(info);
+            client.capture_event(event, None);
+            client.flush(Some(PANIC_FLUSH_TIMEOUT));
+        }
+        next(info);
+    }));
+}
+
+fn self_client() -> Option<Arc<Client>> {
+    CLIENT.read().unwrap().clone()
+}
+
+/// Whether the record is reported: the warnings and errors of the controller.
+fn is_reported(metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    metadata.level() <= Level::Warn
+        && (target == "sentry_kubernetes" || target.starts_with("sentry_kubernetes::"))
+        && !IGNORED_TARGETS.iter().any(|t| target.starts_with(t))
+}
+
+/// Forwards the logs to the wrapped logger, reporting the warnings and errors of the controller to
+/// the self-monitoring client once initialized.
+pub struct SelfMonitoringLogger {
+    inner: Box<dyn Log>,
+}
+
+impl SelfMonitoringLogger {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Log for SelfMonitoringLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        is_reported(metadata) || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if is_reported(record.metadata()) {
+            if let Some(client) = self_client() {
+                client.capture_event(event_from_record(record), None);
+            }
+        }
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::self_monitoring::is_reported;
+    use log::{Level, Metadata};
+
+    #[test]
+    pub fn test_is_reported() {
+        let metadata = |level, target| Metadata::builder().level(level).target(target).build();
+        assert!(is_reported(&metadata(Level::Error, "sentry_kubernetes")));
+        assert!(is_reported(&metadata(
+            Level::Warn,
+            "sentry_kubernetes::processor"
+        )));
+        assert!(!is_reported(&metadata(
+            Level::Info,
+            "sentry_kubernetes::processor"
+        )));
+        assert!(!is_reported(&metadata(
+            Level::Warn,
+            "sentry_kubernetes::transport"
+        )));
+        assert!(!is_reported(&metadata(Level::Error, "sentry")));
+        assert!(!is_reported(&metadata(Level::Error, "kube_runtime")));
+    }
+}
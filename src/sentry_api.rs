@@ -0,0 +1,130 @@
+use reqwest::header::AUTHORIZATION;
+use sentry::types::{Dsn, Uuid};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Timeout of the requests to the sentry API.
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A client of the web API of sentry, authenticated with an auth token, to update the issues of
+/// the reported events.
+pub struct SentryApi {
+    client: reqwest::Client,
+    api_url: String,
+    organization: String,
+    token: String,
+}
+
+impl SentryApi {
+    /// The API URL defaults to the scheme and the host of the DSN (ex: "https://sentry.io").
+    pub fn new(api_url: Option<String>, dsn: &str, organization: &str, token: &str) -> Self {
+        let api_url = api_url
+            .or_else(|| dsn.parse::<Dsn>().ok().map(|dsn| api_url_of(&dsn)))
+            .unwrap_or_default();
+        let client = reqwest::Client::builder()
+            .timeout(API_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            organization: organization.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// The id of the issue grouping the event. Fails with a 404 status until the event is ingested.
+    pub async fn issue_id(&self, event_id: Uuid) -> Result<String, reqwest::Error> {
+        let url = format!(
+            "{}/api/0/organizations/{}/eventids/{}/",
+            self.api_url,
+            self.organization,
+            event_id.simple()
+        );
+        let found: Value = self
+            .client
+            .get(url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(match &found["groupId"] {
+            Value::String(id) => id.clone(),
+            id => id.to_string(),
+        })
+    }
+
+    /// The details of the issue.
+    pub async fn issue(&self, issue_id: &str) -> Result<Value, reqwest::Error> {
+        self.client
+            .get(format!("{}/api/0/issues/{}/", self.api_url, issue_id))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// Updates the issue (ex: its status or its assignee).
+    pub async fn update_issue(&self, issue_id: &str, update: &Value) -> Result<(), reqwest::Error> {
+        self.client
+            .put(format!("{}/api/0/issues/{}/", self.api_url, issue_id))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .json(update)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// The URL of the sentry instance receiving the events of the DSN. The ingestion hosts of sentry.io
+/// (ex: "o1.ingest.us.sentry.io") are replaced by the host of the API ("us.sentry.io").
+fn api_url_of(dsn: &Dsn) -> String {
+    let host = match dsn.host().split_once(".ingest.") {
+        Some((_, host)) if host.ends_with("sentry.io") => host,
+        _ => dsn.host(),
+    };
+    match dsn.port() {
+        443 | 80 => format!("{}://{}", dsn.scheme(), host),
+        port => format!("{}://{}:{}", dsn.scheme(), host, port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sentry_api::SentryApi;
+
+    #[test]
+    pub fn test_api_url() {
+        let api = SentryApi::new(
+            None,
+            "https://public@o1.ingest.us.sentry.io/1",
+            "acme",
+            "token",
+        );
+        assert_eq!(api.api_url, "https://us.sentry.io");
+
+        let api = SentryApi::new(
+            None,
+            "http://public@sentry.example.com:9000/1",
+            "acme",
+            "token",
+        );
+        assert_eq!(api.api_url, "http://sentry.example.com:9000");
+
+        let api = SentryApi::new(
+            Some("https://sentry.example.com/".to_string()),
+            "",
+            "acme",
+            "token",
+        );
+        assert_eq!(api.api_url, "https://sentry.example.com");
+    }
+}
@@ -1,6 +1,9 @@
+use crate::node::NodeCapacity;
 use k8s_openapi::api::core::v1::Event;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
+use log::warn;
 use sentry::protocol::ClientSdkInfo;
 use sentry::types::protocol::v7;
 use sentry::types::Uuid;
@@ -14,7 +17,29 @@ use std::str::FromStr;
 use std::time::SystemTime;
 
 lazy_static! {
-    static ref CLUSTER_NAME: String = env::var("CLUSTER_NAME").unwrap_or_default();
+    pub static ref CLUSTER_NAME: String = env::var("CLUSTER_NAME").unwrap_or_default();
+    pub static ref CULPRIT_FORMAT: String = env::var("CULPRIT_FORMAT").unwrap_or_default();
+    /// The transaction of the events, with the placeholders of the culprit (none if not set).
+    pub static ref TRANSACTION_FORMAT: Option<String> =
+        env::var("TRANSACTION_FORMAT").ok().filter(|f| !f.is_empty());
+    /// The namespace of the events of the cluster-scoped objects (ex: Nodes, PersistentVolumes).
+    pub static ref FALLBACK_NAMESPACE: String =
+        env::var("FALLBACK_NAMESPACE").unwrap_or_else(|_| "default".to_string());
+    /// The level of the events of unknown types (ex: the custom types of some controllers).
+    pub static ref UNKNOWN_TYPE_LEVEL: Level = match env::var("UNKNOWN_TYPE_LEVEL") {
+        Ok(level) if !level.is_empty() => level.parse().unwrap_or_else(|_| {
+            warn!("Invalid UNKNOWN_TYPE_LEVEL \"{}\", using warning", level);
+            Level::Warning
+        }),
+        _ => Level::Warning,
+    };
+    pub static ref SERVER_NAME_SOURCE: ServerNameSource = match env::var("SERVER_NAME_SOURCE") {
+        Ok(source) if !source.is_empty() => source.parse().unwrap_or_else(|_| {
+            warn!("Invalid SERVER_NAME_SOURCE \"{}\", using the node", source);
+            ServerNameSource::Node
+        }),
+        _ => ServerNameSource::Node,
+    };
     static ref SDK_VALUE: Cow<'static, ClientSdkInfo> = {
         let info = ClientSdkInfo {
             name: "sentry-kubernetes".to_string(),
@@ -27,6 +52,31 @@ lazy_static! {
     };
 }
 
+/// What the server_name of the events is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServerNameSource {
+    /// The node the event comes from, if known.
+    Node,
+    Cluster,
+    Component,
+    /// No server_name.
+    None,
+}
+
+impl FromStr for ServerNameSource {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => Ok(Self::Node),
+            "cluster" => Ok(Self::Cluster),
+            "component" => Ok(Self::Component),
+            "none" => Ok(Self::None),
+            _ => Err(()),
+        }
+    }
+}
+
 pub struct SentryEvent {
     pub uid: Uuid,
     pub type_: String,
@@ -36,11 +86,27 @@ pub struct SentryEvent {
     pub reason: String,
     pub metadata: ObjectMeta,
     pub namespace: String,
+    pub api_version: Option<String>,
     pub kind: Option<String>,
     pub name: String,
+    pub object_labels: BTreeMap<String, String>,
+    pub workload: Option<String>,
     pub message: Option<String>,
     pub creation_timestamp: Option<SystemTime>,
+    /// The time the event last occurred.
+    pub event_time: Option<SystemTime>,
     pub node_labels: BTreeMap<String, String>,
+    pub node_capacity: Option<NodeCapacity>,
+    /// Additional tags of the event (ex: the image of a failed rollout).
+    pub tags: BTreeMap<String, String>,
+    /// Additional contexts of the event (ex: the status of a container), by name.
+    pub contexts: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Replaces the default fingerprint (ex: grouping the autoscaler events per node group).
+    pub grouping: Option<Vec<String>>,
+    /// The cluster the event comes from (CLUSTER_NAME, or the name of the cluster in multi-cluster mode).
+    pub cluster: String,
+    pub environment: Option<String>,
+    pub dsns: Vec<String>,
 }
 
 impl SentryEvent {
@@ -52,6 +118,57 @@ impl SentryEvent {
         }
     }
 
+    /// The grouping fingerprint of the event: reason, namespace, name and kind of the object,
+    /// unless replaced by the grouping of the event.
+    pub fn fingerprint(&self) -> Vec<String> {
+        if let Some(grouping) = self.grouping.as_ref() {
+            return grouping.clone();
+        }
+
+        [
+            self.reason.as_str(),
+            self.namespace.as_str(),
+            self.name.as_str(),
+            self.kind.as_deref().unwrap_or_default(),
+        ]
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+    }
+
+    /// The server_name of the event, from the given source. None if the source is empty.
+    pub fn server_name(&self, source: ServerNameSource) -> Option<String> {
+        let server_name = match source {
+            ServerNameSource::Node => self.source_host.clone(),
+            ServerNameSource::Cluster => Some(self.cluster.clone()),
+            ServerNameSource::Component => Some(self.component.clone()),
+            ServerNameSource::None => None,
+        };
+        server_name.filter(|s| !s.is_empty())
+    }
+
+    /// Renders the culprit (or the transaction) of the event.
+    /// If no format is given, defaults to "{namespace}/{name} {reason}".
+    pub fn culprit(&self, format: &str) -> String {
+        if format.is_empty() {
+            return format!("{} {}", self.obj_name(), self.reason);
+        }
+
+        format
+            .replace("{{namespace}}", &self.namespace)
+            .replace("{{name}}", &self.name)
+            .replace(
+                "{{workload}}",
+                self.workload.as_deref().unwrap_or(&self.name),
+            )
+            .replace("{{kind}}", self.kind.as_deref().unwrap_or_default())
+            .replace("{{reason}}", &self.reason)
+            .replace("{{component}}", &self.component)
+            .trim()
+            .to_string()
+    }
+
     pub fn metadata_map(&self) -> BTreeMap<String, Value> {
         match to_value(&self.metadata) {
             Ok(Value::Object(tree)) => {
@@ -72,18 +189,35 @@ impl SentryEvent {
 
 impl From<Event> for SentryEvent {
     fn from(value: Event) -> Self {
+        let event_time = event_time(&value).map(SystemTime::from);
         let meta = value.metadata;
-        let namespace = value
-            .involved_object
-            .namespace
-            .or(meta.namespace.clone())
-            .unwrap_or_else(|| "default".to_string());
+        // The events of the cluster-scoped objects are recorded in a namespace (usually "default"):
+        // they are tagged, and given the fallback namespace instead.
+        let object_namespace = value.involved_object.namespace.filter(|ns| !ns.is_empty());
+        let cluster_scoped = value.involved_object.kind.is_some() && object_namespace.is_none();
+        let namespace = match object_namespace {
+            Some(namespace) => namespace,
+            None if cluster_scoped => FALLBACK_NAMESPACE.clone(),
+            None => meta
+                .namespace
+                .clone()
+                .unwrap_or_else(|| FALLBACK_NAMESPACE.clone()),
+        };
+        let mut tags = BTreeMap::new();
+        if cluster_scoped {
+            tags.insert("cluster_scoped".to_string(), "true".to_string());
+        }
         let creation_timestamp = meta.creation_timestamp.as_ref().map(|t| t.0.into());
-        let event_type = value.type_.unwrap_or_default().to_lowercase();
-        let level = if event_type == "normal" {
-            "info".to_string()
-        } else {
-            event_type.clone()
+        let raw_type = value.type_.unwrap_or_default();
+        let event_type = raw_type.to_lowercase();
+        let level = match event_type.as_str() {
+            "normal" => Level::Info,
+            _ => event_type.parse().unwrap_or_else(|_| {
+                if !raw_type.is_empty() {
+                    tags.insert("event_type".to_string(), raw_type);
+                }
+                *UNKNOWN_TYPE_LEVEL
+            }),
         };
 
         Self {
@@ -93,11 +227,13 @@ impl From<Event> for SentryEvent {
                 .and_then(|str| Uuid::parse_str(str).ok())
                 .unwrap_or_default(),
             type_: event_type,
-            level: Level::from_str(&level).unwrap(),
+            level,
             component: value
                 .source
                 .as_ref()
                 .and_then(|s| s.component.clone())
+                .filter(|c| !c.is_empty())
+                .or_else(|| value.reporting_component.clone())
                 .unwrap_or_default(),
             source_host: value.source.and_then(|s| s.host).or_else(|| {
                 if value.involved_object.kind.as_deref() == Some("Node") {
@@ -109,11 +245,22 @@ impl From<Event> for SentryEvent {
             reason: value.reason.unwrap_or_default(),
             metadata: meta,
             namespace,
+            api_version: value.involved_object.api_version,
             kind: value.involved_object.kind,
             name: value.involved_object.name.unwrap_or_default(),
+            object_labels: Default::default(),
+            workload: None,
             message: value.message,
             creation_timestamp,
+            event_time,
             node_labels: Default::default(),
+            node_capacity: None,
+            tags,
+            contexts: Default::default(),
+            grouping: None,
+            cluster: CLUSTER_NAME.clone(),
+            environment: None,
+            dsns: vec![],
         }
     }
 }
@@ -121,10 +268,9 @@ impl From<Event> for SentryEvent {
 impl From<&SentryEvent> for v7::Event<'_> {
     fn from(value: &SentryEvent) -> Self {
         let mut tags = BTreeMap::new();
-        let mut fingerprint: Vec<Cow<str>> = vec![];
 
-        if !CLUSTER_NAME.is_empty() {
-            tags.insert("cluster".to_string(), CLUSTER_NAME.clone());
+        if !value.cluster.is_empty() {
+            tags.insert("cluster".to_string(), value.cluster.clone());
         }
 
         if !value.component.is_empty() {
@@ -133,32 +279,32 @@ impl From<&SentryEvent> for v7::Event<'_> {
 
         if !value.reason.is_empty() {
             tags.insert("reason".to_string(), value.reason.clone());
-            fingerprint.push(value.reason.clone().into());
         }
 
         if !value.namespace.is_empty() {
             tags.insert("namespace".to_string(), value.namespace.clone());
-            fingerprint.push(value.namespace.clone().into());
         }
 
         if !value.name.is_empty() {
             tags.insert("name".to_string(), value.name.clone());
-            fingerprint.push(value.name.clone().into());
         }
 
         if let Some(kind) = value.kind.clone() {
             if !kind.is_empty() {
-                tags.insert("kind".to_string(), kind.clone());
-                fingerprint.push(kind.into());
+                tags.insert("kind".to_string(), kind);
             }
         }
 
+        tags.extend(value.tags.clone());
+
         let mut v7_event = v7::Event::new();
         v7_event.event_id = value.uid;
         v7_event.message = value.message.clone();
-        v7_event.culprit = Some(format!("{} {}", value.obj_name(), value.reason));
-        v7_event.server_name = value.source_host.clone().map(|s| s.into());
+        v7_event.culprit = Some(value.culprit(&CULPRIT_FORMAT));
+        v7_event.transaction = TRANSACTION_FORMAT.as_deref().map(|f| value.culprit(f));
+        v7_event.server_name = value.server_name(*SERVER_NAME_SOURCE).map(|s| s.into());
         v7_event.sdk = Some(Cow::Borrowed(SDK_VALUE.deref()));
+        v7_event.environment = value.environment.clone().map(|e| e.into());
         if let Some(timestamp) = value.creation_timestamp {
             v7_event.timestamp = timestamp;
         }
@@ -173,8 +319,24 @@ impl From<&SentryEvent> for v7::Event<'_> {
         );
         extra.insert("node labels".to_string(), Value::Object(labels));
 
-        v7_event.extra = value.metadata_map();
-        v7_event.fingerprint = fingerprint.into();
+        if let Some(node_capacity) = value.node_capacity.as_ref() {
+            v7_event
+                .contexts
+                .insert("device".to_string(), node_capacity.into());
+        }
+        for (name, context) in &value.contexts {
+            v7_event
+                .contexts
+                .insert(name.clone(), v7::Context::Other(context.clone()));
+        }
+
+        v7_event.extra = extra;
+        v7_event.fingerprint = value
+            .fingerprint()
+            .into_iter()
+            .map(Cow::Owned)
+            .collect::<Vec<_>>()
+            .into();
         v7_event.level = value.level;
         v7_event.tags = tags;
 
@@ -182,12 +344,26 @@ impl From<&SentryEvent> for v7::Event<'_> {
     }
 }
 
+/// The time the event last occurred.
+pub fn event_time(event: &Event) -> Option<DateTime<Utc>> {
+    event
+        .series
+        .as_ref()
+        .and_then(|s| s.last_observed_time.as_ref())
+        .map(|t| t.0)
+        .or_else(|| event.last_timestamp.as_ref().map(|t| t.0))
+        .or_else(|| event.event_time.as_ref().map(|t| t.0))
+        .or_else(|| event.first_timestamp.as_ref().map(|t| t.0))
+        .or_else(|| event.metadata.creation_timestamp.as_ref().map(|t| t.0))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::sentry_event::SentryEvent;
-    use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
-    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
-    use k8s_openapi::chrono::DateTime;
+    use crate::sentry_event::{event_time, SentryEvent, ServerNameSource};
+    use k8s_openapi::api::core::v1::{Event, EventSeries, EventSource, ObjectReference};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta, Time};
+    use k8s_openapi::chrono::{DateTime, Utc};
+    use sentry::types::protocol::v7;
     use sentry::Level;
 
     #[test]
@@ -250,9 +426,128 @@ mod tests {
             type_: Some("Warning".to_string()),
         };
 
-        let sentry_event = SentryEvent::from(event);
+        let mut sentry_event = SentryEvent::from(event);
+        assert_eq!(
+            sentry_event.fingerprint(),
+            vec!["Failed", "kube-system", "coredns-bbbc4b766-fv96b", "Pod"]
+        );
         assert_eq!(sentry_event.level, Level::Warning);
         assert_eq!(sentry_event.level.to_string(), "warning");
         assert_eq!(sentry_event.type_, "warning");
+
+        assert_eq!(
+            sentry_event.culprit(""),
+            "kube-system/coredns-bbbc4b766-fv96b Failed"
+        );
+        assert_eq!(
+            sentry_event.culprit("{{workload}} ({{kind}})"),
+            "coredns-bbbc4b766-fv96b (Pod)"
+        );
+
+        sentry_event.workload = Some("coredns".to_string());
+        assert_eq!(
+            sentry_event.culprit("{{namespace}}/{{workload}}"),
+            "kube-system/coredns"
+        );
+
+        let v7_event = v7::Event::from(&sentry_event);
+        assert_eq!(
+            v7_event.culprit.as_deref(),
+            Some("kube-system/coredns-bbbc4b766-fv96b Failed")
+        );
+        assert_eq!(
+            v7_event.transaction, None,
+            "no transaction without a format"
+        );
+    }
+
+    #[test]
+    pub fn test_cluster_scoped() {
+        let event = |kind: &str, namespace: Option<&str>| Event {
+            involved_object: ObjectReference {
+                kind: Some(kind.to_string()),
+                name: Some("node-1".to_string()),
+                namespace: namespace.map(str::to_string),
+                ..Default::default()
+            },
+            metadata: ObjectMeta {
+                namespace: Some("kube-system".to_string()),
+                ..Default::default()
+            },
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        };
+
+        let node = SentryEvent::from(event("Node", Some("")));
+        assert_eq!(node.namespace, "default");
+        assert_eq!(node.tags["cluster_scoped"], "true");
+
+        let pod = SentryEvent::from(event("Pod", Some("shop")));
+        assert_eq!(pod.namespace, "shop");
+        assert!(!pod.tags.contains_key("cluster_scoped"));
+
+        let mut unknown = event("Pod", None);
+        unknown.involved_object.kind = None;
+        assert_eq!(SentryEvent::from(unknown).namespace, "kube-system");
+    }
+
+    #[test]
+    pub fn test_event_types() {
+        let event = |type_: Option<&str>| Event {
+            type_: type_.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert_eq!(SentryEvent::from(event(Some("Normal"))).level, Level::Info);
+        assert_eq!(SentryEvent::from(event(Some("Error"))).level, Level::Error);
+
+        let custom = SentryEvent::from(event(Some("Critical")));
+        assert_eq!(custom.level, Level::Warning);
+        assert_eq!(custom.tags["event_type"], "Critical");
+
+        let missing = SentryEvent::from(event(None));
+        assert_eq!(missing.level, Level::Warning);
+        assert!(missing.tags.is_empty());
+    }
+
+    #[test]
+    pub fn test_server_name() {
+        let mut event = SentryEvent::from(Event {
+            source: Some(EventSource {
+                component: Some("kubelet".to_string()),
+                host: Some("node-1".to_string()),
+            }),
+            ..Default::default()
+        });
+        event.cluster = "eu-1".to_string();
+
+        let server_name = |source: &str| event.server_name(source.parse().unwrap());
+        assert_eq!(server_name("node").as_deref(), Some("node-1"));
+        assert_eq!(server_name("cluster").as_deref(), Some("eu-1"));
+        assert_eq!(server_name("component").as_deref(), Some("kubelet"));
+        assert_eq!(server_name("none"), None);
+        assert!("hostname".parse::<ServerNameSource>().is_err());
+
+        event.cluster = String::new();
+        assert_eq!(event.server_name(ServerNameSource::Cluster), None);
+    }
+
+    #[test]
+    pub fn test_event_time() {
+        let time = |t: &str| -> DateTime<Utc> { DateTime::parse_from_rfc3339(t).unwrap().into() };
+        let mut event = Event::default();
+        assert_eq!(event_time(&event), None);
+
+        event.event_time = Some(MicroTime(time("2023-04-08T22:27:40Z")));
+        assert_eq!(event_time(&event), Some(time("2023-04-08T22:27:40Z")));
+
+        event.last_timestamp = Some(Time(time("2023-04-08T22:28:03Z")));
+        assert_eq!(event_time(&event), Some(time("2023-04-08T22:28:03Z")));
+
+        event.series = Some(EventSeries {
+            count: Some(3),
+            last_observed_time: Some(MicroTime(time("2023-04-08T22:29:10Z"))),
+        });
+        assert_eq!(event_time(&event), Some(time("2023-04-08T22:29:10Z")));
     }
 }
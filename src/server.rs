@@ -0,0 +1,79 @@
+use crate::digest::constant_time_eq;
+use crate::health::HEALTH;
+use crate::metrics::METRICS;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/// Serves the operational endpoints (prometheus metrics, liveness and readiness probes).
+pub async fn serve(addr: SocketAddr) {
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|r| async { Ok::<_, Infallible>(route(r)) }))
+    });
+
+    info!("Listening on {}", addr);
+    match Server::try_bind(&addr) {
+        Ok(server) => {
+            if let Err(e) = server.serve(service).await {
+                error!("HTTP server error: {}", e);
+            }
+        }
+        Err(e) => error!("Cannot listen on {}: {}", addr, e),
+    }
+}
+
+/// Returns true if the request has the bearer token (ex: of the admin API).
+pub fn authorized(request: &Request<Body>, token: &str) -> bool {
+    request
+        .headers()
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+}
+
+fn route(request: Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(METRICS.render().into())
+            .unwrap(),
+        (&Method::GET, "/healthz") if HEALTH.is_live() => Response::new("ok".into()),
+        (&Method::GET, "/healthz") => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("watch stalled".into())
+            .unwrap(),
+        (&Method::GET, "/readyz") if HEALTH.is_ready() => Response::new("ok".into()),
+        (&Method::GET, "/readyz") => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("not ready".into())
+            .unwrap(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::route;
+    use hyper::{Body, Request, StatusCode};
+
+    #[test]
+    pub fn test_route() {
+        let response = route(Request::get("/metrics").body(Body::empty()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = route(Request::get("/healthz").body(Body::empty()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = route(Request::get("/readyz").body(Body::empty()).unwrap());
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = route(Request::get("/unknown").body(Body::empty()).unwrap());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
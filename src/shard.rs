@@ -0,0 +1,81 @@
+/// The subset of the namespaces handled by a replica, when the events are sharded across multiple
+/// replicas. Namespaces are assigned by hash, so that every replica agrees on the assignment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    index: u32,
+    count: u32,
+}
+
+impl Shard {
+    pub fn new(index: u32, count: u32) -> Self {
+        Self {
+            index,
+            count: count.max(1),
+        }
+    }
+
+    /// Returns true if the events of the namespace are handled by this shard.
+    pub fn owns(&self, namespace: &str) -> bool {
+        fnv1a(namespace.as_bytes()) % u64::from(self.count) == u64::from(self.index)
+    }
+
+    /// The name of a resource owned by the shard (ex: its checkpoint ConfigMap): the shards do not
+    /// share their state.
+    pub fn resource_name(&self, name: &str) -> String {
+        format!("{}-{}", name, self.index)
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// The ordinal of a StatefulSet pod, from its hostname (ex: 2 for "sentry-kubernetes-2").
+pub fn hostname_ordinal(hostname: &str) -> Option<u32> {
+    hostname.rsplit_once('-')?.1.parse().ok()
+}
+
+/// 64-bit FNV-1a hash: stable across processes and versions, unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shard::{fnv1a, hostname_ordinal, Shard};
+
+    #[test]
+    pub fn test_owns() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        let namespaces = (0..100).map(|i| format!("ns-{}", i)).collect::<Vec<_>>();
+        let shards = (0..3).map(|i| Shard::new(i, 3)).collect::<Vec<_>>();
+        for namespace in &namespaces {
+            let owners = shards.iter().filter(|s| s.owns(namespace)).count();
+            assert_eq!(owners, 1);
+        }
+
+        assert!(namespaces.iter().all(|ns| Shard::new(0, 1).owns(ns)));
+        assert!(namespaces.iter().all(|ns| Shard::new(0, 0).owns(ns)));
+    }
+
+    #[test]
+    pub fn test_resource_name() {
+        assert_eq!(
+            Shard::new(2, 3).resource_name("sentry-kubernetes-checkpoint"),
+            "sentry-kubernetes-checkpoint-2"
+        );
+    }
+
+    #[test]
+    pub fn test_hostname_ordinal() {
+        assert_eq!(hostname_ordinal("sentry-kubernetes-2"), Some(2));
+        assert_eq!(hostname_ordinal("sentry-kubernetes-7d9f8-x2x4z"), None);
+        assert_eq!(hostname_ordinal("localhost"), None);
+    }
+}
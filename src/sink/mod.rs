@@ -0,0 +1,70 @@
+use crate::config::SinkConfig;
+use crate::routing::ClientPool;
+use crate::sentry_event::SentryEvent;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+mod ndjson;
+mod otlp;
+mod pagerduty;
+mod s3;
+mod sentry;
+mod slack;
+mod webhook;
+
+pub use self::ndjson::NdjsonSink;
+pub use self::otlp::OtlpSink;
+pub use self::pagerduty::PagerDutySink;
+pub use self::s3::S3Sink;
+pub use self::sentry::{omit_server_name, SentrySink};
+pub use self::slack::SlackSink;
+pub use self::webhook::WebhookSink;
+
+/// A destination of the processed events.
+/// Every configured sink receives all the events passing the filters.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, event: &SentryEvent);
+
+    /// Flushes the buffered events, if any. Called on shutdown.
+    async fn close(&self) {}
+}
+
+#[async_trait]
+impl<F: Fn(&SentryEvent) + Send + Sync> EventSink for F {
+    async fn send(&self, event: &SentryEvent) {
+        self(event)
+    }
+}
+
+/// Serializes the event as JSON, in the sentry event format.
+fn to_json(event: &SentryEvent) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec(&::sentry::protocol::Event::from(event))
+}
+
+/// Builds the configured sinks. If no sink is configured, events are sent to sentry.
+pub fn build(configs: &[SinkConfig], client_pool: &Arc<ClientPool>) -> Vec<Box<dyn EventSink>> {
+    if configs.is_empty() {
+        return vec![Box::new(SentrySink::new(
+            Default::default(),
+            client_pool.clone(),
+        ))];
+    }
+
+    configs
+        .iter()
+        .map(|config| -> Box<dyn EventSink> {
+            match config {
+                SinkConfig::Sentry(sentry) => {
+                    Box::new(SentrySink::new(sentry.clone(), client_pool.clone()))
+                }
+                SinkConfig::Webhook(webhook) => Box::new(WebhookSink::new(webhook.clone())),
+                SinkConfig::Ndjson(ndjson) => Box::new(NdjsonSink::new(ndjson.clone())),
+                SinkConfig::Otlp(otlp) => Box::new(OtlpSink::new(otlp.clone())),
+                SinkConfig::Slack(slack) => Box::new(SlackSink::new(slack.clone())),
+                SinkConfig::PagerDuty(pagerduty) => Box::new(PagerDutySink::new(pagerduty.clone())),
+                SinkConfig::S3(s3) => Box::new(S3Sink::new(s3.clone())),
+            }
+        })
+        .collect()
+}
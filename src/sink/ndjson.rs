@@ -0,0 +1,126 @@
+use crate::config::NdjsonConfig;
+use crate::metrics::METRICS;
+use crate::sentry_event::SentryEvent;
+use crate::sink::{to_json, EventSink};
+use async_trait::async_trait;
+use log::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Writes each event as a JSON line to stdout or to a file.
+/// Files are rotated (path.1, path.2, ...) when they exceed the configured size.
+pub struct NdjsonSink {
+    config: NdjsonConfig,
+    file: Mutex<Option<File>>,
+}
+
+impl NdjsonSink {
+    pub fn new(config: NdjsonConfig) -> Self {
+        Self {
+            config,
+            file: Default::default(),
+        }
+    }
+
+    fn write_line(&self, line: &[u8]) -> io::Result<()> {
+        let Some(path) = self.config.path.as_deref().filter(|p| *p != "-") else {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(line)?;
+            return stdout.write_all(b"\n");
+        };
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(open(path)?);
+        }
+
+        let current = file.as_mut().unwrap();
+        if self.config.max_size > 0 && current.metadata()?.len() >= self.config.max_size {
+            rotate(Path::new(path), self.config.max_files)?;
+            *current = open(path)?;
+        }
+
+        current.write_all(line)?;
+        current.write_all(b"\n")
+    }
+}
+
+#[async_trait]
+impl EventSink for NdjsonSink {
+    async fn send(&self, event: &SentryEvent) {
+        let result = to_json(event)
+            .map_err(io::Error::from)
+            .and_then(|line| self.write_line(&line));
+
+        if let Err(e) = result {
+            warn!("Cannot write event to ndjson output: {}", e);
+            METRICS.send_failure("ndjson");
+        }
+    }
+}
+
+fn open(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+
+    rotated.into()
+}
+
+/// Shifts path.N-1 to path.N (dropping the oldest file) and moves the current file to path.1.
+fn rotate(path: &Path, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+
+    for index in (1..max_files).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(from, rotated_path(path, index + 1))?;
+        }
+    }
+
+    fs::rename(path, rotated_path(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::NdjsonConfig;
+    use crate::sink::ndjson::{rotated_path, NdjsonSink};
+    use std::fs;
+
+    #[test]
+    pub fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("ndjson-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.ndjson");
+
+        let sink = NdjsonSink::new(NdjsonConfig {
+            path: Some(path.to_string_lossy().to_string()),
+            max_size: 10,
+            max_files: 2,
+        });
+
+        for line in ["first-line", "second-line", "third-line", "fourth-line"] {
+            sink.write_line(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth-line\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "third-line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "second-line\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use crate::config::OtlpConfig;
+use crate::metrics::METRICS;
+use crate::sentry_event::SentryEvent;
+use crate::sink::EventSink;
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use sentry::Level;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Exports the events as OpenTelemetry log records, using the OTLP/HTTP JSON encoding.
+pub struct OtlpSink {
+    url: String,
+    config: OtlpConfig,
+    client: reqwest::Client,
+}
+
+impl OtlpSink {
+    pub fn new(config: OtlpConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            url: logs_url(&config.endpoint),
+            config,
+            client,
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for OtlpSink {
+    async fn send(&self, event: &SentryEvent) {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(export_request(event).to_string());
+
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(target: "sentry_kubernetes::otlp", "Event exported to {}", self.url);
+                return;
+            }
+            Ok(response) => format!("unexpected status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+
+        warn!("Cannot export event to {}: {}", self.url, error);
+        METRICS.send_failure("otlp");
+    }
+}
+
+/// Appends the logs signal path to the endpoint, unless a path is already specified.
+fn logs_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    let has_path = endpoint
+        .split_once("://")
+        .map_or(endpoint, |(_, rest)| rest)
+        .contains('/');
+
+    if has_path {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/logs", endpoint)
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+pub(super) fn severity_number(level: Level) -> u8 {
+    match level {
+        Level::Debug => 5,
+        Level::Info => 9,
+        Level::Warning => 13,
+        Level::Error => 17,
+        Level::Fatal => 21,
+    }
+}
+
+fn export_request(event: &SentryEvent) -> Value {
+    let mut resource = vec![attribute("service.name", "sentry-kubernetes")];
+    if !event.cluster.is_empty() {
+        resource.push(attribute("k8s.cluster.name", &event.cluster));
+    }
+    if !event.namespace.is_empty() {
+        resource.push(attribute("k8s.namespace.name", &event.namespace));
+    }
+    if let Some(node) = event.source_host.as_deref() {
+        resource.push(attribute("k8s.node.name", node));
+    }
+
+    let mut attributes = vec![
+        attribute("k8s.event.reason", &event.reason),
+        attribute("k8s.event.type", &event.type_),
+        attribute("k8s.object.name", &event.name),
+    ];
+    if let Some(kind) = event.kind.as_deref() {
+        attributes.push(attribute("k8s.object.kind", kind));
+    }
+    if !event.component.is_empty() {
+        attributes.push(attribute("k8s.event.component", &event.component));
+    }
+    if let Some(workload) = event.workload.as_deref() {
+        attributes.push(attribute("k8s.workload.name", workload));
+    }
+
+    let now = SystemTime::now();
+    json!({
+        "resourceLogs": [{
+            "resource": { "attributes": resource },
+            "scopeLogs": [{
+                "scope": { "name": "sentry-kubernetes" },
+                "logRecords": [{
+                    "timeUnixNano": unix_nanos(event.creation_timestamp.unwrap_or(now)),
+                    "observedTimeUnixNano": unix_nanos(now),
+                    "severityNumber": severity_number(event.level),
+                    "severityText": event.level.to_string().to_uppercase(),
+                    "body": { "stringValue": event.message.clone().unwrap_or_default() },
+                    "attributes": attributes,
+                }],
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sentry_event::SentryEvent;
+    use crate::sink::otlp::{export_request, logs_url};
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+
+    #[test]
+    pub fn test_logs_url() {
+        assert_eq!(
+            logs_url("http://collector:4318"),
+            "http://collector:4318/v1/logs"
+        );
+        assert_eq!(
+            logs_url("http://collector:4318/"),
+            "http://collector:4318/v1/logs"
+        );
+        assert_eq!(
+            logs_url("https://otlp.example.com/custom/logs"),
+            "https://otlp.example.com/custom/logs"
+        );
+    }
+
+    #[test]
+    pub fn test_export_request() {
+        let mut event = SentryEvent::from(Event {
+            type_: Some("Warning".to_string()),
+            reason: Some("BackOff".to_string()),
+            message: Some("Back-off restarting failed container".to_string()),
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        event.source_host = Some("node-1".to_string());
+
+        let request = export_request(&event);
+        let resource = &request["resourceLogs"][0]["resource"]["attributes"];
+        assert!(resource
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|a| a["key"] == "k8s.node.name" && a["value"]["stringValue"] == "node-1"));
+
+        let record = &request["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["severityNumber"], 13);
+        assert_eq!(record["severityText"], "WARNING");
+        assert_eq!(
+            record["body"]["stringValue"],
+            "Back-off restarting failed container"
+        );
+    }
+}
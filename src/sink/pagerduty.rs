@@ -0,0 +1,167 @@
+use crate::config::PagerDutyConfig;
+use crate::metrics::METRICS;
+use crate::sentry_event::SentryEvent;
+use crate::sink::EventSink;
+use async_trait::async_trait;
+use log::{debug, warn};
+use sentry::Level;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Maximum length of the summary accepted by the PagerDuty events API.
+const MAX_SUMMARY_LENGTH: usize = 1024;
+
+/// Triggers (and resolves) PagerDuty incidents through the Events API v2.
+/// The event fingerprint is used as deduplication key.
+pub struct PagerDutySink {
+    config: PagerDutyConfig,
+    client: reqwest::Client,
+}
+
+impl PagerDutySink {
+    pub fn new(config: PagerDutyConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .unwrap_or_default();
+
+        Self { config, client }
+    }
+
+    fn payload(&self, event: &SentryEvent) -> Option<Value> {
+        if let Some(triggered_by) = self.config.resolve_reasons.get(&event.reason) {
+            let mut fingerprint = event.fingerprint();
+            if let Some(reason) = fingerprint.first_mut() {
+                *reason = triggered_by.clone();
+            }
+
+            return Some(json!({
+                "routing_key": self.config.routing_key,
+                "event_action": "resolve",
+                "dedup_key": fingerprint.join("/"),
+            }));
+        }
+
+        let reason_matches =
+            self.config.reasons.is_empty() || self.config.reasons.contains(&event.reason);
+        if event.level < self.config.min_level || !reason_matches {
+            return None;
+        }
+
+        let mut summary = format!(
+            "{} {}: {}",
+            event.reason,
+            event.obj_name(),
+            event.message.as_deref().unwrap_or_default()
+        );
+        if let Some((index, _)) = summary.char_indices().nth(MAX_SUMMARY_LENGTH) {
+            summary.truncate(index);
+        }
+
+        Some(json!({
+            "routing_key": self.config.routing_key,
+            "event_action": "trigger",
+            "dedup_key": event.fingerprint().join("/"),
+            "payload": {
+                "summary": summary,
+                "source": event.source_host.clone().unwrap_or_else(|| event.obj_name()),
+                "severity": severity(event.level),
+                "component": event.kind.as_deref().map(|k| format!("{}/{}", k, event.name)),
+                "group": event.namespace,
+                "class": event.reason,
+                "custom_details": {
+                    "cluster": event.cluster,
+                    "component": event.component,
+                    "workload": event.workload,
+                    "message": event.message,
+                },
+            },
+        }))
+    }
+}
+
+#[async_trait]
+impl EventSink for PagerDutySink {
+    async fn send(&self, event: &SentryEvent) {
+        let Some(payload) = self.payload(event) else {
+            return;
+        };
+
+        let error = match self
+            .client
+            .post(&self.config.url)
+            .json(&payload)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => {
+                debug!(target: "sentry_kubernetes::pagerduty", "Event sent to pagerduty ({})", payload["event_action"]);
+                return;
+            }
+            Ok(response) => format!("unexpected status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+
+        warn!("Cannot send event to pagerduty: {}", error);
+        METRICS.send_failure("pagerduty");
+    }
+}
+
+fn severity(level: Level) -> &'static str {
+    match level {
+        Level::Fatal => "critical",
+        Level::Error => "error",
+        Level::Warning => "warning",
+        _ => "info",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::PagerDutyConfig;
+    use crate::sentry_event::SentryEvent;
+    use crate::sink::pagerduty::PagerDutySink;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+
+    fn node_event(type_: &str, reason: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            type_: Some(type_.to_string()),
+            reason: Some(reason.to_string()),
+            involved_object: ObjectReference {
+                kind: Some("Node".to_string()),
+                name: Some("node-1".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_payload() {
+        let config: PagerDutyConfig = serde_yaml::from_str(
+            r#"
+routingKey: abc
+minLevel: warning
+reasons: [ NodeNotReady ]
+resolveReasons:
+  NodeReady: NodeNotReady
+"#,
+        )
+        .unwrap();
+        let sink = PagerDutySink::new(config);
+
+        let trigger = sink
+            .payload(&node_event("Warning", "NodeNotReady"))
+            .unwrap();
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["dedup_key"], "NodeNotReady/default/node-1/Node");
+        assert_eq!(trigger["payload"]["severity"], "warning");
+        assert_eq!(trigger["payload"]["source"], "node-1");
+
+        assert!(sink.payload(&node_event("Warning", "Rebooted")).is_none());
+
+        let resolve = sink.payload(&node_event("Normal", "NodeReady")).unwrap();
+        assert_eq!(resolve["event_action"], "resolve");
+        assert_eq!(resolve["dedup_key"], trigger["dedup_key"]);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::config::{SentryConfig, SentryTarget};
+use crate::metrics::METRICS;
+use crate::routing::ClientPool;
+use crate::sentry_event::SentryEvent;
+use crate::sink::otlp::severity_number;
+use crate::sink::EventSink;
+use async_trait::async_trait;
+use log::{debug, warn};
+use sentry::protocol::{Envelope, Event};
+use sentry::types::Uuid;
+use sentry::{Client, Hub, Level};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type BeforeSend = Arc<dyn Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync>;
+
+/// The server_name of the events without server_name, removed before sending them.
+const OMITTED_SERVER_NAME: &str = "";
+
+/// Chains the given before_send callback (ex: the rules) with the removal of the placeholder of
+/// the omitted server_name: the clients capturing the events of the sink must use it.
+pub fn omit_server_name(next: Option<BeforeSend>) -> BeforeSend {
+    Arc::new(move |mut event: Event<'static>| {
+        if event.server_name.as_deref() == Some(OMITTED_SERVER_NAME) {
+            event.server_name = None;
+        }
+        match next.as_ref() {
+            Some(next) => next(event),
+            None => Some(event),
+        }
+    })
+}
+
+/// Captures the events with the sentry clients of the DSNs they have been routed to.
+/// Events without any routed DSN are captured with the main hub client.
+/// Depending on their level, events are reported as issues, structured logs or both.
+/// Structured logs are batched: they are sent every `logFlushInterval` seconds,
+/// or as soon as `logBatchSize` logs are buffered for a DSN. The issues are not batched,
+/// sentry accepting a single event per envelope.
+pub struct SentrySink {
+    config: SentryConfig,
+    client_pool: Arc<ClientPool>,
+    logs: Arc<LogBuffer>,
+}
+
+/// The structured logs waiting to be sent, by DSN.
+#[derive(Default)]
+struct LogBuffer {
+    batches: Mutex<BTreeMap<String, LogBatch>>,
+}
+
+struct LogBatch {
+    client: Arc<Client>,
+    records: Vec<Value>,
+}
+
+impl LogBuffer {
+    /// Buffers the record. Returns the batch of the client if it is full.
+    fn push(&self, client: &Arc<Client>, record: Value, batch_size: usize) -> Option<Vec<Value>> {
+        let dsn = client.dsn().map(|d| d.to_string()).unwrap_or_default();
+        let mut batches = self.batches.lock().unwrap();
+        let batch = batches.entry(dsn).or_insert_with(|| LogBatch {
+            client: client.clone(),
+            records: vec![],
+        });
+        batch.records.push(record);
+
+        (batch.records.len() >= batch_size).then(|| std::mem::take(&mut batch.records))
+    }
+
+    fn flush(&self) {
+        let batches = std::mem::take(&mut *self.batches.lock().unwrap());
+        for batch in batches.into_values() {
+            send_logs(&batch.client, batch.records);
+        }
+    }
+}
+
+impl SentrySink {
+    pub fn new(config: SentryConfig, client_pool: Arc<ClientPool>) -> Self {
+        let logs = Arc::new(LogBuffer::default());
+
+        // The flusher task ends once the sink has been dropped.
+        let flusher = logs.clone();
+        let flush_interval = Duration::from_secs(config.log_flush_interval.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                flusher.flush();
+                if Arc::strong_count(&flusher) == 1 {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            config,
+            client_pool,
+            logs,
+        }
+    }
+
+    fn capture_issue(&self, sentry_event: &SentryEvent) {
+        let mut event = Event::from(sentry_event);
+        if event.server_name.is_none() {
+            // Keeps the sentry client from setting the hostname of the controller.
+            event.server_name = Some(OMITTED_SERVER_NAME.into());
+        }
+        if sentry_event.dsns.is_empty() {
+            let uuid = sentry::capture_event(event);
+            debug!(target: "sentry_kubernetes::sentry_client", "Captured event (uuid = {})", uuid);
+            return;
+        }
+
+        for dsn in &sentry_event.dsns {
+            if let Some(uuid) = self.client_pool.capture_event(dsn, event.clone()) {
+                debug!(target: "sentry_kubernetes::sentry_client", "Captured event (uuid = {}, dsn = {})", uuid, dsn);
+            }
+        }
+    }
+
+    fn capture_log(&self, sentry_event: &SentryEvent) {
+        let clients = if sentry_event.dsns.is_empty() {
+            Hub::current().client().into_iter().collect::<Vec<_>>()
+        } else {
+            sentry_event
+                .dsns
+                .iter()
+                .filter_map(|dsn| self.client_pool.client(dsn))
+                .collect()
+        };
+
+        // The logs go through the before_send rules and the sampling of the client, as the issues.
+        let event = Event::from(sentry_event);
+        for client in clients {
+            let sent = match client.options().before_send.as_ref() {
+                Some(before_send) => before_send(event.clone()),
+                None => Some(event.clone()),
+            };
+            let Some(sent) = sent else {
+                debug!(target: "sentry_kubernetes::sentry_client", "Log dropped by before_send (uid = {})", sentry_event.uid);
+                continue;
+            };
+
+            let environment = sentry_event
+                .environment
+                .clone()
+                .or_else(|| client.options().environment.as_ref().map(|e| e.to_string()));
+            let release = client.options().release.as_ref().map(|r| r.to_string());
+
+            let mut record = log_record(sentry_event, environment, release);
+            apply_rules(&mut record, &event, &sent);
+            debug!(target: "sentry_kubernetes::sentry_client", "Captured log (uid = {})", sentry_event.uid);
+            if let Some(records) = self.logs.push(&client, record, self.config.log_batch_size) {
+                send_logs(&client, records);
+            }
+        }
+    }
+}
+
+fn send_logs(client: &Client, records: Vec<Value>) {
+    if records.is_empty() {
+        return;
+    }
+
+    let count = records.len();
+    match log_envelope(records) {
+        Ok(envelope) => {
+            client.send_envelope(envelope);
+            debug!(target: "sentry_kubernetes::sentry_client", "Sent {} logs", count);
+        }
+        Err(e) => {
+            warn!("Cannot build sentry log envelope: {}", e);
+            METRICS.send_failure("sentry");
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for SentrySink {
+    async fn send(&self, sentry_event: &SentryEvent) {
+        let target = self
+            .config
+            .levels
+            .get(&sentry_event.level.to_string())
+            .copied()
+            .unwrap_or_default();
+
+        if target != SentryTarget::Logs {
+            self.capture_issue(sentry_event);
+        }
+        if target != SentryTarget::Issues {
+            self.capture_log(sentry_event);
+        }
+    }
+
+    async fn close(&self) {
+        self.logs.flush();
+    }
+}
+
+fn log_level(level: Level) -> &'static str {
+    match level {
+        Level::Debug => "debug",
+        Level::Info => "info",
+        Level::Warning => "warn",
+        Level::Error => "error",
+        Level::Fatal => "fatal",
+    }
+}
+
+fn log_record(event: &SentryEvent, environment: Option<String>, release: Option<String>) -> Value {
+    let mut attributes = Map::new();
+    let mut attribute = |key: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            attributes.insert(key.to_string(), json!({ "value": value, "type": "string" }));
+        }
+    };
+
+    attribute("sentry.environment", environment);
+    attribute("sentry.release", release);
+    attribute("k8s.cluster.name", Some(event.cluster.clone()));
+    attribute("k8s.namespace.name", Some(event.namespace.clone()));
+    attribute("k8s.node.name", event.source_host.clone());
+    attribute("k8s.object.kind", event.kind.clone());
+    attribute("k8s.object.name", Some(event.name.clone()));
+    attribute("k8s.workload.name", event.workload.clone());
+    attribute("k8s.event.reason", Some(event.reason.clone()));
+    attribute("k8s.event.component", Some(event.component.clone()));
+
+    let timestamp = event
+        .creation_timestamp
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    json!({
+        "timestamp": timestamp,
+        "trace_id": Uuid::new_v4().as_simple().to_string(),
+        "level": log_level(event.level),
+        "severity_number": severity_number(event.level),
+        "body": event.message.clone().unwrap_or_else(|| event.reason.clone()),
+        "attributes": attributes,
+    })
+}
+
+/// Applies the changes of the before_send rules to the log record: the level and the added tags.
+fn apply_rules(record: &mut Value, event: &Event, sent: &Event) {
+    record["level"] = json!(log_level(sent.level));
+    record["severity_number"] = json!(severity_number(sent.level));
+    for (name, value) in &sent.tags {
+        if event.tags.get(name) != Some(value) {
+            record["attributes"][name] = json!({ "value": value, "type": "string" });
+        }
+    }
+}
+
+/// Builds an envelope with a log item containing the given records (sentry logs product).
+fn log_envelope(records: Vec<Value>) -> serde_json::Result<Envelope> {
+    let header = json!({
+        "type": "log",
+        "item_count": records.len(),
+        "content_type": "application/vnd.sentry.items.log+json",
+    });
+    let payload = json!({ "items": records });
+
+    let mut bytes = b"{}\n".to_vec();
+    serde_json::to_writer(&mut bytes, &header)?;
+    bytes.push(b'\n');
+    serde_json::to_writer(&mut bytes, &payload)?;
+    bytes.push(b'\n');
+
+    Ok(Envelope::from_bytes_raw(bytes).expect("raw envelopes are not validated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::before_send::BeforeSendRules;
+    use crate::config::BeforeSendRule;
+    use crate::routing::ClientPool;
+    use crate::sentry_event::SentryEvent;
+    use crate::sink::sentry::{log_envelope, log_record, omit_server_name, LogBuffer, SentrySink};
+    use crate::sink::EventSink;
+    use crate::transport::HttpTransportFactory;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use sentry::{Client, ClientOptions};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn event() -> SentryEvent {
+        SentryEvent::from(Event {
+            type_: Some("Normal".to_string()),
+            reason: Some("Scheduled".to_string()),
+            message: Some("Successfully assigned default/web-1 to node-1".to_string()),
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-1".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_log_record() {
+        let record = log_record(&event(), Some("production".to_string()), None);
+        assert_eq!(record["level"], "info");
+        assert_eq!(record["severity_number"], 9);
+        assert_eq!(
+            record["body"],
+            "Successfully assigned default/web-1 to node-1"
+        );
+        assert_eq!(record["trace_id"].as_str().unwrap().len(), 32);
+
+        let attributes = record["attributes"].as_object().unwrap();
+        assert_eq!(attributes["sentry.environment"]["value"], "production");
+        assert_eq!(attributes["k8s.object.kind"]["value"], "Pod");
+        assert_eq!(attributes["k8s.event.reason"]["value"], "Scheduled");
+        assert!(!attributes.contains_key("sentry.release"));
+    }
+
+    #[test]
+    pub fn test_log_envelope() {
+        let mut bytes = vec![];
+        log_envelope(vec![
+            log_record(&event(), None, None),
+            log_record(&event(), None, None),
+        ])
+        .unwrap()
+        .to_writer(&mut bytes)
+        .unwrap();
+
+        let lines = String::from_utf8(bytes).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{}");
+        assert!(lines[1].contains("\"type\":\"log\""));
+        assert!(lines[1].contains("\"item_count\":2"));
+        assert!(lines[2].starts_with("{\"items\":["));
+    }
+
+    #[test]
+    pub fn test_log_buffer() {
+        let client = Arc::new(Client::from(ClientOptions {
+            dsn: Some(sentry::types::Dsn::from_str("https://public@sentry.example.com/1").unwrap()),
+            ..Default::default()
+        }));
+
+        let buffer = LogBuffer::default();
+        let record = || log_record(&event(), None, None);
+        assert!(buffer.push(&client, record(), 2).is_none());
+        assert_eq!(buffer.push(&client, record(), 2).map(|r| r.len()), Some(2));
+        assert!(buffer.push(&client, record(), 2).is_none());
+        assert_eq!(buffer.batches.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_log_before_send_rules() {
+        let rules: Vec<BeforeSendRule> = serde_yaml::from_str(
+            r#"
+- match: { tags: { namespace: "kube-*" } }
+  drop: true
+- match: { levels: [info] }
+  tags: { team: platform }
+  level: warning
+"#,
+        )
+        .unwrap();
+        let pool = Arc::new(ClientPool::new(ClientOptions {
+            transport: Some(Arc::new(HttpTransportFactory::new(Default::default()))),
+            before_send: Some(omit_server_name(
+                BeforeSendRules::new(rules).before_send(None),
+            )),
+            ..Default::default()
+        }));
+        let config = serde_yaml::from_str("{ levels: { info: logs } }").unwrap();
+        let sink = SentrySink::new(config, pool);
+        let records = || {
+            sink.logs
+                .batches
+                .lock()
+                .unwrap()
+                .values()
+                .flat_map(|b| b.records.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut dropped = event();
+        dropped.dsns = vec!["https://public@sentry.example.com/1".to_string()];
+        dropped.namespace = "kube-system".to_string();
+        sink.send(&dropped).await;
+        assert!(records().is_empty(), "dropped by the rule");
+
+        let mut kept = event();
+        kept.dsns = dropped.dsns.clone();
+        kept.namespace = "shop".to_string();
+        sink.send(&kept).await;
+        let records = records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["level"], "warn");
+        assert_eq!(records[0]["attributes"]["team"]["value"], "platform");
+        assert!(records[0]["attributes"].get("namespace").is_none());
+    }
+
+    #[test]
+    pub fn test_omit_server_name() {
+        let event = |server_name: &'static str| sentry::protocol::Event {
+            server_name: Some(server_name.into()),
+            ..Default::default()
+        };
+
+        let before_send = omit_server_name(None);
+        assert_eq!(before_send(event("")).unwrap().server_name, None);
+        assert_eq!(
+            before_send(event("node-1")).unwrap().server_name.as_deref(),
+            Some("node-1")
+        );
+
+        let before_send = omit_server_name(Some(Arc::new(|_| None)));
+        assert!(before_send(event("")).is_none());
+    }
+}
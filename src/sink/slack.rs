@@ -0,0 +1,164 @@
+use crate::config::SlackConfig;
+use crate::metrics::METRICS;
+use crate::routing::glob_match;
+use crate::sentry_event::SentryEvent;
+use crate::sink::EventSink;
+use async_trait::async_trait;
+use log::{debug, warn};
+use sentry::Level;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Posts a message to a Slack incoming webhook for the events at or above the configured level.
+pub struct SlackSink {
+    config: SlackConfig,
+    client: reqwest::Client,
+}
+
+impl SlackSink {
+    pub fn new(config: SlackConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .unwrap_or_default();
+
+        Self { config, client }
+    }
+
+    /// Returns the webhook URL and the channel for the given namespace,
+    /// applying the first matching namespace override.
+    fn destination(&self, namespace: &str) -> (&str, Option<&str>) {
+        let mut url = self.config.webhook_url.as_str();
+        let mut channel = self.config.channel.as_deref();
+        if let Some(o) = self
+            .config
+            .namespaces
+            .iter()
+            .find(|o| glob_match(&o.namespace, namespace))
+        {
+            url = o.webhook_url.as_deref().unwrap_or(url);
+            channel = o.channel.as_deref().or(channel);
+        }
+
+        (url, channel)
+    }
+}
+
+#[async_trait]
+impl EventSink for SlackSink {
+    async fn send(&self, event: &SentryEvent) {
+        if event.level < self.config.min_level {
+            return;
+        }
+
+        let (url, channel) = self.destination(&event.namespace);
+        let mut payload = message(event);
+        if let Some(channel) = channel {
+            payload["channel"] = channel.into();
+        }
+
+        let error = match self.client.post(url).json(&payload).send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(target: "sentry_kubernetes::slack", "Event posted to slack");
+                return;
+            }
+            Ok(response) => format!("unexpected status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+
+        warn!("Cannot post event to slack: {}", error);
+        METRICS.send_failure("slack");
+    }
+}
+
+fn message(event: &SentryEvent) -> Value {
+    let icon = match event.level {
+        Level::Fatal => ":rotating_light:",
+        Level::Error => ":red_circle:",
+        Level::Warning => ":warning:",
+        _ => ":information_source:",
+    };
+
+    let mut context = vec![format!("*Level:* {}", event.level)];
+    if !event.cluster.is_empty() {
+        context.push(format!("*Cluster:* {}", event.cluster));
+    }
+    if let Some(kind) = event.kind.as_deref() {
+        context.push(format!("*Kind:* {}", kind));
+    }
+    if let Some(node) = event.source_host.as_deref() {
+        context.push(format!("*Node:* {}", node));
+    }
+
+    json!({
+        "text": format!(
+            "{} *{}* `{}`\n{}\n{}",
+            icon,
+            event.reason,
+            event.obj_name(),
+            event.message.as_deref().unwrap_or_default(),
+            context.join(" | "),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SlackConfig;
+    use crate::sentry_event::SentryEvent;
+    use crate::sink::slack::{message, SlackSink};
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+
+    #[test]
+    pub fn test_destination() {
+        let config: SlackConfig = serde_yaml::from_str(
+            r##"
+webhookUrl: https://hooks.slack.com/services/default
+channel: "#alerts"
+namespaces:
+  - namespace: "payments-*"
+    channel: "#payments"
+  - namespace: "search"
+    webhookUrl: https://hooks.slack.com/services/search
+"##,
+        )
+        .unwrap();
+        let sink = SlackSink::new(config);
+
+        assert_eq!(
+            sink.destination("default"),
+            ("https://hooks.slack.com/services/default", Some("#alerts"))
+        );
+        assert_eq!(
+            sink.destination("payments-eu"),
+            (
+                "https://hooks.slack.com/services/default",
+                Some("#payments")
+            )
+        );
+        assert_eq!(
+            sink.destination("search"),
+            ("https://hooks.slack.com/services/search", Some("#alerts"))
+        );
+    }
+
+    #[test]
+    pub fn test_message() {
+        let event = SentryEvent::from(Event {
+            type_: Some("Warning".to_string()),
+            reason: Some("NodeNotReady".to_string()),
+            message: Some("Node is not ready".to_string()),
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let text = message(&event)["text"].as_str().unwrap().to_string();
+        assert!(text.starts_with(":warning: *NodeNotReady* `shop/web-0`\nNode is not ready\n"));
+        assert!(text.contains("*Kind:* Pod"));
+    }
+}
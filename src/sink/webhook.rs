@@ -0,0 +1,250 @@
+use crate::config::WebhookConfig;
+use crate::digest::hex;
+use crate::metrics::METRICS;
+use crate::sentry_event::SentryEvent;
+use crate::sink::{to_json, EventSink};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// Header carrying the hex-encoded HMAC-SHA256 signature of the body, if a secret is configured.
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// POSTs the events as JSON (in the sentry event format) to the configured URL.
+/// The events are posted (and retried) by a background task: `send` only queues them,
+/// dropping them when the queue is full.
+pub struct WebhookSink {
+    queue: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Poster {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .unwrap_or_default();
+
+        // The worker ends once the queue has been closed and drained.
+        let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(config.queue_size.max(1));
+        let poster = Poster { config, client };
+        let worker = tokio::spawn(async move {
+            while let Some(body) = receiver.recv().await {
+                poster.deliver(&body).await;
+            }
+        });
+
+        Self {
+            queue: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+        }
+    }
+}
+
+impl Poster {
+    async fn deliver(&self, body: &[u8]) {
+        let mut attempt = 0;
+        loop {
+            match self.post(body).await {
+                Ok(()) => {
+                    debug!(target: "sentry_kubernetes::webhook", "Event posted to {}", self.config.url);
+                    return;
+                }
+                Err(e) if attempt < self.config.retries && e.is_retryable() => {
+                    attempt += 1;
+                    debug!(target: "sentry_kubernetes::webhook", "Webhook error ({}), retrying", e);
+                    sleep(Duration::from_secs(1 << attempt.min(6))).await;
+                }
+                Err(e) => {
+                    warn!("Cannot post event to webhook {}: {}", self.config.url, e);
+                    METRICS.send_failure("webhook");
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<(), PostError> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        if let Some(secret) = self.config.secret.as_deref() {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, body)));
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(PostError::Status(response.status())),
+            Err(e) => Err(PostError::Transport(e.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn send(&self, event: &SentryEvent) {
+        let body = match to_json(event) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Cannot serialize event for webhook: {}", e);
+                METRICS.send_failure("webhook");
+                return;
+            }
+        };
+
+        let queued = match self.queue.lock().unwrap().as_ref() {
+            Some(queue) => queue.try_send(body).is_ok(),
+            None => false,
+        };
+        if !queued {
+            warn!("Webhook queue full or closed, dropping event");
+            METRICS.event_dropped("webhook_queue_full");
+        }
+    }
+
+    /// Waits for the queued events to be posted.
+    async fn close(&self) {
+        self.queue.lock().unwrap().take();
+        let worker = self.worker.lock().unwrap().take();
+        if let Some(worker) = worker {
+            let _ = worker.await;
+        }
+    }
+}
+
+enum PostError {
+    Status(StatusCode),
+    Transport(String),
+}
+
+impl PostError {
+    /// Client errors (except rate limiting) won't be fixed by retrying.
+    fn is_retryable(&self) -> bool {
+        match self {
+            PostError::Status(status) => {
+                !status.is_client_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            PostError::Transport(_) => true,
+        }
+    }
+}
+
+impl Display for PostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostError::Status(status) => write!(f, "unexpected status {}", status),
+            PostError::Transport(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    hex(&mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sentry_event::SentryEvent;
+    use crate::sink::webhook::{sign, PostError};
+    use crate::sink::{EventSink, WebhookSink};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    pub fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    pub fn test_is_retryable() {
+        assert!(PostError::Transport("connection refused".to_string()).is_retryable());
+        assert!(PostError::Status(StatusCode::SERVICE_UNAVAILABLE).is_retryable());
+        assert!(PostError::Status(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!PostError::Status(StatusCode::BAD_REQUEST).is_retryable());
+    }
+
+    #[tokio::test]
+    pub async fn test_post_queued_events() {
+        let received: Arc<Mutex<Vec<serde_json::Value>>> = Default::default();
+        let store = received.clone();
+        let service = make_service_fn(move |_| {
+            let store = store.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let store = store.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        store
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice(&body).unwrap());
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let config =
+            serde_json::from_value(json!({ "url": format!("http://{}/hook", addr) })).unwrap();
+        let sink = WebhookSink::new(config);
+        for name in ["web-0", "web-1"] {
+            sink.send(&SentryEvent::from(Event {
+                involved_object: ObjectReference {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }))
+            .await;
+        }
+
+        // Closing the sink waits for the queued events to be posted.
+        sink.close().await;
+        let names = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| body["tags"]["name"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["web-0", "web-1"]);
+
+        sink.send(&SentryEvent::from(Event::default())).await;
+        assert_eq!(received.lock().unwrap().len(), 2, "dropped after close");
+    }
+}
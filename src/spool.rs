@@ -0,0 +1,138 @@
+use log::{debug, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A disk-backed queue of envelopes which could not be delivered.
+/// Each envelope is stored in its own file, named after the spool time so that
+/// the lexical order is the chronological one. When the spool exceeds its maximum
+/// size the oldest envelopes are evicted; expired envelopes are never replayed.
+pub struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+    sequence: AtomicU64,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf, max_size: u64, max_age: Duration) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            max_size,
+            max_age,
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    pub fn push(&self, body: &[u8]) -> io::Result<()> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{:020}-{:06}.envelope", millis, sequence % 1_000_000);
+
+        // Written to a temporary file first, so that partial envelopes are never replayed.
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, body)?;
+        fs::rename(tmp, self.dir.join(name))?;
+
+        self.evict();
+        Ok(())
+    }
+
+    /// Returns the oldest (not expired) envelope. It stays in the spool until removed.
+    pub fn oldest(&self) -> Option<(PathBuf, Vec<u8>)> {
+        self.evict();
+        let (path, _) = self.entries().into_iter().next()?;
+        let body = fs::read(&path).ok()?;
+
+        Some((path, body))
+    }
+
+    pub fn remove(&self, path: &Path) {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Cannot remove spooled envelope {}: {}", path.display(), e);
+        }
+    }
+
+    /// The spooled envelopes, oldest first, with their size.
+    fn entries(&self) -> Vec<(PathBuf, u64)> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+
+        let mut entries = dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "envelope"))
+            .map(|entry| {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+                (entry.path(), size)
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        entries
+    }
+
+    fn evict(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut entries = self.entries();
+        let mut total: u64 = entries.iter().map(|(_, size)| size).sum();
+
+        entries.retain(|(path, size)| {
+            let fresh = spooled_at(path).is_some_and(|at| now.saturating_sub(at) <= self.max_age);
+            if !fresh || total > self.max_size {
+                debug!(target: "sentry_kubernetes::spool", "Evicting spooled envelope {}", path.display());
+                total -= size;
+                let _ = fs::remove_file(path);
+                return false;
+            }
+
+            true
+        });
+    }
+}
+
+/// Reads the spool time (since the unix epoch) from the file name.
+fn spooled_at(path: &Path) -> Option<Duration> {
+    let name = path.file_name()?.to_str()?;
+    let (millis, _) = name.split_once('-')?;
+
+    Some(Duration::from_millis(millis.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::Spool;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_spool() {
+        let dir = std::env::temp_dir().join(format!("spool-test-{}", std::process::id()));
+        let spool = Spool::new(dir.clone(), 12, Duration::from_secs(3600)).unwrap();
+        assert!(spool.oldest().is_none());
+
+        spool.push(b"first").unwrap();
+        spool.push(b"second").unwrap();
+        // "first" is evicted: the spool would exceed 12 bytes.
+        spool.push(b"third").unwrap();
+
+        let (path, body) = spool.oldest().unwrap();
+        assert_eq!(body, b"second");
+        spool.remove(&path);
+        assert_eq!(spool.oldest().unwrap().1, b"third");
+
+        fs::write(dir.join("00000000000000000001-000000.envelope"), b"old").unwrap();
+        assert_eq!(spool.oldest().unwrap().1, b"third");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use crate::cache::TtlCache;
+use crate::sentry_event::SentryEvent;
+use sentry::Level;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Number of interrupted nodes remembered, and for how long their pods' failures are tagged.
+const INTERRUPTED_CACHE_SIZE: usize = 1000;
+const INTERRUPTED_TTL: Duration = Duration::from_secs(1800);
+
+/// The reasons of the events announcing the interruption of a node (Karpenter, the AWS node
+/// termination handler, the preemption of GKE VMs).
+const INTERRUPTION_REASONS: [&str; 7] = [
+    "SpotInterrupted",
+    "SpotInterruption",
+    "SpotRebalanceRecommendation",
+    "RebalanceRecommendation",
+    "InstanceTerminating",
+    "InstanceStopping",
+    "PreemptScheduled",
+];
+
+/// The reasons of the pod events caused by the loss of their node.
+const NODE_LOSS_REASONS: [&str; 3] = ["NodeShutdown", "TaintManagerEviction", "NodeNotReady"];
+
+/// The labels of the spot (or preemptible) nodes, and their value.
+const SPOT_LABELS: [(&str, &str); 5] = [
+    ("karpenter.sh/capacity-type", "spot"),
+    ("eks.amazonaws.com/capacityType", "SPOT"),
+    ("cloud.google.com/gke-spot", "true"),
+    ("cloud.google.com/gke-preemptible", "true"),
+    ("kubernetes.azure.com/scalesetpriority", "spot"),
+];
+
+/// Tags the interruption events of the spot nodes, and the failures of the pods of the interrupted
+/// nodes, with `spot_interruption=true`: spot churn is expected and should not look like an
+/// application failure. Their level is optionally demoted.
+pub struct SpotInterruptions {
+    /// The nodes recently interrupted.
+    interrupted: Mutex<TtlCache<String, ()>>,
+    level: Option<Level>,
+}
+
+impl SpotInterruptions {
+    /// The tagged events are demoted to the given level, if any.
+    pub fn new(level: Option<Level>) -> Self {
+        Self {
+            interrupted: Mutex::new(TtlCache::new(INTERRUPTED_CACHE_SIZE, INTERRUPTED_TTL)),
+            level,
+        }
+    }
+
+    /// Tags the event if related to a spot interruption. The event must be enriched with its node.
+    pub fn tag(&self, sentry_event: &mut SentryEvent) {
+        let node = match sentry_event.kind.as_deref() {
+            Some("Node") => Some(sentry_event.name.clone()),
+            _ => sentry_event.source_host.clone(),
+        };
+
+        let interrupted = if INTERRUPTION_REASONS.contains(&sentry_event.reason.as_str()) {
+            if let Some(node) = node {
+                self.interrupted.lock().unwrap().insert(node, ());
+            }
+            true
+        } else if sentry_event.kind.as_deref() == Some("Pod") {
+            let node_interrupted = match node {
+                Some(node) => self.interrupted.lock().unwrap().get(&node).is_some(),
+                None => false,
+            };
+            node_interrupted
+                || (is_spot(&sentry_event.node_labels)
+                    && NODE_LOSS_REASONS.contains(&sentry_event.reason.as_str()))
+        } else {
+            false
+        };
+        if !interrupted {
+            return;
+        }
+
+        sentry_event
+            .tags
+            .insert("spot_interruption".to_string(), "true".to_string());
+        if let Some(level) = self.level {
+            sentry_event.level = sentry_event.level.min(level);
+        }
+    }
+}
+
+fn is_spot(node_labels: &BTreeMap<String, String>) -> bool {
+    SPOT_LABELS
+        .iter()
+        .any(|(label, value)| node_labels.get(*label).map(String::as_str) == Some(*value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sentry_event::SentryEvent;
+    use crate::spot::SpotInterruptions;
+    use k8s_openapi::api::core::v1::{Event, EventSource, ObjectReference};
+    use sentry::Level;
+
+    fn event(kind: &str, name: &str, reason: &str, host: &str) -> SentryEvent {
+        SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some(kind.to_string()),
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            reason: Some(reason.to_string()),
+            type_: Some("Warning".to_string()),
+            source: Some(EventSource {
+                host: Some(host.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    pub fn test_tag() {
+        let spot = SpotInterruptions::new(Some(Level::Info));
+        let mut failure = event("Pod", "web-0", "BackOff", "node-1");
+        spot.tag(&mut failure);
+        assert!(!failure.tags.contains_key("spot_interruption"));
+
+        let mut interruption = event("Node", "node-1", "SpotInterrupted", "");
+        spot.tag(&mut interruption);
+        assert_eq!(interruption.tags["spot_interruption"], "true");
+        assert_eq!(interruption.level, Level::Info);
+
+        let mut failure = event("Pod", "web-0", "BackOff", "node-1");
+        spot.tag(&mut failure);
+        assert_eq!(failure.tags["spot_interruption"], "true");
+        assert_eq!(failure.level, Level::Info);
+
+        let mut other = event("Pod", "web-1", "NodeNotReady", "node-2");
+        spot.tag(&mut other);
+        assert!(!other.tags.contains_key("spot_interruption"));
+        other
+            .node_labels
+            .insert("karpenter.sh/capacity-type".to_string(), "spot".to_string());
+        spot.tag(&mut other);
+        assert_eq!(other.tags["spot_interruption"], "true");
+    }
+}
@@ -0,0 +1,698 @@
+use crate::admin::ADMIN;
+use crate::assign::OWNER_ANNOTATION;
+use crate::autoscaler::AutoscalerGroups;
+use crate::cache::TtlCache;
+use crate::checkpoint::Checkpoint;
+use crate::config::DsnSource;
+use crate::enrich::Enricher;
+use crate::environment::EnvironmentResolver;
+use crate::filter::FilterChain;
+use crate::kube_api::KubeApi;
+use crate::logging;
+use crate::metrics::METRICS;
+use crate::policy::{Decision, Policy};
+use crate::processor::Verdict;
+use crate::routing::{annotated_dsn, Router};
+use crate::secrets::SecretStore;
+use crate::sentry_event::{event_time, SentryEvent};
+use crate::shard::Shard;
+use crate::sink::EventSink;
+use crate::spot::SpotInterruptions;
+use async_trait::async_trait;
+use futures::future::join_all;
+use futures::{stream, Stream, StreamExt};
+use k8s_openapi::api::core::v1::Event;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Maximum number of controllers walked up looking for annotations (ex: the DSN).
+const MAX_OWNER_DEPTH: usize = 4;
+/// Number of processed events remembered to discard the duplicates.
+pub(crate) const DEDUPE_CACHE_SIZE: usize = 10_000;
+pub(crate) const DEDUPE_TTL: Duration = Duration::from_secs(3600);
+
+/// An event going through the stages, with what the previous stages decided about it.
+/// The discarded events are passed on untouched to the end of the pipeline, where they are traced.
+pub struct Item {
+    pub event: SentryEvent,
+    /// The time of the kubernetes event, recorded in the checkpoint once the event is processed.
+    pub time: Option<DateTime<Utc>>,
+    /// The decision of the policy, if any.
+    pub decision: Decision,
+    pub verdict: Verdict,
+}
+
+impl Item {
+    pub fn new(event: SentryEvent) -> Self {
+        Self {
+            event,
+            time: None,
+            decision: Decision::default(),
+            verdict: Verdict::Send,
+        }
+    }
+
+    pub fn is_discarded(&self) -> bool {
+        matches!(self.verdict, Verdict::Discard(_))
+    }
+}
+
+/// A stage of the pipeline, run by [`run`] on a single event or by [`connect`] on the events of a
+/// channel.
+#[async_trait]
+pub trait Stage: Send + Sync {
+    /// The name of the stage in the metrics.
+    fn name(&self) -> &'static str;
+
+    /// Processes an event not discarded by the previous stages.
+    async fn process(&self, item: Item) -> Item;
+}
+
+/// Runs the stage on the event, unless discarded, recording the time spent in it.
+/// The fields of the event are added to the logs written meanwhile.
+pub async fn run(stage: &(impl Stage + ?Sized), item: Item) -> Item {
+    if item.is_discarded() {
+        return item;
+    }
+
+    let fields = logging::event_fields(&item.event);
+    timed(
+        stage.name(),
+        logging::with_fields(fields, stage.process(item)),
+    )
+    .await
+}
+
+/// Runs the stage on the events received from `input`, up to `concurrency` at the same time,
+/// and sends them to `output`. Ends once `input` is closed and drained, closing `output`.
+/// A stage waiting for room in `output` stops reading `input`: the bounded channels propagate
+/// the backpressure of a slow stage (ex: a sink) to the previous ones.
+pub async fn connect(
+    stage: &(impl Stage + ?Sized),
+    input: mpsc::Receiver<Item>,
+    output: mpsc::Sender<Item>,
+    concurrency: usize,
+) {
+    receive(input)
+        .for_each_concurrent(concurrency.max(1), |item| {
+            let output = output.clone();
+            async move {
+                let _ = output.send(run(stage, item).await).await;
+            }
+        })
+        .await;
+}
+
+/// The items of the channel, as a stream.
+pub fn receive(receiver: mpsc::Receiver<Item>) -> impl Stream<Item = Item> {
+    stream::unfold(receiver, |mut receiver| async move {
+        let item = receiver.recv().await?;
+        Some((item, receiver))
+    })
+}
+
+/// Runs a stage, recording the time spent in it.
+pub(crate) async fn timed<T>(
+    stage: &'static str,
+    future: impl std::future::Future<Output = T>,
+) -> T {
+    let start = Instant::now();
+    let result = future.await;
+    METRICS.stage_duration(stage, start.elapsed());
+
+    result
+}
+
+/// Converts the kubernetes events, discarding the events of the namespaces of other shards and
+/// those already processed.
+pub struct DedupeStage {
+    cluster: String,
+    /// If set, only the events of the namespaces of the shard are processed.
+    shard: Option<Shard>,
+    /// Uid and resource version of the recently processed events.
+    seen: Mutex<TtlCache<(String, String), ()>>,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl DedupeStage {
+    pub fn new(cluster: &str, shard: Option<Shard>, checkpoint: Option<Checkpoint>) -> Self {
+        Self {
+            cluster: cluster.to_string(),
+            shard,
+            seen: Mutex::new(TtlCache::new(DEDUPE_CACHE_SIZE, DEDUPE_TTL)),
+            checkpoint,
+        }
+    }
+
+    pub fn shard(&self) -> Option<Shard> {
+        self.shard
+    }
+
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    /// Returns true if the events of the namespace are handled by the shard.
+    pub fn owns(&self, namespace: &str) -> bool {
+        match self.shard {
+            Some(shard) => shard.owns(namespace),
+            None => true,
+        }
+    }
+
+    pub fn process(&self, event: Event) -> Item {
+        METRICS.event_received();
+        let time = event_time(&event);
+        let verdict = if !self.owns(event.metadata.namespace.as_deref().unwrap_or_default()) {
+            Verdict::Discard("shard")
+        } else if !self.is_new(&event) {
+            Verdict::Discard("duplicate")
+        } else {
+            Verdict::Send
+        };
+
+        let mut sentry_event = SentryEvent::from(event);
+        sentry_event.cluster = self.cluster.clone();
+        Item {
+            time,
+            verdict,
+            ..Item::new(sentry_event)
+        }
+    }
+
+    /// Converts the events of the stream and sends them to `output`, closing it once the stream ends.
+    pub async fn connect(&self, events: impl Stream<Item = Event>, output: mpsc::Sender<Item>) {
+        futures::pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let item = timed("dedupe", async { self.process(event) }).await;
+            if output.send(item).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Returns false if the event (same uid and resource version) has already been processed,
+    /// ex: when the watcher relists the events after a restart, or if it precedes the checkpoint.
+    pub fn is_new(&self, event: &Event) -> bool {
+        if self
+            .checkpoint
+            .as_ref()
+            .is_some_and(|c| c.is_processed(event))
+        {
+            return false;
+        }
+
+        let (Some(uid), Some(version)) = (&event.metadata.uid, &event.metadata.resource_version)
+        else {
+            return true;
+        };
+
+        let key = (uid.clone(), version.clone());
+        let mut seen = self.seen.lock().unwrap();
+        if seen.get(&key).is_some() {
+            return false;
+        }
+
+        seen.insert(key, ());
+        true
+    }
+}
+
+/// Adds the details of the involved objects (ex: the pod, the node) and the cluster to the events.
+pub struct EnrichStage {
+    cluster: String,
+    enricher: Enricher,
+    /// If false, only the cluster, the cluster-autoscaler groups and the spot interruptions are set.
+    enabled: bool,
+    autoscaler: AutoscalerGroups,
+    spot: SpotInterruptions,
+}
+
+impl EnrichStage {
+    pub fn new(cluster: &str, enricher: Enricher, enabled: bool, spot: SpotInterruptions) -> Self {
+        Self {
+            cluster: cluster.to_string(),
+            enricher,
+            enabled,
+            autoscaler: Default::default(),
+            spot,
+        }
+    }
+}
+
+#[async_trait]
+impl Stage for EnrichStage {
+    fn name(&self) -> &'static str {
+        "enrich"
+    }
+
+    async fn process(&self, mut item: Item) -> Item {
+        let sentry_event = &mut item.event;
+        sentry_event.cluster = self.cluster.clone();
+        self.autoscaler.group(sentry_event);
+        if self.enabled {
+            self.enricher.enrich(sentry_event).await;
+        }
+        self.spot.tag(sentry_event);
+
+        item
+    }
+}
+
+/// Decides whether the events are sent, only recorded as breadcrumbs or discarded: the pause and
+/// the mutes of the admin API, then the policy, then the filters.
+pub struct FilterStage {
+    filters: FilterChain,
+    policy: Option<Policy>,
+}
+
+impl FilterStage {
+    pub fn new(filters: FilterChain, policy: Option<Policy>) -> Self {
+        Self { filters, policy }
+    }
+
+    /// The effective filters, as shown by the admin API.
+    pub fn describe(&self) -> Map<String, Value> {
+        self.filters.describe()
+    }
+
+    pub fn verdict(&self, sentry_event: &SentryEvent, decision: &Decision) -> Verdict {
+        if let Some(filter) = ADMIN.filter(&sentry_event.namespace, &sentry_event.reason) {
+            return Verdict::Discard(filter);
+        }
+        if decision.drop {
+            return Verdict::Discard("policy");
+        }
+        if decision.allow {
+            return Verdict::Send;
+        }
+        self.filters.filter(sentry_event)
+    }
+}
+
+#[async_trait]
+impl Stage for FilterStage {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    async fn process(&self, mut item: Item) -> Item {
+        if let Some(policy) = self.policy.as_ref() {
+            item.decision = timed("policy", async { policy.evaluate(&item.event) }).await;
+        }
+        if let Some(level) = item.decision.level {
+            item.event.level = level;
+        }
+
+        item.verdict = self.verdict(&item.event, &item.decision);
+        item
+    }
+}
+
+/// Resolves the environment and the DSNs the events to send are sent to: the DSNs decided by the
+/// policy if any, else the annotated or the routed ones.
+pub struct RouteStage {
+    environment: EnvironmentResolver,
+    router: Router,
+    annotation_routing: bool,
+    issue_owners: bool,
+    kube: Arc<dyn KubeApi>,
+    enricher: Enricher,
+    secrets: SecretStore,
+}
+
+impl RouteStage {
+    pub fn new(
+        environment: EnvironmentResolver,
+        router: Router,
+        enricher: Enricher,
+        kube: Arc<dyn KubeApi>,
+    ) -> Self {
+        Self {
+            environment,
+            router,
+            annotation_routing: false,
+            issue_owners: false,
+            secrets: SecretStore::new(kube.clone()),
+            kube,
+            enricher,
+        }
+    }
+
+    /// Routes the events to the DSN of the annotations of their objects, if any.
+    #[must_use]
+    pub fn annotation_routing(mut self, enabled: bool) -> Self {
+        self.annotation_routing = enabled;
+        self
+    }
+
+    /// Tags the events with the owner of the annotations of their objects, if any.
+    #[must_use]
+    pub fn issue_owners(mut self, enabled: bool) -> Self {
+        self.issue_owners = enabled;
+        self
+    }
+
+    pub async fn route(&self, sentry_event: &mut SentryEvent, policy_dsns: Vec<String>) {
+        sentry_event.environment = self.environment.resolve(&sentry_event.namespace);
+        if self.router.has_label_rules() {
+            let meta = self
+                .enricher
+                .lookup("object labels", self.object_metadata(sentry_event))
+                .await;
+            if let Some(meta) = meta {
+                sentry_event.object_labels = meta.labels.unwrap_or_default();
+            }
+        }
+
+        if self.issue_owners {
+            let owner = self
+                .enricher
+                .lookup(
+                    "owner annotations",
+                    self.annotation(sentry_event, |a| a.get(OWNER_ANNOTATION).cloned()),
+                )
+                .await;
+            if let Some(owner) = owner {
+                sentry_event.tags.insert("owner".to_string(), owner);
+            }
+        }
+
+        let mut routes = self.router.route(sentry_event);
+        if !policy_dsns.is_empty() {
+            routes = policy_dsns.into_iter().map(DsnSource::Inline).collect();
+        } else if self.annotation_routing {
+            let namespace = sentry_event.namespace.clone();
+            let annotated = self
+                .enricher
+                .lookup(
+                    "dsn annotations",
+                    self.annotation(sentry_event, |a| annotated_dsn(a, &namespace)),
+                )
+                .await;
+            if let Some(annotated) = annotated {
+                routes = vec![annotated];
+            }
+        }
+
+        sentry_event.dsns = vec![];
+        for route in &routes {
+            sentry_event.dsns.extend(self.secrets.resolve(route).await);
+        }
+    }
+
+    async fn object_metadata(&self, event: &SentryEvent) -> Option<ObjectMeta> {
+        self.kube
+            .metadata(
+                event.api_version.as_deref()?,
+                event.kind.as_deref()?,
+                &event.namespace,
+                &event.name,
+            )
+            .await
+    }
+
+    /// Reads an annotation (ex: the DSN) of the involved object and its controllers
+    /// (ex: Pod -> ReplicaSet -> Deployment), then of the namespace.
+    async fn annotation<T>(
+        &self,
+        event: &SentryEvent,
+        read: impl Fn(&BTreeMap<String, String>) -> Option<T>,
+    ) -> Option<T> {
+        let mut object = self.object_metadata(event).await;
+        for _ in 0..MAX_OWNER_DEPTH {
+            let Some(meta) = object else {
+                break;
+            };
+
+            if let Some(value) = meta.annotations.as_ref().and_then(&read) {
+                return Some(value);
+            }
+
+            let Some(owner) = meta
+                .owner_references
+                .unwrap_or_default()
+                .into_iter()
+                .find(|o| o.controller == Some(true))
+            else {
+                break;
+            };
+
+            object = self
+                .kube
+                .metadata(
+                    &owner.api_version,
+                    &owner.kind,
+                    &event.namespace,
+                    &owner.name,
+                )
+                .await;
+        }
+
+        let namespace = self
+            .kube
+            .metadata("v1", "Namespace", "", &event.namespace)
+            .await?;
+
+        read(&namespace.annotations?)
+    }
+}
+
+#[async_trait]
+impl Stage for RouteStage {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    async fn process(&self, mut item: Item) -> Item {
+        if item.verdict == Verdict::Send {
+            let dsns = std::mem::take(&mut item.decision.dsn);
+            self.route(&mut item.event, dsns).await;
+        }
+
+        item
+    }
+}
+
+/// Sends the events to all the sinks.
+pub struct SinkStage {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl SinkStage {
+    pub fn new(sinks: Vec<Box<dyn EventSink>>) -> Self {
+        Self { sinks }
+    }
+
+    /// Flushes the events buffered by the sinks.
+    pub async fn close(&self) {
+        join_all(self.sinks.iter().map(|sink| sink.close())).await;
+    }
+}
+
+#[async_trait]
+impl Stage for SinkStage {
+    fn name(&self) -> &'static str {
+        "sink"
+    }
+
+    async fn process(&self, item: Item) -> Item {
+        if item.verdict == Verdict::Send {
+            log::debug!("sending event to sinks");
+            METRICS.event_sent();
+            join_all(self.sinks.iter().map(|sink| sink.send(&item.event))).await;
+        }
+
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enrich::Enricher;
+    use crate::filter::{Filter, FilterChain, LevelFilter};
+    use crate::kube_api::FakeKube;
+    use crate::policy::Decision;
+    use crate::processor::Verdict;
+    use crate::routing::Router;
+    use crate::sentry_event::SentryEvent;
+    use crate::shard::Shard;
+    use crate::spot::SpotInterruptions;
+    use crate::stages::{
+        connect, receive, DedupeStage, EnrichStage, FilterStage, Item, RouteStage, SinkStage, Stage,
+    };
+    use futures::{stream, StreamExt};
+    use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod, PodSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::Utc;
+    use sentry::Level;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn event(uid: &str, version: &str) -> Event {
+        Event {
+            involved_object: ObjectReference {
+                api_version: Some("v1".to_string()),
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            last_timestamp: Some(Time(Utc::now())),
+            metadata: ObjectMeta {
+                namespace: Some("shop".to_string()),
+                uid: Some(uid.to_string()),
+                resource_version: Some(version.to_string()),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn item(level: Level) -> Item {
+        let mut item = Item::new(SentryEvent::from(event("a", "1")));
+        item.event.level = level;
+        item
+    }
+
+    /// Runs the stage over a channel, one item at a time.
+    async fn pipe(stage: &impl Stage, items: Vec<Item>) -> Vec<Item> {
+        let (input, received) = mpsc::channel(items.len().max(1));
+        let (output, sent) = mpsc::channel(items.len().max(1));
+        for item in items {
+            input.send(item).await.unwrap();
+        }
+        drop(input);
+
+        connect(stage, received, output, 1).await;
+        receive(sent).collect().await
+    }
+
+    #[tokio::test]
+    pub async fn test_dedupe_stage() {
+        let stage = DedupeStage::new("prod", None, None);
+        let events = vec![event("a", "1"), event("a", "1"), event("a", "2")];
+        let (output, sent) = mpsc::channel(1);
+
+        let (_, items) = futures::join!(
+            stage.connect(stream::iter(events), output),
+            receive(sent).collect::<Vec<_>>()
+        );
+        let verdicts: Vec<_> = items.iter().map(|i| &i.verdict).collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                &Verdict::Send,
+                &Verdict::Discard("duplicate"),
+                &Verdict::Send
+            ]
+        );
+        assert_eq!(items[0].event.cluster, "prod");
+        assert!(items[0].time.is_some());
+
+        // The namespace is owned by one of the shards, discarded by the other.
+        let verdicts: Vec<_> = (0..2)
+            .map(|index| DedupeStage::new("prod", Some(Shard::new(index, 2)), None))
+            .map(|stage| stage.process(event("b", "1")).verdict)
+            .collect();
+        assert!(verdicts.contains(&Verdict::Send));
+        assert!(verdicts.contains(&Verdict::Discard("shard")));
+    }
+
+    #[tokio::test]
+    pub async fn test_enrich_stage() {
+        let kube = FakeKube {
+            pods: vec![Pod {
+                metadata: ObjectMeta {
+                    name: Some("web-0".to_string()),
+                    namespace: Some("shop".to_string()),
+                    ..Default::default()
+                },
+                spec: Some(PodSpec {
+                    node_name: Some("node-1".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let enricher = Enricher::new(Arc::new(kube), Duration::from_secs(1));
+        let stage = EnrichStage::new("prod", enricher, true, SpotInterruptions::new(None));
+
+        let mut discarded = item(Level::Warning);
+        discarded.verdict = Verdict::Discard("namespace");
+        let items = pipe(&stage, vec![item(Level::Warning), discarded]).await;
+        assert_eq!(items[0].event.cluster, "prod");
+        assert_eq!(items[0].event.source_host.as_deref(), Some("node-1"));
+        assert_eq!(
+            items[1].event.source_host, None,
+            "discarded events are passed on as is"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_filter_stage() {
+        let filters: Vec<Box<dyn Filter>> = vec![Box::new(LevelFilter {
+            levels: vec!["warning".to_string()],
+        })];
+        let stage = FilterStage::new(FilterChain::from(filters), None);
+
+        let items = pipe(&stage, vec![item(Level::Warning), item(Level::Info)]).await;
+        assert_eq!(items[0].verdict, Verdict::Send);
+        assert_eq!(items[1].verdict, Verdict::Breadcrumb);
+
+        let allow = Decision {
+            allow: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            stage.verdict(&item(Level::Info).event, &allow),
+            Verdict::Send
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_route_stage() {
+        let kube = Arc::new(FakeKube::default());
+        let enricher = Enricher::new(kube.clone(), Duration::from_secs(1));
+        let stage = RouteStage::new(Default::default(), Router::default(), enricher, kube);
+
+        let dsn = "https://public@sentry.example.com/4".to_string();
+        let mut routed = item(Level::Warning);
+        routed.decision.dsn = vec![dsn.clone()];
+        let mut breadcrumb = item(Level::Info);
+        breadcrumb.decision.dsn = vec![dsn.clone()];
+        breadcrumb.verdict = Verdict::Breadcrumb;
+
+        let items = pipe(&stage, vec![routed, breadcrumb]).await;
+        assert_eq!(items[0].event.dsns, vec![dsn]);
+        assert!(
+            items[1].event.dsns.is_empty(),
+            "only the events to send are routed"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_sink_stage() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sink_sent = sent.clone();
+        let stage = SinkStage::new(vec![Box::new(move |_: &SentryEvent| {
+            sink_sent.fetch_add(1, Ordering::SeqCst);
+        })]);
+
+        let mut breadcrumb = item(Level::Info);
+        breadcrumb.verdict = Verdict::Breadcrumb;
+        let items = pipe(&stage, vec![item(Level::Warning), breadcrumb]).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+    }
+}
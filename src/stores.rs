@@ -0,0 +1,111 @@
+use futures::prelude::*;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// Local caches of the Pods and Nodes of the cluster, kept up to date by reflectors.
+/// The processor reads the objects from here instead of fetching them on each event.
+pub struct ObjectStores {
+    client: Client,
+    /// The pods reflectors by namespace, or a single cluster-wide one (with an empty key).
+    pods: HashMap<String, Reflected<Pod>>,
+    nodes: Reflected<Node>,
+}
+
+impl ObjectStores {
+    /// Starts the reflectors. They are stopped when the stores are dropped.
+    /// If namespaces are given, the pods are only watched in those namespaces.
+    pub fn start(client: Client, namespaces: &[String]) -> Self {
+        let pods = if namespaces.is_empty() {
+            [(String::new(), Reflected::start(Api::all(client.clone())))].into()
+        } else {
+            namespaces
+                .iter()
+                .map(|ns| {
+                    let api = Api::namespaced(client.clone(), ns);
+                    (ns.clone(), Reflected::start(api))
+                })
+                .collect()
+        };
+
+        Self {
+            pods,
+            nodes: Reflected::start(Api::all(client.clone())),
+            client,
+        }
+    }
+
+    pub async fn pod(&self, namespace: &str, name: &str) -> Option<Arc<Pod>> {
+        let api = Api::namespaced(self.client.clone(), namespace);
+        let key = ObjectRef::new(name).within(namespace);
+        match self.pods.get(namespace).or_else(|| self.pods.get("")) {
+            Some(pods) => pods.get(key, api).await,
+            None => api.get(name).await.ok().map(Arc::new),
+        }
+    }
+
+    pub async fn node(&self, name: &str) -> Option<Arc<Node>> {
+        let api = Api::all(self.client.clone());
+        self.nodes.get(ObjectRef::new(name), api).await
+    }
+}
+
+/// A reflector store. Until the initial listing is completed, the objects are fetched from the API server.
+struct Reflected<K: Resource<DynamicType = ()> + 'static> {
+    store: Store<K>,
+    ready: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl<K> Reflected<K>
+where
+    K: Resource<DynamicType = ()> + Clone + Debug + DeserializeOwned + Send + Sync + 'static,
+{
+    fn start(api: Api<K>) -> Self {
+        let (store, writer) = reflector::store();
+        let ready = Arc::new(AtomicBool::new(false));
+
+        let listed = ready.clone();
+        let stream = watcher(api, Default::default())
+            .default_backoff()
+            .modify(|object: &mut K| object.managed_fields_mut().clear());
+        let task = tokio::spawn(
+            reflector::reflector(writer, stream).for_each(move |event| {
+                match event {
+                    Ok(watcher::Event::Restarted(objects)) => {
+                        debug!(target: "sentry_kubernetes::stores", "Listed {} {}", objects.len(), K::kind(&()));
+                        listed.store(true, Ordering::Relaxed);
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("{} reflector error: {}", K::kind(&()), e),
+                }
+
+                future::ready(())
+            }),
+        );
+
+        Self { store, ready, task }
+    }
+
+    async fn get(&self, key: ObjectRef<K>, api: Api<K>) -> Option<Arc<K>> {
+        if self.ready.load(Ordering::Relaxed) {
+            return self.store.get(&key);
+        }
+
+        api.get(&key.name).await.ok().map(Arc::new)
+    }
+}
+
+impl<K: Resource<DynamicType = ()> + 'static> Drop for Reflected<K> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
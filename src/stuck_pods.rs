@@ -0,0 +1,320 @@
+use crate::sentry_event::SentryEvent;
+use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{Api, Client};
+use log::warn;
+use sentry::types::Uuid;
+use sentry::Level;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a pod can be Pending or not Ready before being reported, and how often it is reported
+/// again while it stays in that state.
+#[derive(Clone, Copy, Debug)]
+pub struct StuckPodThresholds {
+    pub pending: Duration,
+    pub not_ready: Duration,
+    pub realert_interval: Duration,
+}
+
+/// Reports the pods stuck Pending or not Ready, and reports them again periodically while they stay
+/// stuck: the events about a long-standing problem expire, and kubernetes may stop emitting new ones.
+/// Each report escalates the level: warning, then error, then fatal.
+pub struct StuckPodWatcher {
+    client: Client,
+    thresholds: StuckPodThresholds,
+    /// The stuck pods by uid.
+    stuck: Mutex<HashMap<String, Stuck>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Pending,
+    NotReady,
+}
+
+struct Stuck {
+    pod: Pod,
+    state: State,
+    since: DateTime<Utc>,
+    alerts: u32,
+    alerted_at: Option<Instant>,
+}
+
+impl StuckPodWatcher {
+    pub fn new(client: Client, thresholds: StuckPodThresholds) -> Self {
+        Self {
+            client,
+            thresholds,
+            stuck: Default::default(),
+        }
+    }
+
+    /// Records whether the pod is Pending or not Ready. The pods are reported by the periodic checks.
+    pub fn update(&self, pod: &Pod) {
+        let Some(uid) = pod.metadata.uid.clone() else {
+            return;
+        };
+
+        let mut stuck = self.stuck.lock().unwrap();
+        match pod_state(pod) {
+            Some((state, since)) => {
+                let entry = stuck.entry(uid).or_insert_with(|| Stuck {
+                    pod: pod.clone(),
+                    state,
+                    since,
+                    alerts: 0,
+                    alerted_at: None,
+                });
+                if entry.state != state {
+                    entry.state = state;
+                    entry.since = since;
+                    entry.alerts = 0;
+                    entry.alerted_at = None;
+                }
+                entry.pod = pod.clone();
+            }
+            None => {
+                stuck.remove(&uid);
+            }
+        }
+    }
+
+    /// The events of the pods stuck longer than the threshold, not reported within the interval.
+    pub async fn due(&self) -> Vec<SentryEvent> {
+        let now = Utc::now();
+        let due = {
+            let mut stuck = self.stuck.lock().unwrap();
+            stuck
+                .values_mut()
+                .filter(|s| self.is_due(s, now))
+                .map(|s| {
+                    s.alerts += 1;
+                    s.alerted_at = Some(Instant::now());
+                    (s.pod.clone(), s.state, s.since, s.alerts)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut events = vec![];
+        for (pod, state, since, alerts) in due {
+            // The deletions are not notified: check that the pod still exists.
+            let (Some(namespace), Some(name)) = (&pod.metadata.namespace, &pod.metadata.name)
+            else {
+                continue;
+            };
+            let api: Api<Pod> = Api::namespaced(self.client.clone(), namespace);
+            match api.get_opt(name).await {
+                Ok(Some(pod)) if pod_state(&pod).is_some_and(|(s, _)| s == state) => {
+                    events.push(stuck_event(&pod, state, since, alerts, now));
+                }
+                Ok(_) => {
+                    if let Some(uid) = pod.metadata.uid.as_ref() {
+                        self.stuck.lock().unwrap().remove(uid);
+                    }
+                }
+                Err(e) => warn!("Cannot read the pod {}/{}: {}", namespace, name, e),
+            }
+        }
+
+        events
+    }
+
+    fn is_due(&self, stuck: &Stuck, now: DateTime<Utc>) -> bool {
+        let threshold = match stuck.state {
+            State::Pending => self.thresholds.pending,
+            State::NotReady => self.thresholds.not_ready,
+        };
+        let stuck_for = (now - stuck.since).to_std().unwrap_or_default();
+        if stuck_for < threshold {
+            return false;
+        }
+
+        match stuck.alerted_at {
+            Some(alerted_at) => alerted_at.elapsed() >= self.thresholds.realert_interval,
+            None => true,
+        }
+    }
+}
+
+/// Whether the pod is Pending or running but not Ready, and since when.
+fn pod_state(pod: &Pod) -> Option<(State, DateTime<Utc>)> {
+    let status = pod.status.as_ref()?;
+    match status.phase.as_deref() {
+        Some("Pending") => {
+            let since = pod.metadata.creation_timestamp.as_ref()?.0;
+            Some((State::Pending, since))
+        }
+        Some("Running") => {
+            let ready = status
+                .conditions
+                .as_ref()?
+                .iter()
+                .find(|c| c.type_ == "Ready")?;
+            if ready.status == "True" {
+                return None;
+            }
+
+            let since = ready
+                .last_transition_time
+                .as_ref()
+                .or(pod.metadata.creation_timestamp.as_ref())?
+                .0;
+            Some((State::NotReady, since))
+        }
+        _ => None,
+    }
+}
+
+/// The level of the n-th report of a stuck pod.
+fn escalated_level(alerts: u32) -> Level {
+    match alerts {
+        0 | 1 => Level::Warning,
+        2 => Level::Error,
+        _ => Level::Fatal,
+    }
+}
+
+/// An event of the stuck pod, with the number of reports in the "stuck" context.
+fn stuck_event(
+    pod: &Pod,
+    state: State,
+    since: DateTime<Utc>,
+    alerts: u32,
+    now: DateTime<Utc>,
+) -> SentryEvent {
+    let name = pod.metadata.name.clone().unwrap_or_default();
+    let minutes = (now - since).num_minutes();
+    let (reason, message) = match state {
+        State::Pending => (
+            "PodStuckPending",
+            format!("Pod {} has been Pending for {} minutes", name, minutes),
+        ),
+        State::NotReady => (
+            "PodStuckNotReady",
+            format!("Pod {} has not been Ready for {} minutes", name, minutes),
+        ),
+    };
+    let event = Event {
+        metadata: ObjectMeta {
+            namespace: pod.metadata.namespace.clone(),
+            uid: Some(Uuid::new_v4().to_string()),
+            creation_timestamp: Some(Time(now)),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: Some(name),
+            namespace: pod.metadata.namespace.clone(),
+            uid: pod.metadata.uid.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message),
+        type_: Some("Warning".to_string()),
+        reporting_component: Some("sentry-kubernetes".to_string()),
+        last_timestamp: Some(Time(now)),
+        ..Default::default()
+    };
+
+    let status = pod.status.as_ref();
+    let waiting = status
+        .and_then(|s| s.container_statuses.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|c| {
+            let waiting = c.state.as_ref()?.waiting.as_ref()?;
+            Some(json!({ "container": c.name, "reason": waiting.reason, "message": waiting.message }))
+        })
+        .collect::<Vec<_>>();
+    let mut sentry_event = SentryEvent::from(event);
+    sentry_event.level = escalated_level(alerts);
+    sentry_event.source_host = pod.spec.as_ref().and_then(|s| s.node_name.clone());
+    sentry_event.contexts.insert(
+        "stuck".to_string(),
+        BTreeMap::from([
+            ("since".to_string(), json!(since)),
+            ("alerts".to_string(), json!(alerts)),
+            (
+                "phase".to_string(),
+                json!(status.and_then(|s| s.phase.clone())),
+            ),
+            (
+                "conditions".to_string(),
+                json!(status.and_then(|s| s.conditions.clone())),
+            ),
+            ("waitingContainers".to_string(), json!(waiting)),
+        ]),
+    );
+
+    sentry_event
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stuck_pods::{escalated_level, pod_state, stuck_event, State};
+    use k8s_openapi::api::core::v1::{Pod, PodCondition, PodStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+    use k8s_openapi::chrono::{DateTime, Duration, Utc};
+    use sentry::Level;
+
+    fn pod(phase: &str, ready: Option<&str>, time: DateTime<Utc>) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                creation_timestamp: Some(Time(time)),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                phase: Some(phase.to_string()),
+                conditions: ready.map(|status| {
+                    vec![PodCondition {
+                        type_: "Ready".to_string(),
+                        status: status.to_string(),
+                        last_transition_time: Some(Time(time + Duration::minutes(1))),
+                        ..Default::default()
+                    }]
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_pod_state() {
+        let time = Utc::now();
+        assert_eq!(
+            pod_state(&pod("Pending", None, time)),
+            Some((State::Pending, time))
+        );
+        assert_eq!(
+            pod_state(&pod("Running", Some("False"), time)),
+            Some((State::NotReady, time + Duration::minutes(1)))
+        );
+        assert_eq!(pod_state(&pod("Running", Some("True"), time)), None);
+        assert_eq!(pod_state(&pod("Succeeded", None, time)), None);
+    }
+
+    #[test]
+    pub fn test_stuck_event() {
+        let now = Utc::now();
+        let since = now - Duration::minutes(47);
+        let event = stuck_event(&pod("Pending", None, since), State::Pending, since, 2, now);
+        assert_eq!(event.reason, "PodStuckPending");
+        assert_eq!(
+            event.message.as_deref(),
+            Some("Pod web-0 has been Pending for 47 minutes")
+        );
+        assert_eq!(event.level, Level::Error);
+        assert_eq!(event.contexts["stuck"]["alerts"], 2);
+
+        assert_eq!(escalated_level(1), Level::Warning);
+        assert_eq!(escalated_level(5), Level::Fatal);
+    }
+}
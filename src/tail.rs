@@ -0,0 +1,71 @@
+use crate::processor::Verdict;
+use crate::sentry_event::SentryEvent;
+use sentry::protocol::Event;
+
+/// One line describing the decision of the pipeline on the event: the verdict (and the filter which
+/// discarded it), the object, the reason and level, then the fingerprint and the tags computed for
+/// sentry, and the number of DSNs the event is routed to.
+pub fn verdict_line(sentry_event: &SentryEvent, verdict: &Verdict) -> String {
+    let verdict = match verdict {
+        Verdict::Send => "SEND".to_string(),
+        Verdict::Breadcrumb => "BREADCRUMB (level)".to_string(),
+        Verdict::Discard(filter) => format!("DISCARD ({})", filter),
+    };
+
+    let event = Event::from(sentry_event);
+    let tags = event
+        .tags
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut line = format!(
+        "{:<20} {}/{} {} [{}] fingerprint={:?} tags={{{}}}",
+        verdict,
+        sentry_event.kind.as_deref().unwrap_or("-"),
+        sentry_event.obj_name(),
+        sentry_event.reason,
+        sentry_event.level,
+        sentry_event.fingerprint(),
+        tags,
+    );
+    if verdict == "SEND" {
+        line.push_str(&format!(" dsns={}", sentry_event.dsns.len()));
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::Verdict;
+    use crate::sentry_event::SentryEvent;
+    use crate::tail::verdict_line;
+    use k8s_openapi::api::core::v1::{Event, ObjectReference};
+
+    #[test]
+    pub fn test_verdict_line() {
+        let mut event = SentryEvent::from(Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".to_string()),
+                name: Some("web-0".to_string()),
+                namespace: Some("shop".to_string()),
+                ..Default::default()
+            },
+            reason: Some("BackOff".to_string()),
+            type_: Some("Warning".to_string()),
+            ..Default::default()
+        });
+
+        let line = verdict_line(&event, &Verdict::Discard("namespace"));
+        assert!(line.starts_with("DISCARD (namespace)"));
+        assert!(line.contains(" BackOff [warning] "));
+        assert!(line.contains("tags={kind=Pod,name=web-0,namespace=shop,reason=BackOff}"));
+        assert!(!line.contains("dsns="));
+
+        event.dsns = vec!["https://public@sentry.example.com/1".to_string()];
+        let line = verdict_line(&event, &Verdict::Send);
+        assert!(line.starts_with("SEND "));
+        assert!(line.ends_with(" dsns=1"));
+    }
+}
@@ -0,0 +1,630 @@
+use crate::attachment::AttachmentLimits;
+use crate::config::TransportConfig;
+use crate::digest::hex;
+use crate::metrics::METRICS;
+use crate::spool::Spool;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, log, warn, Level};
+use reqwest::header::{CONTENT_ENCODING, RETRY_AFTER};
+use reqwest::{Certificate, NoProxy, Proxy, StatusCode};
+use sentry::{ClientOptions, Envelope, Transport, TransportFactory};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Rate limit applied when sentry responds with 429 without any Retry-After header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Maximum delay between two delivery attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Interval of the replay attempts of the spooled envelopes, when idle.
+const REPLAY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Creates the transports of the sentry clients, with tunable queue size and timeouts.
+pub struct HttpTransportFactory {
+    config: TransportConfig,
+}
+
+impl HttpTransportFactory {
+    pub fn new(config: TransportConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl TransportFactory for HttpTransportFactory {
+    fn create_transport(&self, options: &ClientOptions) -> Arc<dyn Transport> {
+        Arc::new(HttpTransport::new(options, &self.config))
+    }
+}
+
+enum Task {
+    Send(Envelope),
+    Flush(SyncSender<()>),
+    Shutdown,
+}
+
+/// Sends the envelopes from a background thread.
+/// Envelopes are queued up to the configured size: when the queue is full they are
+/// dropped (with a warning) instead of blocking the event processing.
+/// If a spool is configured, envelopes which cannot be delivered (the retries are exhausted, or
+/// they are still queued when the shutdown times out) are persisted on disk and replayed once
+/// sentry is reachable again.
+pub struct HttpTransport {
+    sender: SyncSender<Task>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    spool: Option<Arc<Spool>>,
+    interrupt: Arc<Interrupt>,
+    limits: AttachmentLimits,
+}
+
+impl HttpTransport {
+    pub fn new(options: &ClientOptions, config: &TransportConfig) -> Self {
+        let dsn = options
+            .dsn
+            .as_ref()
+            .expect("transports are created for a dsn");
+        let url = dsn.envelope_api_url().to_string();
+        let spool = config.spool_dir.as_deref().and_then(|dir| {
+            // Each DSN has its own spool directory.
+            let dir = PathBuf::from(dir).join(&hex(&Sha256::digest(url.as_bytes()))[..16]);
+            match Spool::new(
+                dir,
+                config.spool_max_size,
+                Duration::from_secs(config.spool_max_age),
+            ) {
+                Ok(spool) => Some(Arc::new(spool)),
+                Err(e) => {
+                    warn!("Cannot initialize the envelope spool: {}", e);
+                    None
+                }
+            }
+        });
+
+        let interrupt = Arc::new(Interrupt::default());
+        let mut worker = Worker {
+            client: http_client(config),
+            url,
+            auth: dsn.to_auth(Some(&options.user_agent)).to_string(),
+            max_retries: config.max_retries,
+            disabled_until: None,
+            spool: spool.clone(),
+            interrupt: interrupt.clone(),
+            debug: options.debug,
+            compress: config.compression,
+        };
+
+        let (sender, receiver) = sync_channel(config.queue_size.max(1));
+        let handle = thread::Builder::new()
+            .name("sentry-transport".into())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+
+                runtime.block_on(async move {
+                    worker.replay().await;
+                    loop {
+                        match receiver.recv_timeout(REPLAY_INTERVAL) {
+                            Ok(Task::Send(envelope)) => worker.deliver(envelope).await,
+                            Ok(Task::Flush(done)) => {
+                                let _ = done.send(());
+                            }
+                            Ok(Task::Shutdown) | Err(RecvTimeoutError::Disconnected) => return,
+                            Err(RecvTimeoutError::Timeout) => worker.replay().await,
+                        }
+                    }
+                })
+            })
+            .ok();
+
+        Self {
+            sender,
+            handle: Mutex::new(handle),
+            spool,
+            interrupt,
+            limits: AttachmentLimits {
+                max_size: config.max_attachment_size,
+                max_total_size: config.max_attachments_size,
+            },
+        }
+    }
+}
+
+/// The outcome of a delivery attempt.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Sent,
+    /// Temporary failure: retry after the given delay (as requested by sentry), if any.
+    Retry(Option<Duration>),
+    /// The envelope has been rejected (ex: invalid payload), retrying won't help.
+    Rejected(StatusCode),
+}
+
+/// Stops the delivery attempts of the worker, when the transport is shut down before the queue is
+/// drained: the pending envelopes are spooled instead of retried.
+#[derive(Default)]
+struct Interrupt {
+    triggered: AtomicBool,
+    notify: Notify,
+}
+
+impl Interrupt {
+    fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    async fn triggered(&self) {
+        // Created before checking the flag, to be woken up by a concurrent trigger.
+        let notified = self.notify.notified();
+        if !self.triggered.load(Ordering::SeqCst) {
+            notified.await;
+        }
+    }
+}
+
+struct Worker {
+    client: reqwest::Client,
+    url: String,
+    auth: String,
+    max_retries: u32,
+    /// Set when sentry rate limits us: nothing is sent before this instant.
+    disabled_until: Option<Instant>,
+    spool: Option<Arc<Spool>>,
+    interrupt: Arc<Interrupt>,
+    /// Logs the delivery failures at warn level instead of debug.
+    debug: bool,
+    /// Gzip the request bodies.
+    compress: bool,
+}
+
+impl Worker {
+    /// Sends the envelope retrying with exponential backoff on temporary failures.
+    /// While waiting, the following envelopes are buffered in the queue.
+    /// The envelopes which cannot be delivered are spooled, if a spool is configured.
+    async fn deliver(&mut self, envelope: Envelope) {
+        let mut body = vec![];
+        if envelope.to_writer(&mut body).is_err() {
+            return;
+        }
+
+        let interrupt = self.interrupt.clone();
+        let outcome = tokio::select! {
+            biased;
+            _ = interrupt.triggered() => Outcome::Retry(None),
+            outcome = self.send_with_retries(&body) => outcome,
+        };
+
+        match outcome {
+            Outcome::Sent => self.replay().await,
+            Outcome::Rejected(status) => {
+                warn!(
+                    "Envelope rejected by sentry (status {}), discarding it",
+                    status
+                );
+                METRICS.send_failure("sentry");
+            }
+            Outcome::Retry(_) => match &self.spool {
+                Some(spool) => spool_envelope(spool, &body),
+                None => {
+                    warn!("Cannot send envelope to sentry, discarding it");
+                    METRICS.send_failure("sentry");
+                }
+            },
+        }
+    }
+
+    /// Returns the outcome of the last attempt: `Retry` if the retries are exhausted.
+    async fn send_with_retries(&mut self, body: &[u8]) -> Outcome {
+        let mut attempt = 0;
+        loop {
+            if let Some(until) = self.disabled_until.take() {
+                tokio::time::sleep_until(until.into()).await;
+            }
+
+            match self.send(body.to_vec()).await {
+                Outcome::Retry(_) if attempt >= self.max_retries => {
+                    debug!(target: "sentry_kubernetes::transport", "Cannot send envelope after {} attempts", attempt + 1);
+                    return Outcome::Retry(None);
+                }
+                Outcome::Retry(retry_after) => {
+                    let delay = retry_after.unwrap_or_else(|| backoff(attempt));
+                    debug!(target: "sentry_kubernetes::transport", "Retrying envelope in {}s", delay.as_secs());
+                    self.disabled_until = Some(Instant::now() + delay);
+                    attempt += 1;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Sends the spooled envelopes (oldest first) until sentry fails again, or the transport is
+    /// shut down.
+    async fn replay(&mut self) {
+        let Some(spool) = self.spool.clone() else {
+            return;
+        };
+
+        let interrupt = self.interrupt.clone();
+        tokio::select! {
+            _ = interrupt.triggered() => {}
+            _ = self.replay_spool(&spool) => {}
+        }
+    }
+
+    async fn replay_spool(&mut self, spool: &Spool) {
+        while let Some((path, body)) = spool.oldest() {
+            match self.send(body).await {
+                Outcome::Sent => {
+                    debug!(target: "sentry_kubernetes::transport", "Replayed spooled envelope {}", path.display());
+                    spool.remove(&path);
+                }
+                Outcome::Rejected(status) => {
+                    warn!(
+                        "Spooled envelope rejected by sentry (status {}), discarding it",
+                        status
+                    );
+                    METRICS.send_failure("sentry");
+                    spool.remove(&path);
+                }
+                Outcome::Retry(retry_after) => {
+                    self.disabled_until = retry_after.map(|delay| Instant::now() + delay);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Outcome {
+        let request = self
+            .client
+            .post(&self.url)
+            .header("X-Sentry-Auth", &self.auth);
+        let request = match self.compress.then(|| gzip(&body)) {
+            Some(Ok(compressed)) => request.header(CONTENT_ENCODING, "gzip").body(compressed),
+            _ => request.body(body),
+        };
+
+        let level = if self.debug {
+            Level::Warn
+        } else {
+            Level::Debug
+        };
+        match request.send().await {
+            Ok(response) => {
+                let outcome = outcome(&response);
+                if outcome != Outcome::Sent {
+                    log!(target: "sentry_kubernetes::transport", level, "Sentry responded with status {}", response.status());
+                }
+
+                outcome
+            }
+            Err(e) => {
+                log!(target: "sentry_kubernetes::transport", level, "Cannot send envelope to sentry: {}", e);
+                Outcome::Retry(None)
+            }
+        }
+    }
+}
+
+fn spool_envelope(spool: &Spool, body: &[u8]) {
+    match spool.push(body) {
+        Ok(()) => warn!("Cannot send envelope to sentry, spooled to disk"),
+        Err(e) => {
+            warn!("Cannot spool envelope, discarding it: {}", e);
+            METRICS.send_failure("sentry");
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send_envelope(&self, envelope: Envelope) {
+        let envelope = self.limits.apply(envelope);
+        match self.sender.try_send(Task::Send(envelope)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(Task::Send(envelope))) if self.spool.is_some() => {
+                let mut body = vec![];
+                if envelope.to_writer(&mut body).is_ok() {
+                    spool_envelope(self.spool.as_ref().unwrap(), &body);
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                warn!("Sentry transport queue is full, dropping envelope");
+                METRICS.send_failure("sentry");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("Sentry transport is shut down, dropping envelope");
+                METRICS.send_failure("sentry");
+            }
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        let (done, receiver) = sync_channel(1);
+        let _ = self.sender.send(Task::Flush(done));
+
+        receiver.recv_timeout(timeout).is_ok()
+    }
+
+    fn shutdown(&self, timeout: Duration) -> bool {
+        let flushed = self.flush(timeout);
+        if !flushed && self.spool.is_some() {
+            // The queued envelopes are spooled, instead of being lost.
+            self.interrupt.trigger();
+        }
+
+        let _ = self.sender.send(Task::Shutdown);
+        // If the queue is not drained (ex: waiting to retry), the worker thread is not waited,
+        // unless it is spooling the queued envelopes.
+        let wait = flushed || self.spool.is_some();
+        if let Some(handle) = self.handle.lock().unwrap().take().filter(|_| wait) {
+            let _ = handle.join();
+        }
+
+        flushed
+    }
+}
+
+fn http_client(config: &TransportConfig) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(config.http_timeout));
+
+    // Without an explicit proxy, reqwest honors the HTTP(S)_PROXY and NO_PROXY env vars.
+    if let Some(url) = config.proxy.as_deref() {
+        match Proxy::all(url) {
+            Ok(proxy) => {
+                let no_proxy = match config.no_proxy.as_deref() {
+                    Some(hosts) => NoProxy::from_string(hosts),
+                    None => NoProxy::from_env(),
+                };
+                builder = builder.proxy(proxy.no_proxy(no_proxy));
+            }
+            Err(e) => warn!("Invalid sentry proxy \"{}\": {}", url, e),
+        }
+    }
+
+    if let Some(path) = config.ca_certificate.as_deref() {
+        match fs::read_to_string(path) {
+            Ok(bundle) => {
+                for pem in pem_certificates(&bundle) {
+                    match Certificate::from_pem(pem.as_bytes()) {
+                        Ok(certificate) => builder = builder.add_root_certificate(certificate),
+                        Err(e) => warn!("Invalid CA certificate in {}: {}", path, e),
+                    }
+                }
+            }
+            Err(e) => warn!("Cannot read CA certificates from {}: {}", path, e),
+        }
+    }
+
+    builder.build().unwrap_or_default()
+}
+
+/// Splits a PEM bundle into the single certificates.
+fn pem_certificates(bundle: &str) -> Vec<String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut certificates = vec![];
+    let mut rest = bundle;
+    while let Some(start) = rest.find(BEGIN) {
+        let Some(end) = rest[start..].find(END) else {
+            break;
+        };
+
+        let end = start + end + END.len();
+        certificates.push(rest[start..end].to_string());
+        rest = &rest[end..];
+    }
+
+    certificates
+}
+
+/// Classifies the sentry response. Rate limiting (429) and server errors are retried,
+/// honoring the Retry-After header if present.
+fn outcome(response: &reqwest::Response) -> Outcome {
+    let status = response.status();
+    if status.is_success() {
+        return Outcome::Sent;
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(|secs| Duration::from_secs(secs.ceil() as u64));
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        warn!("Rate limited by sentry");
+        Outcome::Retry(Some(retry_after.unwrap_or(DEFAULT_RETRY_AFTER)))
+    } else if status.is_server_error() {
+        Outcome::Retry(retry_after)
+    } else {
+        Outcome::Rejected(status)
+    }
+}
+
+fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Exponential backoff delay of the given (0-based) retry: 1s, 2s, 4s, ... up to MAX_BACKOFF.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(16)).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::CaptureServer;
+    use crate::config::TransportConfig;
+    use crate::transport::{backoff, outcome, pem_certificates, HttpTransportFactory, Outcome};
+    use reqwest::StatusCode;
+    use sentry::protocol::Event;
+    use sentry::{Client, ClientOptions};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_outcome() {
+        let response = |status: u16, retry_after: Option<&str>| {
+            let mut builder = hyper::Response::builder().status(status);
+            if let Some(retry_after) = retry_after {
+                builder = builder.header("retry-after", retry_after);
+            }
+
+            reqwest::Response::from(builder.body("").unwrap())
+        };
+
+        assert_eq!(outcome(&response(200, None)), Outcome::Sent);
+        assert_eq!(
+            outcome(&response(429, Some("5"))),
+            Outcome::Retry(Some(Duration::from_secs(5)))
+        );
+        assert_eq!(
+            outcome(&response(429, None)),
+            Outcome::Retry(Some(Duration::from_secs(60)))
+        );
+        assert_eq!(outcome(&response(503, None)), Outcome::Retry(None));
+        assert_eq!(
+            outcome(&response(413, None)),
+            Outcome::Rejected(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    pub fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(40), Duration::from_secs(60));
+    }
+
+    #[test]
+    pub fn test_pem_certificates() {
+        let bundle =
+            "# Internal CA\n-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n\
+                      -----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----\n\
+                      -----BEGIN CERTIFICATE-----\ntruncated";
+
+        assert_eq!(
+            pem_certificates(bundle),
+            vec![
+                "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----",
+                "-----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----",
+            ]
+        );
+        assert!(pem_certificates("").is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_transport() {
+        let server = CaptureServer::start(None).unwrap();
+        let client = Arc::new(Client::from(ClientOptions {
+            dsn: Some(FromStr::from_str(&server.dsn()).unwrap()),
+            transport: Some(Arc::new(HttpTransportFactory::new(TransportConfig {
+                queue_size: 5,
+                http_timeout: 5,
+                ..Default::default()
+            }))),
+            ..Default::default()
+        }));
+
+        for _ in 0..3 {
+            client.capture_event(Event::default(), None);
+        }
+
+        let flushed =
+            tokio::task::spawn_blocking(move || client.close(Some(Duration::from_secs(5))))
+                .await
+                .unwrap();
+        assert!(flushed);
+        assert_eq!(server.envelopes().len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_proxy() {
+        // The capture server acts as proxy: requests are sent to it instead of the (unresolvable) sentry host.
+        let proxy = CaptureServer::start(None).unwrap();
+        let proxy_url = proxy.dsn().replace("capture@", "");
+        let proxy_url = proxy_url.trim_end_matches("/1");
+        let client = Arc::new(Client::from(ClientOptions {
+            dsn: Some(FromStr::from_str("http://public@sentry.invalid/1").unwrap()),
+            transport: Some(Arc::new(HttpTransportFactory::new(TransportConfig {
+                proxy: Some(proxy_url.to_string()),
+                no_proxy: Some("localhost".to_string()),
+                ..Default::default()
+            }))),
+            ..Default::default()
+        }));
+
+        client.capture_event(Event::default(), None);
+        tokio::task::spawn_blocking(move || client.close(Some(Duration::from_secs(5))))
+            .await
+            .unwrap();
+        assert_eq!(proxy.envelopes().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_spool_undelivered_envelopes() {
+        let dir = std::env::temp_dir().join(format!("transport-spool-{}", std::process::id()));
+        let spool_dir = Some(dir.to_string_lossy().to_string());
+        let spooled = || {
+            std::fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .flat_map(|entry| std::fs::read_dir(entry.unwrap().path()).unwrap())
+                .count()
+        };
+        // Sentry is unreachable through the proxy (nothing listens on the port 1).
+        let client = |proxy: &str, max_retries: u32| {
+            Arc::new(Client::from(ClientOptions {
+                dsn: Some(FromStr::from_str("http://public@sentry.invalid/1").unwrap()),
+                transport: Some(Arc::new(HttpTransportFactory::new(TransportConfig {
+                    proxy: Some(proxy.to_string()),
+                    max_retries,
+                    spool_dir: spool_dir.clone(),
+                    spool_max_size: 1 << 20,
+                    spool_max_age: 3600,
+                    ..Default::default()
+                }))),
+                ..Default::default()
+            }))
+        };
+        let close = |client: Arc<Client>, timeout: u64| {
+            tokio::task::spawn_blocking(move || client.close(Some(Duration::from_secs(timeout))))
+        };
+
+        // Spooled once the retries are exhausted.
+        let unreachable = client("http://127.0.0.1:1", 0);
+        unreachable.capture_event(Event::default(), None);
+        assert!(close(unreachable, 5).await.unwrap());
+        assert_eq!(spooled(), 1);
+
+        // Spooled when still queued (waiting to be retried) on shutdown.
+        let unreachable = client("http://127.0.0.1:1", 10);
+        unreachable.capture_event(Event::default(), None);
+        unreachable.capture_event(Event::default(), None);
+        assert!(!close(unreachable, 1).await.unwrap());
+        assert_eq!(spooled(), 3);
+
+        // Replayed once sentry is reachable.
+        let proxy = CaptureServer::start(None).unwrap();
+        let proxy_url = proxy.dsn().replace("capture@", "");
+        let reachable = client(proxy_url.trim_end_matches("/1"), 0);
+        assert!(close(reachable, 5).await.unwrap());
+        assert_eq!(proxy.envelopes().len(), 3);
+        assert_eq!(spooled(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use sentry::protocol::Event;
+use std::fmt;
+
+#[cfg(feature = "wasm")]
+use {
+    anyhow::Context,
+    lazy_static::lazy_static,
+    log::warn,
+    wasmtime::{Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder},
+};
+
+/// Instructions a plugin may execute on each event, so that a looping plugin cannot stall the pipeline.
+#[cfg(feature = "wasm")]
+const MAX_FUEL: u64 = 50_000_000;
+/// Maximum size of the memory of a plugin.
+#[cfg(feature = "wasm")]
+const MAX_MEMORY: usize = 64 << 20;
+
+#[cfg(feature = "wasm")]
+lazy_static! {
+    static ref ENGINE: Engine = Engine::new(wasmtime::Config::new().consume_fuel(true))
+        .expect("Cannot create the WASM engine");
+}
+
+/// A WASM module transforming the events of a before_send rule, loaded from a file.
+/// The module exports its `memory` and two functions:
+/// - `alloc(len: i32) -> i32`: allocates `len` bytes, where the event is written (JSON, in the
+///   sentry event format);
+/// - `process(ptr: i32, len: i32) -> i64`: returns 0 to keep the event unchanged, -1 to drop it,
+///   or the pointer (high 32 bits) and the length (low 32 bits) of the modified event JSON.
+///
+/// Each event is processed by a fresh instance of the module, with limited memory and instructions.
+/// If the plugin fails, the event is kept unchanged.
+#[derive(Clone)]
+pub struct WasmPlugin {
+    path: String,
+    #[cfg(feature = "wasm")]
+    module: Module,
+}
+
+impl fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WasmPlugin").field(&self.path).finish()
+    }
+}
+
+impl WasmPlugin {
+    /// Loads and compiles the module (binary or text format).
+    #[cfg(feature = "wasm")]
+    pub fn load(path: &str) -> Result<Self> {
+        let module = Module::from_file(&ENGINE, path)
+            .with_context(|| format!("Cannot load the WASM plugin {}", path))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            module,
+        })
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn load(path: &str) -> Result<Self> {
+        anyhow::bail!(
+            "Cannot load the WASM plugin {}: sentry-kubernetes is built without the \"wasm\" feature",
+            path
+        )
+    }
+
+    /// Runs the plugin on the event. Returns None if the plugin dropped the event.
+    pub fn apply(&self, event: Event<'static>) -> Option<Event<'static>> {
+        #[cfg(feature = "wasm")]
+        match self.run(&event) {
+            Ok(Decision::Keep) => Some(event),
+            Ok(Decision::Drop) => None,
+            Ok(Decision::Replace(event)) => Some(*event),
+            Err(e) => {
+                warn!(
+                    "WASM plugin {} failed, keeping the event: {:#}",
+                    self.path, e
+                );
+                Some(event)
+            }
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        Some(event)
+    }
+
+    #[cfg(feature = "wasm")]
+    fn run(&self, event: &Event<'static>) -> Result<Decision> {
+        let input = serde_json::to_vec(event)?;
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store = Store::new(&ENGINE, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.set_fuel(MAX_FUEL)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("The plugin does not export its memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let process = instance.get_typed_func::<(i32, i32), i64>(&mut store, "process")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as usize, &input)?;
+        let result = process.call(&mut store, (ptr, len))?;
+
+        Ok(match result {
+            0 => Decision::Keep,
+            -1 => Decision::Drop,
+            _ => {
+                let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+                let output = memory
+                    .data(&store)
+                    .get(ptr..ptr + len)
+                    .context("The plugin returned an out of bounds event")?;
+                Decision::Replace(Box::new(serde_json::from_slice(output)?))
+            }
+        })
+    }
+}
+
+#[cfg(feature = "wasm")]
+enum Decision {
+    Keep,
+    Drop,
+    Replace(Box<Event<'static>>),
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use crate::wasm::WasmPlugin;
+    use sentry::protocol::Event;
+    use sentry::Level;
+    use std::fs;
+
+    /// Writes the plugin in the text format and loads it.
+    fn plugin(name: &str, process: &str) -> WasmPlugin {
+        let path = std::env::temp_dir().join(format!("{}-{}.wat", name, std::process::id()));
+        let module = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{{\22level\22:\22error\22,\22message\22:\22replaced\22}}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "process") (param i32 i32) (result i64) {}))"#,
+            process
+        );
+        fs::write(&path, module).unwrap();
+
+        let plugin = WasmPlugin::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+        plugin
+    }
+
+    fn event() -> Event<'static> {
+        Event {
+            level: Level::Warning,
+            message: Some("Back-off restarting failed container".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_apply() {
+        let kept = plugin("keep", "(i64.const 0)").apply(event()).unwrap();
+        assert_eq!(kept.message.as_deref(), event().message.as_deref());
+
+        assert!(plugin("drop", "(i64.const -1)").apply(event()).is_none());
+
+        // The replacement is at offset 0, 38 bytes long.
+        let replaced = plugin("replace", "(i64.const 38)").apply(event()).unwrap();
+        assert_eq!(replaced.level, Level::Error);
+        assert_eq!(replaced.message.as_deref(), Some("replaced"));
+
+        let looping = plugin("loop", "(loop (br 0)) (i64.const -1)");
+        assert!(looping.apply(event()).is_some(), "kept when out of fuel");
+
+        let out_of_bounds = plugin("out-of-bounds", "(i64.const 0x7fffffff00000001)");
+        assert!(out_of_bounds.apply(event()).is_some());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::sentry_event::SentryEvent;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use futures::stream::BoxStream;
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Client, Resource};
+use log::warn;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::interval;
+
+/// Converts a changed object to the events to report, if any.
+/// A converter can keep the previous state of the objects to report the transitions only.
+type Converter<K> = Arc<dyn Fn(K) -> BoxFuture<'static, Vec<SentryEvent>> + Send + Sync>;
+
+/// The watch of a resource type, erasing the type of the watched objects.
+trait ResourceWatch: Send + Sync {
+    fn watch(
+        &self,
+        client: Client,
+        config: watcher::Config,
+    ) -> BoxStream<'static, Result<Vec<SentryEvent>, watcher::Error>>;
+}
+
+struct TypedWatch<K> {
+    convert: Converter<K>,
+}
+
+impl<K> ResourceWatch for TypedWatch<K>
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default,
+{
+    fn watch(
+        &self,
+        client: Client,
+        config: watcher::Config,
+    ) -> BoxStream<'static, Result<Vec<SentryEvent>, watcher::Error>> {
+        let convert = self.convert.clone();
+        watcher(Api::<K>::all(client), config)
+            .default_backoff()
+            .applied_objects()
+            .and_then(move |object| convert(object).map(Ok))
+            .boxed()
+    }
+}
+
+/// A function called periodically instead of a watch (ex: reporting the debounced transitions).
+struct PeriodicWatch {
+    period: Duration,
+    poll: Arc<dyn Fn() -> BoxFuture<'static, Vec<SentryEvent>> + Send + Sync>,
+}
+
+impl ResourceWatch for PeriodicWatch {
+    fn watch(
+        &self,
+        _client: Client,
+        _config: watcher::Config,
+    ) -> BoxStream<'static, Result<Vec<SentryEvent>, watcher::Error>> {
+        let poll = self.poll.clone();
+        stream::unfold(interval(self.period), move |mut interval| {
+            let poll = poll.clone();
+            async move {
+                interval.tick().await;
+                Some((Ok(poll().await), interval))
+            }
+        })
+        .boxed()
+    }
+}
+
+/// The resources watched besides the Events (ex: Pods, Nodes, Jobs or custom resources).
+/// Each resource has its own watch and converter, while the converted events share the
+/// filter → route → sink pipeline of the Events.
+#[derive(Default)]
+pub struct WatcherRegistry {
+    watchers: Vec<(&'static str, Box<dyn ResourceWatch>)>,
+}
+
+impl WatcherRegistry {
+    /// Registers a cluster-wide watch of the resource type K.
+    pub fn register<K>(
+        &mut self,
+        name: &'static str,
+        convert: impl Fn(&K) -> Vec<SentryEvent> + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+        K::DynamicType: Default,
+    {
+        self.register_async(name, move |object: K| future::ready(convert(&object)))
+    }
+
+    /// Registers a cluster-wide watch of the resource type K, whose converter looks up other
+    /// objects (ex: the pods of a Job). The objects are converted one at a time.
+    pub fn register_async<K, F>(
+        &mut self,
+        name: &'static str,
+        convert: impl Fn(K) -> F + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+        K::DynamicType: Default,
+        F: Future<Output = Vec<SentryEvent>> + Send + 'static,
+    {
+        let watch = TypedWatch {
+            convert: Arc::new(move |object| convert(object).boxed()),
+        };
+        self.watchers.push((name, Box::new(watch)));
+        self
+    }
+
+    /// Registers a function called with the given period, whose events are reported.
+    pub fn register_periodic<F>(
+        &mut self,
+        name: &'static str,
+        period: Duration,
+        poll: impl Fn() -> F + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        F: Future<Output = Vec<SentryEvent>> + Send + 'static,
+    {
+        let watch = PeriodicWatch {
+            period,
+            poll: Arc::new(move || poll().boxed()),
+        };
+        self.watchers.push((name, Box::new(watch)));
+        self
+    }
+
+    /// Watches all the registered resources. The watch errors are logged and returned, the
+    /// watchers resume on their own.
+    pub fn watch(
+        &self,
+        client: Client,
+        config: watcher::Config,
+    ) -> BoxStream<'static, Result<SentryEvent, watcher::Error>> {
+        stream::select_all(self.watchers.iter().map(|&(name, ref watch)| {
+            watch
+                .watch(client.clone(), config.clone())
+                .inspect_err(move |e| warn!("Kubernetes {} watcher error: {}", name, e))
+        }))
+        .map_ok(|events| stream::iter(events.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sentry_event::SentryEvent;
+    use crate::watchers::WatcherRegistry;
+    use futures::StreamExt;
+    use k8s_openapi::api::core::v1::{Event, Node, Pod};
+    use kube::runtime::watcher;
+    use kube::Client;
+
+    #[tokio::test]
+    pub async fn test_registry() {
+        let client = Client::try_default().await.unwrap();
+        let registry = WatcherRegistry::default();
+        assert!(registry
+            .watch(client.clone(), watcher::Config::default())
+            .next()
+            .await
+            .is_none());
+
+        let mut registry = WatcherRegistry::default();
+        registry
+            .register("pod", |_: &Pod| vec![SentryEvent::from(Event::default())])
+            .register("node", |_: &Node| vec![]);
+        assert_eq!(registry.watchers.len(), 2);
+    }
+}